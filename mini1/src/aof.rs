@@ -0,0 +1,570 @@
+use std::{collections::HashMap, fs::{self, File, OpenOptions}, io::Write, mem, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Duration};
+
+use crate::command::Command;
+use crate::config::Config;
+use crate::database::{Data, Database};
+use crate::rdb;
+use crate::server::Server;
+use crate::utils::{encode_request, execute_command, parse_request, read_frame, write_file_atomically, Frame};
+
+// When commands appended to the AOF are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AppendFsync {
+    // After every write, before the client gets its reply
+    Always,
+    // Once a second, in the background
+    Everysec,
+    // When the operating system decides to
+    No,
+}
+
+impl AppendFsync {
+    pub fn parse(policy: &str) -> Result<Self, &'static str> {
+        match policy.to_lowercase().as_str() {
+            "always" => Ok(AppendFsync::Always),
+            "everysec" => Ok(AppendFsync::Everysec),
+            "no" => Ok(AppendFsync::No),
+            _ => Err("Invalid --appendfsync, expected always, everysec or no"),
+        }
+    }
+}
+
+// The kind of a file listed in the manifest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AofFileType {
+    // The data at the time of the last rewrite, as an RDB file or as commands
+    Base,
+    // Commands appended since the base was written
+    Incr,
+}
+
+// A file of the AOF directory, named "<appendfilename>.<seq>.base.rdb|aof" or "<appendfilename>.<seq>.incr.aof"
+#[derive(Debug, Clone, PartialEq)]
+pub struct AofFile {
+    pub name: String,
+    pub seq: u64,
+    pub file_type: AofFileType,
+}
+
+// The files that make up the AOF, in the format of Redis 7: one "file <name> seq <seq> type <b|i|h>"
+// line per file. The base is replayed first and then the incremental files in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub base: Option<AofFile>,
+    pub incrs: Vec<AofFile>,
+}
+
+impl Manifest {
+    // Parse a manifest. History files are left over from a rewrite and are not replayed.
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut manifest = Manifest::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !words.len().is_multiple_of(2) {
+                return Err("Invalid AOF manifest file format");
+            }
+            let (mut name, mut seq, mut file_type) = (None, None, None);
+            for pair in words.chunks(2) {
+                match pair[0] {
+                    "file" => name = Some(pair[1].to_string()),
+                    "seq" => seq = Some(pair[1].parse::<u64>().map_err(|_| "Invalid AOF manifest file format")?),
+                    "type" => file_type = Some(pair[1]),
+                    _ => {}
+                }
+            }
+            let (name, seq) = name.zip(seq).ok_or("Invalid AOF manifest file format")?;
+            match file_type {
+                Some("b") if manifest.base.is_none() => manifest.base = Some(AofFile { name, seq, file_type: AofFileType::Base }),
+                Some("b") => return Err("Found duplicate base file information"),
+                Some("i") => manifest.incrs.push(AofFile { name, seq, file_type: AofFileType::Incr }),
+                Some("h") => {}
+                _ => return Err("Unknown AOF file type"),
+            }
+        }
+        if manifest.incrs.windows(2).any(|pair| pair[0].seq >= pair[1].seq) {
+            return Err("Found a non-monotonic sequence number");
+        }
+        Ok(manifest)
+    }
+
+    // The manifest as it is written to disk
+    pub fn to_text(&self) -> String {
+        self.base.iter().chain(&self.incrs).map(|file| {
+            let file_type = if file.file_type == AofFileType::Base { "b" } else { "i" };
+            format!("file {} seq {} type {}\n", file.name, file.seq, file_type)
+        }).collect()
+    }
+
+    // The base file for the next rewrite
+    fn next_base(&self, prefix: &str, rdb_preamble: bool) -> AofFile {
+        let seq = self.base.as_ref().map_or(1, |base| base.seq + 1);
+        let extension = if rdb_preamble { "rdb" } else { "aof" };
+        AofFile { name: format!("{}.{}.base.{}", prefix, seq, extension), seq, file_type: AofFileType::Base }
+    }
+
+    // The incremental file that follows the last one
+    fn next_incr(&self, prefix: &str) -> AofFile {
+        let seq = self.incrs.last().map_or(1, |incr| incr.seq + 1);
+        AofFile { name: format!("{}.{}.incr.aof", prefix, seq), seq, file_type: AofFileType::Incr }
+    }
+}
+
+// The path of the manifest in the AOF directory
+pub fn manifest_path(dir: &Path, prefix: &str) -> PathBuf {
+    dir.join(format!("{}.manifest", prefix))
+}
+
+// The append only file, which logs every write command as a RESP request to the last
+// incremental file of the manifest
+#[derive(Debug)]
+pub struct Aof {
+    dir: PathBuf,
+    prefix: String,
+    manifest: Manifest,
+    file: File,
+    fsync: AppendFsync,
+    // The database of the last appended command, so SELECT is only written when it changes
+    selected_db: Option<u8>,
+    // Whether commands were written since the last fsync, and whether an fsync is running
+    pending_fsync: bool,
+    fsync_in_progress: bool,
+    // The replication offset the last fsync covered
+    fsynced_reploff: u64,
+    // The size of the files after the last rewrite or at startup, and now
+    pub base_size: u64,
+    pub current_size: u64,
+    pub rewrite_in_progress: bool,
+    // Unix time in seconds of the last failed rewrite
+    pub rewrite_failed_at: Option<u64>,
+}
+
+impl Aof {
+    // Open the last incremental file of manifest for appending, starting one if there is none
+    pub fn open(config: &Config, manifest: Manifest) -> std::io::Result<Self> {
+        let dir = config.aof_dir();
+        let mut manifest = manifest;
+        if manifest.incrs.is_empty() {
+            let incr = manifest.next_incr(&config.appendfilename);
+            OpenOptions::new().create(true).append(true).open(dir.join(&incr.name))?;
+            manifest.incrs.push(incr);
+            write_file_atomically(&manifest_path(&dir, &config.appendfilename), manifest.to_text().as_bytes())?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(dir.join(&manifest.incrs.last().unwrap().name))?;
+
+        let mut aof = Self {
+            dir,
+            prefix: config.appendfilename.clone(),
+            manifest,
+            file,
+            fsync: config.appendfsync,
+            selected_db: None,
+            pending_fsync: false,
+            fsync_in_progress: false,
+            fsynced_reploff: 0,
+            base_size: 0,
+            current_size: 0,
+            rewrite_in_progress: false,
+            rewrite_failed_at: None,
+        };
+        aof.base_size = aof.files_size();
+        aof.current_size = aof.base_size;
+        Ok(aof)
+    }
+
+    // The total size of the files in the manifest
+    fn files_size(&self) -> u64 {
+        self.manifest.base.iter().chain(&self.manifest.incrs)
+            .filter_map(|file| fs::metadata(self.dir.join(&file.name)).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    // Append the requests of a command that ran in selected_db. Like Redis, the server
+    // stops when a write fails under the always policy, since it could not keep its promise.
+    pub fn append(&mut self, selected_db: u8, requests: &[Frame]) {
+        let mut buf = Vec::new();
+        if self.selected_db != Some(selected_db) {
+            buf.extend(encode_request(&[b"SELECT", selected_db.to_string().as_bytes()]));
+            self.selected_db = Some(selected_db);
+        }
+        for request in requests {
+            let parts: Vec<&[u8]> = request.iter().map(Vec::as_slice).collect();
+            buf.extend(encode_request(&parts));
+        }
+
+        let result = self.file.write_all(&buf).and_then(|_| match self.fsync {
+            AppendFsync::Always => self.file.sync_data(),
+            AppendFsync::Everysec => {
+                self.pending_fsync = true;
+                Ok(())
+            }
+            AppendFsync::No => Ok(()),
+        });
+        match result {
+            Ok(()) => self.current_size += buf.len() as u64,
+            Err(e) => {
+                eprintln!("Error writing to the append only file: {}", e);
+                if self.fsync == AppendFsync::Always {
+                    eprintln!("Can't recover from AOF write error when the AOF fsync policy is 'always'. Exiting...");
+                    std::process::exit(1);
+                }
+                // The next command starts with a SELECT again, in case part of this one was written
+                self.selected_db = None;
+            }
+        }
+    }
+
+    // Start a new incremental file that takes the writes from now on, and return the
+    // base file a rewrite should write along with the sequence of the new incremental file
+    pub fn start_rewrite(&mut self, rdb_preamble: bool) -> std::io::Result<(AofFile, u64)> {
+        let incr = self.manifest.next_incr(&self.prefix);
+        let file = OpenOptions::new().create(true).append(true).open(self.dir.join(&incr.name))?;
+        self.file.sync_data()?;
+
+        let mut manifest = self.manifest.clone();
+        manifest.incrs.push(incr.clone());
+        write_file_atomically(&manifest_path(&self.dir, &self.prefix), manifest.to_text().as_bytes())?;
+        let base = manifest.next_base(&self.prefix, rdb_preamble);
+        self.manifest = manifest;
+        self.file = file;
+        self.selected_db = None;
+        self.pending_fsync = false;
+        Ok((base, incr.seq))
+    }
+
+    // Replace the base with the one a rewrite wrote, keeping the incremental files from
+    // first_seq on, and remove the files it replaces
+    pub fn finish_rewrite(&mut self, base: AofFile, first_seq: u64) -> std::io::Result<()> {
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: self.manifest.incrs.iter().filter(|incr| incr.seq >= first_seq).cloned().collect(),
+        };
+        write_file_atomically(&manifest_path(&self.dir, &self.prefix), manifest.to_text().as_bytes())?;
+        let old = std::mem::replace(&mut self.manifest, manifest);
+        for file in old.base.iter().chain(&old.incrs) {
+            if !self.manifest.base.iter().chain(&self.manifest.incrs).any(|kept| kept.name == file.name) {
+                let _ = fs::remove_file(self.dir.join(&file.name));
+            }
+        }
+        self.base_size = self.files_size();
+        self.current_size = self.base_size;
+        Ok(())
+    }
+
+    // The path of a file in the AOF directory
+    pub fn path(&self, file: &AofFile) -> PathBuf {
+        self.dir.join(&file.name)
+    }
+
+    // The replication offset up to which the writes are on disk, given the current offset.
+    // Writes are appended before they reach the replication stream, so once nothing is
+    // waiting for an fsync the whole stream is covered. With the no policy the operating
+    // system decides when data reaches the disk, so a write counts once it is appended.
+    pub fn fsynced_offset(&self, replication_offset: u64) -> u64 {
+        if self.pending_fsync || self.fsync_in_progress { self.fsynced_reploff } else { replication_offset }
+    }
+}
+
+// The requests that set key to value: a RESTORE, or for a time series, which has no DUMP
+// payload, a DEL followed by TS.CREATE with its options and TS.MADD with its samples
+fn value_requests(key: &str, value: &Data) -> Vec<Frame> {
+    let text = |value: &str| value.as_bytes().to_vec();
+    let Data::TimeSeries(series) = value else {
+        let payload = rdb::dump(value).expect("only time series have no DUMP payload");
+        return vec![vec![b"RESTORE".to_vec(), text(key), b"0".to_vec(), payload, b"REPLACE".to_vec()]];
+    };
+
+    let mut create = vec![b"TS.CREATE".to_vec(), text(key), b"RETENTION".to_vec(), text(&series.retention.to_string()),
+        b"DUPLICATE_POLICY".to_vec(), text(&format!("{:?}", series.duplicate_policy).to_uppercase())];
+    if !series.labels.is_empty() {
+        create.push(b"LABELS".to_vec());
+        create.extend(series.labels.iter().flat_map(|(label, value)| [text(label), text(value)]));
+    }
+    let mut requests = vec![vec![b"DEL".to_vec(), text(key)], create];
+    if !series.samples.is_empty() {
+        let mut add = vec![b"TS.MADD".to_vec()];
+        for (timestamp, value) in &series.samples {
+            add.extend([text(key), text(&timestamp.to_string()), text(&value.to_string())]);
+        }
+        requests.push(add);
+    }
+    requests
+}
+
+// The requests that recreate the current value of each key, or a DEL request for a missing key
+fn key_effects(keys: &[&str], db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: u8) -> Vec<Frame> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(&selected_db);
+    keys.iter().flat_map(|key| match cur_db.and_then(|cur_db| cur_db.data.get(*key)) {
+        Some(value) => value_requests(key, value),
+        None => vec![vec![b"DEL".to_vec(), key.as_bytes().to_vec()]],
+    }).collect()
+}
+
+// The requests to append for a write command that replied with response. Most commands are
+// logged as they were sent. Commands whose outcome depends on chance, the clock or other
+// servers are logged so that replaying them gives the same data: generated IDs and
+// timestamps are filled in, and the others are logged as the resulting values of their keys.
+pub(crate) fn log_entries(cmd: &Command, mut request: Frame, response: &[u8], db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: u8) -> Vec<Frame> {
+    match cmd {
+        // MIGRATE may have removed some of the keys even when it replies with an error
+        Command::Migrate(_) => key_effects(&cmd.keys(), db, selected_db),
+        _ if response.starts_with(b"-") => Vec::new(),
+        Command::Spop(..) | Command::BLPOP(..) | Command::BRPOP(..) | Command::Bzpop(..) | Command::Xread(_)
+        | Command::Xclaim(..) | Command::Xautoclaim(..) => key_effects(&cmd.keys(), db, selected_db),
+        Command::Xadd(_, args) => {
+            // The ID is the argument before the fields, and the reply is the ID that was added
+            let id = request.len() - 2 * args.fields.len() - 1;
+            if let Some(added) = response.strip_prefix(b"$").filter(|_| !response.starts_with(b"$-1")) {
+                let start = added.iter().position(|byte| *byte == b'\n').map_or(0, |newline| newline + 1);
+                request[id] = added[start..].strip_suffix(b"\r\n").unwrap_or(&added[start..]).to_vec();
+            }
+            vec![request]
+        }
+        Command::TsAdd(_, timestamp, ..) => {
+            request[2] = timestamp.to_string().into_bytes();
+            vec![request]
+        }
+        Command::TsMadd(samples) => {
+            for (i, (_, timestamp, _)) in samples.iter().enumerate() {
+                request[2 + 3 * i] = timestamp.to_string().into_bytes();
+            }
+            vec![request]
+        }
+        _ => vec![request],
+    }
+}
+
+// Commands that recreate every database, for starting an AOF from existing data
+pub fn write_databases(databases: &HashMap<u8, Database>) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut indexes: Vec<&u8> = databases.keys().collect();
+    indexes.sort();
+    for index in indexes {
+        let database = &databases[index];
+        if database.data.is_empty() {
+            continue;
+        }
+        out.extend(encode_request(&[b"SELECT", index.to_string().as_bytes()]));
+        for (key, value) in &database.data {
+            for request in value_requests(key, value) {
+                out.extend(encode_request(&request.iter().map(Vec::as_slice).collect::<Vec<_>>()));
+            }
+        }
+    }
+    out
+}
+
+// The contents of a base file: an RDB file, or commands that recreate the databases
+pub fn base_contents(databases: &HashMap<u8, Database>, rdb_preamble: bool) -> Vec<u8> {
+    if rdb_preamble { rdb::save(databases) } else { write_databases(databases) }
+}
+
+// Read the manifest of the AOF directory. An AOF from before the manifest, a single file in
+// --dir, is moved into the directory as the base, like Redis 7 upgrades it.
+pub fn read_manifest(config: &Config) -> std::io::Result<Option<Manifest>> {
+    let dir = config.aof_dir();
+    let path = manifest_path(&dir, &config.appendfilename);
+    match fs::read_to_string(&path) {
+        Ok(text) => return Manifest::parse(&text).map(Some).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+
+    let legacy = config.dir.join(&config.appendfilename);
+    if !legacy.is_file() {
+        return Ok(None);
+    }
+    fs::create_dir_all(&dir)?;
+    let manifest = Manifest { base: Some(Manifest::default().next_base(&config.appendfilename, false)), incrs: Vec::new() };
+    fs::rename(&legacy, dir.join(&manifest.base.as_ref().unwrap().name))?;
+    write_file_atomically(&path, manifest.to_text().as_bytes())?;
+    println!("Moved {} into {} as the AOF base", legacy.display(), dir.display());
+    Ok(Some(manifest))
+}
+
+// Start an AOF from the current databases, with a base file and no incremental files yet
+pub fn create(config: &Config, databases: &HashMap<u8, Database>) -> std::io::Result<Manifest> {
+    let dir = config.aof_dir();
+    fs::create_dir_all(&dir)?;
+    let manifest = Manifest { base: Some(Manifest::default().next_base(&config.appendfilename, config.aof_use_rdb_preamble)), incrs: Vec::new() };
+    write_file_atomically(&dir.join(&manifest.base.as_ref().unwrap().name), &base_contents(databases, config.aof_use_rdb_preamble))?;
+    write_file_atomically(&manifest_path(&dir, &config.appendfilename), manifest.to_text().as_bytes())?;
+    Ok(manifest)
+}
+
+// Load the files of manifest and return how many commands ran. Only the last incremental
+// file may end with a command cut short.
+pub async fn load(config: &Config, manifest: &Manifest, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<usize, String> {
+    let dir = config.aof_dir();
+    let mut count = 0;
+    if let Some(base) = &manifest.base {
+        let path = dir.join(&base.name);
+        if base.name.ends_with(".rdb") {
+            let file = fs::read(&path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+            let databases = rdb::load(&file).map_err(|e| format!("Error loading {}: {}", path.display(), e))?;
+            db.lock().unwrap().extend(databases);
+        } else {
+            count += load_commands(&path, false, db, server).await?;
+        }
+    }
+    for (i, incr) in manifest.incrs.iter().enumerate() {
+        let last = i + 1 == manifest.incrs.len();
+        count += load_commands(&dir.join(&incr.name), config.aof_load_truncated && last, db, server).await?;
+    }
+    db.lock().unwrap().entry(0).or_insert_with(Database::new);
+    Ok(count)
+}
+
+// Replay the commands of an AOF file and return how many ran. A command cut short at the end
+// of the file, as a crash leaves it, is removed from the file when load_truncated is set.
+async fn load_commands(path: &Path, load_truncated: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<usize, String> {
+    let contents = fs::read(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    let mut pos = 0;
+    let mut selected_db = 0;
+    let mut count = 0;
+    while pos < contents.len() {
+        let (frame, consumed) = match read_frame(&contents[pos..]) {
+            Ok(Some(frame)) => frame,
+            Ok(None) if load_truncated => {
+                eprintln!("!!! Warning: short read while loading the AOF file {}!!! Truncating the AOF at offset {}", path.display(), pos);
+                OpenOptions::new().write(true).open(path).and_then(|file| file.set_len(pos as u64))
+                    .map_err(|e| format!("Error truncating the append only file: {}", e))?;
+                break;
+            }
+            Ok(None) => return Err(format!("Unexpected end of file reading {}. Start with --aof-load-truncated yes to load it without the last command", path.display())),
+            Err(_) => return Err(format!("Bad file format reading {} at offset {}", path.display(), pos)),
+        };
+
+        let (cmd, _) = parse_request(frame.clone()).map_err(|e| format!("Error replaying {} at offset {}: {}", path.display(), pos, e))?;
+        // Every command may switch databases, since SELECT entries can appear anywhere
+        let mut cmd_executed = false;
+        execute_command(cmd, db, server, &mut selected_db, &mut cmd_executed, frame).await;
+        pos += consumed;
+        count += 1;
+    }
+    Ok(count)
+}
+
+// Flush the AOF to disk once a second when commands were written, on a blocking task
+// so writes are not held up
+pub async fn run_everysec_fsync(server: Arc<Server>) {
+    let aof = match &server.aof {
+        Some(aof) => aof,
+        None => return,
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let (file, reploff) = {
+            let mut aof = aof.lock().await;
+            if !mem::take(&mut aof.pending_fsync) {
+                continue;
+            }
+            aof.fsync_in_progress = true;
+            // Everything appended so far is covered, which is the whole stream up to now
+            (aof.file.try_clone(), server.replication.lock().await.offset)
+        };
+        let result = match file {
+            Ok(file) => tokio::task::spawn_blocking(move || file.sync_data()).await.unwrap_or_else(|e| Err(e.into())),
+            Err(e) => Err(e),
+        };
+
+        let mut aof = aof.lock().await;
+        aof.fsync_in_progress = false;
+        match result {
+            Ok(()) => aof.fsynced_reploff = reploff,
+            Err(e) => {
+                eprintln!("Error syncing the append only file: {}", e);
+                aof.pending_fsync = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timeseries::{DuplicatePolicy, TimeSeries};
+
+    #[test]
+    fn manifest_round_trip() {
+        let text = "file appendonly.aof.2.base.rdb seq 2 type b\n\
+                    file appendonly.aof.1.base.aof seq 1 type h\n\
+                    # a comment\n\
+                    file appendonly.aof.3.incr.aof seq 3 type i\n\
+                    file appendonly.aof.4.incr.aof seq 4 type i\n";
+        let manifest = Manifest::parse(text).unwrap();
+        assert_eq!(manifest.base, Some(AofFile { name: "appendonly.aof.2.base.rdb".to_string(), seq: 2, file_type: AofFileType::Base }));
+        assert_eq!(manifest.incrs.iter().map(|incr| incr.seq).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(Manifest::parse(&manifest.to_text()), Ok(manifest.clone()));
+
+        assert_eq!(manifest.next_base("appendonly.aof", false).name, "appendonly.aof.3.base.aof");
+        assert_eq!(manifest.next_incr("appendonly.aof").name, "appendonly.aof.5.incr.aof");
+    }
+
+    #[test]
+    fn manifest_rejects_bad_files() {
+        assert!(Manifest::parse("file a seq 1 type b\nfile b seq 2 type b\n").is_err());
+        assert!(Manifest::parse("file a seq 1 type x\n").is_err());
+        assert!(Manifest::parse("file a seq 1 type\n").is_err());
+        assert!(Manifest::parse("file a seq one type i\n").is_err());
+        assert!(Manifest::parse("file a seq 2 type i\nfile b seq 1 type i\n").is_err());
+    }
+
+    // A directory of its own for each test, under the system temporary directory
+    fn test_config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("mini1-aof-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("appendonlydir")).unwrap();
+        Config { dir, ..Config::default() }
+    }
+
+    #[tokio::test]
+    async fn load_truncates_a_command_cut_short() {
+        let mut config = test_config("truncated");
+        let dir = config.aof_dir();
+        let manifest = Manifest::parse("file appendonly.aof.1.base.aof seq 1 type b\nfile appendonly.aof.1.incr.aof seq 1 type i\n").unwrap();
+        fs::write(dir.join("appendonly.aof.1.base.aof"), encode_request(&[b"SET", b"a", b"1"])).unwrap();
+        let mut incr = encode_request(&[b"SET", b"b", b"2"]);
+        let complete = incr.len();
+        incr.extend_from_slice(b"*3\r\n$3\r\nSET\r\n$1\r\nc");
+        fs::write(dir.join("appendonly.aof.1.incr.aof"), &incr).unwrap();
+
+        config.aof_load_truncated = false;
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        let server = Arc::new(Server::new(config.clone(), None, None));
+        assert!(load(&config, &manifest, &db, &server).await.is_err());
+
+        config.aof_load_truncated = true;
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        assert_eq!(load(&config, &manifest, &db, &server).await, Ok(2));
+        assert_eq!(fs::metadata(dir.join("appendonly.aof.1.incr.aof")).unwrap().len(), complete as u64);
+        assert!(db.lock().unwrap()[&0].data.contains_key("b"));
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn time_series_are_written_as_commands() {
+        let config = test_config("timeseries");
+        let mut series = TimeSeries::new(100, DuplicatePolicy::Max, vec![("room".to_string(), "kitchen".to_string())]);
+        series.samples.extend([(10, 1.5), (20, -2.0)]);
+        let mut database = Database::new();
+        database.data.insert("ts".to_string(), Data::TimeSeries(series));
+        let manifest = Manifest::parse("file appendonly.aof.1.base.aof seq 1 type b\n").unwrap();
+        fs::write(config.aof_dir().join("appendonly.aof.1.base.aof"), write_databases(&HashMap::from([(0, database)]))).unwrap();
+
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        let server = Arc::new(Server::new(config.clone(), None, None));
+        load(&config, &manifest, &db, &server).await.unwrap();
+        match &db.lock().unwrap()[&0].data["ts"] {
+            Data::TimeSeries(series) => {
+                assert_eq!(series.samples.iter().map(|(t, v)| (*t, *v)).collect::<Vec<_>>(), vec![(10, 1.5), (20, -2.0)]);
+                assert_eq!((series.retention, series.duplicate_policy), (100, DuplicatePolicy::Max));
+                assert_eq!(series.label("room"), Some("kitchen"));
+            }
+            _ => panic!("expected a time series"),
+        }
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
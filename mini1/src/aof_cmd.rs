@@ -0,0 +1,86 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use crate::aof::base_contents;
+use crate::database::Database;
+use crate::server::Server;
+use crate::stream::now_ms;
+use crate::utils::write_file_atomically;
+
+// Seconds to wait after a failed rewrite before the rewrite rule tries again
+const REWRITE_RETRY_DELAY: u64 = 5;
+
+// Rewrite the AOF in the background. A new incremental file takes the writes from now on,
+// and the databases are copied under the same locks so the copy holds exactly the writes
+// before it. The copy is written as the new base on a blocking task, and then replaces the
+// old base and incremental files in the manifest. Until then the old files stay listed, so
+// a crash during the rewrite loses nothing.
+pub(crate) async fn start_rewrite(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<(), &'static str> {
+    let aof = server.aof.as_ref().ok_or("Background append only file rewriting needs --appendonly yes")?;
+    let (databases, base, first_seq) = {
+        let mut aof = aof.lock().await;
+        if aof.rewrite_in_progress {
+            return Err("Background append only file rewriting already in progress");
+        }
+        let (base, first_seq) = aof.start_rewrite(server.config.aof_use_rdb_preamble).map_err(|e| {
+            eprintln!("Error starting the AOF rewrite: {}", e);
+            "Can't start the append only file rewrite, check the server logs for details"
+        })?;
+        aof.rewrite_in_progress = true;
+        let databases = db.lock().unwrap().clone();
+        (databases, base, first_seq)
+    };
+
+    let server = Arc::clone(server);
+    tokio::spawn(async move {
+        let aof = server.aof.as_ref().unwrap();
+        let path = aof.lock().await.path(&base);
+        let rdb_preamble = server.config.aof_use_rdb_preamble;
+        let written = tokio::task::spawn_blocking(move || write_file_atomically(&path, &base_contents(&databases, rdb_preamble)))
+            .await
+            .unwrap_or_else(|e| Err(e.into()));
+
+        let mut aof = aof.lock().await;
+        aof.rewrite_in_progress = false;
+        let path = aof.path(&base);
+        match written.and_then(|_| aof.finish_rewrite(base, first_seq)) {
+            Ok(()) => println!("Background AOF rewrite finished successfully"),
+            Err(e) => {
+                eprintln!("Error rewriting the append only file: {}", e);
+                let _ = std::fs::remove_file(path);
+                aof.rewrite_failed_at = Some(now_ms() / 1000);
+            }
+        }
+    });
+    Ok(())
+}
+
+// BGREWRITEAOF command
+pub async fn execute_bgrewriteaof_cmd(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<String, &'static str> {
+    start_rewrite(db, server).await?;
+    Ok("+Background append only file rewriting started\r\n".to_string())
+}
+
+// Check once a second whether the AOF grew by the rewrite percentage since the last
+// rewrite, while being at least the minimum size, and rewrite it when it did
+pub async fn run_aof_rewrite_rule(db: Arc<Mutex<HashMap<u8, Database>>>, server: Arc<Server>) {
+    let (aof, percentage) = match (&server.aof, server.config.auto_aof_rewrite_percentage) {
+        (Some(aof), percentage) if percentage > 0 => (aof, percentage),
+        _ => return,
+    };
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let due = {
+            let aof = aof.lock().await;
+            let growth = aof.current_size.saturating_sub(aof.base_size).saturating_mul(100) / aof.base_size.max(1);
+            !aof.rewrite_in_progress
+                && aof.rewrite_failed_at.is_none_or(|failed_at| now_ms() / 1000 - failed_at >= REWRITE_RETRY_DELAY)
+                && aof.current_size >= server.config.auto_aof_rewrite_min_size
+                && growth >= percentage
+        };
+        if due {
+            println!("Starting automatic rewriting of AOF");
+            let _ = start_rewrite(&db, &server).await;
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// Offline checks of the persistence files of mini1: verify and dump RDB files, and verify
+// and repair AOFs
+use std::{collections::{BTreeMap, HashMap}, fs::{self, OpenOptions}, io::Write, path::{Path, PathBuf}, process::ExitCode};
+
+use serde_json::{json, Map, Value};
+
+use mini1::aof::Manifest;
+use mini1::database::{Data, Database};
+use mini1::rdb;
+use mini1::utils::{encode_request, read_frame};
+
+const USAGE: &str = "Usage:
+  mini1-check rdb <file> [--json|--resp]   Verify an RDB file, or print its contents as JSON or RESP commands
+  mini1-check aof <file|manifest|dir> [--fix]   Verify an AOF, or cut a command cut short off its last file";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["rdb", path] => check_rdb(Path::new(path)),
+        ["rdb", path, "--json"] => load_rdb(Path::new(path)).map(|databases| println!("{}", serde_json::to_string_pretty(&to_json(&databases)).unwrap())),
+        ["rdb", path, "--resp"] => load_rdb(Path::new(path)).and_then(|databases| {
+            std::io::stdout().write_all(&to_resp(&databases)).map_err(|e| format!("Error writing the commands: {}", e))
+        }),
+        ["aof", path] => check_aof(Path::new(path), false),
+        ["aof", path, "--fix"] => check_aof(Path::new(path), true),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn load_rdb(path: &Path) -> Result<HashMap<u8, Database>, String> {
+    let file = fs::read(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+    let (databases, expiries) = rdb::load_with_expiries(&file).map_err(|e| format!("--- RDB ERROR DETECTED ---\n{}: {}", path.display(), e))?;
+    warn_expiries(path, expiries);
+    Ok(databases)
+}
+
+// Keys do not expire in mini1, so warn about the expiries the loader left out. The warning goes
+// to stderr, where it does not mix with a dump.
+fn warn_expiries(path: &Path, expiries: rdb::Expiries) {
+    if expiries.skipped + expiries.dropped > 0 {
+        eprintln!("WARNING: {} has keys with an expiry, which mini1 does not keep: {} expired keys are skipped and {} keys load without their expiry", path.display(), expiries.skipped, expiries.dropped);
+    }
+}
+
+// Verify the signature, version, values and checksum of an RDB file
+fn check_rdb(path: &Path) -> Result<(), String> {
+    println!("Checking RDB file {}", path.display());
+    let databases = load_rdb(path)?;
+    let keys: usize = databases.values().map(|database| database.data.len()).sum();
+    let non_empty = databases.values().filter(|database| !database.data.is_empty()).count();
+    println!("{} keys in {} databases", keys, non_empty);
+    println!("\\o/ RDB looks OK! \\o/");
+    Ok(())
+}
+
+// The databases of an RDB file as JSON, with databases, keys and unordered collections sorted so
+// that dumps of the same data are equal
+fn to_json(databases: &HashMap<u8, Database>) -> Value {
+    let mut out = Map::new();
+    for (index, database) in sorted(databases) {
+        if database.data.is_empty() {
+            continue;
+        }
+        let mut keys = Map::new();
+        for (key, value) in sorted(&database.data) {
+            keys.insert(key.clone(), json!({ "type": value.type_name(), "value": value_to_json(value) }));
+        }
+        out.insert(index.to_string(), Value::Object(keys));
+    }
+    Value::Object(out)
+}
+
+fn value_to_json(value: &Data) -> Value {
+    match value {
+        Data::Scalar(bytes) => json!(String::from_utf8_lossy(bytes)),
+        Data::List(list) => json!(list),
+        Data::Hash(hash) => json!(hash.iter().collect::<BTreeMap<_, _>>()),
+        Data::Set(set) => {
+            let mut members: Vec<&String> = set.iter().collect();
+            members.sort();
+            json!(members)
+        }
+        Data::SortedSet(zset) => json!(zset.iter().map(|(member, score)| json!([member, score])).collect::<Vec<_>>()),
+        Data::Stream(stream) => json!({
+            "last_id": stream.last_id.to_string(),
+            "entries_added": stream.entries_added,
+            "max_deleted_id": stream.max_deleted_id.to_string(),
+            "entries": stream.entries.iter().map(|(id, fields)| json!({ "id": id.to_string(), "fields": fields.iter().flat_map(|(field, value)| [field, value]).collect::<Vec<_>>() })).collect::<Vec<_>>(),
+            "groups": stream.groups.iter().map(|(name, group)| json!({
+                "name": name,
+                "last_delivered_id": group.last_delivered.to_string(),
+                "entries_read": group.entries_read,
+                "consumers": group.consumers.keys().collect::<Vec<_>>(),
+                "pending": group.pending.iter().map(|(id, entry)| json!({
+                    "id": id.to_string(),
+                    "consumer": entry.consumer,
+                    "delivery_time": entry.delivery_time,
+                    "delivery_count": entry.delivery_count,
+                })).collect::<Vec<_>>(),
+            })).collect::<Vec<_>>(),
+        }),
+        Data::Json(value) => value.clone(),
+        Data::Bloom(bloom) => json!({
+            "error_rate": bloom.error_rate,
+            "expansion": bloom.expansion,
+            "filters": bloom.filters.iter().map(|filter| json!({ "capacity": filter.capacity, "items": filter.count, "hashes": filter.hashes, "bits": filter.bit_count })).collect::<Vec<_>>(),
+        }),
+        Data::CountMinSketch(cms) => json!({ "width": cms.width, "depth": cms.depth, "count": cms.count }),
+        Data::TimeSeries(series) => json!({
+            "retention": series.retention,
+            "duplicate_policy": format!("{:?}", series.duplicate_policy).to_lowercase(),
+            "labels": series.labels.iter().map(|(label, value)| json!([label, value])).collect::<Vec<_>>(),
+            "samples": series.samples.iter().map(|(timestamp, value)| json!([timestamp, value])).collect::<Vec<_>>(),
+        }),
+    }
+}
+
+// The databases of an RDB file as the commands that recreate them, sorted like the JSON dump.
+// Bloom filters and Count-Min Sketches cannot be rebuilt from commands and are written as
+// RESTORE, and the pending entries of stream consumer groups are left out.
+fn to_resp(databases: &HashMap<u8, Database>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (index, database) in sorted(databases) {
+        if database.data.is_empty() {
+            continue;
+        }
+        out.extend(encode_request(&[b"SELECT", index.to_string().as_bytes()]));
+        for (key, value) in sorted(&database.data) {
+            for request in value_to_requests(key, value) {
+                let parts: Vec<&[u8]> = request.iter().map(Vec::as_slice).collect();
+                out.extend(encode_request(&parts));
+            }
+        }
+    }
+    out
+}
+
+fn value_to_requests(key: &str, value: &Data) -> Vec<Vec<Vec<u8>>> {
+    let request = |command: &str, args: Vec<Vec<u8>>| {
+        let mut request = vec![command.as_bytes().to_vec(), key.as_bytes().to_vec()];
+        request.extend(args);
+        request
+    };
+    let text = |value: &str| value.as_bytes().to_vec();
+
+    match value {
+        Data::Scalar(bytes) => vec![request("SET", vec![bytes.clone()])],
+        Data::List(list) => vec![request("RPUSH", list.iter().map(|item| text(item)).collect())],
+        Data::Hash(hash) => vec![request("HSET", hash.iter().collect::<BTreeMap<_, _>>().into_iter().flat_map(|(field, value)| [text(field), text(value)]).collect())],
+        Data::Set(set) => {
+            let mut members: Vec<&String> = set.iter().collect();
+            members.sort();
+            vec![request("SADD", members.into_iter().map(|member| text(member)).collect())]
+        }
+        Data::SortedSet(zset) => vec![request("ZADD", zset.iter().flat_map(|(member, score)| [text(&score.to_string()), text(member)]).collect())],
+        Data::Stream(stream) => {
+            let mut requests: Vec<_> = stream.entries.iter().map(|(id, fields)| {
+                let mut args = vec![text(&id.to_string())];
+                args.extend(fields.iter().flat_map(|(field, value)| [text(field), text(value)]));
+                request("XADD", args)
+            }).collect();
+            for (name, group) in &stream.groups {
+                let mut create = vec![b"XGROUP".to_vec(), b"CREATE".to_vec(), text(key), text(name), text(&group.last_delivered.to_string()), b"MKSTREAM".to_vec()];
+                if let Some(entries_read) = group.entries_read {
+                    create.extend([b"ENTRIESREAD".to_vec(), text(&entries_read.to_string())]);
+                }
+                requests.push(create);
+            }
+            requests
+        }
+        Data::Json(value) => vec![request("JSON.SET", vec![b"$".to_vec(), text(&value.to_string())])],
+        Data::TimeSeries(series) => {
+            let mut create = vec![b"RETENTION".to_vec(), text(&series.retention.to_string()), b"DUPLICATE_POLICY".to_vec(), text(&format!("{:?}", series.duplicate_policy).to_uppercase())];
+            if !series.labels.is_empty() {
+                create.push(b"LABELS".to_vec());
+                create.extend(series.labels.iter().flat_map(|(label, value)| [text(label), text(value)]));
+            }
+            let mut requests = vec![request("TS.CREATE", create)];
+            for (timestamp, value) in &series.samples {
+                requests.push(request("TS.ADD", vec![text(&timestamp.to_string()), text(&value.to_string())]));
+            }
+            requests
+        }
+        Data::Bloom(_) | Data::CountMinSketch(_) => vec![request("RESTORE", vec![b"0".to_vec(), rdb::dump(value).expect("Bloom filters and sketches have a DUMP payload")])],
+    }
+}
+
+fn sorted<K: Ord, V>(map: &HashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries: Vec<(&K, &V)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    entries
+}
+
+// The files of an AOF to check, in the order they are replayed: those of a manifest, given as
+// the manifest itself or its directory, or a single file
+fn aof_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let manifest_path = if path.is_dir() {
+        let manifests: Vec<PathBuf> = fs::read_dir(path).map_err(|e| format!("Error reading {}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "manifest"))
+            .collect();
+        match manifests.as_slice() {
+            [manifest] => manifest.clone(),
+            [] => return Err(format!("No manifest found in {}", path.display())),
+            _ => return Err(format!("More than one manifest found in {}", path.display())),
+        }
+    } else if path.extension().is_some_and(|extension| extension == "manifest") {
+        path.to_path_buf()
+    } else {
+        return Ok(vec![path.to_path_buf()]);
+    };
+
+    let text = fs::read_to_string(&manifest_path).map_err(|e| format!("Error reading {}: {}", manifest_path.display(), e))?;
+    let manifest = Manifest::parse(&text).map_err(|e| format!("{}: {}", manifest_path.display(), e))?;
+    let dir = manifest_path.parent().unwrap_or(Path::new("."));
+    Ok(manifest.base.iter().chain(&manifest.incrs).map(|file| dir.join(&file.name)).collect())
+}
+
+// The length of the valid commands at the start of an AOF file and the number of lines they
+// take, with the error that ended them if they do not take the whole file
+fn valid_commands(contents: &[u8]) -> (usize, usize, Option<&'static str>) {
+    let mut pos = 0;
+    while pos < contents.len() {
+        match read_frame(&contents[pos..]) {
+            Ok(Some((_, consumed))) => pos += consumed,
+            Ok(None) => return (pos, line_count(&contents[..pos]), Some("Unexpected EOF")),
+            Err(e) => return (pos, line_count(&contents[..pos]), Some(e)),
+        }
+    }
+    (pos, line_count(contents), None)
+}
+
+fn line_count(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|byte| **byte == b'\n').count()
+}
+
+// Verify every file of an AOF. An RDB base is verified like an RDB file. With fix, a last file
+// that ends with an invalid or cut short command is truncated to the commands before it, like
+// redis-check-aof does; invalid commands in other files cannot be fixed.
+fn check_aof(path: &Path, fix: bool) -> Result<(), String> {
+    let files = aof_files(path)?;
+    for (i, file) in files.iter().enumerate() {
+        let name = file.display();
+        let contents = fs::read(file).map_err(|e| format!("Error reading {}: {}", name, e))?;
+        if contents.starts_with(b"REDIS") {
+            let (_, expiries) = rdb::load_with_expiries(&contents).map_err(|e| format!("RDB preamble of the AOF file {} is not valid: {}", name, e))?;
+            warn_expiries(file, expiries);
+            println!("RDB preamble of {} is OK", name);
+            continue;
+        }
+
+        let (ok_up_to, ok_up_to_line, error) = valid_commands(&contents);
+        println!("AOF analyzed: filename={}, size={}, ok_up_to={}, ok_up_to_line={}, diff={}", name, contents.len(), ok_up_to, ok_up_to_line, contents.len() - ok_up_to);
+        let error = match error {
+            Some(error) => error,
+            None => {
+                println!("AOF {} is valid", name);
+                continue;
+            }
+        };
+        println!("{} at offset {}", error, ok_up_to);
+
+        if !fix {
+            return Err(format!("AOF {} is not valid. Use the --fix option to try fixing it.", name));
+        }
+        if i + 1 != files.len() {
+            return Err(format!("AOF {} is not the last file of the AOF, so it cannot be fixed", name));
+        }
+        OpenOptions::new().write(true).open(file).and_then(|handle| handle.set_len(ok_up_to as u64))
+            .map_err(|e| format!("Failed to truncate AOF {}: {}", name, e))?;
+        println!("Successfully truncated AOF {}", name);
+    }
+    Ok(())
+}
@@ -0,0 +1,419 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+
+// Bit offsets are limited to the 512MB maximum string size, like Redis
+const MAX_BIT_OFFSET: u64 = 1 << 32;
+
+// Whether the start and end of a BITCOUNT or BITPOS range count bytes or bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+// A start/end range of BITCOUNT or BITPOS. BITPOS may leave out the end.
+#[derive(Debug, Clone, Copy)]
+pub struct BitRange {
+    pub start: i64,
+    pub end: Option<i64>,
+    pub unit: BitUnit,
+}
+
+// The operator applied by BITOP
+#[derive(Debug, Clone, Copy)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+// A BITFIELD integer type such as i8 or u16
+#[derive(Debug, Clone, Copy)]
+pub struct BitfieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+// How BITFIELD handles SET and INCRBY results that do not fit the type
+#[derive(Debug, Clone, Copy)]
+pub enum BitfieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+// One BITFIELD subcommand; offsets are already resolved to bits
+#[derive(Debug, Clone)]
+pub enum BitfieldOp {
+    Get(BitfieldType, u64),
+    Set(BitfieldType, u64, i64),
+    Incrby(BitfieldType, u64, i64),
+    Overflow(BitfieldOverflow),
+}
+
+// Parse a bit offset as used by SETBIT and GETBIT
+pub fn parse_bit_offset(offset: &str) -> Result<u64, &'static str> {
+    match offset.parse::<u64>() {
+        Ok(offset) if offset < MAX_BIT_OFFSET => Ok(offset),
+        _ => Err("bit offset is not an integer or out of range"),
+    }
+}
+
+// Parse a bit value, which must be 0 or 1
+pub fn parse_bit(bit: &str) -> Result<bool, &'static str> {
+    match bit {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err("bit is not an integer or out of range"),
+    }
+}
+
+// Parse the optional [start [end [BYTE|BIT]]] arguments of BITCOUNT and BITPOS.
+// BITCOUNT requires an end whenever a start is given.
+pub fn parse_bit_range(args: &[String], end_required: bool) -> Result<Option<BitRange>, &'static str> {
+    let parse_index = |index: &String| index.parse::<i64>().map_err(|_| "value is not an integer or out of range");
+    let start = match args.first() {
+        Some(start) => parse_index(start)?,
+        None => return Ok(None),
+    };
+    let end = args.get(1).map(parse_index).transpose()?;
+    if end.is_none() && end_required {
+        return Err("syntax error");
+    }
+    let unit = match args.get(2).map(|unit| unit.to_uppercase()) {
+        None => BitUnit::Byte,
+        Some(unit) if unit == "BYTE" => BitUnit::Byte,
+        Some(unit) if unit == "BIT" => BitUnit::Bit,
+        Some(_) => return Err("syntax error"),
+    };
+    if args.len() > 3 {
+        return Err("syntax error");
+    }
+    Ok(Some(BitRange { start, end, unit }))
+}
+
+// Parse a BITFIELD type: i1 to i64, or u1 to u63
+fn parse_bitfield_type(field_type: &str) -> Result<BitfieldType, &'static str> {
+    let invalid = "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.";
+    let signed = match field_type.chars().next() {
+        Some('i') | Some('I') => true,
+        Some('u') | Some('U') => false,
+        _ => return Err(invalid),
+    };
+    let bits: u32 = field_type[1..].parse().map_err(|_| invalid)?;
+    let max_bits = if signed { 64 } else { 63 };
+    if bits == 0 || bits > max_bits {
+        return Err(invalid);
+    }
+    Ok(BitfieldType { signed, bits })
+}
+
+// Parse a BITFIELD offset. "#N" means the N-th field of the given type.
+fn parse_bitfield_offset(offset: &str, field_type: BitfieldType) -> Result<u64, &'static str> {
+    let invalid = "bit offset is not an integer or out of range";
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index.parse::<u64>().ok().and_then(|index| index.checked_mul(field_type.bits as u64)),
+        None => offset.parse::<u64>().ok(),
+    };
+    match offset {
+        Some(offset) if offset < MAX_BIT_OFFSET => Ok(offset),
+        _ => Err(invalid),
+    }
+}
+
+// Parse the subcommands of BITFIELD (or BITFIELD_RO, which only allows GET) after the key
+pub fn parse_bitfield_args(args: &[String], read_only: bool) -> Result<Vec<BitfieldOp>, &'static str> {
+    let parse_value = |value: &String| value.parse::<i64>().map_err(|_| "value is not an integer or out of range");
+    let mut ops = Vec::new();
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        let subcommand = arg.to_uppercase();
+        if read_only && subcommand != "GET" {
+            return Err("BITFIELD_RO only supports the GET subcommand");
+        }
+        match subcommand.as_str() {
+            "GET" if args.len() >= index + 3 => {
+                let field_type = parse_bitfield_type(&args[index + 1])?;
+                ops.push(BitfieldOp::Get(field_type, parse_bitfield_offset(&args[index + 2], field_type)?));
+                index += 3;
+            }
+            "SET" | "INCRBY" if args.len() >= index + 4 => {
+                let field_type = parse_bitfield_type(&args[index + 1])?;
+                let offset = parse_bitfield_offset(&args[index + 2], field_type)?;
+                let value = parse_value(&args[index + 3])?;
+                ops.push(if subcommand == "SET" {
+                    BitfieldOp::Set(field_type, offset, value)
+                } else {
+                    BitfieldOp::Incrby(field_type, offset, value)
+                });
+                index += 4;
+            }
+            "OVERFLOW" if args.len() >= index + 2 => {
+                let overflow = match args[index + 1].to_uppercase().as_str() {
+                    "WRAP" => BitfieldOverflow::Wrap,
+                    "SAT" => BitfieldOverflow::Sat,
+                    "FAIL" => BitfieldOverflow::Fail,
+                    _ => return Err("Invalid OVERFLOW type specified"),
+                };
+                ops.push(BitfieldOp::Overflow(overflow));
+                index += 2;
+            }
+            _ => return Err("syntax error"),
+        }
+    }
+    Ok(ops)
+}
+
+// Look up the string stored at key, failing if the key holds another type
+fn get_bytes<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a Vec<u8>>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Scalar(bytes)) => Ok(Some(bytes)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the string stored at key, creating an empty one if the key does not exist
+fn get_or_create_bytes<'a>(cur_db: &'a mut Database, key: &str) -> Result<&'a mut Vec<u8>, &'static str> {
+    match cur_db.data.entry(key.to_string()).or_insert_with(|| Data::Scalar(Vec::new())) {
+        Data::Scalar(bytes) => Ok(bytes),
+        _ => Err(WRONG_TYPE),
+    }
+}
+
+// Read the bit at offset; bits are numbered from the most significant bit of the first byte
+fn get_bit(bytes: &[u8], offset: u64) -> bool {
+    match bytes.get((offset / 8) as usize) {
+        Some(byte) => byte & (0x80 >> (offset % 8)) != 0,
+        None => false,
+    }
+}
+
+// Write the bit at offset, growing the string with zero bytes as needed
+fn set_bit(bytes: &mut Vec<u8>, offset: u64, value: bool) {
+    let index = (offset / 8) as usize;
+    if index >= bytes.len() {
+        bytes.resize(index + 1, 0);
+    }
+    let mask = 0x80 >> (offset % 8);
+    if value {
+        bytes[index] |= mask;
+    } else {
+        bytes[index] &= !mask;
+    }
+}
+
+// Resolve a range with possibly negative indexes to inclusive bit offsets, or None if it is empty
+fn resolve_bit_range(len: usize, start: i64, end: i64, unit: BitUnit) -> Option<(u64, u64)> {
+    let len = match unit {
+        BitUnit::Byte => len as i64,
+        BitUnit::Bit => len as i64 * 8,
+    };
+    let start = if start < 0 { (start + len).max(0) } else { start };
+    let end = if end < 0 { end + len } else { end.min(len - 1) };
+    if len == 0 || start > end {
+        return None;
+    }
+    match unit {
+        BitUnit::Byte => Some((start as u64 * 8, end as u64 * 8 + 7)),
+        BitUnit::Bit => Some((start as u64, end as u64)),
+    }
+}
+
+// SETBIT command
+pub fn execute_setbit_cmd(key: String, offset: u64, value: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let bytes = get_or_create_bytes(cur_db, &key)?;
+
+    let old = get_bit(bytes, offset);
+    set_bit(bytes, offset, value);
+
+    Ok(format!(":{}\r\n", old as u8))
+}
+
+// GETBIT command
+pub fn execute_getbit_cmd(key: String, offset: u64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let bit = get_bytes(cur_db, &key)?.is_some_and(|bytes| get_bit(bytes, offset));
+
+    Ok(format!(":{}\r\n", bit as u8))
+}
+
+// BITCOUNT command
+pub fn execute_bitcount_cmd(key: String, range: Option<BitRange>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let bytes = match get_bytes(cur_db, &key)? {
+        Some(bytes) => bytes,
+        None => return Ok(":0\r\n".to_string()),
+    };
+
+    let count = match range {
+        None => bytes.iter().map(|byte| byte.count_ones() as u64).sum(),
+        Some(range) => match resolve_bit_range(bytes.len(), range.start, range.end.unwrap_or(-1), range.unit) {
+            Some((first, last)) => (first..=last).filter(|offset| get_bit(bytes, *offset)).count() as u64,
+            None => 0,
+        },
+    };
+
+    Ok(format!(":{}\r\n", count))
+}
+
+// BITPOS command
+pub fn execute_bitpos_cmd(key: String, bit: bool, range: Option<BitRange>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    // A missing key is an empty string: no set bits, and a clear bit at offset 0
+    let bytes = match get_bytes(cur_db, &key)? {
+        Some(bytes) => bytes,
+        None => return Ok(format!(":{}\r\n", if bit { -1 } else { 0 })),
+    };
+
+    let (start, end, unit) = match range {
+        Some(range) => (range.start, range.end, range.unit),
+        None => (0, None, BitUnit::Byte),
+    };
+    let (first, last) = match resolve_bit_range(bytes.len(), start, end.unwrap_or(-1), unit) {
+        Some(bits) => bits,
+        None => return Ok(":-1\r\n".to_string()),
+    };
+
+    let position = match (first..=last).find(|offset| get_bit(bytes, *offset) == bit) {
+        Some(offset) => offset as i64,
+        // Without an explicit end the string is treated as padded with clear bits
+        None if !bit && end.is_none() => last as i64 + 1,
+        None => -1,
+    };
+
+    Ok(format!(":{}\r\n", position))
+}
+
+// BITOP command
+pub fn execute_bitop_cmd(operation: BitOperation, destination: String, keys: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in &keys {
+        sources.push(get_bytes(cur_db, key)?.map_or(&[][..], |bytes| bytes.as_slice()));
+    }
+
+    // Shorter strings are treated as padded with zero bytes up to the longest one
+    let len = sources.iter().map(|bytes| bytes.len()).max().unwrap_or(0);
+    let byte_at = |bytes: &[u8], index: usize| bytes.get(index).copied().unwrap_or(0);
+    let result: Vec<u8> = (0..len).map(|index| {
+        let mut values = sources.iter().map(|bytes| byte_at(bytes, index));
+        let first = values.next().unwrap_or(0);
+        match operation {
+            BitOperation::And => values.fold(first, |acc, byte| acc & byte),
+            BitOperation::Or => values.fold(first, |acc, byte| acc | byte),
+            BitOperation::Xor => values.fold(first, |acc, byte| acc ^ byte),
+            BitOperation::Not => !first,
+        }
+    }).collect();
+
+    // The destination is overwritten whatever it held, and removed when the result is empty
+    if result.is_empty() {
+        cur_db.data.remove(&destination);
+    } else {
+        cur_db.data.insert(destination, Data::Scalar(result));
+    }
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// Read a field of the given type at a bit offset, sign-extending signed types
+fn read_field(bytes: &[u8], field_type: BitfieldType, offset: u64) -> i64 {
+    let value = (0..field_type.bits as u64).fold(0u64, |acc, bit| (acc << 1) | get_bit(bytes, offset + bit) as u64);
+    if field_type.signed && field_type.bits < 64 {
+        let shift = 64 - field_type.bits;
+        ((value << shift) as i64) >> shift
+    } else {
+        value as i64
+    }
+}
+
+// Write the low bits of value as a field of the given type at a bit offset
+fn write_field(bytes: &mut Vec<u8>, field_type: BitfieldType, offset: u64, value: i64) {
+    let bits = field_type.bits as u64;
+    for bit in 0..bits {
+        set_bit(bytes, offset + bit, (value as u64 >> (bits - 1 - bit)) & 1 == 1);
+    }
+}
+
+// Fit a value into the range of the field type, or None if it overflows with FAIL
+fn apply_overflow(value: i128, field_type: BitfieldType, overflow: BitfieldOverflow) -> Option<i64> {
+    let (min, max) = if field_type.signed {
+        (-(1i128 << (field_type.bits - 1)), (1i128 << (field_type.bits - 1)) - 1)
+    } else {
+        (0, (1i128 << field_type.bits) - 1)
+    };
+    if (min..=max).contains(&value) {
+        return Some(value as i64);
+    }
+    match overflow {
+        BitfieldOverflow::Wrap => Some(((value - min).rem_euclid(max - min + 1) + min) as i64),
+        BitfieldOverflow::Sat => Some(value.clamp(min, max) as i64),
+        BitfieldOverflow::Fail => None,
+    }
+}
+
+// BITFIELD and BITFIELD_RO commands
+pub fn execute_bitfield_cmd(key: String, ops: Vec<BitfieldOp>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    // Only create the key when a subcommand writes to it
+    let writes = ops.iter().any(|op| matches!(op, BitfieldOp::Set(..) | BitfieldOp::Incrby(..)));
+    let mut empty = Vec::new();
+    let bytes = if writes {
+        get_or_create_bytes(cur_db, &key)?
+    } else {
+        match cur_db.data.get_mut(&key) {
+            Some(Data::Scalar(bytes)) => bytes,
+            Some(_) => return Err(WRONG_TYPE),
+            None => &mut empty,
+        }
+    };
+
+    let mut overflow = BitfieldOverflow::Wrap;
+    let mut results = Vec::new();
+    for op in ops {
+        match op {
+            BitfieldOp::Get(field_type, offset) => results.push(Some(read_field(bytes, field_type, offset))),
+            BitfieldOp::Set(field_type, offset, value) => {
+                let old = read_field(bytes, field_type, offset);
+                let new = apply_overflow(value as i128, field_type, overflow);
+                if let Some(new) = new {
+                    write_field(bytes, field_type, offset, new);
+                }
+                results.push(new.map(|_| old));
+            }
+            BitfieldOp::Incrby(field_type, offset, increment) => {
+                let old = read_field(bytes, field_type, offset);
+                let new = apply_overflow(old as i128 + increment as i128, field_type, overflow);
+                if let Some(new) = new {
+                    write_field(bytes, field_type, offset, new);
+                }
+                results.push(new);
+            }
+            BitfieldOp::Overflow(mode) => overflow = mode,
+        }
+    }
+
+    let mut response = format!("*{}\r\n", results.len());
+    for result in results {
+        response += &match result {
+            Some(value) => format!(":{}\r\n", value),
+            None => "$-1\r\n".to_string(),
+        };
+    }
+
+    Ok(response)
+}
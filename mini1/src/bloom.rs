@@ -0,0 +1,153 @@
+use std::mem;
+
+use crate::hyperloglog::murmur_hash64a;
+
+// Each filter added to a scalable filter has a tighter error rate than the one before,
+// so the combined false positive rate stays below the requested one
+pub const TIGHTENING_RATIO: f64 = 0.5;
+
+pub const DEFAULT_ERROR_RATE: f64 = 0.01;
+pub const DEFAULT_CAPACITY: u64 = 100;
+pub const DEFAULT_EXPANSION: u32 = 2;
+
+// The most bits one filter may have, so a huge capacity fails instead of exhausting memory
+pub const MAX_FILTER_BITS: u64 = 1 << 32;
+
+// A fixed size Bloom filter, sized and hashed like RedisBloom's so filters can be exchanged with it
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    pub bits: Vec<u64>,
+    pub bit_count: u64,
+    pub hashes: u32,
+    pub capacity: u64,
+    pub count: u64,
+    pub error_rate: f64,
+}
+
+impl BloomFilter {
+    // A filter sized for capacity items at the given false positive rate
+    fn new(capacity: u64, error_rate: f64) -> Result<Self, &'static str> {
+        let bit_count = filter_bits(capacity, error_rate)?;
+        let hashes = (bits_per_entry(error_rate) * 2f64.ln()).ceil().max(1.0) as u32;
+        Ok(Self { bits: vec![0; bit_count.div_ceil(64) as usize], bit_count, hashes, capacity, count: 0, error_rate })
+    }
+
+    // The bit positions of an item, using double hashing
+    fn positions(&self, hash: (u64, u64)) -> impl Iterator<Item = u64> + '_ {
+        (0..self.hashes as u64).map(move |i| hash.0.wrapping_add(i.wrapping_mul(hash.1)) % self.bit_count)
+    }
+
+    fn contains(&self, hash: (u64, u64)) -> bool {
+        self.positions(hash).all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn insert(&mut self, hash: (u64, u64)) {
+        let positions: Vec<u64> = self.positions(hash).collect();
+        for bit in positions {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+    }
+}
+
+// The bits per item of a filter with the given false positive rate
+pub fn bits_per_entry(error_rate: f64) -> f64 {
+    -error_rate.ln() / (2f64.ln() * 2f64.ln())
+}
+
+// The bits of a filter for capacity items, failing if there would be more than MAX_FILTER_BITS
+pub fn filter_bits(capacity: u64, error_rate: f64) -> Result<u64, &'static str> {
+    let bits = (capacity as f64 * bits_per_entry(error_rate)).max(1.0);
+    if bits > MAX_FILTER_BITS as f64 {
+        return Err("filter is too large");
+    }
+    Ok((bits as u64).div_ceil(8) * 8)
+}
+
+// A scalable Bloom filter: a chain of filters, where a new and larger one is
+// added whenever the last one is full. An expansion of 0 means non-scaling.
+#[derive(Debug, Clone)]
+pub struct ScalableBloom {
+    pub filters: Vec<BloomFilter>,
+    pub error_rate: f64,
+    pub expansion: u32,
+}
+
+impl ScalableBloom {
+    pub fn new(capacity: u64, error_rate: f64, expansion: u32) -> Result<Self, &'static str> {
+        Ok(Self { filters: vec![BloomFilter::new(capacity, error_rate * TIGHTENING_RATIO)?], error_rate, expansion })
+    }
+
+    fn hash(item: &[u8]) -> (u64, u64) {
+        let first = murmur_hash64a(item, 0xc6a4_a793_5bd1_e995);
+        (first, murmur_hash64a(item, first))
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let hash = Self::hash(item);
+        self.filters.iter().any(|filter| filter.contains(hash))
+    }
+
+    // Add an item, returning false if it may already have been added
+    pub fn add(&mut self, item: &[u8]) -> Result<bool, &'static str> {
+        let hash = Self::hash(item);
+        if self.filters.iter().any(|filter| filter.contains(hash)) {
+            return Ok(false);
+        }
+
+        let last = self.filters.last().expect("a scalable filter has at least one filter");
+        if last.count >= last.capacity {
+            if self.expansion == 0 {
+                return Err("non scaling filter is full");
+            }
+            let capacity = last.capacity.saturating_mul(self.expansion as u64);
+            let error_rate = self.error_rate * TIGHTENING_RATIO.powi(self.filters.len() as i32 + 1);
+            self.filters.push(BloomFilter::new(capacity, error_rate)?);
+        }
+        self.filters.last_mut().expect("a scalable filter has at least one filter").insert(hash);
+        Ok(true)
+    }
+
+    // The number of items the filters can hold before another one is needed
+    pub fn capacity(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.capacity).sum()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.filters.iter().map(|filter| filter.count).sum()
+    }
+
+    // The bytes used by the filter, including its bit arrays
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.filters.iter()
+            .map(|filter| mem::size_of::<BloomFilter>() + filter.bits.capacity() * mem::size_of::<u64>())
+            .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_are_sized_for_their_capacity() {
+        let filter = ScalableBloom::new(DEFAULT_CAPACITY, DEFAULT_ERROR_RATE, DEFAULT_EXPANSION).unwrap();
+        assert_eq!(filter.filters[0].bit_count, 1104);
+        assert_eq!(filter.filters[0].bits.len(), 18);
+    }
+
+    #[test]
+    fn huge_filters_are_rejected() {
+        assert_eq!(ScalableBloom::new(u64::MAX, 0.01, 2).err(), Some("filter is too large"));
+        assert_eq!(filter_bits(1 << 40, 0.5), Err("filter is too large"));
+    }
+
+    #[test]
+    fn scaling_stops_at_the_size_limit() {
+        let mut filter = ScalableBloom::new(1, 0.01, u32::MAX).unwrap();
+        assert_eq!(filter.add(b"a"), Ok(true));
+        assert_eq!(filter.add(b"b"), Err("filter is too large"));
+        assert_eq!(filter.filters.len(), 1);
+        assert_eq!(filter.count(), 1);
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::bloom::{filter_bits, ScalableBloom, TIGHTENING_RATIO, DEFAULT_CAPACITY, DEFAULT_ERROR_RATE, DEFAULT_EXPANSION};
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+use crate::utils::{bulk_string, optional_bulk_string};
+
+// The options of BF.RESERVE
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomOptions {
+    pub error_rate: f64,
+    pub capacity: u64,
+    pub expansion: u32,
+}
+
+// Parse "<error_rate> <capacity> [EXPANSION <expansion>] [NONSCALING]"
+pub fn parse_bf_reserve_args(args: &[String]) -> Result<BloomOptions, &'static str> {
+    let error_rate: f64 = args[0].parse().map_err(|_| "bad error rate")?;
+    if !(error_rate > 0.0 && error_rate < 1.0) {
+        return Err("error rate should be between 0 and 1");
+    }
+    let capacity: u64 = args[1].parse().map_err(|_| "bad capacity")?;
+    if capacity == 0 {
+        return Err("capacity should be larger than 0");
+    }
+    filter_bits(capacity, error_rate * TIGHTENING_RATIO)?;
+
+    let mut options = BloomOptions { error_rate, capacity, expansion: DEFAULT_EXPANSION };
+    let mut nonscaling = false;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "EXPANSION" if i + 1 < args.len() => {
+                options.expansion = args[i + 1].parse().ok().filter(|expansion| *expansion > 0).ok_or("bad expansion")?;
+                i += 2;
+            }
+            "NONSCALING" => {
+                nonscaling = true;
+                i += 1;
+            }
+            _ => return Err("Syntax error. Usage: BF.RESERVE <key> <error_rate> <capacity> [EXPANSION <expansion>] [NONSCALING]"),
+        }
+    }
+    if nonscaling {
+        options.expansion = 0;
+    }
+    Ok(options)
+}
+
+// Look up the Bloom filter stored at key, failing if the key holds another type
+fn get_bloom<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a ScalableBloom>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Bloom(filter)) => Ok(Some(filter)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the Bloom filter stored at key, creating one with the default options if the key does not exist
+fn get_or_create_bloom<'a>(cur_db: &'a mut Database, key: &str) -> Result<&'a mut ScalableBloom, &'static str> {
+    let entry = cur_db.data.entry(key.to_string())
+        .or_insert_with(|| Data::Bloom(ScalableBloom::new(DEFAULT_CAPACITY, DEFAULT_ERROR_RATE, DEFAULT_EXPANSION).expect("the default filter is small")));
+    match entry {
+        Data::Bloom(filter) => Ok(filter),
+        _ => Err(WRONG_TYPE),
+    }
+}
+
+// BF.RESERVE command
+pub fn execute_bf_reserve_cmd(key: String, options: BloomOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if cur_db.data.contains_key(&key) {
+        return Err("item exists");
+    }
+
+    cur_db.data.insert(key, Data::Bloom(ScalableBloom::new(options.capacity, options.error_rate, options.expansion)?));
+    Ok("+OK\r\n".to_string())
+}
+
+// BF.ADD and BF.MADD commands. BF.ADD replies with a single integer, BF.MADD with an array.
+pub fn execute_bf_add_cmd(key: String, items: Vec<Vec<u8>>, multiple: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let filter = get_or_create_bloom(cur_db, &key)?;
+
+    if !multiple {
+        return Ok(format!(":{}\r\n", filter.add(&items[0])? as u8));
+    }
+    // A full non-scaling filter reports an error for each item it could not add
+    let mut response = format!("*{}\r\n", items.len());
+    for item in &items {
+        response += &match filter.add(item) {
+            Ok(added) => format!(":{}\r\n", added as u8),
+            Err(e) => format!("-{}\r\n", e),
+        };
+    }
+    Ok(response)
+}
+
+// BF.EXISTS and BF.MEXISTS commands
+pub fn execute_bf_exists_cmd(key: String, items: Vec<Vec<u8>>, multiple: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let filter = get_bloom(cur_db, &key)?;
+    let exists = |item: &Vec<u8>| filter.is_some_and(|filter| filter.contains(item)) as u8;
+
+    if !multiple {
+        return Ok(format!(":{}\r\n", exists(&items[0])));
+    }
+    let mut response = format!("*{}\r\n", items.len());
+    for item in &items {
+        response += &format!(":{}\r\n", exists(item));
+    }
+    Ok(response)
+}
+
+// BF.INFO command
+pub fn execute_bf_info_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let filter = get_bloom(cur_db, &key)?.ok_or("not found")?;
+
+    let mut response = "*10\r\n".to_string();
+    response += &bulk_string("Capacity");
+    response += &format!(":{}\r\n", filter.capacity());
+    response += &bulk_string("Size");
+    response += &format!(":{}\r\n", filter.memory_usage());
+    response += &bulk_string("Number of filters");
+    response += &format!(":{}\r\n", filter.filters.len());
+    response += &bulk_string("Number of items inserted");
+    response += &format!(":{}\r\n", filter.count());
+    response += &bulk_string("Expansion rate");
+    response += &match filter.expansion {
+        0 => optional_bulk_string(None),
+        expansion => format!(":{}\r\n", expansion),
+    };
+    Ok(response)
+}
@@ -0,0 +1,499 @@
+use std::collections::HashMap;
+
+use crate::replication::new_replid;
+use crate::stream::now_ms;
+use crate::utils::{encode_request, Frame};
+
+// The number of hash slots the keyspace is split into
+pub const CLUSTER_SLOTS: usize = 16384;
+
+// CRC16 with the XMODEM polynomial, the checksum Redis Cluster hashes keys with
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// The hash slot of a key. When the key has a non-empty {hash tag}, only the tag is hashed,
+// so keys sharing a tag land in the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|byte| *byte == b'{').and_then(|start| {
+        let tag = &key[start + 1..];
+        tag.iter().position(|byte| *byte == b'}').filter(|len| *len > 0).map(|len| &tag[..len])
+    });
+    crc16(tag.unwrap_or(key)) & (CLUSTER_SLOTS as u16 - 1)
+}
+
+// Parse a slot number
+pub fn parse_slot(slot: &str) -> Result<u16, &'static str> {
+    slot.parse().ok().filter(|slot| (*slot as usize) < CLUSTER_SLOTS).ok_or("Invalid or out of range slot")
+}
+
+// A node of the cluster, as this node knows it
+#[derive(Debug, Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    pub ip: String,
+    pub port: u16,
+    pub bus_port: u16,
+    // The epoch of the slots the node claims. The claim with the greater epoch wins.
+    pub config_epoch: u64,
+    // The replication offset the node last reported
+    pub repl_offset: u64,
+    // Met with CLUSTER MEET or through gossip, and still waiting for a reply to learn its real ID
+    pub handshake: bool,
+    // Unix time in milliseconds the node was added
+    pub created: u64,
+    // Unix time in milliseconds of the oldest PING still waiting for a reply, 0 when none is,
+    // and of the last reply
+    pub ping_sent: u64,
+    pub pong_received: u64,
+    // Whether a task runs the link to the node, and whether that link is connected
+    pub link_running: bool,
+    pub connected: bool,
+}
+
+impl ClusterNode {
+    fn new(id: String, ip: String, port: u16, bus_port: u16, handshake: bool) -> Self {
+        Self { id, ip, port, bus_port, config_epoch: 0, repl_offset: 0, handshake, created: now_ms(), ping_sent: 0, pong_received: 0, link_running: false, connected: false }
+    }
+
+    // Whether the node did not reply for longer than node_timeout milliseconds
+    pub fn pfail(&self, node_timeout: u64) -> bool {
+        self.ping_sent > 0 && now_ms().saturating_sub(self.ping_sent) > node_timeout
+    }
+}
+
+// The kind of a message on the cluster bus. MEET and PING ask for a PONG; MEET also asks the
+// receiver to add the sender to its nodes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BusMessageKind {
+    Ping,
+    Pong,
+    Meet,
+}
+
+// A message on the cluster bus: the state of the sender, and gossip about the nodes it knows
+#[derive(Debug)]
+pub struct BusMessage {
+    pub kind: BusMessageKind,
+    pub id: String,
+    pub port: u16,
+    pub bus_port: u16,
+    pub current_epoch: u64,
+    pub config_epoch: u64,
+    pub repl_offset: u64,
+    pub slots: Vec<(u16, u16)>,
+    // The ID, IP, port and bus port of other nodes
+    pub gossip: Vec<(String, String, u16, u16)>,
+}
+
+impl BusMessage {
+    // Decode a message, sent as a request of "<kind> <id> <port> <bus-port> <current-epoch>
+    // <config-epoch> <replication-offset> <slot ranges> [<id> <ip> <port> <bus-port> ...]"
+    pub fn parse(frame: &Frame) -> Result<Self, &'static str> {
+        let parts: Vec<String> = frame.iter().map(|part| String::from_utf8_lossy(part).into_owned()).collect();
+        if parts.len() < 8 || !(parts.len() - 8).is_multiple_of(4) {
+            return Err("Invalid cluster bus message");
+        }
+        let kind = match parts[0].as_str() {
+            "PING" => BusMessageKind::Ping,
+            "PONG" => BusMessageKind::Pong,
+            "MEET" => BusMessageKind::Meet,
+            _ => return Err("Invalid cluster bus message"),
+        };
+        let number = |part: &str| part.parse().map_err(|_| "Invalid cluster bus message");
+        let port = |part: &str| part.parse().map_err(|_| "Invalid cluster bus message");
+        let gossip = parts[8..].chunks(4)
+            .map(|node| Ok((node[0].clone(), node[1].clone(), port(&node[2])?, port(&node[3])?)))
+            .collect::<Result<_, &'static str>>()?;
+        Ok(Self {
+            kind,
+            id: parts[1].clone(),
+            port: port(&parts[2])?,
+            bus_port: port(&parts[3])?,
+            current_epoch: number(&parts[4])?,
+            config_epoch: number(&parts[5])?,
+            repl_offset: number(&parts[6])?,
+            slots: parse_slot_ranges(&parts[7])?,
+            gossip,
+        })
+    }
+}
+
+// Format slot ranges as "<start>-<end>" or "<slot>", separated by commas
+fn format_slot_ranges(ranges: &[(u16, u16)]) -> String {
+    ranges.iter().map(|(start, end)| format_slot_range(*start, *end)).collect::<Vec<_>>().join(",")
+}
+
+fn format_slot_range(start: u16, end: u16) -> String {
+    if start == end { start.to_string() } else { format!("{}-{}", start, end) }
+}
+
+// Parse slot ranges formatted by format_slot_ranges
+fn parse_slot_ranges(ranges: &str) -> Result<Vec<(u16, u16)>, &'static str> {
+    ranges.split(',').filter(|range| !range.is_empty()).map(|range| {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let (start, end) = (parse_slot(start)?, parse_slot(end)?);
+        if start > end { Err("Invalid slot range") } else { Ok((start, end)) }
+    }).collect()
+}
+
+// The cluster as this node knows it: the nodes, which node serves each slot, and the slots
+// being migrated to or from this node. Nodes ping each other on the cluster bus with their
+// slots and what they know of the other nodes, so the view converges on every node.
+#[derive(Debug)]
+pub struct Cluster {
+    // The ID of this node
+    pub myself: String,
+    // The greatest epoch seen in the cluster
+    pub current_epoch: u64,
+    pub nodes: HashMap<String, ClusterNode>,
+    // The ID of the node serving each slot
+    slots: Vec<Option<String>>,
+    // The slots this node moves to another node, and receives from another node, by node ID
+    pub migrating: HashMap<u16, String>,
+    pub importing: HashMap<u16, String>,
+    // Whether the configuration changed since it was last saved
+    pub changed: bool,
+}
+
+impl Cluster {
+    // A cluster of this node alone, under a new ID
+    pub fn new(ip: &str, port: u16, bus_port: u16) -> Self {
+        let id = new_replid();
+        let mut myself = ClusterNode::new(id.clone(), ip.to_string(), port, bus_port, false);
+        myself.connected = true;
+        Self {
+            myself: id.clone(),
+            current_epoch: 0,
+            nodes: HashMap::from([(id, myself)]),
+            slots: vec![None; CLUSTER_SLOTS],
+            migrating: HashMap::new(),
+            importing: HashMap::new(),
+            changed: true,
+        }
+    }
+
+    pub fn myself(&self) -> &ClusterNode {
+        &self.nodes[&self.myself]
+    }
+
+    fn myself_mut(&mut self) -> &mut ClusterNode {
+        self.nodes.get_mut(&self.myself).unwrap()
+    }
+
+    // The node serving slot, if any
+    pub fn slot_owner(&self, slot: u16) -> Option<&ClusterNode> {
+        self.slots[slot as usize].as_ref().and_then(|id| self.nodes.get(id))
+    }
+
+    pub fn set_slot_owner(&mut self, slot: u16, id: Option<String>) {
+        self.slots[slot as usize] = id;
+        self.changed = true;
+    }
+
+    // The ranges of slots served by the node of id
+    pub fn slot_ranges(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = Vec::new();
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_deref() != Some(id) {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end as usize + 1 == slot => *end = slot as u16,
+                _ => ranges.push((slot as u16, slot as u16)),
+            }
+        }
+        ranges
+    }
+
+    pub fn assigned_slots(&self) -> usize {
+        self.slots.iter().filter(|owner| owner.is_some()).count()
+    }
+
+    // The cluster is up when every slot is served
+    pub fn state_ok(&self) -> bool {
+        self.slots.iter().all(Option::is_some)
+    }
+
+    // The nodes, myself first and then by ID, so replies are stable
+    pub fn sorted_nodes(&self) -> Vec<&ClusterNode> {
+        let mut nodes: Vec<&ClusterNode> = self.nodes.values().collect();
+        nodes.sort_by_key(|node| (node.id != self.myself, node.id.clone()));
+        nodes
+    }
+
+    // Start a handshake with the node at ip:port, unless one is already going on
+    pub fn meet(&mut self, ip: &str, port: u16, bus_port: u16) {
+        if self.nodes.values().any(|node| node.handshake && node.ip == ip && node.bus_port == bus_port) {
+            return;
+        }
+        let id = new_replid();
+        self.nodes.insert(id.clone(), ClusterNode::new(id, ip.to_string(), port, bus_port, true));
+    }
+
+    // Take a new epoch as the config epoch of this node, so its claims win over older ones
+    pub fn bump_epoch(&mut self) {
+        self.current_epoch += 1;
+        let epoch = self.current_epoch;
+        self.myself_mut().config_epoch = epoch;
+        self.changed = true;
+    }
+
+    // A message of kind about this node, at replication offset repl_offset, with gossip about
+    // the nodes past their handshake
+    pub fn message(&self, kind: BusMessageKind, repl_offset: u64) -> Vec<u8> {
+        let myself = self.myself();
+        let name = match kind {
+            BusMessageKind::Ping => "PING",
+            BusMessageKind::Pong => "PONG",
+            BusMessageKind::Meet => "MEET",
+        };
+        let mut parts = vec![
+            name.to_string(), myself.id.clone(), myself.port.to_string(), myself.bus_port.to_string(),
+            self.current_epoch.to_string(), myself.config_epoch.to_string(), repl_offset.to_string(), format_slot_ranges(&self.slot_ranges(&self.myself)),
+        ];
+        for node in self.nodes.values().filter(|node| node.id != self.myself && !node.handshake) {
+            parts.extend([node.id.clone(), node.ip.clone(), node.port.to_string(), node.bus_port.to_string()]);
+        }
+        let parts: Vec<&[u8]> = parts.iter().map(String::as_bytes).collect();
+        encode_request(&parts)
+    }
+
+    // Update the view of the cluster with a message from peer_ip. link is the ID of the node
+    // whose link the message is a reply on, if it is one. Returns the ID that node has now,
+    // which changes when a handshake completes, or None when the link has no node anymore.
+    pub fn process(&mut self, message: &BusMessage, peer_ip: &str, link: Option<&str>) -> Option<String> {
+        let now = now_ms();
+        let mut link = link.map(String::from);
+        // The reply to a MEET gives the real ID of the node. A node already known under that
+        // ID keeps its own link.
+        if let Some(link_id) = link.clone().filter(|link_id| *link_id != message.id) {
+            if self.nodes.get(&link_id).is_some_and(|node| node.handshake) {
+                let mut node = self.nodes.remove(&link_id).unwrap();
+                link = None;
+                if message.id != self.myself && !self.nodes.contains_key(&message.id) {
+                    node.id = message.id.clone();
+                    node.handshake = false;
+                    self.nodes.insert(message.id.clone(), node);
+                    link = Some(message.id.clone());
+                    self.changed = true;
+                }
+            }
+        }
+        if message.kind == BusMessageKind::Meet && message.id != self.myself && !self.nodes.contains_key(&message.id) {
+            let node = ClusterNode::new(message.id.clone(), peer_ip.to_string(), message.port, message.bus_port, false);
+            self.nodes.insert(message.id.clone(), node);
+            self.changed = true;
+        }
+        // Nodes that were not met are ignored
+        let sender = match self.nodes.get_mut(&message.id).filter(|node| node.id != self.myself) {
+            Some(sender) => sender,
+            None => return link.filter(|link_id| self.nodes.contains_key(link_id)),
+        };
+
+        if (sender.ip.as_str(), sender.port, sender.bus_port) != (peer_ip, message.port, message.bus_port) {
+            (sender.ip, sender.port, sender.bus_port) = (peer_ip.to_string(), message.port, message.bus_port);
+            self.changed = true;
+        }
+        sender.repl_offset = message.repl_offset;
+        if message.kind == BusMessageKind::Pong {
+            sender.ping_sent = 0;
+            sender.pong_received = now;
+        }
+        if sender.config_epoch != message.config_epoch {
+            sender.config_epoch = message.config_epoch;
+            self.changed = true;
+        }
+        if message.current_epoch > self.current_epoch {
+            self.current_epoch = message.current_epoch;
+            self.changed = true;
+        }
+
+        // A claimed slot goes to the sender when nobody serves it or its node claimed it with an
+        // older config epoch. Slots this node is importing are settled by CLUSTER SETSLOT.
+        for (start, end) in &message.slots {
+            for slot in *start..=*end {
+                if self.importing.contains_key(&slot) {
+                    continue;
+                }
+                let owner_epoch = match self.slot_owner(slot) {
+                    Some(owner) if owner.id == message.id => continue,
+                    Some(owner) => Some(owner.config_epoch),
+                    None => None,
+                };
+                if owner_epoch.is_none_or(|epoch| epoch < message.config_epoch) {
+                    if self.slots[slot as usize].as_deref() == Some(self.myself.as_str()) {
+                        self.migrating.remove(&slot);
+                    }
+                    self.set_slot_owner(slot, Some(message.id.clone()));
+                }
+            }
+        }
+
+        // Two nodes with the same config epoch could each win a claim on a slot, so the one with
+        // the smaller ID takes a new epoch
+        if message.config_epoch == self.myself().config_epoch && self.myself < message.id {
+            self.bump_epoch();
+        }
+
+        // Meet the nodes the sender knows about
+        for (id, ip, port, bus_port) in &message.gossip {
+            if *id != self.myself && !self.nodes.contains_key(id) {
+                self.meet(ip, *port, *bus_port);
+            }
+        }
+
+        link.filter(|link_id| self.nodes.contains_key(link_id))
+    }
+
+    // Describe the nodes the way CLUSTER NODES does, one line per node:
+    // "<id> <ip>:<port>@<bus-port> <flags> <primary> <ping-sent> <pong-received> <config-epoch> <link-state> <slot> ...".
+    // The configuration file uses the same lines, without the nodes still in their handshake.
+    pub fn describe_nodes(&self, node_timeout: u64, with_handshakes: bool) -> String {
+        let mut description = String::new();
+        for node in self.sorted_nodes() {
+            if node.handshake && !with_handshakes {
+                continue;
+            }
+            let myself = node.id == self.myself;
+            let mut flags = vec![];
+            if myself {
+                flags.push("myself");
+            }
+            flags.push(if node.handshake { "handshake" } else { "master" });
+            if !myself && node.pfail(node_timeout) {
+                flags.push("fail?");
+            }
+            description += &format!("{} {}:{}@{} {} - {} {} {} {}",
+                node.id, node.ip, node.port, node.bus_port, flags.join(","), node.ping_sent, node.pong_received, node.config_epoch,
+                if myself || node.connected { "connected" } else { "disconnected" });
+            for (start, end) in self.slot_ranges(&node.id) {
+                description += &format!(" {}", format_slot_range(start, end));
+            }
+            if myself {
+                let mut migrations: Vec<(u16, String)> = self.migrating.iter().map(|(slot, id)| (*slot, format!("[{}->-{}]", slot, id))).collect();
+                migrations.extend(self.importing.iter().map(|(slot, id)| (*slot, format!("[{}-<-{}]", slot, id))));
+                migrations.sort();
+                for (_, migration) in migrations {
+                    description += &format!(" {}", migration);
+                }
+            }
+            description += "\n";
+        }
+        description
+    }
+
+    // The contents of the configuration file: the nodes, then the epoch of the cluster
+    pub fn to_config(&self) -> String {
+        format!("{}vars currentEpoch {} lastVoteEpoch 0\n", self.describe_nodes(u64::MAX, false), self.current_epoch)
+    }
+
+    // Read a configuration file written by to_config. This node keeps its ID, epochs and slots,
+    // and takes its address from the command line.
+    pub fn from_config(config: &str, ip: &str, port: u16, bus_port: u16) -> Result<Self, &'static str> {
+        const CORRUPTED: &str = "Unrecoverable error: corrupted cluster config file";
+        let mut nodes = HashMap::new();
+        let mut slots = vec![None; CLUSTER_SLOTS];
+        let (mut migrating, mut importing) = (HashMap::new(), HashMap::new());
+        let mut myself = None;
+        let mut current_epoch = 0;
+        for line in config.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields[0] == "vars" {
+                for pair in fields[1..].chunks(2) {
+                    if let ["currentEpoch", epoch] = pair {
+                        current_epoch = epoch.parse().map_err(|_| CORRUPTED)?;
+                    }
+                }
+                continue;
+            }
+            if fields.len() < 8 {
+                return Err(CORRUPTED);
+            }
+            let id = fields[0].to_string();
+            let (node_ip, ports) = fields[1].rsplit_once(':').ok_or(CORRUPTED)?;
+            let (node_port, node_bus_port) = ports.split_once('@').ok_or(CORRUPTED)?;
+            let mut node = ClusterNode::new(id.clone(), node_ip.to_string(), node_port.parse().map_err(|_| CORRUPTED)?, node_bus_port.parse().map_err(|_| CORRUPTED)?, false);
+            node.config_epoch = fields[6].parse().map_err(|_| CORRUPTED)?;
+            if fields[2].split(',').any(|flag| flag == "myself") {
+                (node.ip, node.port, node.bus_port, node.connected) = (ip.to_string(), port, bus_port, true);
+                myself = Some(id.clone());
+            }
+            for slot in &fields[8..] {
+                if let Some(migration) = slot.strip_prefix('[').and_then(|slot| slot.strip_suffix(']')) {
+                    if let Some((slot, target)) = migration.split_once("->-") {
+                        migrating.insert(parse_slot(slot).map_err(|_| CORRUPTED)?, target.to_string());
+                    } else if let Some((slot, source)) = migration.split_once("-<-") {
+                        importing.insert(parse_slot(slot).map_err(|_| CORRUPTED)?, source.to_string());
+                    }
+                    continue;
+                }
+                for (start, end) in parse_slot_ranges(slot).map_err(|_| CORRUPTED)? {
+                    slots[start as usize..=end as usize].fill(Some(id.clone()));
+                }
+            }
+            nodes.insert(id, node);
+        }
+        let myself = myself.ok_or(CORRUPTED)?;
+        Ok(Self { myself, current_epoch, nodes, slots, migrating, importing, changed: false })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_hash_slots_match_redis() {
+        // Values of CLUSTER KEYSLOT in Redis
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(key_hash_slot(b"123456789"), 12739);
+        assert_eq!(key_hash_slot(b"{user1000}.following"), key_hash_slot(b"user1000"));
+        assert_eq!(key_hash_slot(b"{user1000}.following"), 3443);
+    }
+
+    #[test]
+    fn hash_tags_need_a_non_empty_first_tag() {
+        assert_eq!(key_hash_slot(b"{}foo"), 9500);
+        assert_ne!(key_hash_slot(b"{}foo"), key_hash_slot(b"foo"));
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") & 16383);
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+        assert_eq!(key_hash_slot(b"foo{bar}{zap}"), key_hash_slot(b"bar"));
+        assert_eq!(key_hash_slot(b"foo{bar"), crc16(b"foo{bar") & 16383);
+    }
+
+    #[test]
+    fn parse_slot_bounds() {
+        assert_eq!(parse_slot("0"), Ok(0));
+        assert_eq!(parse_slot("16383"), Ok(16383));
+        assert!(parse_slot("16384").is_err());
+        assert!(parse_slot("-1").is_err());
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut cluster = Cluster::new("127.0.0.1", 7000, 17000);
+        let id = cluster.myself.clone();
+        for slot in 0..100 {
+            cluster.set_slot_owner(slot, Some(id.clone()));
+        }
+        cluster.migrating.insert(5, "a".repeat(40));
+        cluster.current_epoch = 7;
+
+        let loaded = Cluster::from_config(&cluster.to_config(), "127.0.0.1", 7001, 17001).unwrap();
+        assert_eq!(loaded.myself, id);
+        assert_eq!((loaded.myself().port, loaded.myself().bus_port), (7001, 17001));
+        assert_eq!(loaded.current_epoch, 7);
+        assert_eq!(loaded.slot_ranges(&id), vec![(0, 99)]);
+        assert_eq!(loaded.migrating.get(&5), Some(&"a".repeat(40)));
+        assert!(Cluster::from_config("garbage\n", "127.0.0.1", 7001, 17001).is_err());
+    }
+}
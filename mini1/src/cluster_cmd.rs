@@ -0,0 +1,500 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}, time::timeout};
+
+use crate::cluster::{key_hash_slot, parse_slot, BusMessage, BusMessageKind, Cluster};
+use crate::command::Command;
+use crate::config::Config;
+use crate::database::Database;
+use crate::server::Server;
+use crate::stream::now_ms;
+use crate::utils::{bulk_array, bulk_string, read_frame, write_file_atomically, Frame};
+
+// Milliseconds between the PINGs sent to every node on the cluster bus
+const CLUSTER_PING_PERIOD: u64 = 1000;
+// Milliseconds between the runs of the cluster cron
+const CLUSTER_CRON_PERIOD: u64 = 100;
+
+const CLUSTER_DISABLED: &str = "This instance has cluster support disabled";
+
+#[derive(Debug, Clone)]
+pub enum SetslotAction {
+    Migrating(String),
+    Importing(String),
+    Stable,
+    Node(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum ClusterSubcommand {
+    Info,
+    Myid,
+    Nodes,
+    Slots,
+    Shards,
+    Keyslot(String),
+    Countkeysinslot(u16),
+    Getkeysinslot(u16, usize),
+    Addslots(Vec<u16>),
+    Delslots(Vec<u16>),
+    Meet(String, u16, Option<u16>),
+    Setslot(u16, SetslotAction),
+}
+
+impl ClusterSubcommand {
+    // Parse the arguments of CLUSTER
+    pub fn parse(parts: &[String]) -> Result<Self, &'static str> {
+        let (subcommand, args) = parts.split_first().ok_or("Syntax error. Usage: CLUSTER <subcommand> [<argument> ...]")?;
+        let subcommand = subcommand.to_uppercase();
+        let arity = |count: usize, usage: &'static str| if args.len() == count { Ok(()) } else { Err(usage) };
+        Ok(match subcommand.as_str() {
+            "INFO" => {
+                arity(0, "Syntax error. Usage: CLUSTER INFO")?;
+                ClusterSubcommand::Info
+            }
+            "MYID" => {
+                arity(0, "Syntax error. Usage: CLUSTER MYID")?;
+                ClusterSubcommand::Myid
+            }
+            "NODES" => {
+                arity(0, "Syntax error. Usage: CLUSTER NODES")?;
+                ClusterSubcommand::Nodes
+            }
+            "SLOTS" => {
+                arity(0, "Syntax error. Usage: CLUSTER SLOTS")?;
+                ClusterSubcommand::Slots
+            }
+            "SHARDS" => {
+                arity(0, "Syntax error. Usage: CLUSTER SHARDS")?;
+                ClusterSubcommand::Shards
+            }
+            "KEYSLOT" => {
+                arity(1, "Syntax error. Usage: CLUSTER KEYSLOT <key>")?;
+                ClusterSubcommand::Keyslot(args[0].clone())
+            }
+            "COUNTKEYSINSLOT" => {
+                arity(1, "Syntax error. Usage: CLUSTER COUNTKEYSINSLOT <slot>")?;
+                ClusterSubcommand::Countkeysinslot(parse_slot(&args[0])?)
+            }
+            "GETKEYSINSLOT" => {
+                arity(2, "Syntax error. Usage: CLUSTER GETKEYSINSLOT <slot> <count>")?;
+                ClusterSubcommand::Getkeysinslot(parse_slot(&args[0])?, args[1].parse().map_err(|_| "Invalid number of keys")?)
+            }
+            "ADDSLOTS" | "DELSLOTS" => {
+                if args.is_empty() { return Err("Syntax error. Usage: CLUSTER ADDSLOTS|DELSLOTS <slot> [<slot> ...]"); }
+                let slots = args.iter().map(|slot| parse_slot(slot)).collect::<Result<_, _>>()?;
+                if subcommand == "ADDSLOTS" { ClusterSubcommand::Addslots(slots) } else { ClusterSubcommand::Delslots(slots) }
+            }
+            "ADDSLOTSRANGE" | "DELSLOTSRANGE" => {
+                if args.is_empty() || !args.len().is_multiple_of(2) { return Err("Syntax error. Usage: CLUSTER ADDSLOTSRANGE|DELSLOTSRANGE <start-slot> <end-slot> [<start-slot> <end-slot> ...]"); }
+                let mut slots = Vec::new();
+                for range in args.chunks(2) {
+                    let (start, end) = (parse_slot(&range[0])?, parse_slot(&range[1])?);
+                    if start > end { return Err("start slot number is greater than end slot number"); }
+                    slots.extend(start..=end);
+                }
+                if subcommand == "ADDSLOTSRANGE" { ClusterSubcommand::Addslots(slots) } else { ClusterSubcommand::Delslots(slots) }
+            }
+            "MEET" => {
+                if args.len() != 2 && args.len() != 3 { return Err("Syntax error. Usage: CLUSTER MEET <ip> <port> [<bus-port>]"); }
+                let port = args[1].parse().map_err(|_| "Invalid base port specified")?;
+                let bus_port = args.get(2).map(|bus_port| bus_port.parse().map_err(|_| "Invalid bus port specified")).transpose()?;
+                ClusterSubcommand::Meet(args[0].clone(), port, bus_port)
+            }
+            "SETSLOT" => {
+                let usage = "Syntax error. Usage: CLUSTER SETSLOT <slot> IMPORTING <node-id>|MIGRATING <node-id>|NODE <node-id>|STABLE";
+                let slot = parse_slot(args.first().ok_or(usage)?)?;
+                let action = match (args.get(1).map(|action| action.to_uppercase()).as_deref(), args.get(2)) {
+                    (Some("MIGRATING"), Some(id)) if args.len() == 3 => SetslotAction::Migrating(id.clone()),
+                    (Some("IMPORTING"), Some(id)) if args.len() == 3 => SetslotAction::Importing(id.clone()),
+                    (Some("NODE"), Some(id)) if args.len() == 3 => SetslotAction::Node(id.clone()),
+                    (Some("STABLE"), None) => SetslotAction::Stable,
+                    _ => return Err(usage),
+                };
+                ClusterSubcommand::Setslot(slot, action)
+            }
+            _ => return Err("Unknown CLUSTER subcommand"),
+        })
+    }
+}
+
+// The keys of database 0, the only one in cluster mode, that hash to slot
+fn keys_in_slot(db: &Arc<Mutex<HashMap<u8, Database>>>, slot: u16) -> Vec<String> {
+    let db = db.lock().unwrap();
+    let mut keys: Vec<String> = db.get(&0).map_or(Vec::new(), |cur_db| {
+        cur_db.data.keys().filter(|key| key_hash_slot(key.as_bytes()) == slot).cloned().collect()
+    });
+    keys.sort();
+    keys
+}
+
+// Write the configuration file when the configuration changed
+fn save_cluster_config(cluster: &mut Cluster, config: &Config) {
+    if !cluster.changed {
+        return;
+    }
+    match write_file_atomically(&config.cluster_config_path(), cluster.to_config().as_bytes()) {
+        Ok(()) => cluster.changed = false,
+        Err(e) => eprintln!("Error saving the cluster configuration: {}", e),
+    }
+}
+
+// CLUSTER command
+pub async fn execute_cluster_cmd(subcommand: ClusterSubcommand, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<String, &'static str> {
+    let cluster = server.cluster.as_ref().ok_or(CLUSTER_DISABLED)?;
+    let repl_offset = server.replication.lock().await.offset;
+    let node_timeout = server.config.cluster_node_timeout;
+
+    let mut cluster = cluster.lock().unwrap();
+    let response = match subcommand {
+        ClusterSubcommand::Info => {
+            let assigned = cluster.assigned_slots();
+            let pfail: usize = cluster.nodes.values()
+                .filter(|node| node.id != cluster.myself && node.pfail(node_timeout))
+                .map(|node| cluster.slot_ranges(&node.id).iter().map(|(start, end)| (end - start + 1) as usize).sum::<usize>())
+                .sum();
+            let size = cluster.nodes.keys().filter(|id| !cluster.slot_ranges(id).is_empty()).count();
+            let mut info = format!("cluster_state:{}\r\n", if cluster.state_ok() { "ok" } else { "fail" });
+            info += &format!("cluster_slots_assigned:{}\r\ncluster_slots_ok:{}\r\n", assigned, assigned - pfail);
+            info += &format!("cluster_slots_pfail:{}\r\ncluster_slots_fail:0\r\n", pfail);
+            info += &format!("cluster_known_nodes:{}\r\ncluster_size:{}\r\n", cluster.nodes.len(), size);
+            info += &format!("cluster_current_epoch:{}\r\ncluster_my_epoch:{}\r\n", cluster.current_epoch, cluster.myself().config_epoch);
+            bulk_string(&info)
+        }
+        ClusterSubcommand::Myid => bulk_string(&cluster.myself),
+        ClusterSubcommand::Nodes => bulk_string(&cluster.describe_nodes(node_timeout, true)),
+        ClusterSubcommand::Slots => {
+            let mut ranges: Vec<(u16, u16, String)> = Vec::new();
+            for node in cluster.sorted_nodes() {
+                for (start, end) in cluster.slot_ranges(&node.id) {
+                    ranges.push((start, end, format!("*3\r\n{}:{}\r\n{}", bulk_string(&node.ip), node.port, bulk_string(&node.id))));
+                }
+            }
+            ranges.sort();
+            let mut response = format!("*{}\r\n", ranges.len());
+            for (start, end, node) in ranges {
+                response += &format!("*3\r\n:{}\r\n:{}\r\n{}", start, end, node);
+            }
+            response
+        }
+        ClusterSubcommand::Shards => {
+            // Every node is a primary, so each one is a shard of its own
+            let nodes = cluster.sorted_nodes();
+            let mut response = format!("*{}\r\n", nodes.len());
+            for node in nodes {
+                let ranges = cluster.slot_ranges(&node.id);
+                response += &format!("*4\r\n{}*{}\r\n", bulk_string("slots"), ranges.len() * 2);
+                for (start, end) in ranges {
+                    response += &format!(":{}\r\n:{}\r\n", start, end);
+                }
+                let offset = if node.id == cluster.myself { repl_offset } else { node.repl_offset };
+                let health = if node.id != cluster.myself && node.pfail(node_timeout) { "fail" } else { "online" };
+                response += &format!("{}*1\r\n*14\r\n", bulk_string("nodes"));
+                response += &format!("{}{}{}:{}\r\n", bulk_string("id"), bulk_string(&node.id), bulk_string("port"), node.port);
+                for field in ["ip", &node.ip, "endpoint", &node.ip, "role", "master"] {
+                    response += &bulk_string(field);
+                }
+                response += &format!("{}:{}\r\n", bulk_string("replication-offset"), offset);
+                response += &format!("{}{}", bulk_string("health"), bulk_string(health));
+            }
+            response
+        }
+        ClusterSubcommand::Keyslot(key) => format!(":{}\r\n", key_hash_slot(key.as_bytes())),
+        ClusterSubcommand::Countkeysinslot(slot) => format!(":{}\r\n", keys_in_slot(db, slot).len()),
+        ClusterSubcommand::Getkeysinslot(slot, count) => {
+            let keys = keys_in_slot(db, slot);
+            bulk_array(&keys[..count.min(keys.len())])
+        }
+        ClusterSubcommand::Addslots(slots) => {
+            for (i, slot) in slots.iter().enumerate() {
+                if cluster.slot_owner(*slot).is_some() {
+                    return Err("Slot is already busy");
+                }
+                if slots[..i].contains(slot) {
+                    return Err("Slot specified multiple times");
+                }
+            }
+            let myself = cluster.myself.clone();
+            for slot in slots {
+                cluster.importing.remove(&slot);
+                cluster.set_slot_owner(slot, Some(myself.clone()));
+            }
+            "+OK\r\n".to_string()
+        }
+        ClusterSubcommand::Delslots(slots) => {
+            for (i, slot) in slots.iter().enumerate() {
+                if cluster.slot_owner(*slot).is_none() {
+                    return Err("Slot is already unassigned");
+                }
+                if slots[..i].contains(slot) {
+                    return Err("Slot specified multiple times");
+                }
+            }
+            for slot in slots {
+                cluster.migrating.remove(&slot);
+                cluster.set_slot_owner(slot, None);
+            }
+            "+OK\r\n".to_string()
+        }
+        ClusterSubcommand::Meet(ip, port, bus_port) => {
+            let ip = ip.parse::<std::net::IpAddr>().map_err(|_| "Invalid node address specified")?.to_string();
+            let bus_port = bus_port.or_else(|| port.checked_add(10000)).ok_or("Invalid bus port specified")?;
+            cluster.meet(&ip, port, bus_port);
+            "+OK\r\n".to_string()
+        }
+        ClusterSubcommand::Setslot(slot, action) => {
+            let myself = cluster.myself.clone();
+            let owned = cluster.slot_owner(slot).is_some_and(|owner| owner.id == myself);
+            match action {
+                SetslotAction::Migrating(id) => {
+                    if !owned { return Err("I'm not the owner of hash slot"); }
+                    if !cluster.nodes.contains_key(&id) { return Err("I don't know about node"); }
+                    cluster.migrating.insert(slot, id);
+                }
+                SetslotAction::Importing(id) => {
+                    if owned { return Err("I'm already the owner of hash slot"); }
+                    if !cluster.nodes.contains_key(&id) { return Err("I don't know about node"); }
+                    cluster.importing.insert(slot, id);
+                }
+                SetslotAction::Stable => {
+                    cluster.migrating.remove(&slot);
+                    cluster.importing.remove(&slot);
+                }
+                SetslotAction::Node(id) => {
+                    if !cluster.nodes.contains_key(&id) { return Err("Unknown node"); }
+                    if owned && id != myself && !keys_in_slot(db, slot).is_empty() {
+                        return Err("Can't assign hashslot to a different node while I still hold keys for this hash slot.");
+                    }
+                    if id != myself {
+                        cluster.migrating.remove(&slot);
+                    }
+                    // The node that imported the slot takes a new epoch, so its claim wins on the other nodes
+                    if id == myself && cluster.importing.remove(&slot).is_some() {
+                        cluster.bump_epoch();
+                    }
+                    cluster.set_slot_owner(slot, Some(id));
+                }
+            }
+            cluster.changed = true;
+            "+OK\r\n".to_string()
+        }
+    };
+    save_cluster_config(&mut cluster, &server.config);
+    Ok(response)
+}
+
+// ASKING command, which lets the next command run on a slot this node is importing
+pub fn execute_asking_cmd(server: &Arc<Server>) -> Result<String, &'static str> {
+    server.cluster.as_ref().ok_or(CLUSTER_DISABLED)?;
+    Ok("+OK\r\n".to_string())
+}
+
+// Whether a command can run on this node in cluster mode. The keys of a command must all hash
+// to one slot, which this node serves; other nodes are pointed at with MOVED, and with ASK
+// while the slot migrates away and its keys are no longer here. asking is set when the command
+// follows ASKING, to run on a slot this node is importing.
+pub(crate) fn check_cluster_slot(cmd: &Command, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Server, asking: bool) -> Result<(), String> {
+    let cluster = match &server.cluster {
+        Some(cluster) => cluster,
+        None => return Ok(()),
+    };
+    match cmd {
+        Command::Select(index) if *index != 0 => return Err("SELECT is not allowed in cluster mode".to_string()),
+        Command::Swapdb(..) => return Err("SWAPDB is not allowed in cluster mode".to_string()),
+        Command::Move(..) => return Err("MOVE is not allowed in cluster mode".to_string()),
+        _ => {}
+    }
+
+    let keys = cmd.keys();
+    let slot = match keys.first() {
+        Some(key) => key_hash_slot(key.as_bytes()),
+        None => return Ok(()),
+    };
+    if keys.iter().any(|key| key_hash_slot(key.as_bytes()) != slot) {
+        return Err("CROSSSLOT Keys in request don't hash to the same slot".to_string());
+    }
+
+    let cluster = cluster.lock().unwrap();
+    let owner = cluster.slot_owner(slot).ok_or("CLUSTERDOWN Hash slot not served")?;
+    if !cluster.state_ok() {
+        return Err("CLUSTERDOWN The cluster is down".to_string());
+    }
+    let missing_keys = || {
+        let db = db.lock().unwrap();
+        keys.iter().filter(|key| !db.get(&0).is_some_and(|cur_db| cur_db.data.contains_key(**key))).count()
+    };
+    const TRYAGAIN: &str = "TRYAGAIN Multiple keys request during rehashing of slot";
+
+    if owner.id == cluster.myself {
+        if let Some(target) = cluster.migrating.get(&slot).and_then(|id| cluster.nodes.get(id)) {
+            match missing_keys() {
+                0 => {}
+                missing if missing < keys.len() => return Err(TRYAGAIN.to_string()),
+                _ => return Err(format!("ASK {} {}:{}", slot, target.ip, target.port)),
+            }
+        }
+        return Ok(());
+    }
+    if asking && cluster.importing.contains_key(&slot) {
+        if keys.len() > 1 && missing_keys() > 0 {
+            return Err(TRYAGAIN.to_string());
+        }
+        return Ok(());
+    }
+    Err(format!("MOVED {} {}:{}", slot, owner.ip, owner.port))
+}
+
+// Read the next message of a cluster bus link, or None when the other side closed it
+async fn read_message(stream: &mut TcpStream, pending: &mut Vec<u8>) -> Result<Option<Frame>, String> {
+    loop {
+        if let Some((frame, consumed)) = read_frame(pending)? {
+            pending.drain(..consumed);
+            return Ok(Some(frame));
+        }
+        let mut chunk = [0; 16 * 1024];
+        match stream.read(&mut chunk).await {
+            Ok(0) => return Ok(None),
+            Ok(bytes_read) => pending.extend_from_slice(&chunk[..bytes_read]),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+// Accept the links other nodes open to this node on the cluster bus
+pub async fn run_cluster_bus(listener: TcpListener, server: Arc<Server>) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                eprintln!("Error accepting a cluster bus connection: {}", e);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            if let Err(e) = serve_cluster_link(socket, &server).await {
+                eprintln!("Error on a cluster bus connection: {}", e);
+            }
+        });
+    }
+}
+
+// Reply to the MEETs and PINGs of another node with a PONG
+async fn serve_cluster_link(mut socket: TcpStream, server: &Server) -> Result<(), String> {
+    let cluster = server.cluster.as_ref().ok_or(CLUSTER_DISABLED)?;
+    let peer_ip = socket.peer_addr().map_err(|e| e.to_string())?.ip().to_string();
+    let mut pending = Vec::new();
+    while let Some(frame) = read_message(&mut socket, &mut pending).await? {
+        let message = BusMessage::parse(&frame)?;
+        let repl_offset = server.replication.lock().await.offset;
+        let reply = {
+            let mut cluster = cluster.lock().unwrap();
+            cluster.process(&message, &peer_ip, None);
+            cluster.message(BusMessageKind::Pong, repl_offset)
+        };
+        socket.write_all(&reply).await.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Keep a link to the node of id, connecting again after it fails, until the node is forgotten
+async fn run_cluster_link(server: Arc<Server>, mut id: String) {
+    let cluster = match &server.cluster {
+        Some(cluster) => cluster,
+        None => return,
+    };
+    loop {
+        let address = cluster.lock().unwrap().nodes.get_mut(&id).map(|node| {
+            // A node that cannot be reached counts as not replying
+            if node.ping_sent == 0 {
+                node.ping_sent = now_ms();
+            }
+            (node.ip.clone(), node.bus_port)
+        });
+        let (ip, bus_port) = match address {
+            Some(address) => address,
+            None => return,
+        };
+        match ping_node(&server, &ip, bus_port, &mut id).await {
+            Ok(()) => return,
+            Err(e) => eprintln!("Error on the cluster bus link to {}:{}: {}", ip, bus_port, e),
+        }
+        match cluster.lock().unwrap().nodes.get_mut(&id) {
+            Some(node) => node.connected = false,
+            None => return,
+        }
+        tokio::time::sleep(Duration::from_millis(CLUSTER_PING_PERIOD)).await;
+    }
+}
+
+// Connect to a node and send it a PING, or a MEET while in the handshake, every
+// CLUSTER_PING_PERIOD. Returns once the node is forgotten, and fails with the link.
+async fn ping_node(server: &Server, ip: &str, bus_port: u16, id: &mut String) -> Result<(), String> {
+    let cluster = server.cluster.as_ref().ok_or(CLUSTER_DISABLED)?;
+    let limit = Duration::from_millis(server.config.cluster_node_timeout);
+    let mut stream = match timeout(limit, TcpStream::connect((ip, bus_port))).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(format!("error connecting: {}", e)),
+        Err(_) => return Err("timeout connecting".to_string()),
+    };
+    let peer_ip = stream.peer_addr().map_err(|e| e.to_string())?.ip().to_string();
+    let mut pending = Vec::new();
+    loop {
+        let repl_offset = server.replication.lock().await.offset;
+        let message = {
+            let mut cluster = cluster.lock().unwrap();
+            let node = match cluster.nodes.get_mut(id.as_str()) {
+                Some(node) => node,
+                None => return Ok(()),
+            };
+            node.connected = true;
+            if node.ping_sent == 0 {
+                node.ping_sent = now_ms();
+            }
+            let kind = if node.handshake { BusMessageKind::Meet } else { BusMessageKind::Ping };
+            cluster.message(kind, repl_offset)
+        };
+        stream.write_all(&message).await.map_err(|e| e.to_string())?;
+
+        let frame = match timeout(limit, read_message(&mut stream, &mut pending)).await {
+            Ok(Ok(Some(frame))) => frame,
+            Ok(Ok(None)) => return Err("connection closed".to_string()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err("timeout waiting for a PONG".to_string()),
+        };
+        let message = BusMessage::parse(&frame)?;
+        match cluster.lock().unwrap().process(&message, &peer_ip, Some(id.as_str())) {
+            Some(link_id) => *id = link_id,
+            None => return Ok(()),
+        }
+        tokio::time::sleep(Duration::from_millis(CLUSTER_PING_PERIOD)).await;
+    }
+}
+
+// Every CLUSTER_CRON_PERIOD, forget the nodes whose handshake got no reply within the node
+// timeout, start the links to new nodes, and save the configuration when it changed
+pub async fn run_cluster_cron(server: Arc<Server>) {
+    let cluster = match &server.cluster {
+        Some(cluster) => cluster,
+        None => return,
+    };
+    let mut interval = tokio::time::interval(Duration::from_millis(CLUSTER_CRON_PERIOD));
+    loop {
+        interval.tick().await;
+        let mut cluster = cluster.lock().unwrap();
+        let now = now_ms();
+        let node_timeout = server.config.cluster_node_timeout;
+        cluster.nodes.retain(|_, node| {
+            let expired = node.handshake && now.saturating_sub(node.created) > node_timeout;
+            if expired {
+                println!("Handshake with node {}:{} timed out", node.ip, node.port);
+            }
+            !expired
+        });
+        let myself = cluster.myself.clone();
+        for node in cluster.nodes.values_mut().filter(|node| node.id != myself && !node.link_running) {
+            node.link_running = true;
+            tokio::spawn(run_cluster_link(Arc::clone(&server), node.id.clone()));
+        }
+        save_cluster_config(&mut cluster, &server.config);
+    }
+}
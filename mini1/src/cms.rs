@@ -0,0 +1,76 @@
+use std::mem;
+
+// A Count-Min Sketch: depth rows of width counters. An item is counted in one
+// counter per row, and its estimate is the smallest of those counters.
+#[derive(Debug, Clone)]
+pub struct CountMinSketch {
+    pub width: usize,
+    pub depth: usize,
+    pub counters: Vec<u32>,
+    pub count: u64,
+}
+
+impl CountMinSketch {
+    pub fn new(width: usize, depth: usize) -> Self {
+        Self { width, depth, counters: vec![0; width * depth], count: 0 }
+    }
+
+    // The dimensions giving estimates within error * total count, with the given probability of exceeding that
+    pub fn dimensions_for(error: f64, probability: f64) -> (usize, usize) {
+        let width = (2.0 / error).ceil() as usize;
+        let depth = (probability.ln() / 0.5f64.ln()).ceil() as usize;
+        (width.max(1), depth.max(1))
+    }
+
+    // The index of the counter for an item in each row, hashed like RedisBloom's sketches
+    fn indexes<'a>(&self, item: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let width = self.width;
+        (0..self.depth).map(move |row| row * width + (murmur_hash2(item, row as u32) % width as u32) as usize)
+    }
+
+    // Count an item increment more times, returning its new estimate
+    pub fn increment(&mut self, item: &[u8], increment: u32) -> u32 {
+        let indexes: Vec<usize> = self.indexes(item).collect();
+        for &index in &indexes {
+            self.counters[index] = self.counters[index].saturating_add(increment);
+        }
+        self.count = self.count.saturating_add(increment as u64);
+        indexes.into_iter().map(|index| self.counters[index]).min().unwrap_or(0)
+    }
+
+    pub fn query(&self, item: &[u8]) -> u32 {
+        self.indexes(item).map(|index| self.counters[index]).min().unwrap_or(0)
+    }
+
+    // The bytes used by the sketch, including its counters
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.counters.capacity() * mem::size_of::<u32>()
+    }
+}
+
+// The 32-bit MurmurHash2 of a key
+fn murmur_hash2(key: &[u8], seed: u32) -> u32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = seed ^ key.len() as u32;
+
+    let mut chunks = key.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
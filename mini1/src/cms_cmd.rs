@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::cms::CountMinSketch;
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+use crate::utils::bulk_string;
+
+static KEY_DOES_NOT_EXIST: &str = "CMS: key does not exist";
+
+// Check that a sketch has counters, but not so many that allocating them would exhaust memory
+fn check_dimensions(width: usize, depth: usize) -> Result<(usize, usize), &'static str> {
+    if width == 0 || depth == 0 {
+        return Err("CMS: width and depth must be larger than 0");
+    }
+    width.checked_mul(depth).filter(|counters| *counters <= 1 << 30).ok_or("CMS: sketch is too large")?;
+    Ok((width, depth))
+}
+
+// Parse CMS.INITBYDIM "<width> <depth>"
+pub fn parse_cms_dimensions(args: &[String]) -> Result<(usize, usize), &'static str> {
+    let width: usize = args[0].parse().map_err(|_| "CMS: invalid width")?;
+    let depth: usize = args[1].parse().map_err(|_| "CMS: invalid depth")?;
+    check_dimensions(width, depth)
+}
+
+// Parse CMS.INITBYPROB "<error> <probability>" into the dimensions they call for
+pub fn parse_cms_probabilities(args: &[String]) -> Result<(usize, usize), &'static str> {
+    let error: f64 = args[0].parse().ok().filter(|error| *error > 0.0 && *error < 1.0).ok_or("CMS: invalid overestimation value")?;
+    let probability: f64 = args[1].parse().ok().filter(|probability| *probability > 0.0 && *probability < 1.0).ok_or("CMS: invalid prob value")?;
+    let (width, depth) = CountMinSketch::dimensions_for(error, probability);
+    check_dimensions(width, depth)
+}
+
+// Look up the sketch stored at key, failing if the key holds another type or does not exist
+fn get_cms<'a>(cur_db: &'a Database, key: &str) -> Result<&'a CountMinSketch, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::CountMinSketch(sketch)) => Ok(sketch),
+        Some(_) => Err(WRONG_TYPE),
+        None => Err(KEY_DOES_NOT_EXIST),
+    }
+}
+
+// Look up the sketch stored at key mutably, failing if the key holds another type or does not exist
+fn get_cms_mut<'a>(cur_db: &'a mut Database, key: &str) -> Result<&'a mut CountMinSketch, &'static str> {
+    match cur_db.data.get_mut(key) {
+        Some(Data::CountMinSketch(sketch)) => Ok(sketch),
+        Some(_) => Err(WRONG_TYPE),
+        None => Err(KEY_DOES_NOT_EXIST),
+    }
+}
+
+// CMS.INITBYDIM and CMS.INITBYPROB commands
+pub fn execute_cms_init_cmd(key: String, width: usize, depth: usize, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if cur_db.data.contains_key(&key) {
+        return Err("CMS: key already exists");
+    }
+
+    cur_db.data.insert(key, Data::CountMinSketch(CountMinSketch::new(width, depth)));
+    Ok("+OK\r\n".to_string())
+}
+
+// CMS.INCRBY command
+pub fn execute_cms_incrby_cmd(key: String, increments: Vec<(Vec<u8>, u32)>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let sketch = get_cms_mut(cur_db, &key)?;
+
+    let mut response = format!("*{}\r\n", increments.len());
+    for (item, increment) in &increments {
+        response += &format!(":{}\r\n", sketch.increment(item, *increment));
+    }
+    Ok(response)
+}
+
+// CMS.QUERY command
+pub fn execute_cms_query_cmd(key: String, items: Vec<Vec<u8>>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let sketch = get_cms(cur_db, &key)?;
+
+    let mut response = format!("*{}\r\n", items.len());
+    for item in &items {
+        response += &format!(":{}\r\n", sketch.query(item));
+    }
+    Ok(response)
+}
+
+// CMS.INFO command
+pub fn execute_cms_info_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let sketch = get_cms(cur_db, &key)?;
+
+    let mut response = "*8\r\n".to_string();
+    response += &bulk_string("width");
+    response += &format!(":{}\r\n", sketch.width);
+    response += &bulk_string("depth");
+    response += &format!(":{}\r\n", sketch.depth);
+    response += &bulk_string("count");
+    response += &format!(":{}\r\n", sketch.count);
+    response += &bulk_string("size");
+    response += &format!(":{}\r\n", sketch.memory_usage());
+    Ok(response)
+}
@@ -1,16 +1,151 @@
 use std::fmt;
+use crate::set_cmd::SetOperation;
+use crate::sorted_set::ScoreBound;
+use crate::zset_cmd::{ZaddOptions, ZcombineOptions, ZrangeOptions};
+use crate::stream::StreamId;
+use crate::stream_cmd::{StreamTrim, XaddArgs, XclaimOptions, XgroupArgs, XinfoArgs, XpendingRange, XreadArgs};
+use crate::bit_cmd::{BitfieldOp, BitOperation, BitRange};
+use crate::geo_cmd::GeoSearchOptions;
+use crate::json_cmd::JsonSetCondition;
+use crate::json_path::JsonPath;
+use crate::bloom_cmd::BloomOptions;
+use crate::timeseries_cmd::{TsCreateOptions, TsRangeArgs};
+use crate::keyspace_cmd::{ObjectSubcommand, RestoreOptions, ScanOptions};
+use crate::migrate_cmd::MigrateArgs;
+use crate::sort_cmd::SortArgs;
+use crate::cluster_cmd::ClusterSubcommand;
 
 #[derive(Debug, Clone)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Command {
     Select(u8),
     Get(String),
-    Set(String, String),
+    Set(String, Vec<u8>),
     Ping(Option<String>),
     Exists(Vec<String>),
     Rpush(String, Vec<String>),
     Lpush(String, Vec<String>),
     BLPOP(Vec<String>, f64),
     BRPOP(Vec<String>, f64),
+    Hset(String, Vec<(String, String)>),
+    Hsetnx(String, String, String),
+    Hget(String, String),
+    Hmget(String, Vec<String>),
+    Hgetall(String),
+    Hdel(String, Vec<String>),
+    Hexists(String, String),
+    Hlen(String),
+    Hkeys(String),
+    Hvals(String),
+    Hstrlen(String, String),
+    Hincrby(String, String, i64),
+    Hincrbyfloat(String, String, f64),
+    Hrandfield(String, Option<i64>, bool),
+    Sadd(String, Vec<String>),
+    Srem(String, Vec<String>),
+    Sismember(String, String),
+    Smismember(String, Vec<String>),
+    Smembers(String),
+    Scard(String),
+    Spop(String, Option<usize>),
+    Srandmember(String, Option<i64>),
+    Smove(String, String, String),
+    SetOperation(SetOperation, Vec<String>),
+    SetOperationStore(SetOperation, String, Vec<String>),
+    Sintercard(Vec<String>, usize),
+    Zadd(String, ZaddOptions, Vec<(f64, String)>),
+    Zincrby(String, f64, String),
+    Zrem(String, Vec<String>),
+    Zscore(String, String),
+    Zrank(String, String, bool),
+    Zcard(String),
+    Zcount(String, ScoreBound, ScoreBound),
+    Zrange(String, ZrangeOptions),
+    Zrangestore(String, String, ZrangeOptions),
+    Zpop(String, Option<usize>, bool),
+    Bzpop(Vec<String>, f64, bool),
+    ZsetOperation(SetOperation, Vec<String>, ZcombineOptions),
+    ZsetOperationStore(SetOperation, String, Vec<String>, ZcombineOptions),
+    Xadd(String, XaddArgs),
+    Xlen(String),
+    Xrange(String, StreamId, StreamId, Option<usize>, bool),
+    Xdel(String, Vec<StreamId>),
+    Xtrim(String, StreamTrim),
+    Xread(XreadArgs),
+    Xgroup(XgroupArgs),
+    Xack(String, String, Vec<StreamId>),
+    Xpending(String, String, Option<XpendingRange>),
+    Xclaim(String, String, String, u64, Vec<StreamId>, XclaimOptions),
+    Xautoclaim(String, String, String, u64, StreamId, usize, bool),
+    Xinfo(XinfoArgs),
+    Setbit(String, u64, bool),
+    Getbit(String, u64),
+    Bitcount(String, Option<BitRange>),
+    Bitpos(String, bool, Option<BitRange>),
+    Bitop(BitOperation, String, Vec<String>),
+    Bitfield(String, Vec<BitfieldOp>),
+    Pfadd(String, Vec<Vec<u8>>),
+    Pfcount(Vec<String>),
+    Pfmerge(String, Vec<String>),
+    Geoadd(String, ZaddOptions, Vec<(f64, f64, String)>),
+    Geopos(String, Vec<String>),
+    Geodist(String, String, String, f64),
+    Geohash(String, Vec<String>),
+    Geosearch(String, GeoSearchOptions),
+    Geosearchstore(String, String, GeoSearchOptions),
+    JsonSet(String, JsonPath, serde_json::Value, JsonSetCondition),
+    JsonGet(String, Vec<JsonPath>),
+    JsonDel(String, JsonPath),
+    JsonNumincrby(String, JsonPath, serde_json::Number),
+    JsonArrappend(String, JsonPath, Vec<serde_json::Value>),
+    JsonType(String, JsonPath),
+    BfReserve(String, BloomOptions),
+    BfAdd(String, Vec<Vec<u8>>, bool),
+    BfExists(String, Vec<Vec<u8>>, bool),
+    BfInfo(String),
+    CmsInit(String, usize, usize),
+    CmsIncrby(String, Vec<(Vec<u8>, u32)>),
+    CmsQuery(String, Vec<Vec<u8>>),
+    CmsInfo(String),
+    TsCreate(String, TsCreateOptions),
+    TsAdd(String, u64, f64, TsCreateOptions),
+    TsMadd(Vec<(String, u64, f64)>),
+    TsRange(String, TsRangeArgs, bool),
+    TsMrange(TsRangeArgs),
+    Del(Vec<String>),
+    Type(String),
+    Rename(String, String, bool),
+    Copy(String, String, Option<u8>, bool),
+    Keys(String),
+    Scan(u64, ScanOptions),
+    Hscan(String, u64, ScanOptions),
+    Sscan(String, u64, ScanOptions),
+    Zscan(String, u64, ScanOptions),
+    Dbsize,
+    Flush(bool, bool),
+    Swapdb(u8, u8),
+    Move(String, u8),
+    Object(ObjectSubcommand, String),
+    Touch(Vec<String>),
+    Randomkey,
+    Dump(String),
+    Restore(String, u64, Vec<u8>, RestoreOptions),
+    Migrate(MigrateArgs),
+    Sort(SortArgs),
+    Save,
+    Bgsave,
+    Lastsave,
+    Bgrewriteaof,
+    Replicaof(Option<(String, u16)>),
+    Replconf(Vec<(String, String)>),
+    Psync(String, i64),
+    Sync,
+    Role,
+    Info(Option<String>),
+    Wait(usize, i64),
+    Waitaof(usize, usize, i64),
+    Cluster(ClusterSubcommand),
+    Asking,
 }
 
 // Implement the Display trait for Command (Debugging purposes)
@@ -19,7 +154,7 @@ impl fmt::Display for Command {
         match self {
             Command::Select(db) => write!(f, "Select database {}", db),
             Command::Get(key) => write!(f, "Get value for key {}", key),
-            Command::Set(key, value) => write!(f, "Set value for key {} to {}", key, value),
+            Command::Set(key, value) => write!(f, "Set value for key {} to {}", key, String::from_utf8_lossy(value)),
             Command::Ping(message) => match message {
                 Some(msg) => write!(f, "Ping with message {}", msg),
                 None => write!(f, "Ping"),
@@ -29,6 +164,213 @@ impl fmt::Display for Command {
             Command::Lpush(key, values) => write!(f, "Push values {:?} to key {}", values, key),
             Command::BLPOP(keys, timeout) => write!(f, "BLPOP on keys {:?} with timeout {}", keys, timeout),
             Command::BRPOP(keys, timeout) => write!(f, "BRPOP on keys {:?} with timeout {}", keys, timeout),
+            Command::Hset(key, pairs) => write!(f, "Set fields {:?} in hash {}", pairs, key),
+            Command::Hsetnx(key, field, value) => write!(f, "Set field {} in hash {} to {} if absent", field, key, value),
+            Command::Hget(key, field) => write!(f, "Get field {} from hash {}", field, key),
+            Command::Hmget(key, fields) => write!(f, "Get fields {:?} from hash {}", fields, key),
+            Command::Hgetall(key) => write!(f, "Get all fields from hash {}", key),
+            Command::Hdel(key, fields) => write!(f, "Delete fields {:?} from hash {}", fields, key),
+            Command::Hexists(key, field) => write!(f, "Check if field {} exists in hash {}", field, key),
+            Command::Hlen(key) => write!(f, "Get number of fields in hash {}", key),
+            Command::Hkeys(key) => write!(f, "Get field names of hash {}", key),
+            Command::Hvals(key) => write!(f, "Get values of hash {}", key),
+            Command::Hstrlen(key, field) => write!(f, "Get length of field {} in hash {}", field, key),
+            Command::Hincrby(key, field, increment) => write!(f, "Increment field {} in hash {} by {}", field, key, increment),
+            Command::Hincrbyfloat(key, field, increment) => write!(f, "Increment field {} in hash {} by {}", field, key, increment),
+            Command::Hrandfield(key, count, _) => write!(f, "Get {:?} random fields from hash {}", count, key),
+            Command::Sadd(key, members) => write!(f, "Add members {:?} to set {}", members, key),
+            Command::Srem(key, members) => write!(f, "Remove members {:?} from set {}", members, key),
+            Command::Sismember(key, member) => write!(f, "Check if {} is a member of set {}", member, key),
+            Command::Smismember(key, members) => write!(f, "Check if {:?} are members of set {}", members, key),
+            Command::Smembers(key) => write!(f, "Get members of set {}", key),
+            Command::Scard(key) => write!(f, "Get number of members in set {}", key),
+            Command::Spop(key, count) => write!(f, "Pop {:?} random members from set {}", count, key),
+            Command::Srandmember(key, count) => write!(f, "Get {:?} random members from set {}", count, key),
+            Command::Smove(source, destination, member) => write!(f, "Move member {} from set {} to set {}", member, source, destination),
+            Command::SetOperation(operation, keys) => write!(f, "Compute {:?} of sets {:?}", operation, keys),
+            Command::SetOperationStore(operation, destination, keys) => write!(f, "Store {:?} of sets {:?} in {}", operation, keys, destination),
+            Command::Sintercard(keys, limit) => write!(f, "Count intersection of sets {:?} up to {}", keys, limit),
+            Command::Zadd(key, _, pairs) => write!(f, "Add members {:?} to sorted set {}", pairs, key),
+            Command::Zincrby(key, increment, member) => write!(f, "Increment member {} of sorted set {} by {}", member, key, increment),
+            Command::Zrem(key, members) => write!(f, "Remove members {:?} from sorted set {}", members, key),
+            Command::Zscore(key, member) => write!(f, "Get score of member {} in sorted set {}", member, key),
+            Command::Zrank(key, member, _) => write!(f, "Get rank of member {} in sorted set {}", member, key),
+            Command::Zcard(key) => write!(f, "Get number of members in sorted set {}", key),
+            Command::Zcount(key, min, max) => write!(f, "Count members of sorted set {} with scores between {:?} and {:?}", key, min, max),
+            Command::Zrange(key, options) => write!(f, "Get range {:?} of sorted set {}", options.spec, key),
+            Command::Zrangestore(destination, source, options) => write!(f, "Store range {:?} of sorted set {} in {}", options.spec, source, destination),
+            Command::Zpop(key, count, max) => write!(f, "Pop {:?} members with {} scores from sorted set {}", count, if *max { "highest" } else { "lowest" }, key),
+            Command::Bzpop(keys, timeout, max) => write!(f, "Blocking pop of {} score on sorted sets {:?} with timeout {}", if *max { "highest" } else { "lowest" }, keys, timeout),
+            Command::ZsetOperation(operation, keys, _) => write!(f, "Compute {:?} of sorted sets {:?}", operation, keys),
+            Command::ZsetOperationStore(operation, destination, keys, _) => write!(f, "Store {:?} of sorted sets {:?} in {}", operation, keys, destination),
+            Command::Xadd(key, args) => write!(f, "Add entry {:?} with ID {} to stream {}", args.fields, args.id, key),
+            Command::Xlen(key) => write!(f, "Get number of entries in stream {}", key),
+            Command::Xrange(key, start, end, _, rev) => write!(f, "Get entries {} to {} of stream {}{}", start, end, key, if *rev { " in reverse" } else { "" }),
+            Command::Xdel(key, ids) => write!(f, "Delete entries {:?} from stream {}", ids, key),
+            Command::Xtrim(key, trim) => write!(f, "Trim stream {} with {:?}", key, trim.strategy),
+            Command::Xread(args) => write!(f, "Read streams {:?} from IDs {:?}", args.keys, args.ids),
+            Command::Xgroup(args) => write!(f, "Manage consumer group {:?}", args),
+            Command::Xack(key, group, ids) => write!(f, "Acknowledge entries {:?} of stream {} for group {}", ids, key, group),
+            Command::Xpending(key, group, _) => write!(f, "Get pending entries of stream {} for group {}", key, group),
+            Command::Xclaim(key, group, consumer, _, ids, _) => write!(f, "Claim entries {:?} of stream {} in group {} for {}", ids, key, group, consumer),
+            Command::Xautoclaim(key, group, consumer, _, start, _, _) => write!(f, "Auto-claim entries of stream {} from {} in group {} for {}", key, start, group, consumer),
+            Command::Xinfo(args) => write!(f, "Get stream information {:?}", args),
+            Command::Setbit(key, offset, value) => write!(f, "Set bit {} of key {} to {}", offset, key, *value as u8),
+            Command::Getbit(key, offset) => write!(f, "Get bit {} of key {}", offset, key),
+            Command::Bitcount(key, range) => write!(f, "Count set bits in range {:?} of key {}", range, key),
+            Command::Bitpos(key, bit, range) => write!(f, "Find first {} bit in range {:?} of key {}", *bit as u8, range, key),
+            Command::Bitop(operation, destination, keys) => write!(f, "Store bitwise {:?} of keys {:?} in {}", operation, keys, destination),
+            Command::Bitfield(key, ops) => write!(f, "Run bitfield operations {:?} on key {}", ops, key),
+            Command::Pfadd(key, elements) => write!(f, "Add {} elements to HyperLogLog {}", elements.len(), key),
+            Command::Pfcount(keys) => write!(f, "Count unique elements of HyperLogLogs {:?}", keys),
+            Command::Pfmerge(destination, sources) => write!(f, "Merge HyperLogLogs {:?} into {}", sources, destination),
+            Command::Geoadd(key, _, positions) => write!(f, "Add positions {:?} to geo index {}", positions, key),
+            Command::Geopos(key, members) => write!(f, "Get positions of members {:?} of geo index {}", members, key),
+            Command::Geodist(key, member1, member2, _) => write!(f, "Get distance between {} and {} in geo index {}", member1, member2, key),
+            Command::Geohash(key, members) => write!(f, "Get geohashes of members {:?} of geo index {}", members, key),
+            Command::Geosearch(key, options) => write!(f, "Search {:?} around {:?} in geo index {}", options.shape, options.origin, key),
+            Command::Geosearchstore(destination, source, options) => write!(f, "Store search {:?} around {:?} in geo index {} in {}", options.shape, options.origin, source, destination),
+            Command::JsonSet(key, path, value, _) => write!(f, "Set path {} of JSON document {} to {}", path.text, key, value),
+            Command::JsonGet(key, paths) => write!(f, "Get paths {:?} of JSON document {}", paths.iter().map(|path| &path.text).collect::<Vec<_>>(), key),
+            Command::JsonDel(key, path) => write!(f, "Delete path {} of JSON document {}", path.text, key),
+            Command::JsonNumincrby(key, path, increment) => write!(f, "Increment path {} of JSON document {} by {}", path.text, key, increment),
+            Command::JsonArrappend(key, path, values) => write!(f, "Append {} values to path {} of JSON document {}", values.len(), path.text, key),
+            Command::JsonType(key, path) => write!(f, "Get type of path {} of JSON document {}", path.text, key),
+            Command::BfReserve(key, options) => write!(f, "Reserve Bloom filter {} for {} items at error rate {}", key, options.capacity, options.error_rate),
+            Command::BfAdd(key, items, _) => write!(f, "Add {} items to Bloom filter {}", items.len(), key),
+            Command::BfExists(key, items, _) => write!(f, "Check {} items in Bloom filter {}", items.len(), key),
+            Command::BfInfo(key) => write!(f, "Get info of Bloom filter {}", key),
+            Command::CmsInit(key, width, depth) => write!(f, "Create Count-Min Sketch {} with width {} and depth {}", key, width, depth),
+            Command::CmsIncrby(key, increments) => write!(f, "Increment {} items in Count-Min Sketch {}", increments.len(), key),
+            Command::CmsQuery(key, items) => write!(f, "Query {} items in Count-Min Sketch {}", items.len(), key),
+            Command::CmsInfo(key) => write!(f, "Get info of Count-Min Sketch {}", key),
+            Command::TsCreate(key, _) => write!(f, "Create time series {}", key),
+            Command::TsAdd(key, timestamp, value, _) => write!(f, "Add sample {} at {} to time series {}", value, timestamp, key),
+            Command::TsMadd(samples) => write!(f, "Add {} samples to time series", samples.len()),
+            Command::TsRange(key, range, _) => write!(f, "Get samples of time series {} from {} to {}", key, range.from, range.to),
+            Command::TsMrange(range) => write!(f, "Get samples of {} time series filters from {} to {}", range.filters.len(), range.from, range.to),
+            Command::Del(keys) => write!(f, "Delete keys {:?}", keys),
+            Command::Type(key) => write!(f, "Get type of key {}", key),
+            Command::Rename(key, new_key, _) => write!(f, "Rename key {} to {}", key, new_key),
+            Command::Copy(source, destination, _, _) => write!(f, "Copy key {} to {}", source, destination),
+            Command::Keys(pattern) => write!(f, "Get keys matching {}", pattern),
+            Command::Scan(cursor, _) => write!(f, "Scan keys from cursor {}", cursor),
+            Command::Hscan(key, cursor, _) => write!(f, "Scan hash {} from cursor {}", key, cursor),
+            Command::Sscan(key, cursor, _) => write!(f, "Scan set {} from cursor {}", key, cursor),
+            Command::Zscan(key, cursor, _) => write!(f, "Scan sorted set {} from cursor {}", key, cursor),
+            Command::Dbsize => write!(f, "Get number of keys"),
+            Command::Flush(all, _) => write!(f, "Flush {}", if *all { "all databases" } else { "database" }),
+            Command::Swapdb(first, second) => write!(f, "Swap databases {} and {}", first, second),
+            Command::Move(key, destination_db) => write!(f, "Move key {} to database {}", key, destination_db),
+            Command::Object(subcommand, key) => write!(f, "Get {:?} of key {}", subcommand, key),
+            Command::Touch(keys) => write!(f, "Touch keys {:?}", keys),
+            Command::Randomkey => write!(f, "Get a random key"),
+            Command::Dump(key) => write!(f, "Serialize key {}", key),
+            Command::Restore(key, ..) => write!(f, "Restore key {} from a serialized value", key),
+            Command::Migrate(args) => write!(f, "Migrate keys {:?} to {}:{}", args.keys, args.host, args.port),
+            Command::Sort(args) => write!(f, "Sort key {}", args.key),
+            Command::Save => write!(f, "Save a snapshot"),
+            Command::Bgsave => write!(f, "Save a snapshot in the background"),
+            Command::Lastsave => write!(f, "Get the time of the last snapshot"),
+            Command::Bgrewriteaof => write!(f, "Rewrite the append only file in the background"),
+            Command::Replicaof(primary) => match primary {
+                Some((host, port)) => write!(f, "Replicate {}:{}", host, port),
+                None => write!(f, "Stop replicating"),
+            },
+            Command::Replconf(options) => write!(f, "Set replication options {:?}", options),
+            Command::Psync(replid, offset) => write!(f, "Synchronize with replication ID {} from offset {}", replid, offset),
+            Command::Sync => write!(f, "Synchronize as a replica"),
+            Command::Role => write!(f, "Get the replication role"),
+            Command::Info(section) => write!(f, "Get information about {}", section.as_deref().unwrap_or("the server")),
+            Command::Wait(numreplicas, timeout) => write!(f, "Wait for {} replicas to acknowledge with timeout {}", numreplicas, timeout),
+            Command::Waitaof(numlocal, numreplicas, timeout) => write!(f, "Wait for {} local and {} replica AOFs to fsync with timeout {}", numlocal, numreplicas, timeout),
+            Command::Cluster(subcommand) => write!(f, "Run cluster subcommand {:?}", subcommand),
+            Command::Asking => write!(f, "Ask for a slot being imported"),
+        }
+    }
+}
+
+impl Command {
+    // The keys of the selected database that a command reads or writes
+    pub fn keys(&self) -> Vec<&str> {
+        match self {
+            Command::Get(key) | Command::Set(key, ..) | Command::Rpush(key, ..) | Command::Lpush(key, ..)
+            | Command::Hset(key, ..) | Command::Hsetnx(key, ..) | Command::Hget(key, ..) | Command::Hmget(key, ..)
+            | Command::Hgetall(key) | Command::Hdel(key, ..) | Command::Hexists(key, ..) | Command::Hlen(key)
+            | Command::Hkeys(key) | Command::Hvals(key) | Command::Hstrlen(key, ..) | Command::Hincrby(key, ..)
+            | Command::Hincrbyfloat(key, ..) | Command::Hrandfield(key, ..) | Command::Hscan(key, ..)
+            | Command::Sadd(key, ..) | Command::Srem(key, ..) | Command::Sismember(key, ..) | Command::Smismember(key, ..)
+            | Command::Smembers(key) | Command::Scard(key) | Command::Spop(key, ..) | Command::Srandmember(key, ..)
+            | Command::Sscan(key, ..) | Command::Zadd(key, ..) | Command::Zincrby(key, ..) | Command::Zrem(key, ..)
+            | Command::Zscore(key, ..) | Command::Zrank(key, ..) | Command::Zcard(key) | Command::Zcount(key, ..)
+            | Command::Zrange(key, ..) | Command::Zpop(key, ..) | Command::Zscan(key, ..)
+            | Command::Xadd(key, ..) | Command::Xlen(key) | Command::Xrange(key, ..) | Command::Xdel(key, ..)
+            | Command::Xtrim(key, ..) | Command::Xack(key, ..) | Command::Xpending(key, ..) | Command::Xclaim(key, ..)
+            | Command::Xautoclaim(key, ..) | Command::Setbit(key, ..) | Command::Getbit(key, ..) | Command::Bitcount(key, ..)
+            | Command::Bitpos(key, ..) | Command::Bitfield(key, ..) | Command::Pfadd(key, ..)
+            | Command::Geoadd(key, ..) | Command::Geopos(key, ..) | Command::Geodist(key, ..) | Command::Geohash(key, ..)
+            | Command::Geosearch(key, ..) | Command::JsonSet(key, ..) | Command::JsonGet(key, ..) | Command::JsonDel(key, ..)
+            | Command::JsonNumincrby(key, ..) | Command::JsonArrappend(key, ..) | Command::JsonType(key, ..)
+            | Command::BfReserve(key, ..) | Command::BfAdd(key, ..) | Command::BfExists(key, ..) | Command::BfInfo(key)
+            | Command::CmsInit(key, ..) | Command::CmsIncrby(key, ..) | Command::CmsQuery(key, ..) | Command::CmsInfo(key)
+            | Command::TsCreate(key, ..) | Command::TsAdd(key, ..) | Command::TsRange(key, ..)
+            | Command::Type(key) | Command::Move(key, ..) | Command::Object(_, key) | Command::Dump(key) | Command::Restore(key, ..) => vec![key],
+            Command::Exists(keys) | Command::BLPOP(keys, ..) | Command::BRPOP(keys, ..) | Command::SetOperation(_, keys)
+            | Command::Sintercard(keys, ..) | Command::Bzpop(keys, ..) | Command::ZsetOperation(_, keys, ..)
+            | Command::Pfcount(keys) | Command::Del(keys) | Command::Touch(keys) => keys.iter().map(String::as_str).collect(),
+            Command::Smove(first, second, ..) | Command::Zrangestore(first, second, ..) | Command::Geosearchstore(first, second, ..)
+            | Command::Rename(first, second, ..) | Command::Copy(first, second, ..) => vec![first, second],
+            Command::SetOperationStore(_, destination, keys) | Command::ZsetOperationStore(_, destination, keys, ..)
+            | Command::Bitop(_, destination, keys) | Command::Pfmerge(destination, keys) => {
+                std::iter::once(destination).chain(keys).map(String::as_str).collect()
+            }
+            Command::Xread(XreadArgs { keys, .. }) | Command::Migrate(MigrateArgs { keys, .. }) => keys.iter().map(String::as_str).collect(),
+            Command::Xgroup(args) => match args {
+                XgroupArgs::Create { key, .. } | XgroupArgs::SetId { key, .. } | XgroupArgs::Destroy { key, .. }
+                | XgroupArgs::CreateConsumer { key, .. } | XgroupArgs::DelConsumer { key, .. } => vec![key],
+            },
+            Command::Xinfo(XinfoArgs::Stream(key) | XinfoArgs::Groups(key) | XinfoArgs::Consumers(key, _)) => vec![key],
+            Command::Sort(SortArgs { key, store, .. }) => std::iter::once(key).chain(store).map(String::as_str).collect(),
+            Command::TsMadd(samples) => samples.iter().map(|(key, ..)| key.as_str()).collect(),
+            Command::Select(_) | Command::Ping(_) | Command::TsMrange(_) | Command::Keys(_) | Command::Scan(..)
+            | Command::Dbsize | Command::Flush(..) | Command::Swapdb(..) | Command::Randomkey
+            | Command::Save | Command::Bgsave | Command::Lastsave | Command::Bgrewriteaof | Command::Replicaof(_)
+            | Command::Replconf(_) | Command::Psync(..) | Command::Sync | Command::Role | Command::Info(_) | Command::Wait(..)
+            | Command::Waitaof(..) | Command::Cluster(_) | Command::Asking => Vec::new(),
+        }
+    }
+
+    // Whether a command may wait on other clients or on the network before it replies
+    pub fn may_wait(&self) -> bool {
+        match self {
+            Command::BLPOP(..) | Command::BRPOP(..) | Command::Bzpop(..) | Command::Migrate(_) | Command::Wait(..) | Command::Waitaof(..) => true,
+            Command::Xread(XreadArgs { block, .. }) => block.is_some(),
+            _ => false,
+        }
+    }
+
+    // Whether a command may change the data, which counts towards the save rules
+    pub fn is_write(&self) -> bool {
+        match self {
+            Command::Xread(XreadArgs { group, .. }) => group.is_some(),
+            Command::Sort(SortArgs { store, .. }) => store.is_some(),
+            Command::Migrate(MigrateArgs { copy, .. }) => !copy,
+            _ => !matches!(self,
+                Command::Select(_) | Command::Get(_) | Command::Ping(_) | Command::Exists(_) | Command::Hget(..) | Command::Hmget(..)
+                | Command::Hgetall(_) | Command::Hexists(..) | Command::Hlen(_) | Command::Hkeys(_) | Command::Hvals(_)
+                | Command::Hstrlen(..) | Command::Hrandfield(..) | Command::Sismember(..) | Command::Smismember(..)
+                | Command::Smembers(_) | Command::Scard(_) | Command::Srandmember(..) | Command::SetOperation(..)
+                | Command::Sintercard(..) | Command::Zscore(..) | Command::Zrank(..) | Command::Zcard(_) | Command::Zcount(..)
+                | Command::Zrange(..) | Command::ZsetOperation(..) | Command::Xlen(_) | Command::Xrange(..)
+                | Command::Xpending(..) | Command::Xinfo(_) | Command::Getbit(..) | Command::Bitcount(..) | Command::Bitpos(..)
+                | Command::Pfcount(_) | Command::Geopos(..) | Command::Geodist(..) | Command::Geohash(..) | Command::Geosearch(..)
+                | Command::JsonGet(..) | Command::JsonType(..) | Command::BfExists(..) | Command::BfInfo(_)
+                | Command::CmsQuery(..) | Command::CmsInfo(_) | Command::TsRange(..) | Command::TsMrange(_)
+                | Command::Type(_) | Command::Keys(_) | Command::Scan(..) | Command::Hscan(..) | Command::Sscan(..)
+                | Command::Zscan(..) | Command::Dbsize | Command::Object(..) | Command::Touch(_) | Command::Randomkey
+                | Command::Dump(_) | Command::Save | Command::Bgsave | Command::Lastsave | Command::Bgrewriteaof
+                | Command::Replicaof(_) | Command::Replconf(_) | Command::Psync(..) | Command::Sync | Command::Role | Command::Info(_)
+                | Command::Wait(..) | Command::Waitaof(..) | Command::Cluster(_) | Command::Asking),
         }
     }
 }
@@ -0,0 +1,180 @@
+use std::path::PathBuf;
+
+use crate::aof::AppendFsync;
+
+// Server settings, read from the command line
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub port: u16,
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    // Snapshot after <seconds> when at least <changes> writes happened since the last one
+    pub save: Vec<(u64, u64)>,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    // The directory in --dir that holds the AOF files and their manifest
+    pub appenddirname: String,
+    pub appendfsync: AppendFsync,
+    // Load an AOF whose last command was cut short, instead of refusing to start
+    pub aof_load_truncated: bool,
+    // Write the base of a rewritten AOF as an RDB file instead of commands
+    pub aof_use_rdb_preamble: bool,
+    // Rewrite the AOF when it grew by this percentage since the last rewrite, 0 to never do it,
+    // once it is at least the minimum size in bytes
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: u64,
+    // The primary to replicate from at startup
+    pub replicaof: Option<(String, u16)>,
+    // Refuse writes from clients while replicating
+    pub replica_read_only: bool,
+    // The size in bytes of the backlog kept for replicas to catch up after a disconnection
+    pub repl_backlog_size: u64,
+    // Seconds without traffic after which a link between a primary and a replica is dropped
+    pub repl_timeout: u64,
+    // Run as a node of a cluster, with a cluster bus on the port + 10000
+    pub cluster_enabled: bool,
+    // The file in --dir where the node keeps its view of the cluster
+    pub cluster_config_file: String,
+    // Milliseconds without a reply after which a node is considered failing
+    pub cluster_node_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 6379,
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            appendfsync: AppendFsync::Everysec,
+            aof_load_truncated: true,
+            aof_use_rdb_preamble: true,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            replicaof: None,
+            replica_read_only: true,
+            repl_backlog_size: 1024 * 1024,
+            repl_timeout: 60,
+            cluster_enabled: false,
+            cluster_config_file: "nodes.conf".to_string(),
+            cluster_node_timeout: 15000,
+        }
+    }
+}
+
+impl Config {
+    // Parse "[--port <port>] [--dir <dir>] [--dbfilename <name>] [--save \"<seconds> <changes> ...\"]
+    // [--appendonly yes|no] [--appendfilename <name>] [--appenddirname <name>] [--appendfsync always|everysec|no]
+    // [--aof-load-truncated yes|no] [--aof-use-rdb-preamble yes|no] [--auto-aof-rewrite-percentage <percentage>]
+    // [--auto-aof-rewrite-min-size <bytes>[kb|mb|gb]] [--replicaof <host> <port>] [--replica-read-only yes|no]
+    // [--repl-backlog-size <bytes>[kb|mb|gb]] [--repl-timeout <seconds>] [--cluster-enabled yes|no]
+    // [--cluster-config-file <name>] [--cluster-node-timeout <milliseconds>]",
+    // where an empty --save disables automatic snapshots
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
+        let mut config = Config::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--port" => config.port = args.next().and_then(|port| port.parse().ok()).ok_or("Invalid --port")?,
+                "--dir" => config.dir = args.next().map(PathBuf::from).ok_or("Invalid --dir")?,
+                "--dbfilename" => config.dbfilename = args.next().filter(|name| is_file_name(name)).ok_or("Invalid --dbfilename")?,
+                "--save" => config.save = parse_save_rules(&args.next().ok_or("Invalid --save")?)?,
+                "--appendonly" => config.appendonly = parse_yes_no(args.next()).ok_or("Invalid --appendonly")?,
+                "--appendfilename" => config.appendfilename = args.next().filter(|name| is_file_name(name)).ok_or("Invalid --appendfilename")?,
+                "--appenddirname" => config.appenddirname = args.next().filter(|name| is_file_name(name)).ok_or("Invalid --appenddirname")?,
+                "--appendfsync" => config.appendfsync = AppendFsync::parse(&args.next().unwrap_or_default())?,
+                "--aof-load-truncated" => config.aof_load_truncated = parse_yes_no(args.next()).ok_or("Invalid --aof-load-truncated")?,
+                "--aof-use-rdb-preamble" => config.aof_use_rdb_preamble = parse_yes_no(args.next()).ok_or("Invalid --aof-use-rdb-preamble")?,
+                "--auto-aof-rewrite-percentage" => {
+                    config.auto_aof_rewrite_percentage = args.next().and_then(|percentage| percentage.parse().ok()).ok_or("Invalid --auto-aof-rewrite-percentage")?;
+                }
+                "--auto-aof-rewrite-min-size" => {
+                    config.auto_aof_rewrite_min_size = args.next().and_then(|size| parse_size(&size)).ok_or("Invalid --auto-aof-rewrite-min-size")?;
+                }
+                "--replicaof" => {
+                    let host = args.next().ok_or("Invalid --replicaof")?;
+                    let port = args.next().and_then(|port| port.parse().ok()).ok_or("Invalid --replicaof")?;
+                    config.replicaof = Some((host, port));
+                }
+                "--replica-read-only" => config.replica_read_only = parse_yes_no(args.next()).ok_or("Invalid --replica-read-only")?,
+                "--repl-backlog-size" => {
+                    config.repl_backlog_size = args.next().and_then(|size| parse_size(&size)).filter(|size| *size > 0).ok_or("Invalid --repl-backlog-size")?;
+                }
+                "--repl-timeout" => config.repl_timeout = args.next().and_then(|seconds| seconds.parse().ok()).filter(|seconds| *seconds > 0).ok_or("Invalid --repl-timeout")?,
+                "--cluster-enabled" => config.cluster_enabled = parse_yes_no(args.next()).ok_or("Invalid --cluster-enabled")?,
+                "--cluster-config-file" => config.cluster_config_file = args.next().filter(|name| is_file_name(name)).ok_or("Invalid --cluster-config-file")?,
+                "--cluster-node-timeout" => {
+                    config.cluster_node_timeout = args.next().and_then(|timeout| timeout.parse().ok()).filter(|timeout| *timeout > 0).ok_or("Invalid --cluster-node-timeout")?;
+                }
+                _ => return Err("Unknown argument. Usage: mini1 [--port <port>] [--dir <dir>] [--dbfilename <name>] [--save \"<seconds> <changes> ...\"] [--appendonly yes|no] [--appendfilename <name>] [--appenddirname <name>] [--appendfsync always|everysec|no] [--aof-load-truncated yes|no] [--aof-use-rdb-preamble yes|no] [--auto-aof-rewrite-percentage <percentage>] [--auto-aof-rewrite-min-size <bytes>[kb|mb|gb]] [--replicaof <host> <port>] [--replica-read-only yes|no] [--repl-backlog-size <bytes>[kb|mb|gb]] [--repl-timeout <seconds>] [--cluster-enabled yes|no] [--cluster-config-file <name>] [--cluster-node-timeout <milliseconds>]"),
+            }
+        }
+        if config.cluster_enabled && config.replicaof.is_some() {
+            return Err("--replicaof is not allowed in cluster mode");
+        }
+        if config.cluster_enabled && config.port.checked_add(10000).is_none() {
+            return Err("Invalid --port: the cluster bus port is --port + 10000");
+        }
+        Ok(config)
+    }
+
+    // The path of the RDB file
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    // The directory of the AOF files
+    pub fn aof_dir(&self) -> PathBuf {
+        self.dir.join(&self.appenddirname)
+    }
+
+    // The path of the cluster configuration file
+    pub fn cluster_config_path(&self) -> PathBuf {
+        self.dir.join(&self.cluster_config_file)
+    }
+
+    // The port of the cluster bus
+    pub fn cluster_bus_port(&self) -> u16 {
+        self.port + 10000
+    }
+}
+
+// Whether name can name a file in a directory. Names with spaces are refused, since
+// the AOF manifest separates its fields with spaces.
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', ' ', '\t', '\n'])
+}
+
+// Parse a size in bytes, with an optional kb, mb or gb suffix
+fn parse_size(size: &str) -> Option<u64> {
+    let size = size.to_lowercase();
+    let (number, unit) = match size.len().checked_sub(2).map(|split| size.split_at(split)) {
+        Some((number, "kb")) => (number, 1024),
+        Some((number, "mb")) => (number, 1024 * 1024),
+        Some((number, "gb")) => (number, 1024 * 1024 * 1024),
+        _ => (size.as_str(), 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+// Parse "yes" or "no"
+fn parse_yes_no(value: Option<String>) -> Option<bool> {
+    match value?.to_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+// Parse pairs of "<seconds> <changes>"
+fn parse_save_rules(rules: &str) -> Result<Vec<(u64, u64)>, &'static str> {
+    let numbers = rules.split_whitespace()
+        .map(|number| number.parse::<u64>().map_err(|_| "Invalid --save"))
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() % 2 != 0 {
+        return Err("Invalid --save");
+    }
+    Ok(numbers.chunks(2).map(|rule| (rule[0], rule[1])).collect())
+}
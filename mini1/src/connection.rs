@@ -2,12 +2,15 @@ use std::{collections::HashMap, error::Error};
 use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream};
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use crate::{database::Database, parse_request, execute_command, command::Command};
+use crate::{database::Database, server::Server, command::Command, cluster_cmd::check_cluster_slot, replication_cmd::{refuses_writes, serve_replica}, utils::{execute_command, parse_request, read_frame, Frame}};
 
-pub async fn handle_connection(socket: &mut TcpStream, db: &Arc<Mutex<HashMap<u8, Database>>>, tx: mpsc::Sender<String>) -> Result<(), Box<dyn Error>> {
+pub async fn handle_connection(socket: &mut TcpStream, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, tx: mpsc::Sender<String>) -> Result<(), Box<dyn Error>> {
     let mut buf = [0; 1024];
+    let mut pending = Vec::new(); // Bytes received but not yet parsed into a full request
     let mut selected_db = 0; // Default namespace
     let mut cmd_executed = false;
+    let mut listening_port = None; // The port a replica listens on, from REPLCONF listening-port
+    let mut asking = false; // Set by ASKING, for the next command only
 
     loop {
         let bytes_read = socket.read(&mut buf).await?;
@@ -15,47 +18,95 @@ pub async fn handle_connection(socket: &mut TcpStream, db: &Arc<Mutex<HashMap<u8
         // If the client closed the connection, break the loop
         if bytes_read == 0 { break; }
 
-        let request = std::str::from_utf8(&buf[..bytes_read])?;
-        println!("Received: {:?}", request);
+        pending.extend_from_slice(&buf[..bytes_read]);
+        println!("Received: {:?}", String::from_utf8_lossy(&buf[..bytes_read]));
 
-        let response = match parse_request(request) {
-            Ok((cmd, args)) => {
-                // Send a message to the main thread whenever a command is executed
-                tx.send(format!("Executed command: {}", cmd)).await.unwrap();
+        // A read may hold part of a request, or several pipelined requests
+        let mut response = Vec::new();
+        loop {
+            let frame = match read_frame(&pending) {
+                Ok(Some((frame, consumed))) => {
+                    pending.drain(..consumed);
+                    frame
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    pending.clear();
+                    response.extend_from_slice(format!("-{}\r\n", e).as_bytes());
+                    break;
+                }
+            };
 
-                // Push the command into the queue for the selected database
-                push_command(&db, &mut selected_db, (cmd.clone(), args.clone()));
+            match parse_request(frame.clone()) {
+                Ok((cmd, _)) => {
+                    // Send a message to the main thread whenever a command is executed
+                    tx.send(format!("Executed command: {}", cmd)).await.unwrap();
 
-                // Process and execute queued commands for the selected database
-                execute_queued_commands(db, &mut selected_db, &mut cmd_executed, args).await
-            },
-            Err(e) => format!("-{}\r\n", e),
-        };
+                    // RESTORE-ASKING, which MIGRATE sends in cluster mode, is a RESTORE that follows ASKING
+                    let asked = std::mem::take(&mut asking) || frame[0].eq_ignore_ascii_case(b"RESTORE-ASKING");
+                    match &cmd {
+                        // The connection becomes the link to a replica
+                        Command::Psync(replid, offset) => {
+                            socket.write_all(&response).await?;
+                            return serve_replica(socket, db, server, listening_port, Some((replid.clone(), *offset))).await;
+                        }
+                        Command::Sync => {
+                            socket.write_all(&response).await?;
+                            return serve_replica(socket, db, server, listening_port, None).await;
+                        }
+                        Command::Replconf(options) => {
+                            if let Some((_, port)) = options.iter().find(|(option, _)| option == "listening-port") {
+                                listening_port = port.parse().ok();
+                            }
+                        }
+                        Command::Asking => asking = server.cluster.is_some(),
+                        _ if cmd.is_write() && refuses_writes(server).await => {
+                            response.extend_from_slice(b"-READONLY You can't write against a read only replica.\r\n");
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    // In cluster mode, commands on the keys of other nodes are redirected
+                    if let Err(e) = check_cluster_slot(&cmd, db, server, asked) {
+                        response.extend_from_slice(format!("-{}\r\n", e).as_bytes());
+                        continue;
+                    }
 
-        socket.write_all(response.as_bytes()).await?; // Write the response back to the client
+                    // Push the command into the queue for the selected database
+                    push_command(db, &mut selected_db, (cmd.clone(), frame));
+
+                    // Process and execute queued commands for the selected database
+                    response.extend(execute_queued_commands(db, server, &mut selected_db, &mut cmd_executed).await);
+                },
+                Err(e) => response.extend_from_slice(format!("-{}\r\n", e).as_bytes()),
+            }
+        }
+
+        if !response.is_empty() {
+            socket.write_all(&response).await?; // Write the response back to the client
+        }
     }
 
     Ok(())
 }
 
 // Process and execute queued commands for the selected database
-async fn execute_queued_commands(db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8, cmd_executed: &mut bool, args: Vec<String>) -> String {
+async fn execute_queued_commands(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, selected_db: &mut u8, cmd_executed: &mut bool) -> Vec<u8> {
     println!("Executing queued commands");
-    let mut response = String::new();
+    let mut response = Vec::new();
 
-    while let Some(cmd) = {
+    while let Some((cmd, request)) = {
         let mut db_guard = db.lock().unwrap();
         let db_entry = db_guard.get_mut(selected_db).unwrap();
         db_entry.queue.pop_front()
     } {
-        response += &execute_command(cmd.clone(), db, selected_db, cmd_executed, args.clone()).await;
+        response.extend(execute_command(cmd, db, server, selected_db, cmd_executed, request).await);
     }
 
     response
 }
 
 // Push the command into the queue for the selected database
-fn push_command(db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8, command: (Command, Vec<String>)) {
-    let (cmd, _) = command;
-    db.lock().unwrap().get_mut(selected_db).unwrap().queue.push_back(cmd);
+fn push_command(db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8, command: (Command, Frame)) {
+    db.lock().unwrap().get_mut(selected_db).unwrap().queue.push_back(command);
 }
\ No newline at end of file
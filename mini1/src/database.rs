@@ -1,21 +1,139 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use crate::command::Command;
+use crate::utils::Frame;
+use crate::sorted_set::SortedSet;
+use crate::stream::Stream;
+use crate::bloom::ScalableBloom;
+use crate::cms::CountMinSketch;
+use crate::timeseries::TimeSeries;
+use crate::stream::now_ms;
 use std::fmt;
+use rand::Rng;
+
+// The limits under which Redis keeps values in its compact encodings
+const LISTPACK_MAX_ENTRIES: usize = 128;
+const LISTPACK_MAX_VALUE: usize = 64;
+const INTSET_MAX_ENTRIES: usize = 512;
+const EMBSTR_MAX_LENGTH: usize = 44;
+
+// The LFU counter of a new key, and how slowly the counter grows as it gets larger
+pub const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
 
 #[derive(Debug)]
 #[derive(Clone)]
 pub enum Data {
-    Scalar(String),
+    Scalar(Vec<u8>),
     List(VecDeque<String>),
+    Hash(HashMap<String, String>),
+    Set(HashSet<String>),
+    SortedSet(SortedSet),
+    Stream(Stream),
+    Json(serde_json::Value),
+    Bloom(ScalableBloom),
+    CountMinSketch(CountMinSketch),
+    TimeSeries(TimeSeries),
 }
 
 // Implement the Display trait for Data (Debugging purposes)
 impl fmt::Display for Data {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Data::Scalar(s) => write!(f, "{}", s),
+            Data::Scalar(s) => write!(f, "{}", String::from_utf8_lossy(s)),
             Data::List(l) => write!(f, "{:?}", l),
+            Data::Hash(h) => write!(f, "{:?}", h),
+            Data::Set(s) => write!(f, "{:?}", s),
+            Data::SortedSet(z) => write!(f, "{:?}", z),
+            Data::Stream(s) => write!(f, "{:?}", s.entries),
+            Data::Json(j) => write!(f, "{}", j),
+            Data::Bloom(b) => write!(f, "Bloom filter with {} items", b.count()),
+            Data::CountMinSketch(c) => write!(f, "Count-Min Sketch {}x{}", c.width, c.depth),
+            Data::TimeSeries(t) => write!(f, "{:?}", t.samples),
+        }
+    }
+}
+
+impl Data {
+    // The type name reported by TYPE
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Data::Scalar(_) => "string",
+            Data::List(_) => "list",
+            Data::Hash(_) => "hash",
+            Data::Set(_) => "set",
+            Data::SortedSet(_) => "zset",
+            Data::Stream(_) => "stream",
+            Data::Json(_) => "ReJSON-RL",
+            Data::Bloom(_) => "MBbloom--",
+            Data::CountMinSketch(_) => "CMSk-TYPE",
+            Data::TimeSeries(_) => "TSDB-TYPE",
+        }
+    }
+
+    // The encoding reported by OBJECT ENCODING. It is derived from the current value
+    // with the default Redis thresholds, since values are not stored in compact forms here.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Data::Scalar(s) if s.len() <= 20 && std::str::from_utf8(s).is_ok_and(|s| s.parse::<i64>().is_ok()) => "int",
+            Data::Scalar(s) if s.len() <= EMBSTR_MAX_LENGTH => "embstr",
+            Data::Scalar(_) => "raw",
+            Data::List(l) if small(l.len(), l.iter().map(String::len)) => "listpack",
+            Data::List(_) => "quicklist",
+            Data::Hash(h) if small(h.len(), h.iter().map(|(field, value)| field.len().max(value.len()))) => "listpack",
+            Data::Hash(_) => "hashtable",
+            Data::Set(s) if s.len() <= INTSET_MAX_ENTRIES && s.iter().all(|member| member.parse::<i64>().is_ok()) => "intset",
+            Data::Set(s) if small(s.len(), s.iter().map(String::len)) => "listpack",
+            Data::Set(_) => "hashtable",
+            Data::SortedSet(z) if small(z.len(), z.iter().map(|(member, _)| member.len())) => "listpack",
+            Data::SortedSet(_) => "skiplist",
+            Data::Stream(_) => "stream",
+            Data::Json(_) | Data::Bloom(_) | Data::CountMinSketch(_) | Data::TimeSeries(_) => "raw",
+        }
+    }
+}
+
+// Whether a collection is small enough for the listpack encoding, given its length and value sizes
+fn small(len: usize, mut value_sizes: impl Iterator<Item = usize>) -> bool {
+    len <= LISTPACK_MAX_ENTRIES && value_sizes.all(|size| size <= LISTPACK_MAX_VALUE)
+}
+
+// Access metadata kept for every key: when it was last accessed, and a logarithmic
+// access frequency counter that decays by one every minute, like Redis' LFU counter
+#[derive(Debug, Clone, Copy)]
+pub struct KeyMeta {
+    pub last_access_ms: u64,
+    pub lfu_counter: u8,
+    pub lfu_decay_minute: u64,
+}
+
+impl KeyMeta {
+    fn new() -> Self {
+        let now = now_ms();
+        Self { last_access_ms: now, lfu_counter: LFU_INIT_VAL, lfu_decay_minute: now / 60_000 }
+    }
+
+    // The counter after decaying it for the minutes since it was last decayed
+    pub fn decayed_counter(&self) -> u8 {
+        let elapsed = (now_ms() / 60_000).saturating_sub(self.lfu_decay_minute);
+        self.lfu_counter.saturating_sub(elapsed.min(u8::MAX as u64) as u8)
+    }
+
+    // Record an access: the counter grows with a probability that shrinks as it gets larger
+    fn access(&mut self) {
+        let now = now_ms();
+        let mut counter = self.decayed_counter();
+        if counter < u8::MAX {
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            if rand::thread_rng().gen::<f64>() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+                counter += 1;
+            }
         }
+        *self = Self { last_access_ms: now, lfu_counter: counter, lfu_decay_minute: now / 60_000 };
+    }
+
+    // Seconds since the key was last accessed
+    pub fn idle_seconds(&self) -> u64 {
+        now_ms().saturating_sub(self.last_access_ms) / 1000
     }
 }
 
@@ -23,15 +141,31 @@ impl fmt::Display for Data {
 #[derive(Clone)]
 pub struct Database {
     pub data: HashMap<String, Data>,
-    pub queue: VecDeque<Command>,
+    pub queue: VecDeque<(Command, Frame)>,
+    // Access metadata of the keys in data. Keys without an entry have not been accessed yet.
+    pub meta: HashMap<String, KeyMeta>,
 }
 
-#[allow(dead_code)]
 impl Database {
     pub fn new() -> Self {
         Self {
             data: HashMap::new(),
             queue: VecDeque::new(),
+            meta: HashMap::new(),
         }
     }
+
+    // Record an access to key, or forget its metadata if the key no longer exists
+    pub fn touch(&mut self, key: &str) {
+        if self.data.contains_key(key) {
+            self.meta.entry(key.to_string()).or_insert_with(KeyMeta::new).access();
+        } else {
+            self.meta.remove(key);
+        }
+    }
+
+    // The metadata of key, which is fresh for a key that has not been accessed yet
+    pub fn key_meta(&self, key: &str) -> KeyMeta {
+        self.meta.get(key).copied().unwrap_or_else(KeyMeta::new)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,56 @@
+use std::{collections::HashMap, mem, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+
+// Drop flushed values on a blocking task, so large databases are freed without holding up the server
+fn free_in_background(flushed: Vec<HashMap<String, Data>>) {
+    tokio::task::spawn_blocking(move || drop(flushed));
+}
+
+// DBSIZE command
+pub fn execute_dbsize_cmd(db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    Ok(format!(":{}\r\n", cur_db.data.len()))
+}
+
+// FLUSHDB and FLUSHALL commands. The keys are removed right away either way; with ASYNC
+// the memory they used is freed in the background.
+pub fn execute_flush_cmd(all: bool, asynchronous: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let flushed: Vec<HashMap<String, Data>> = {
+        let mut db = db.lock().unwrap();
+        if all {
+            db.values_mut().map(|database| {
+                database.meta.clear();
+                mem::take(&mut database.data)
+            }).collect()
+        } else {
+            let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+            cur_db.meta.clear();
+            vec![mem::take(&mut cur_db.data)]
+        }
+    };
+
+    if asynchronous {
+        free_in_background(flushed);
+    }
+    Ok("+OK\r\n".to_string())
+}
+
+// SWAPDB command. The keys and their metadata are swapped, while each database keeps its command queue.
+pub fn execute_swapdb_cmd(first: u8, second: u8, db: &Arc<Mutex<HashMap<u8, Database>>>) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    if first == second {
+        return Ok("+OK\r\n".to_string());
+    }
+
+    // Both databases are swapped under the same lock, so no client sees a half-swapped state
+    let first_db = db.entry(first).or_insert_with(Database::new);
+    let first_contents = (mem::take(&mut first_db.data), mem::take(&mut first_db.meta));
+    let second_db = db.entry(second).or_insert_with(Database::new);
+    let second_contents = (mem::replace(&mut second_db.data, first_contents.0), mem::replace(&mut second_db.meta, first_contents.1));
+    let first_db = db.entry(first).or_insert_with(Database::new);
+    (first_db.data, first_db.meta) = second_contents;
+    Ok("+OK\r\n".to_string())
+}
@@ -2,6 +2,12 @@ use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
 use tokio::time::{timeout, Duration};
 
 use crate::database::{Database, Data};
+use crate::utils::bulk_bytes;
+
+pub(crate) static WRONG_TYPE: &str = "Wrong Type Operation against a key holding the wrong kind of value";
+// The largest number of picks of HRANDFIELD and SRANDMEMBER with a negative count,
+// which may repeat members and so is not bounded by the size of the key
+pub(crate) const MAX_RANDOM_PICKS: u64 = 1 << 24;
 
 // SELECT command
 pub async fn execute_select_cmd(index: u8, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
@@ -13,27 +19,27 @@ pub async fn execute_select_cmd(index: u8, db: &Arc<Mutex<HashMap<u8, Database>>
     if db.contains_key(selected_db) {
         Ok("+OK\r\n".to_string())
     } else {
-        db.insert(*selected_db, Database { data: HashMap::new(), queue: VecDeque::new() });
+        db.insert(*selected_db, Database::new());
         Ok("+OK\r\n".to_string())
     }
 }
 
 // GET command
-pub fn execute_get_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+pub fn execute_get_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<Vec<u8>, &'static str> {
     let db = db.lock().unwrap();
     let cur_db = db.get(selected_db).ok_or("Database not found")?;
     println!("Current DB: {:?}", cur_db);
     println!("Key: {:?}", key);
 
     match cur_db.data.get(&key) {
-        Some(Data::Scalar(value)) => Ok(format!("+{}\r\n", value)),
-        Some(Data::List(_)) => Err("Wrong Type Operation against a key holding the wrong kind of value"),
-        None => Ok("$-1\r\n".to_string()), // RESP2 Null Bulk String for non-existent key
+        Some(Data::Scalar(value)) => Ok(bulk_bytes(value)), // Bulk string, since values may hold any bytes
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(b"$-1\r\n".to_vec()), // RESP2 Null Bulk String for non-existent key
     }
 }
 
 // SET command
-pub fn execute_set_cmd(key: String, value: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+pub fn execute_set_cmd(key: String, value: Vec<u8>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
     let mut db = db.lock().unwrap();
     let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
     println!("Current DB: {:?}", cur_db);
@@ -122,96 +128,102 @@ pub fn execute_lpush_cmd(key: String, values: Vec<String>, db: &Arc<Mutex<HashMa
     Ok(format!(":{}\r\n", list_len))
 }
 
-// BLPOP command
-pub async fn execute_blpop_cmd(keys: Vec<String>, timeout_duration: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
-    let timeout_duration = Duration::from_secs_f64(timeout_duration);
-
-    for key in &keys {
-        let mut db_lock = db.lock().unwrap();
-        let cur_db = db_lock.get_mut(selected_db).ok_or("Database not found")?;
-
-        if let Some(data) = cur_db.data.get_mut(key) {
-            if let Data::List(list) = data {
-                if let Some(value) = list.pop_front() {
-                    drop(db_lock);
-                    return Ok(format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, value.len(), value));
-                }
-            }
-        }
-        drop(db_lock);
+// Run attempt until it produces a reply, polling until the timeout expires, and return None
+// when it does. This is the blocking mechanism shared by the blocking commands. A timeout
+// of 0 blocks forever, like in Redis, except for BLPOP and BRPOP (see block_on_lists).
+pub(crate) async fn block_until<F>(timeout_duration: f64, mut attempt: F) -> Result<Option<String>, &'static str>
+where
+    F: FnMut() -> Result<Option<String>, &'static str>,
+{
+    if !timeout_duration.is_finite() || timeout_duration < 0.0 {
+        return Err("timeout is negative");
+    }
+    if let Some(response) = attempt()? {
+        return Ok(Some(response));
     }
 
-    let result = timeout(timeout_duration, async {
+    let poll = async {
         loop {
-            for key in &keys {
-                let mut db_lock = db.lock().unwrap();
-                let cur_db = db_lock.get_mut(selected_db).ok_or("Database not found")?;
-
-                if let Some(data) = cur_db.data.get_mut(key) {
-                    if let Data::List(list) = data {
-                        if !list.is_empty() {
-                            if let Some(value) = list.pop_front() {
-                                drop(db_lock);
-                                return Ok(format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, value.len(), value));
-                            }
-                        }
-                    }
-                }
-                drop(db_lock);
+            if let Some(response) = attempt()? {
+                return Ok(Some(response));
             }
             tokio::time::sleep(Duration::from_secs_f64(0.1)).await;
         }
-    }).await;
+    };
+    if timeout_duration == 0.0 {
+        return poll.await;
+    }
 
-    match result {
+    match timeout(Duration::from_secs_f64(timeout_duration), poll).await {
         Ok(response) => response,
-        Err(_) => Err("Timeout"),
+        Err(_) => Ok(None),
     }
 }
 
-// BRPOP command
-pub async fn execute_brpop_cmd(keys: Vec<String>, timeout_duration: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
-    let timeout_duration = Duration::from_secs_f64(timeout_duration);
-
-    for key in &keys {
-        let mut db_lock = db.lock().unwrap();
-        let cur_db = db_lock.get_mut(selected_db).ok_or("Database not found")?;
-
-        if let Some(data) = cur_db.data.get_mut(key) {
-            if let Data::List(list) = data {
-                if let Some(value) = list.pop_back() {
-                    drop(db_lock);
-                    return Ok(format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, value.len(), value));
-                }
+// Like block_until, replying with an error when the timeout expires, as the blocking pops do
+pub(crate) async fn block_until_ready<F>(timeout_duration: f64, attempt: F) -> Result<String, &'static str>
+where
+    F: FnMut() -> Result<Option<String>, &'static str>,
+{
+    block_until(timeout_duration, attempt).await?.ok_or("Timeout")
+}
+
+// Pop a value from the first non-empty list among keys, from the front or the back
+fn pop_from_lists(keys: &[String], front: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<Option<String>, &'static str> {
+    let mut db_lock = db.lock().unwrap();
+    let cur_db = db_lock.get_mut(selected_db).ok_or("Database not found")?;
+
+    for key in keys {
+        if let Some(Data::List(list)) = cur_db.data.get_mut(key) {
+            let value = if front { list.pop_front() } else { list.pop_back() };
+            if let Some(value) = value {
+                return Ok(Some(format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, value.len(), value)));
             }
         }
-        drop(db_lock);
     }
 
-    let result = timeout(timeout_duration, async {
-        loop {
-            for key in &keys {
-                let mut db_lock = db.lock().unwrap();
-                let cur_db = db_lock.get_mut(selected_db).ok_or("Database not found")?;
-
-                if let Some(data) = cur_db.data.get_mut(key) {
-                    if let Data::List(list) = data {
-                        if !list.is_empty() {
-                            if let Some(value) = list.pop_back() {
-                                drop(db_lock);
-                                return Ok(format!("*2\r\n${}\r\n{}\r\n${}\r\n{}\r\n", key.len(), key, value.len(), value));
-                            }
-                        }
-                    }
-                }
-                drop(db_lock);
-            }
-            tokio::time::sleep(Duration::from_secs_f64(0.1)).await;
-        }
-    }).await;
+    Ok(None)
+}
 
-    match result {
-        Ok(response) => response,
-        Err(_) => Err("Timeout"),
+// BLPOP and BRPOP keep their original timeout handling: the timeout is waited as given,
+// so 0 gives up after the first attempt instead of blocking forever like the later
+// blocking commands. Negative and non-finite timeouts are rejected up front.
+async fn block_on_lists(keys: &[String], front: bool, timeout_duration: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    if !timeout_duration.is_finite() || timeout_duration < 0.0 {
+        return Err("Invalid timeout");
+    }
+    if timeout_duration == 0.0 {
+        return pop_from_lists(keys, front, db, selected_db)?.ok_or("Timeout");
+    }
+    block_until_ready(timeout_duration, || pop_from_lists(keys, front, db, selected_db)).await
+}
+
+// BLPOP command
+pub async fn execute_blpop_cmd(keys: Vec<String>, timeout_duration: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    block_on_lists(&keys, true, timeout_duration, db, selected_db).await
+}
+
+// BRPOP command
+pub async fn execute_brpop_cmd(keys: Vec<String>, timeout_duration: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    block_on_lists(&keys, false, timeout_duration, db, selected_db).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn list_pops_keep_baseline_timeouts() {
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        let keys = vec!["l".to_string()];
+
+        assert_eq!(execute_blpop_cmd(keys.clone(), 0.0, &db, &mut 0).await, Err("Timeout"));
+        assert_eq!(execute_brpop_cmd(keys.clone(), 0.05, &db, &mut 0).await, Err("Timeout"));
+        assert_eq!(execute_blpop_cmd(keys.clone(), -1.0, &db, &mut 0).await, Err("Invalid timeout"));
+        assert_eq!(execute_brpop_cmd(keys.clone(), f64::NAN, &db, &mut 0).await, Err("Invalid timeout"));
+
+        execute_rpush_cmd("l".to_string(), vec!["a".to_string(), "b".to_string()], &db, &mut 0).unwrap();
+        assert_eq!(execute_blpop_cmd(keys.clone(), 0.0, &db, &mut 0).await, Ok("*2\r\n$1\r\nl\r\n$1\r\na\r\n".to_string()));
+        assert_eq!(execute_brpop_cmd(keys, 0.0, &db, &mut 0).await, Ok("*2\r\n$1\r\nl\r\n$1\r\nb\r\n".to_string()));
     }
-}
\ No newline at end of file
+}
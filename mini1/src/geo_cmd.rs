@@ -0,0 +1,341 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::database::Database;
+use crate::geohash;
+use crate::sorted_set::{ScoreBound, SortedSet};
+use crate::utils::{bulk_string, format_float, optional_bulk_string};
+use crate::zset_cmd::{execute_zadd_cmd, get_zset, store_zset, ZaddOptions};
+
+// Where GEOSEARCH measures from
+#[derive(Debug, Clone)]
+pub enum GeoOrigin {
+    Member(String),
+    LonLat(f64, f64),
+}
+
+// The area GEOSEARCH covers, in meters
+#[derive(Debug, Clone, Copy)]
+pub enum GeoShape {
+    Radius(f64),
+    Box(f64, f64),
+}
+
+// The order of GEOSEARCH results by distance
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeoSort {
+    Unsorted,
+    Asc,
+    Desc,
+}
+
+// The arguments of GEOSEARCH and GEOSEARCHSTORE after the keys
+#[derive(Debug, Clone)]
+pub struct GeoSearchOptions {
+    pub origin: GeoOrigin,
+    pub shape: GeoShape,
+    pub unit: f64,
+    pub sort: GeoSort,
+    pub count: Option<(usize, bool)>,
+    pub with_coord: bool,
+    pub with_dist: bool,
+    pub with_hash: bool,
+    pub store_dist: bool,
+}
+
+// A member found by GEOSEARCH, with its distance in meters
+struct GeoMatch {
+    member: String,
+    score: f64,
+    distance: f64,
+}
+
+// Parse a distance unit, returning the number of meters it stands for
+pub fn parse_unit(unit: &str) -> Result<f64, &'static str> {
+    match unit.to_lowercase().as_str() {
+        "m" => Ok(1.0),
+        "km" => Ok(1000.0),
+        "ft" => Ok(0.3048),
+        "mi" => Ok(1609.34),
+        _ => Err("unsupported unit provided. please use M, KM, FT, MI"),
+    }
+}
+
+fn parse_float(value: &str) -> Result<f64, &'static str> {
+    match value.parse::<f64>() {
+        Ok(value) if !value.is_nan() => Ok(value),
+        _ => Err("value is not a valid float"),
+    }
+}
+
+// Parse a longitude and latitude, rejecting positions that cannot be indexed
+fn parse_position(longitude: &str, latitude: &str) -> Result<(f64, f64), &'static str> {
+    let (longitude, latitude) = (parse_float(longitude)?, parse_float(latitude)?);
+    geohash::validate(longitude, latitude)?;
+    Ok((longitude, latitude))
+}
+
+// The parsed flags and (longitude, latitude, member) triples of a GEOADD command
+type GeoaddArgs = (ZaddOptions, Vec<(f64, f64, String)>);
+
+// Parse GEOADD arguments after the key: [NX|XX] [CH] longitude latitude member [...]
+pub fn parse_geoadd_args(args: &[String]) -> Result<GeoaddArgs, &'static str> {
+    let mut options = ZaddOptions::default();
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        match arg.to_uppercase().as_str() {
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "CH" => options.ch = true,
+            _ => break,
+        }
+        index += 1;
+    }
+    if options.nx && options.xx {
+        return Err("XX and NX options at the same time are not compatible");
+    }
+
+    let rest = &args[index..];
+    if rest.is_empty() || !rest.len().is_multiple_of(3) {
+        return Err("Syntax error. Usage: GEOADD <key> [NX|XX] [CH] <longitude> <latitude> <member> [<longitude> <latitude> <member> ...]");
+    }
+    let mut positions = Vec::with_capacity(rest.len() / 3);
+    for triple in rest.chunks(3) {
+        let (longitude, latitude) = parse_position(&triple[0], &triple[1])?;
+        positions.push((longitude, latitude, triple[2].clone()));
+    }
+    Ok((options, positions))
+}
+
+// Parse GEOSEARCH arguments after the key. GEOSEARCHSTORE accepts STOREDIST instead of the WITH flags.
+pub fn parse_geosearch_args(args: &[String], store: bool) -> Result<GeoSearchOptions, &'static str> {
+    let mut origin = None;
+    let mut shape = None;
+    let mut unit = 1.0;
+    let mut sort = GeoSort::Unsorted;
+    let mut count = None;
+    let (mut with_coord, mut with_dist, mut with_hash, mut store_dist) = (false, false, false, false);
+
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        let arg = arg.to_uppercase();
+        let remaining = args.len() - index - 1;
+        match arg.as_str() {
+            "FROMMEMBER" if remaining >= 1 => {
+                if origin.is_some() { return Err("exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"); }
+                origin = Some(GeoOrigin::Member(args[index + 1].clone()));
+                index += 1;
+            }
+            "FROMLONLAT" if remaining >= 2 => {
+                if origin.is_some() { return Err("exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"); }
+                let (longitude, latitude) = parse_position(&args[index + 1], &args[index + 2])?;
+                origin = Some(GeoOrigin::LonLat(longitude, latitude));
+                index += 2;
+            }
+            "BYRADIUS" if remaining >= 2 => {
+                if shape.is_some() { return Err("exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"); }
+                let radius = parse_float(&args[index + 1]).map_err(|_| "need numeric radius")?;
+                if radius < 0.0 { return Err("radius cannot be negative"); }
+                unit = parse_unit(&args[index + 2])?;
+                shape = Some(GeoShape::Radius(radius * unit));
+                index += 2;
+            }
+            "BYBOX" if remaining >= 3 => {
+                if shape.is_some() { return Err("exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"); }
+                let width = parse_float(&args[index + 1]).map_err(|_| "need numeric width")?;
+                let height = parse_float(&args[index + 2]).map_err(|_| "need numeric height")?;
+                if width < 0.0 || height < 0.0 { return Err("height or width cannot be negative"); }
+                unit = parse_unit(&args[index + 3])?;
+                shape = Some(GeoShape::Box(width * unit, height * unit));
+                index += 3;
+            }
+            "ASC" => sort = GeoSort::Asc,
+            "DESC" => sort = GeoSort::Desc,
+            "COUNT" if remaining >= 1 => {
+                let limit: i64 = args[index + 1].parse().map_err(|_| "value is not an integer or out of range")?;
+                if limit <= 0 { return Err("COUNT must be > 0"); }
+                let any = args.get(index + 2).is_some_and(|arg| arg.eq_ignore_ascii_case("ANY"));
+                count = Some((limit as usize, any));
+                index += if any { 2 } else { 1 };
+            }
+            "WITHCOORD" if !store => with_coord = true,
+            "WITHDIST" if !store => with_dist = true,
+            "WITHHASH" if !store => with_hash = true,
+            "STOREDIST" if store => store_dist = true,
+            "ANY" => return Err("the ANY argument requires COUNT argument"),
+            _ => return Err("syntax error"),
+        }
+        index += 1;
+    }
+
+    let origin = origin.ok_or("exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH")?;
+    let shape = shape.ok_or("exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH")?;
+
+    // A COUNT without ANY returns the closest matches
+    if sort == GeoSort::Unsorted && matches!(count, Some((_, false))) {
+        sort = GeoSort::Asc;
+    }
+
+    Ok(GeoSearchOptions { origin, shape, unit, sort, count, with_coord, with_dist, with_hash, store_dist })
+}
+
+// Find the members inside the search area, in the order GEOSEARCH replies with them
+fn geo_search(zset: &SortedSet, options: &GeoSearchOptions) -> Result<Vec<GeoMatch>, &'static str> {
+    let (longitude, latitude) = match &options.origin {
+        GeoOrigin::LonLat(longitude, latitude) => (*longitude, *latitude),
+        GeoOrigin::Member(member) => geohash::decode(zset.score(member).ok_or("could not decode requested zset member")?),
+    };
+    let (half_width, half_height) = match options.shape {
+        GeoShape::Radius(radius) => (radius, radius),
+        GeoShape::Box(width, height) => (width / 2.0, height / 2.0),
+    };
+
+    // Only the geohash cells around the origin are scanned, then each candidate is checked exactly
+    let mut matches = Vec::new();
+    'cells: for (min, max) in geohash::search_ranges(longitude, latitude, half_width, half_height) {
+        let min = ScoreBound { value: min, exclusive: false };
+        let max = ScoreBound { value: max, exclusive: true };
+        for (member, score) in zset.range_by_score(&min, &max) {
+            let (member_longitude, member_latitude) = geohash::decode(score);
+            let distance = match options.shape {
+                GeoShape::Radius(radius) => Some(geohash::distance(longitude, latitude, member_longitude, member_latitude)).filter(|distance| *distance <= radius),
+                GeoShape::Box(width, height) => geohash::distance_if_in_box(width, height, longitude, latitude, member_longitude, member_latitude),
+            };
+            if let Some(distance) = distance {
+                matches.push(GeoMatch { member: member.clone(), score, distance });
+                // With ANY the search stops as soon as enough matches were found
+                if matches!(options.count, Some((count, true)) if matches.len() >= count) {
+                    break 'cells;
+                }
+            }
+        }
+    }
+
+    match options.sort {
+        GeoSort::Asc => matches.sort_by(|a, b| a.distance.total_cmp(&b.distance)),
+        GeoSort::Desc => matches.sort_by(|a, b| b.distance.total_cmp(&a.distance)),
+        GeoSort::Unsorted => {}
+    }
+    if let Some((count, _)) = options.count {
+        matches.truncate(count);
+    }
+    Ok(matches)
+}
+
+// Format a distance in the given unit, with the four decimals Redis uses
+fn format_distance(meters: f64, unit: f64) -> String {
+    format!("{:.4}", meters / unit)
+}
+
+// Encode a longitude/latitude pair as a RESP array
+fn position_array(score: f64) -> String {
+    let (longitude, latitude) = geohash::decode(score);
+    format!("*2\r\n{}{}", bulk_string(&format_float(longitude)), bulk_string(&format_float(latitude)))
+}
+
+// GEOADD command
+pub fn execute_geoadd_cmd(key: String, options: ZaddOptions, positions: Vec<(f64, f64, String)>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let pairs = positions.into_iter()
+        .map(|(longitude, latitude, member)| (geohash::encode(longitude, latitude) as f64, member))
+        .collect();
+    execute_zadd_cmd(key, options, pairs, db, selected_db)
+}
+
+// GEOPOS command
+pub fn execute_geopos_cmd(key: String, members: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let zset = get_zset(cur_db, &key)?;
+
+    let mut response = format!("*{}\r\n", members.len());
+    for member in &members {
+        response += &match zset.and_then(|zset| zset.score(member)) {
+            Some(score) => position_array(score),
+            None => "*-1\r\n".to_string(),
+        };
+    }
+
+    Ok(response)
+}
+
+// GEODIST command
+pub fn execute_geodist_cmd(key: String, member1: String, member2: String, unit: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let zset = get_zset(cur_db, &key)?;
+
+    let scores = zset.and_then(|zset| Some((zset.score(&member1)?, zset.score(&member2)?)));
+    let distance = scores.map(|(score1, score2)| {
+        let ((lon1, lat1), (lon2, lat2)) = (geohash::decode(score1), geohash::decode(score2));
+        format_distance(geohash::distance(lon1, lat1, lon2, lat2), unit)
+    });
+
+    Ok(optional_bulk_string(distance.as_deref()))
+}
+
+// GEOHASH command
+pub fn execute_geohash_cmd(key: String, members: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let zset = get_zset(cur_db, &key)?;
+
+    let mut response = format!("*{}\r\n", members.len());
+    for member in &members {
+        let hash = zset.and_then(|zset| zset.score(member)).map(geohash::hash_string);
+        response += &optional_bulk_string(hash.as_deref());
+    }
+
+    Ok(response)
+}
+
+// GEOSEARCH command
+pub fn execute_geosearch_cmd(key: String, options: GeoSearchOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let matches = match get_zset(cur_db, &key)? {
+        Some(zset) => geo_search(zset, &options)?,
+        None => Vec::new(),
+    };
+
+    let extras = options.with_dist as usize + options.with_hash as usize + options.with_coord as usize;
+    let mut response = format!("*{}\r\n", matches.len());
+    for found in &matches {
+        if extras == 0 {
+            response += &bulk_string(&found.member);
+            continue;
+        }
+        response += &format!("*{}\r\n", extras + 1);
+        response += &bulk_string(&found.member);
+        if options.with_dist {
+            response += &bulk_string(&format_distance(found.distance, options.unit));
+        }
+        if options.with_hash {
+            response += &format!(":{}\r\n", found.score as u64);
+        }
+        if options.with_coord {
+            response += &position_array(found.score);
+        }
+    }
+
+    Ok(response)
+}
+
+// GEOSEARCHSTORE command
+pub fn execute_geosearchstore_cmd(destination: String, source: String, options: GeoSearchOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let matches = match get_zset(cur_db, &source)? {
+        Some(zset) => geo_search(zset, &options)?,
+        None => Vec::new(),
+    };
+
+    // STOREDIST stores the distances in the requested unit instead of the positions
+    let mut result = SortedSet::new();
+    for found in matches {
+        let score = if options.store_dist { found.distance / options.unit } else { found.score };
+        result.insert(found.member, score);
+    }
+    let len = result.len();
+    store_zset(cur_db, destination, result);
+
+    Ok(format!(":{}\r\n", len))
+}
@@ -0,0 +1,181 @@
+// Geohash helpers for the geo commands. Positions are stored in sorted sets
+// as 52-bit geohash scores: 26 bits of longitude and 26 bits of latitude,
+// interleaved, with the same ranges and Earth model as Redis.
+
+pub const GEO_STEP_MAX: u32 = 26;
+const GEO_LONG_MIN: f64 = -180.0;
+const GEO_LONG_MAX: f64 = 180.0;
+const GEO_LAT_MIN: f64 = -85.051_128_78;
+const GEO_LAT_MAX: f64 = 85.051_128_78;
+const EARTH_RADIUS_IN_METERS: f64 = 6_372_797.560_856;
+const MERCATOR_MAX: f64 = 20_037_726.37;
+const GEO_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+// Check that a position can be indexed
+pub fn validate(longitude: f64, latitude: f64) -> Result<(), &'static str> {
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude) || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude) {
+        return Err("invalid longitude,latitude pair");
+    }
+    Ok(())
+}
+
+// Spread the low 32 bits of x over the even bit positions
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+// Collect the even bit positions of x into the low 32 bits
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    ((x | (x >> 16)) & 0x0000_0000_ffff_ffff) as u32
+}
+
+// Latitude bits go to the even positions and longitude bits to the odd ones
+fn interleave(lat_bits: u32, long_bits: u32) -> u64 {
+    spread(lat_bits) | (spread(long_bits) << 1)
+}
+
+// The cell index of a value within [min, max] split into 2^step cells
+fn cell_index(value: f64, min: f64, max: f64, step: u32) -> u32 {
+    let offset = (value - min) / (max - min) * (1u64 << step) as f64;
+    (offset as u64).min((1u64 << step) - 1) as u32
+}
+
+// Encode a position with the given latitude range, as used for scores and for GEOHASH strings
+fn encode_with_lat_range(longitude: f64, latitude: f64, lat_min: f64, lat_max: f64) -> u64 {
+    let lat_bits = cell_index(latitude, lat_min, lat_max, GEO_STEP_MAX);
+    let long_bits = cell_index(longitude, GEO_LONG_MIN, GEO_LONG_MAX, GEO_STEP_MAX);
+    interleave(lat_bits, long_bits)
+}
+
+// The 52-bit score of a position
+pub fn encode(longitude: f64, latitude: f64) -> u64 {
+    encode_with_lat_range(longitude, latitude, GEO_LAT_MIN, GEO_LAT_MAX)
+}
+
+// The center of the cell a score describes, as (longitude, latitude)
+pub fn decode(score: f64) -> (f64, f64) {
+    let bits = score as u64;
+    let lat_bits = squash(bits);
+    let long_bits = squash(bits >> 1);
+    let cells = (1u64 << GEO_STEP_MAX) as f64;
+
+    let cell_center = |index: u32, min: f64, max: f64| {
+        let low = min + (index as f64 / cells) * (max - min);
+        let high = min + ((index as f64 + 1.0) / cells) * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    (cell_center(long_bits, GEO_LONG_MIN, GEO_LONG_MAX), cell_center(lat_bits, GEO_LAT_MIN, GEO_LAT_MAX))
+}
+
+// The standard 11 character geohash string of a score. Standard geohashes use
+// latitudes up to +/-90, so the position is re-encoded first.
+pub fn hash_string(score: f64) -> String {
+    let (longitude, latitude) = decode(score);
+    let bits = encode_with_lat_range(longitude, latitude, -90.0, 90.0);
+    (0..11).map(|i| {
+        // Only 52 bits are available, so the last character is always '0'
+        let index = if i == 10 { 0 } else { (bits >> (52 - (i + 1) * 5)) & 0x1f };
+        GEO_ALPHABET[index as usize] as char
+    }).collect()
+}
+
+// Great-circle distance in meters, using the haversine formula
+pub fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+// The distance to a point if it lies within a width x height box (in meters) centered on (lon1, lat1)
+pub fn distance_if_in_box(width: f64, height: f64, lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> Option<f64> {
+    let lat_distance = EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs();
+    if lat_distance > height / 2.0 {
+        return None;
+    }
+    if distance(lon2, lat2, lon1, lat2) > width / 2.0 {
+        return None;
+    }
+    Some(distance(lon1, lat1, lon2, lat2))
+}
+
+// The coarsest precision whose cells are still about as large as the search radius
+fn estimate_step(radius: f64, latitude: f64) -> u32 {
+    if radius == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut radius = radius;
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    // Cells get narrower towards the poles
+    if latitude.abs() > 66.0 {
+        step -= 1;
+        if latitude.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u32
+}
+
+// Score ranges [min, max) covering every position within half_width and
+// half_height meters of the center. Each range is one geohash cell.
+pub fn search_ranges(longitude: f64, latitude: f64, half_width: f64, half_height: f64) -> Vec<(f64, f64)> {
+    // The bounding box of the search area in degrees
+    let lat_delta = (half_height / EARTH_RADIUS_IN_METERS).to_degrees();
+    let widest_lat = if latitude < 0.0 { latitude - lat_delta } else { latitude + lat_delta };
+    // Boxes reaching over a pole span every longitude
+    let long_delta = if widest_lat.abs() >= 90.0 {
+        f64::INFINITY
+    } else {
+        (half_width / EARTH_RADIUS_IN_METERS / widest_lat.to_radians().cos()).to_degrees()
+    };
+    let (lat_low, lat_high) = ((latitude - lat_delta).max(GEO_LAT_MIN), (latitude + lat_delta).min(GEO_LAT_MAX));
+
+    let mut step = estimate_step(half_width.max(half_height), latitude);
+    loop {
+        let cells = 1i64 << step;
+        let lat_cells = cell_index(lat_low, GEO_LAT_MIN, GEO_LAT_MAX, step)..=cell_index(lat_high, GEO_LAT_MIN, GEO_LAT_MAX, step);
+
+        // Longitudes wrap around the antimeridian
+        let long_cells: Vec<u32> = if !long_delta.is_finite() || long_delta >= 180.0 {
+            (0..cells as u32).collect()
+        } else {
+            let cell_width = (GEO_LONG_MAX - GEO_LONG_MIN) / cells as f64;
+            let first = ((longitude - long_delta - GEO_LONG_MIN) / cell_width).floor() as i64;
+            let last = ((longitude + long_delta - GEO_LONG_MIN) / cell_width).floor() as i64;
+            let last = last.min(first + cells - 1);
+            let mut indexes: Vec<u32> = (first..=last).map(|index| index.rem_euclid(cells) as u32).collect();
+            indexes.sort_unstable();
+            indexes.dedup();
+            indexes
+        };
+
+        // Keep the number of ranges small by falling back to coarser cells
+        if lat_cells.clone().count() * long_cells.len() > 16 && step > 1 {
+            step -= 1;
+            continue;
+        }
+
+        let shift = 2 * (GEO_STEP_MAX - step);
+        let mut ranges: Vec<(f64, f64)> = lat_cells
+            .flat_map(|lat_cell| long_cells.iter().map(move |long_cell| interleave(lat_cell, *long_cell)))
+            .map(|cell| ((cell << shift) as f64, ((cell + 1) << shift) as f64))
+            .collect();
+        ranges.sort_by(|a, b| a.0.total_cmp(&b.0));
+        return ranges;
+    }
+}
@@ -0,0 +1,282 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+use rand::{seq::IteratorRandom, seq::SliceRandom, thread_rng};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::{MAX_RANDOM_PICKS, WRONG_TYPE};
+use crate::keyspace_cmd::{scan_page, scan_reply, ScanOptions};
+use crate::utils::{bulk_array, bulk_string, format_float, optional_bulk_string};
+
+// Look up the hash stored at key, failing if the key holds another type
+fn get_hash<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a HashMap<String, String>>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Hash(hash)) => Ok(Some(hash)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the hash stored at key, creating an empty one if the key does not exist
+fn get_or_create_hash<'a>(cur_db: &'a mut Database, key: &str) -> Result<&'a mut HashMap<String, String>, &'static str> {
+    match cur_db.data.entry(key.to_string()).or_insert_with(|| Data::Hash(HashMap::new())) {
+        Data::Hash(hash) => Ok(hash),
+        _ => Err(WRONG_TYPE),
+    }
+}
+
+// HSET command
+pub fn execute_hset_cmd(key: String, pairs: Vec<(String, String)>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let hash = get_or_create_hash(cur_db, &key)?;
+
+    let mut added = 0;
+    for (field, value) in pairs {
+        if hash.insert(field, value).is_none() {
+            added += 1;
+        }
+    }
+
+    Ok(format!(":{}\r\n", added))
+}
+
+// HSETNX command
+pub fn execute_hsetnx_cmd(key: String, field: String, value: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let hash = get_or_create_hash(cur_db, &key)?;
+
+    if hash.contains_key(&field) {
+        return Ok(":0\r\n".to_string());
+    }
+    hash.insert(field, value);
+
+    Ok(":1\r\n".to_string())
+}
+
+// HGET command
+pub fn execute_hget_cmd(key: String, field: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let value = get_hash(cur_db, &key)?.and_then(|hash| hash.get(&field));
+
+    Ok(optional_bulk_string(value.map(|v| v.as_str())))
+}
+
+// HMGET command
+pub fn execute_hmget_cmd(key: String, fields: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let hash = get_hash(cur_db, &key)?;
+
+    let mut response = format!("*{}\r\n", fields.len());
+    for field in &fields {
+        let value = hash.and_then(|hash| hash.get(field));
+        response += &optional_bulk_string(value.map(|v| v.as_str()));
+    }
+
+    Ok(response)
+}
+
+// HGETALL command
+pub fn execute_hgetall_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    let mut items = Vec::new();
+    if let Some(hash) = get_hash(cur_db, &key)? {
+        for (field, value) in hash {
+            items.push(field.as_str());
+            items.push(value.as_str());
+        }
+    }
+
+    Ok(bulk_array(&items))
+}
+
+// HDEL command
+pub fn execute_hdel_cmd(key: String, fields: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let (removed, now_empty) = match cur_db.data.get_mut(&key) {
+        Some(Data::Hash(hash)) => {
+            let removed = fields.iter().filter(|field| hash.remove(*field).is_some()).count();
+            (removed, hash.is_empty())
+        }
+        Some(_) => return Err(WRONG_TYPE),
+        None => (0, false),
+    };
+
+    // An empty hash is removed from the keyspace, like in Redis
+    if now_empty {
+        cur_db.data.remove(&key);
+    }
+
+    Ok(format!(":{}\r\n", removed))
+}
+
+// HEXISTS command
+pub fn execute_hexists_cmd(key: String, field: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let exists = get_hash(cur_db, &key)?.is_some_and(|hash| hash.contains_key(&field));
+
+    Ok(format!(":{}\r\n", exists as u8))
+}
+
+// HLEN command
+pub fn execute_hlen_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let len = get_hash(cur_db, &key)?.map_or(0, |hash| hash.len());
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// HKEYS command
+pub fn execute_hkeys_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let fields: Vec<&String> = get_hash(cur_db, &key)?.map_or(Vec::new(), |hash| hash.keys().collect());
+
+    Ok(bulk_array(&fields))
+}
+
+// HVALS command
+pub fn execute_hvals_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let values: Vec<&String> = get_hash(cur_db, &key)?.map_or(Vec::new(), |hash| hash.values().collect());
+
+    Ok(bulk_array(&values))
+}
+
+// HSTRLEN command
+pub fn execute_hstrlen_cmd(key: String, field: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let len = get_hash(cur_db, &key)?.and_then(|hash| hash.get(&field)).map_or(0, |value| value.len());
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// HINCRBY command
+pub fn execute_hincrby_cmd(key: String, field: String, increment: i64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    // Validate before creating the hash, so a failure leaves a missing key absent
+    let current: i64 = match get_hash(cur_db, &key)?.and_then(|hash| hash.get(&field)) {
+        Some(value) => value.parse().map_err(|_| "hash value is not an integer")?,
+        None => 0,
+    };
+    let updated = current.checked_add(increment).ok_or("increment or decrement would overflow")?;
+    get_or_create_hash(cur_db, &key)?.insert(field, updated.to_string());
+
+    Ok(format!(":{}\r\n", updated))
+}
+
+// HINCRBYFLOAT command
+pub fn execute_hincrbyfloat_cmd(key: String, field: String, increment: f64, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let current: f64 = match get_hash(cur_db, &key)?.and_then(|hash| hash.get(&field)) {
+        Some(value) => value.parse().map_err(|_| "hash value is not a float")?,
+        None => 0.0,
+    };
+    let updated = current + increment;
+    if !updated.is_finite() {
+        return Err("increment would produce NaN or Infinity");
+    }
+    let updated = format_float(updated);
+    get_or_create_hash(cur_db, &key)?.insert(field, updated.clone());
+
+    Ok(bulk_string(&updated))
+}
+
+// HRANDFIELD command
+pub fn execute_hrandfield_cmd(key: String, count: Option<i64>, with_values: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let hash = get_hash(cur_db, &key)?;
+    let mut rng = thread_rng();
+
+    // Without a count, reply with a single field (or nil for a missing key)
+    let count = match count {
+        Some(count) => count,
+        None => {
+            let field = hash.and_then(|hash| hash.keys().choose(&mut rng));
+            return Ok(optional_bulk_string(field.map(|f| f.as_str())));
+        }
+    };
+    if count < 0 && count.unsigned_abs() > MAX_RANDOM_PICKS {
+        return Err("value is out of range");
+    }
+
+    let entries: Vec<(&String, &String)> = hash.map_or(Vec::new(), |hash| hash.iter().collect());
+    let picked: Vec<&(&String, &String)> = if entries.is_empty() {
+        Vec::new()
+    } else if count >= 0 {
+        // A positive count returns distinct fields
+        entries.choose_multiple(&mut rng, count as usize).collect()
+    } else {
+        // A negative count may return the same field several times
+        (0..count.unsigned_abs()).map(|_| entries.choose(&mut rng).unwrap()).collect()
+    };
+
+    let mut items = Vec::new();
+    for (field, value) in picked {
+        items.push(field.as_str());
+        if with_values {
+            items.push(value.as_str());
+        }
+    }
+
+    Ok(bulk_array(&items))
+}
+
+// HSCAN command
+pub fn execute_hscan_cmd(key: String, cursor: u64, options: ScanOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let hash = match get_hash(cur_db, &key)? {
+        Some(hash) => hash,
+        None => return Ok(scan_reply(0, bulk_array::<&str>(&[]))),
+    };
+
+    let (next_cursor, page) = scan_page(hash.iter(), cursor, options.count);
+    let pairs: Vec<&String> = page.into_iter()
+        .filter(|(field, _)| options.matches(field))
+        .flat_map(|(field, value)| [field, value])
+        .collect();
+    Ok(scan_reply(next_cursor, bulk_array(&pairs)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hrandfield_bounds_negative_count() {
+        let hash = HashMap::from([("f".to_string(), "v".to_string())]);
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        db.lock().unwrap().get_mut(&0).unwrap().data.insert("h".to_string(), Data::Hash(hash));
+
+        assert_eq!(execute_hrandfield_cmd("h".to_string(), Some(-3), false, &db, &mut 0), Ok(bulk_array(&["f", "f", "f"])));
+        assert_eq!(execute_hrandfield_cmd("h".to_string(), Some(i64::MIN), true, &db, &mut 0), Err("value is out of range"));
+        assert_eq!(execute_hrandfield_cmd("h".to_string(), Some(i64::MAX), false, &db, &mut 0), Ok(bulk_array(&["f"])));
+    }
+
+    #[test]
+    fn hincrby_errors_leave_missing_keys_absent() {
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+
+        assert_eq!(execute_hincrbyfloat_cmd("h".to_string(), "f".to_string(), f64::INFINITY, &db, &mut 0), Err("increment would produce NaN or Infinity"));
+        assert!(!db.lock().unwrap()[&0].data.contains_key("h"));
+
+        assert_eq!(execute_hincrby_cmd("h".to_string(), "f".to_string(), 2, &db, &mut 0), Ok(":2\r\n".to_string()));
+        assert_eq!(execute_hincrby_cmd("h".to_string(), "f".to_string(), i64::MAX, &db, &mut 0), Err("increment or decrement would overflow"));
+        assert_eq!(execute_hget_cmd("h".to_string(), "f".to_string(), &db, &mut 0), Ok(bulk_string("2")));
+    }
+}
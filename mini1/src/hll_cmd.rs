@@ -0,0 +1,109 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+use crate::hyperloglog::{self, HLL_REGISTERS};
+
+// Look up the HyperLogLog stored at key, failing if the key holds anything else
+fn get_hll<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a Vec<u8>>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Scalar(hll)) => {
+            hyperloglog::validate(hll)?;
+            Ok(Some(hll))
+        }
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the HyperLogLog stored at key, creating an empty one if the key does not exist.
+// The flag tells whether it was created.
+fn get_or_create_hll<'a>(cur_db: &'a mut Database, key: &str) -> Result<(&'a mut Vec<u8>, bool), &'static str> {
+    get_hll(cur_db, key)?;
+    let created = !cur_db.data.contains_key(key);
+    match cur_db.data.entry(key.to_string()).or_insert_with(|| Data::Scalar(hyperloglog::new_hll())) {
+        Data::Scalar(hll) => Ok((hll, created)),
+        _ => Err(WRONG_TYPE),
+    }
+}
+
+// PFADD command
+pub fn execute_pfadd_cmd(key: String, elements: Vec<Vec<u8>>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let (hll, mut updated) = get_or_create_hll(cur_db, &key)?;
+
+    for element in &elements {
+        updated |= hyperloglog::add(hll, element)?;
+    }
+    if updated {
+        hyperloglog::invalidate_cache(hll);
+    }
+
+    Ok(format!(":{}\r\n", updated as u8))
+}
+
+// PFCOUNT command
+pub fn execute_pfcount_cmd(keys: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    // Several keys are counted as their union, without touching the cached cardinalities
+    if keys.len() > 1 {
+        let mut registers = vec![0; HLL_REGISTERS];
+        for key in &keys {
+            if let Some(hll) = get_hll(cur_db, key)? {
+                hyperloglog::merge_registers(&mut registers, hll)?;
+            }
+        }
+        return Ok(format!(":{}\r\n", hyperloglog::estimate(&registers)));
+    }
+
+    get_hll(cur_db, &keys[0])?;
+    let hll = match cur_db.data.get_mut(&keys[0]) {
+        Some(Data::Scalar(hll)) => hll,
+        _ => return Ok(":0\r\n".to_string()),
+    };
+
+    // A single key reuses its cached cardinality, or refreshes it
+    let cardinality = match hyperloglog::cached_cardinality(hll) {
+        Some(cardinality) => cardinality,
+        None => {
+            let cardinality = hyperloglog::estimate(&hyperloglog::registers(hll)?);
+            hyperloglog::set_cached_cardinality(hll, cardinality);
+            cardinality
+        }
+    };
+
+    Ok(format!(":{}\r\n", cardinality))
+}
+
+// PFMERGE command
+pub fn execute_pfmerge_cmd(destination: String, sources: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    // The destination takes part in the union when it exists
+    let mut registers = vec![0; HLL_REGISTERS];
+    let mut use_dense = false;
+    for key in std::iter::once(&destination).chain(&sources) {
+        if let Some(hll) = get_hll(cur_db, key)? {
+            use_dense |= hyperloglog::is_dense(hll);
+            hyperloglog::merge_registers(&mut registers, hll)?;
+        }
+    }
+
+    let (hll, _) = get_or_create_hll(cur_db, &destination)?;
+    for (index, count) in registers.into_iter().enumerate() {
+        if count > 0 {
+            hyperloglog::set_register(hll, index, count)?;
+        }
+    }
+    // The result is dense if any input was
+    if use_dense {
+        hyperloglog::make_dense(hll)?;
+    }
+    hyperloglog::invalidate_cache(hll);
+
+    Ok("+OK\r\n".to_string())
+}
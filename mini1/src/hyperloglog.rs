@@ -0,0 +1,488 @@
+// HyperLogLog values use the same string layout as Redis, so they can be
+// exchanged with real Redis servers. A 16 byte header ("HYLL", the encoding,
+// three unused bytes and a little-endian cardinality cache whose top bit marks
+// it as stale) is followed by 16384 6-bit registers, either packed (dense) or
+// run-length encoded (sparse).
+
+const HLL_P: u32 = 14;
+const HLL_Q: u32 = 64 - HLL_P;
+pub const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_P_MASK: u64 = HLL_REGISTERS as u64 - 1;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HDR_SIZE: usize = 16;
+const HLL_DENSE_SIZE: usize = HLL_HDR_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+// Sparse opcodes: ZERO (00xxxxxx), XZERO (01xxxxxx yyyyyyyy) and VAL (1vvvvvxx)
+const SPARSE_VAL_MAX_VALUE: u8 = 32;
+const SPARSE_VAL_MAX_LEN: usize = 4;
+const SPARSE_ZERO_MAX_LEN: usize = 64;
+const SPARSE_XZERO_MAX_LEN: usize = 16384;
+
+// A sparse value is converted to dense once it grows past this size (hll-sparse-max-bytes)
+const SPARSE_MAX_BYTES: usize = 3000;
+
+pub const INVALID_HLL: &str = "WRONGTYPE Key is not a valid HyperLogLog string value.";
+pub const CORRUPTED_HLL: &str = "INVALIDOBJ Corrupted HLL object detected";
+
+// An empty HyperLogLog: a sparse header followed by a single XZERO run
+pub fn new_hll() -> Vec<u8> {
+    let mut hll = vec![0; HLL_HDR_SIZE];
+    hll[..4].copy_from_slice(b"HYLL");
+    hll[4] = HLL_SPARSE;
+    for _ in 0..HLL_REGISTERS / SPARSE_XZERO_MAX_LEN {
+        push_zero_run(&mut hll, SPARSE_XZERO_MAX_LEN);
+    }
+    hll
+}
+
+// Check that a string holds a HyperLogLog
+pub fn validate(hll: &[u8]) -> Result<(), &'static str> {
+    if hll.len() < HLL_HDR_SIZE || &hll[..4] != b"HYLL" || hll[4] > HLL_SPARSE {
+        return Err(INVALID_HLL);
+    }
+    if hll[4] == HLL_DENSE && hll.len() != HLL_DENSE_SIZE {
+        return Err(INVALID_HLL);
+    }
+    Ok(())
+}
+
+// The cached cardinality, unless it was invalidated by a later write
+pub fn cached_cardinality(hll: &[u8]) -> Option<u64> {
+    if hll[15] & 0x80 != 0 {
+        return None;
+    }
+    Some(u64::from_le_bytes(hll[8..16].try_into().unwrap()))
+}
+
+pub fn set_cached_cardinality(hll: &mut [u8], cardinality: u64) {
+    hll[8..16].copy_from_slice(&cardinality.to_le_bytes());
+}
+
+pub fn invalidate_cache(hll: &mut [u8]) {
+    hll[15] |= 0x80;
+}
+
+pub fn is_dense(hll: &[u8]) -> bool {
+    hll[4] == HLL_DENSE
+}
+
+// MurmurHash2, 64-bit version, as used by Redis to hash elements
+pub(crate) fn murmur_hash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// The register an element maps to, and the length of the zero run (plus one) in the rest of its hash
+fn pattern_length(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, 0xadc8_3b19);
+    let index = (hash & HLL_P_MASK) as usize;
+    let hash = (hash >> HLL_P) | (1 << HLL_Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * HLL_BITS / 8;
+    let fb = (index * HLL_BITS) & 7;
+    let b0 = registers[byte] as u16;
+    let b1 = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((b0 >> fb) | (b1 << (8 - fb))) as u8) & HLL_REGISTER_MAX
+}
+
+fn dense_put(registers: &mut [u8], index: usize, value: u8) {
+    let byte = index * HLL_BITS / 8;
+    let fb = (index * HLL_BITS) & 7;
+    let value = value as u16;
+    registers[byte] &= !((HLL_REGISTER_MAX as u16) << fb) as u8;
+    registers[byte] |= (value << fb) as u8;
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((HLL_REGISTER_MAX as u16) >> (8 - fb)) as u8;
+        *next |= (value >> (8 - fb)) as u8;
+    }
+}
+
+// Raise a dense register to count, returning true if it changed
+fn dense_set(registers: &mut [u8], index: usize, count: u8) -> bool {
+    if count > dense_get(registers, index) {
+        dense_put(registers, index, count);
+        true
+    } else {
+        false
+    }
+}
+
+fn is_zero_op(op: u8) -> bool {
+    op & 0xc0 == 0
+}
+
+fn is_xzero_op(op: u8) -> bool {
+    op & 0xc0 == 0x40
+}
+
+fn val_op(value: u8, len: usize) -> u8 {
+    0x80 | ((value - 1) << 2) | (len - 1) as u8
+}
+
+fn val_value(op: u8) -> u8 {
+    ((op >> 2) & 0x1f) + 1
+}
+
+fn val_len(op: u8) -> usize {
+    (op & 0x3) as usize + 1
+}
+
+// Append a ZERO or XZERO opcode for a run of up to 16384 empty registers
+fn push_zero_run(seq: &mut Vec<u8>, len: usize) {
+    if len > SPARSE_ZERO_MAX_LEN {
+        seq.push(0x40 | ((len - 1) >> 8) as u8);
+        seq.push(((len - 1) & 0xff) as u8);
+    } else {
+        seq.push((len - 1) as u8);
+    }
+}
+
+// The opcode length in bytes and the number of registers covered by the opcode at p
+fn sparse_opcode(hll: &[u8], p: usize) -> Result<(usize, usize), &'static str> {
+    let op = hll[p];
+    if is_zero_op(op) {
+        Ok((1, (op & 0x3f) as usize + 1))
+    } else if is_xzero_op(op) {
+        let low = *hll.get(p + 1).ok_or(CORRUPTED_HLL)? as usize;
+        Ok((2, ((((op & 0x3f) as usize) << 8) | low) + 1))
+    } else {
+        Ok((1, val_len(op)))
+    }
+}
+
+// Decode a sparse value into (register value, run length) pairs covering every register
+fn sparse_runs(hll: &[u8]) -> Result<Vec<(u8, usize)>, &'static str> {
+    let mut runs = Vec::new();
+    let mut covered = 0;
+    let mut p = HLL_HDR_SIZE;
+    while p < hll.len() {
+        let (oplen, span) = sparse_opcode(hll, p)?;
+        let value = if hll[p] & 0x80 != 0 { val_value(hll[p]) } else { 0 };
+        runs.push((value, span));
+        covered += span;
+        p += oplen;
+    }
+    if covered != HLL_REGISTERS {
+        return Err(CORRUPTED_HLL);
+    }
+    Ok(runs)
+}
+
+// Convert a sparse value to the dense encoding, keeping the header
+fn sparse_to_dense(hll: &mut Vec<u8>) -> Result<(), &'static str> {
+    let runs = sparse_runs(hll)?;
+    let mut dense = hll[..HLL_HDR_SIZE].to_vec();
+    dense[4] = HLL_DENSE;
+    dense.resize(HLL_DENSE_SIZE, 0);
+
+    let mut index = 0;
+    for (value, len) in runs {
+        if value > 0 {
+            for register in index..index + len {
+                dense_put(&mut dense[HLL_HDR_SIZE..], register, value);
+            }
+        }
+        index += len;
+    }
+    *hll = dense;
+    Ok(())
+}
+
+// Convert to dense and set the register there, used when a sparse value can no longer hold it
+fn promote(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, &'static str> {
+    sparse_to_dense(hll)?;
+    Ok(dense_set(&mut hll[HLL_HDR_SIZE..], index, count))
+}
+
+// Merge adjacent VAL opcodes with the same value, scanning a few opcodes from start
+fn merge_adjacent_values(hll: &mut Vec<u8>, start: usize) {
+    let mut p = start;
+    let mut scan = 5;
+    while p < hll.len() && scan > 0 {
+        scan -= 1;
+        let op = hll[p];
+        if is_xzero_op(op) {
+            p += 2;
+            continue;
+        }
+        if is_zero_op(op) {
+            p += 1;
+            continue;
+        }
+        if let Some(&next) = hll.get(p + 1) {
+            let len = val_len(op) + val_len(next);
+            if next & 0x80 != 0 && val_value(op) == val_value(next) && len <= SPARSE_VAL_MAX_LEN {
+                hll[p + 1] = val_op(val_value(op), len);
+                hll.remove(p);
+                // Try again to merge the result with the opcode on its right
+                continue;
+            }
+        }
+        p += 1;
+    }
+}
+
+// Raise a sparse register to count, splitting the run that covers it. This
+// follows Redis step by step, since the bytes produced depend on the order of updates.
+fn sparse_set(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, &'static str> {
+    if count > SPARSE_VAL_MAX_VALUE {
+        return promote(hll, index, count);
+    }
+
+    // Locate the opcode covering the register, and the one before it
+    let mut p = HLL_HDR_SIZE;
+    let mut prev = None;
+    let mut first = 0;
+    let mut oplen = 0;
+    let mut span = 0;
+    while p < hll.len() {
+        (oplen, span) = sparse_opcode(hll, p)?;
+        if index < first + span {
+            break;
+        }
+        prev = Some(p);
+        p += oplen;
+        first += span;
+    }
+    if span == 0 || p >= hll.len() {
+        return Err(CORRUPTED_HLL);
+    }
+
+    let op = hll[p];
+    let is_val = op & 0x80 != 0;
+    if is_val {
+        if val_value(op) >= count {
+            return Ok(false);
+        }
+        // A VAL run covering only this register is updated in place
+        if val_len(op) == 1 {
+            hll[p] = val_op(count, 1);
+            merge_adjacent_values(hll, prev.unwrap_or(HLL_HDR_SIZE));
+            return Ok(true);
+        }
+    }
+    // So is a ZERO run of length 1
+    if is_zero_op(op) && span == 1 {
+        hll[p] = val_op(count, 1);
+        merge_adjacent_values(hll, prev.unwrap_or(HLL_HDR_SIZE));
+        return Ok(true);
+    }
+
+    // Otherwise split the run into up to three opcodes around the register
+    let last = first + span - 1;
+    let mut seq = Vec::with_capacity(5);
+    if is_val {
+        let value = val_value(op);
+        if index != first {
+            seq.push(val_op(value, index - first));
+        }
+        seq.push(val_op(count, 1));
+        if index != last {
+            seq.push(val_op(value, last - index));
+        }
+    } else {
+        if index != first {
+            push_zero_run(&mut seq, index - first);
+        }
+        seq.push(val_op(count, 1));
+        if index != last {
+            push_zero_run(&mut seq, last - index);
+        }
+    }
+
+    if seq.len() > oplen && hll.len() + seq.len() - oplen > SPARSE_MAX_BYTES {
+        return promote(hll, index, count);
+    }
+    hll.splice(p..p + oplen, seq);
+    merge_adjacent_values(hll, prev.unwrap_or(HLL_HDR_SIZE));
+    Ok(true)
+}
+
+// Raise a register to count in either encoding, returning true if it changed
+pub fn set_register(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, &'static str> {
+    if is_dense(hll) {
+        Ok(dense_set(&mut hll[HLL_HDR_SIZE..], index, count))
+    } else {
+        sparse_set(hll, index, count)
+    }
+}
+
+// Add an element, returning true if a register changed
+pub fn add(hll: &mut Vec<u8>, element: &[u8]) -> Result<bool, &'static str> {
+    let (index, count) = pattern_length(element);
+    set_register(hll, index, count)
+}
+
+// Convert a sparse value to dense if it is not dense already
+pub fn make_dense(hll: &mut Vec<u8>) -> Result<(), &'static str> {
+    if is_dense(hll) { Ok(()) } else { sparse_to_dense(hll) }
+}
+
+// Raise each of the given registers to the value in hll, as PFMERGE and multi-key PFCOUNT do
+pub fn merge_registers(max: &mut [u8], hll: &[u8]) -> Result<(), &'static str> {
+    if is_dense(hll) {
+        for (index, register) in max.iter_mut().enumerate() {
+            *register = (*register).max(dense_get(&hll[HLL_HDR_SIZE..], index));
+        }
+    } else {
+        let mut index = 0;
+        for (value, len) in sparse_runs(hll)? {
+            for register in &mut max[index..index + len] {
+                *register = (*register).max(value);
+            }
+            index += len;
+        }
+    }
+    Ok(())
+}
+
+// Decode every register of a value
+pub fn registers(hll: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut registers = vec![0; HLL_REGISTERS];
+    merge_registers(&mut registers, hll)?;
+    Ok(registers)
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+// Estimate the cardinality from decoded registers, using the improved estimator
+// by Otmar Ertl that Redis uses (standard error 1.04/sqrt(16384), about 0.81%)
+pub fn estimate(registers: &[u8]) -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+
+    let mut z = m * tau((m - histogram[HLL_Q as usize + 1] as f64) / m);
+    for j in (1..=HLL_Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_hll_matches_redis() {
+        // What Redis stores for PFADD on a new key without elements
+        assert_eq!(new_hll(), b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x7f\xff".to_vec());
+        assert_eq!(estimate(&registers(&new_hll()).unwrap()), 0);
+    }
+
+    #[test]
+    fn sparse_and_dense_registers_agree() {
+        let mut sparse = new_hll();
+        let mut dense = new_hll();
+        make_dense(&mut dense).unwrap();
+        assert!(is_dense(&dense));
+        for (index, count) in [(0, 1), (1, 1), (5, 32), (6, 33), (100, 2), (HLL_REGISTERS - 1, HLL_REGISTER_MAX), (101, 1), (100, 1)] {
+            assert_eq!(set_register(&mut sparse, index, count).unwrap(), set_register(&mut dense, index, count).unwrap());
+        }
+        // 33 does not fit a sparse VAL opcode, so that register promoted the value to dense
+        assert!(is_dense(&sparse));
+        assert_eq!(registers(&sparse).unwrap(), registers(&dense).unwrap());
+        assert_eq!(registers(&dense).unwrap()[100], 2);
+    }
+
+    #[test]
+    fn sparse_values_stay_sparse_while_small() {
+        let mut hll = new_hll();
+        for i in 0..100 {
+            add(&mut hll, format!("element:{}", i).as_bytes()).unwrap();
+        }
+        assert!(!is_dense(&hll));
+        let mut dense = hll.clone();
+        make_dense(&mut dense).unwrap();
+        assert_eq!(registers(&hll).unwrap(), registers(&dense).unwrap());
+        assert!((98..=102).contains(&estimate(&registers(&hll).unwrap())));
+    }
+
+    #[test]
+    fn estimates_are_within_the_standard_error() {
+        let mut hll = new_hll();
+        for i in 0..100_000 {
+            add(&mut hll, format!("element:{}", i).as_bytes()).unwrap();
+        }
+        assert!(is_dense(&hll));
+        let estimate = estimate(&registers(&hll).unwrap()) as f64;
+        assert!((estimate - 100_000.0).abs() / 100_000.0 < 0.03, "estimate {}", estimate);
+    }
+
+    #[test]
+    fn validate_rejects_other_strings() {
+        assert!(validate(&new_hll()).is_ok());
+        assert_eq!(validate(b"HYLL"), Err(INVALID_HLL));
+        let mut dense = new_hll();
+        make_dense(&mut dense).unwrap();
+        dense.pop();
+        assert_eq!(validate(&dense), Err(INVALID_HLL));
+    }
+}
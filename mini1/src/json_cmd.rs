@@ -0,0 +1,257 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+use serde_json::{Map, Number, Value};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+use crate::json_path::{self, JsonPath};
+use crate::utils::{bulk_string, optional_bulk_string};
+
+static PATH_DOES_NOT_EXIST: &str = "Path does not exist";
+static KEY_DOES_NOT_EXIST: &str = "could not perform this operation on a key that doesn't exist";
+
+// The condition JSON.SET places on the path: NX (must not exist) or XX (must exist)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JsonSetCondition {
+    Always,
+    Nx,
+    Xx,
+}
+
+// Parse a JSON value given as a command argument
+pub fn parse_json(value: &str) -> Result<Value, &'static str> {
+    serde_json::from_str(value).map_err(|_| "invalid JSON value")
+}
+
+// Parse a JSON number given as a command argument
+pub fn parse_json_number(value: &str) -> Result<Number, &'static str> {
+    match parse_json(value) {
+        Ok(Value::Number(number)) => Ok(number),
+        _ => Err("expected a number"),
+    }
+}
+
+// Look up the JSON document stored at key, failing if the key holds another type
+fn get_json<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a Value>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Json(document)) => Ok(Some(document)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the JSON document stored at key mutably, failing if the key holds another type
+fn get_json_mut<'a>(cur_db: &'a mut Database, key: &str) -> Result<Option<&'a mut Value>, &'static str> {
+    match cur_db.data.get_mut(key) {
+        Some(Data::Json(document)) => Ok(Some(document)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Serialize the values a path selects: the first one for a legacy path, or a JSON array for JSONPath
+fn serialize_matches(document: &Value, path: &JsonPath) -> Result<Value, &'static str> {
+    let mut values = path.locate(document).into_iter().filter_map(|location| json_path::get(document, &location).cloned());
+    if path.legacy {
+        values.next().ok_or(PATH_DOES_NOT_EXIST)
+    } else {
+        Ok(Value::Array(values.collect()))
+    }
+}
+
+// Add two JSON numbers, keeping integers exact where possible
+fn add_numbers(a: &Number, b: &Number) -> Result<Number, &'static str> {
+    if let Some(sum) = a.as_i64().zip(b.as_i64()).and_then(|(a, b)| a.checked_add(b)) {
+        return Ok(Number::from(sum));
+    }
+    let sum = a.as_f64().unwrap_or(0.0) + b.as_f64().unwrap_or(0.0);
+    Number::from_f64(sum).ok_or("result is not a finite number")
+}
+
+// JSON.SET command
+pub fn execute_json_set_cmd(key: String, path: JsonPath, value: Value, condition: JsonSetCondition, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let document = match get_json_mut(cur_db, &key)? {
+        Some(document) => document,
+        None => {
+            if !path.is_root() {
+                return Err("new objects must be created at the root");
+            }
+            if condition == JsonSetCondition::Xx {
+                return Ok("$-1\r\n".to_string());
+            }
+            cur_db.data.insert(key, Data::Json(value));
+            return Ok("+OK\r\n".to_string());
+        }
+    };
+
+    let locations = path.locate(document);
+    if !locations.is_empty() {
+        if condition == JsonSetCondition::Nx {
+            return Ok("$-1\r\n".to_string());
+        }
+        for location in &locations {
+            if let Some(target) = json_path::get_mut(document, location) {
+                *target = value.clone();
+            }
+        }
+        return Ok("+OK\r\n".to_string());
+    }
+
+    // A path ending in a missing key adds that key to every object its parent path selects
+    if condition == JsonSetCondition::Xx {
+        return Ok("$-1\r\n".to_string());
+    }
+    let (parent, new_key) = match path.split_last_key() {
+        Some(split) => split,
+        None => return Ok("$-1\r\n".to_string()),
+    };
+    let mut updated = false;
+    for location in parent.locate(document) {
+        if let Some(Value::Object(map)) = json_path::get_mut(document, &location) {
+            map.insert(new_key.to_string(), value.clone());
+            updated = true;
+        }
+    }
+
+    Ok(if updated { "+OK\r\n".to_string() } else { "$-1\r\n".to_string() })
+}
+
+// JSON.GET command
+pub fn execute_json_get_cmd(key: String, paths: Vec<JsonPath>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let document = match get_json(cur_db, &key)? {
+        Some(document) => document,
+        None => return Ok("$-1\r\n".to_string()),
+    };
+
+    // Several paths are answered with an object keyed by path
+    let result = if paths.len() == 1 {
+        serialize_matches(document, &paths[0])?
+    } else {
+        let mut results = Map::new();
+        for path in &paths {
+            results.insert(path.text.clone(), serialize_matches(document, path)?);
+        }
+        Value::Object(results)
+    };
+
+    Ok(bulk_string(&result.to_string()))
+}
+
+// JSON.DEL command
+pub fn execute_json_del_cmd(key: String, path: JsonPath, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let document = match get_json_mut(cur_db, &key)? {
+        Some(document) => document,
+        None => return Ok(":0\r\n".to_string()),
+    };
+
+    // Deleting the root deletes the key
+    if path.is_root() {
+        cur_db.data.remove(&key);
+        return Ok(":1\r\n".to_string());
+    }
+
+    // Remove the last locations first, so array indexes of earlier ones stay valid
+    let mut locations = path.locate(document);
+    locations.sort();
+    let deleted = locations.iter().rev().filter(|location| json_path::remove(document, location)).count();
+
+    Ok(format!(":{}\r\n", deleted))
+}
+
+// JSON.NUMINCRBY command
+pub fn execute_json_numincrby_cmd(key: String, path: JsonPath, increment: Number, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let document = get_json_mut(cur_db, &key)?.ok_or(KEY_DOES_NOT_EXIST)?;
+
+    // Compute every result before writing, so an error leaves the document unchanged
+    let mut results = Vec::new();
+    for location in path.locate(document) {
+        match json_path::get(document, &location) {
+            Some(Value::Number(number)) => results.push((location, Value::Number(add_numbers(number, &increment)?))),
+            _ if path.legacy => return Err("path value is not a number"),
+            _ => results.push((location, Value::Null)),
+        }
+    }
+    for (location, result) in &results {
+        if let (Some(target), Value::Number(_)) = (json_path::get_mut(document, location), result) {
+            *target = result.clone();
+        }
+    }
+
+    let results: Vec<Value> = results.into_iter().map(|(_, result)| result).collect();
+    if path.legacy {
+        let result = results.into_iter().next().ok_or(PATH_DOES_NOT_EXIST)?;
+        return Ok(bulk_string(&result.to_string()));
+    }
+    Ok(bulk_string(&Value::Array(results).to_string()))
+}
+
+// JSON.ARRAPPEND command
+pub fn execute_json_arrappend_cmd(key: String, path: JsonPath, values: Vec<Value>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let document = get_json_mut(cur_db, &key)?.ok_or(KEY_DOES_NOT_EXIST)?;
+
+    let locations = path.locate(document);
+    if path.legacy && !matches!(locations.first().and_then(|location| json_path::get(document, location)), Some(Value::Array(_))) {
+        return Err(if locations.is_empty() { PATH_DOES_NOT_EXIST } else { "path value is not an array" });
+    }
+
+    // Each selected array reports its new length; anything else reports nil
+    let mut lengths = Vec::with_capacity(locations.len());
+    for location in &locations {
+        match json_path::get_mut(document, location) {
+            Some(Value::Array(items)) => {
+                items.extend(values.iter().cloned());
+                lengths.push(Some(items.len()));
+            }
+            _ => lengths.push(None),
+        }
+    }
+
+    if path.legacy {
+        return Ok(format!(":{}\r\n", lengths[0].unwrap_or(0)));
+    }
+    let mut response = format!("*{}\r\n", lengths.len());
+    for length in lengths {
+        response += &match length {
+            Some(length) => format!(":{}\r\n", length),
+            None => "$-1\r\n".to_string(),
+        };
+    }
+    Ok(response)
+}
+
+// JSON.TYPE command
+pub fn execute_json_type_cmd(key: String, path: JsonPath, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let document = match get_json(cur_db, &key)? {
+        Some(document) => document,
+        None => return Ok("$-1\r\n".to_string()),
+    };
+
+    let types: Vec<&str> = path.locate(document).iter()
+        .filter_map(|location| json_path::get(document, location))
+        .map(json_path::type_name)
+        .collect();
+
+    if path.legacy {
+        return Ok(match types.first() {
+            Some(type_name) => format!("+{}\r\n", type_name),
+            None => optional_bulk_string(None),
+        });
+    }
+    let mut response = format!("*{}\r\n", types.len());
+    for type_name in types {
+        response += &bulk_string(type_name);
+    }
+    Ok(response)
+}
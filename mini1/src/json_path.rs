@@ -0,0 +1,233 @@
+use serde_json::Value;
+
+// One step of a JSONPath expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Key(String),
+    Index(i64),
+    Wildcard,
+    // ".." followed by a key or "*": apply the selector at every depth
+    Descendants(Box<Selector>),
+}
+
+// One step from a value to one of its children
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+// The steps from the root of a document to a value
+pub type Location = Vec<Step>;
+
+// A parsed path. Legacy paths (".a.b" or "a.b") select at most one value,
+// while JSONPath expressions ("$.a.b") may select many.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    pub text: String,
+    pub selectors: Vec<Selector>,
+    pub legacy: bool,
+}
+
+impl JsonPath {
+    // Parse "$..." as JSONPath, and anything else as a legacy path
+    pub fn parse(path: &str) -> Result<Self, &'static str> {
+        let (expression, legacy) = match path.strip_prefix('$') {
+            Some(expression) => (expression.to_string(), false),
+            None if path == "." => (String::new(), true),
+            None if path.starts_with('.') || path.starts_with('[') => (path.to_string(), true),
+            None => (format!(".{}", path), true),
+        };
+        Ok(Self { text: path.to_string(), selectors: parse_selectors(&expression)?, legacy })
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.selectors.is_empty()
+    }
+
+    // The locations of every value the path selects, in document order
+    pub fn locate(&self, root: &Value) -> Vec<Location> {
+        let mut found = Vec::new();
+        walk(root, &self.selectors, &mut Vec::new(), &mut found);
+        if self.legacy {
+            found.truncate(1);
+        }
+        found
+    }
+
+    // Split a path ending in a plain key into the path of its parent and the key,
+    // which is where JSON.SET adds a key that does not exist yet
+    pub fn split_last_key(&self) -> Option<(JsonPath, &str)> {
+        match self.selectors.split_last() {
+            Some((Selector::Key(key), parent)) => {
+                let parent = JsonPath { text: self.text.clone(), selectors: parent.to_vec(), legacy: self.legacy };
+                Some((parent, key.as_str()))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Parse the part of a path after "$"
+fn parse_selectors(expression: &str) -> Result<Vec<Selector>, &'static str> {
+    let invalid = "invalid JSON path";
+    let chars: Vec<char> = expression.chars().collect();
+    let mut selectors = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let descendants = chars[pos] == '.' && chars.get(pos + 1) == Some(&'.');
+        let selector = match chars[pos] {
+            '.' => {
+                pos += if descendants { 2 } else { 1 };
+                if chars.get(pos) == Some(&'[') {
+                    if !descendants {
+                        return Err(invalid);
+                    }
+                    parse_bracket(&chars, &mut pos)?
+                } else {
+                    let start = pos;
+                    while pos < chars.len() && chars[pos] != '.' && chars[pos] != '[' {
+                        pos += 1;
+                    }
+                    match chars[start..pos].iter().collect::<String>() {
+                        name if name.is_empty() => return Err(invalid),
+                        name if name == "*" => Selector::Wildcard,
+                        name => Selector::Key(name),
+                    }
+                }
+            }
+            '[' => parse_bracket(&chars, &mut pos)?,
+            _ => return Err(invalid),
+        };
+        selectors.push(if descendants { Selector::Descendants(Box::new(selector)) } else { selector });
+    }
+
+    Ok(selectors)
+}
+
+// Parse a bracketed selector starting at pos: [*], [index], ['key'] or ["key"]
+fn parse_bracket(chars: &[char], pos: &mut usize) -> Result<Selector, &'static str> {
+    let invalid = "invalid JSON path";
+    let start = *pos + 1;
+
+    // Quoted keys may contain any character but the quote itself
+    if let Some(&quote) = chars.get(start).filter(|c| **c == '\'' || **c == '"') {
+        let end = start + 1 + chars[start + 1..].iter().position(|c| *c == quote).ok_or(invalid)?;
+        if chars.get(end + 1) != Some(&']') {
+            return Err(invalid);
+        }
+        *pos = end + 2;
+        return Ok(Selector::Key(chars[start + 1..end].iter().collect()));
+    }
+
+    let close = start + chars[start..].iter().position(|c| *c == ']').ok_or(invalid)?;
+    let inner: String = chars[start..close].iter().collect();
+    *pos = close + 1;
+    match inner.trim() {
+        "*" => Ok(Selector::Wildcard),
+        index => Ok(Selector::Index(index.parse().map_err(|_| invalid)?)),
+    }
+}
+
+// The children of an object or array, with the steps leading to them
+fn children(value: &Value) -> Vec<(Step, &Value)> {
+    match value {
+        Value::Object(map) => map.iter().map(|(key, child)| (Step::Key(key.clone()), child)).collect(),
+        Value::Array(items) => items.iter().enumerate().map(|(index, child)| (Step::Index(index), child)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Collect the locations matched by selectors, starting at value
+fn walk(value: &Value, selectors: &[Selector], location: &mut Location, found: &mut Vec<Location>) {
+    let (selector, rest) = match selectors.split_first() {
+        Some(split) => split,
+        None => {
+            found.push(location.clone());
+            return;
+        }
+    };
+
+    match selector {
+        Selector::Descendants(inner) => {
+            // Apply the inner selector here, then at every level below
+            apply(value, inner, rest, location, found);
+            for (step, child) in children(value) {
+                location.push(step);
+                walk(child, selectors, location, found);
+                location.pop();
+            }
+        }
+        _ => apply(value, selector, rest, location, found),
+    }
+}
+
+// Apply a single non-recursive selector to value, then walk the rest of the path
+fn apply(value: &Value, selector: &Selector, rest: &[Selector], location: &mut Location, found: &mut Vec<Location>) {
+    let matched: Vec<(Step, &Value)> = match (selector, value) {
+        (Selector::Key(key), Value::Object(map)) => map.get(key).map(|child| (Step::Key(key.clone()), child)).into_iter().collect(),
+        (Selector::Index(index), Value::Array(items)) => {
+            let index = if *index < 0 { items.len() as i64 + index } else { *index };
+            usize::try_from(index).ok()
+                .and_then(|index| items.get(index).map(|child| (Step::Index(index), child)))
+                .into_iter()
+                .collect()
+        }
+        (Selector::Wildcard, _) => children(value),
+        _ => Vec::new(),
+    };
+
+    for (step, child) in matched {
+        location.push(step);
+        walk(child, rest, location, found);
+        location.pop();
+    }
+}
+
+// The value at a location
+pub fn get<'a>(root: &'a Value, location: &[Step]) -> Option<&'a Value> {
+    location.iter().try_fold(root, |value, step| match (step, value) {
+        (Step::Key(key), Value::Object(map)) => map.get(key),
+        (Step::Index(index), Value::Array(items)) => items.get(*index),
+        _ => None,
+    })
+}
+
+// The value at a location, mutably
+pub fn get_mut<'a>(root: &'a mut Value, location: &[Step]) -> Option<&'a mut Value> {
+    location.iter().try_fold(root, |value, step| match (step, value) {
+        (Step::Key(key), Value::Object(map)) => map.get_mut(key),
+        (Step::Index(index), Value::Array(items)) => items.get_mut(*index),
+        _ => None,
+    })
+}
+
+// Remove the value at a non-root location, returning true if it existed
+pub fn remove(root: &mut Value, location: &[Step]) -> bool {
+    let (last, parent) = match location.split_last() {
+        Some(split) => split,
+        None => return false,
+    };
+    match (last, get_mut(root, parent)) {
+        (Step::Key(key), Some(Value::Object(map))) => map.shift_remove(key).is_some(),
+        (Step::Index(index), Some(Value::Array(items))) if *index < items.len() => {
+            items.remove(*index);
+            true
+        }
+        _ => false,
+    }
+}
+
+// The type name JSON.TYPE reports for a value
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,368 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+use rand::{seq::IteratorRandom, thread_rng};
+
+use crate::database::{Database, KeyMeta, LFU_INIT_VAL};
+use crate::hyperloglog::murmur_hash64a;
+use crate::rdb;
+use crate::stream::now_ms;
+use crate::utils::{bulk_array, bulk_bytes, bulk_string, glob_match, optional_bulk_string};
+
+// Seed of the hash that orders keys and members for the SCAN family
+const SCAN_SEED: u64 = 0x5ca7_c0de;
+
+// DEL and UNLINK commands. Values are freed right away, so UNLINK behaves like DEL.
+pub fn execute_del_cmd(keys: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let deleted = keys.iter().filter(|key| cur_db.data.remove(*key).is_some()).count();
+    Ok(format!(":{}\r\n", deleted))
+}
+
+// TYPE command
+pub fn execute_type_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    Ok(format!("+{}\r\n", cur_db.data.get(&key).map_or("none", |value| value.type_name())))
+}
+
+// RENAME and RENAMENX commands. The access metadata of the key moves along with its value.
+pub fn execute_rename_cmd(key: String, new_key: String, nx: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if !cur_db.data.contains_key(&key) {
+        return Err("no such key");
+    }
+
+    if nx && cur_db.data.contains_key(&new_key) {
+        return Ok(":0\r\n".to_string());
+    }
+
+    if key != new_key {
+        let value = cur_db.data.remove(&key).ok_or("no such key")?;
+        match cur_db.meta.remove(&key) {
+            Some(meta) => cur_db.meta.insert(new_key.clone(), meta),
+            None => cur_db.meta.remove(&new_key),
+        };
+        cur_db.data.insert(new_key, value);
+    }
+    Ok(if nx { ":1\r\n".to_string() } else { "+OK\r\n".to_string() })
+}
+
+// COPY command. The destination may be in another database, which is created if it does not exist yet.
+pub fn execute_copy_cmd(source: String, destination: String, destination_db: Option<u8>, replace: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let destination_db = destination_db.unwrap_or(*selected_db);
+    if destination_db == *selected_db && source == destination {
+        return Err("source and destination objects are the same");
+    }
+
+    let value = match db.get(selected_db).ok_or("Database not found")?.data.get(&source) {
+        Some(value) => value.clone(),
+        None => return Ok(":0\r\n".to_string()),
+    };
+    let target = db.entry(destination_db).or_insert_with(Database::new);
+    if !replace && target.data.contains_key(&destination) {
+        return Ok(":0\r\n".to_string());
+    }
+
+    target.data.insert(destination, value);
+    Ok(":1\r\n".to_string())
+}
+
+// The options of SCAN, HSCAN, SSCAN and ZSCAN. The type filter is only accepted by SCAN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanOptions {
+    pub pattern: Option<String>,
+    pub count: usize,
+    pub type_name: Option<String>,
+}
+
+impl ScanOptions {
+    // Whether a key or member selected by the cursor is reported
+    pub(crate) fn matches(&self, item: &str) -> bool {
+        self.pattern.as_ref().is_none_or(|pattern| glob_match(pattern.as_bytes(), item.as_bytes()))
+    }
+}
+
+// Parse "<cursor> [MATCH <pattern>] [COUNT <count>]", and "[TYPE <type>]" for SCAN
+pub fn parse_scan_args(args: &[String], allow_type: bool) -> Result<(u64, ScanOptions), &'static str> {
+    let cursor = args[0].parse().map_err(|_| "invalid cursor")?;
+    let mut options = ScanOptions { pattern: None, count: 10, type_name: None };
+
+    let mut i = 1;
+    while i + 1 < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "MATCH" => options.pattern = Some(args[i + 1].clone()),
+            "COUNT" => options.count = args[i + 1].parse().ok().filter(|count| *count > 0).ok_or("value is out of range, must be positive")?,
+            "TYPE" if allow_type => options.type_name = Some(args[i + 1].clone()),
+            _ => return Err("syntax error"),
+        }
+        i += 2;
+    }
+    if i != args.len() {
+        return Err("syntax error");
+    }
+    Ok((cursor, options))
+}
+
+// The position of a key or member in scan order
+fn scan_hash(item: &str) -> u64 {
+    murmur_hash64a(item.as_bytes(), SCAN_SEED)
+}
+
+// Select the next page of a scan. Items are visited in the order of a fixed hash,
+// and the cursor is the hash to continue from, so an item present for the whole
+// scan is returned exactly once however the underlying table grows or rehashes.
+// Returns the next cursor (0 when the scan is complete) and the page.
+pub(crate) fn scan_page<'a, T>(items: impl Iterator<Item = (&'a String, T)>, cursor: u64, count: usize) -> (u64, Vec<(&'a String, T)>) {
+    let mut candidates: Vec<(u64, &String, T)> = items
+        .map(|(item, value)| (scan_hash(item), item, value))
+        .filter(|(hash, _, _)| *hash >= cursor)
+        .collect();
+
+    let mut next_cursor = 0;
+    if candidates.len() > count {
+        candidates.select_nth_unstable_by_key(count - 1, |(hash, _, _)| *hash);
+        // Items sharing the hash of the last one stay on this page, so a cursor never splits them
+        let last = candidates[count - 1].0;
+        let (page, rest): (Vec<_>, Vec<_>) = candidates.into_iter().partition(|(hash, _, _)| *hash <= last);
+        next_cursor = rest.iter().map(|(hash, _, _)| *hash).min().unwrap_or(0);
+        candidates = page;
+    }
+    candidates.sort_by_key(|(hash, _, _)| *hash);
+
+    (next_cursor, candidates.into_iter().map(|(_, item, value)| (item, value)).collect())
+}
+
+// Encode a scan reply from the next cursor and the RESP array of the page
+pub(crate) fn scan_reply(cursor: u64, page: String) -> String {
+    format!("*2\r\n{}{}", bulk_string(&cursor.to_string()), page)
+}
+
+// KEYS command
+pub fn execute_keys_cmd(pattern: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    let keys: Vec<&String> = cur_db.data.keys().filter(|key| glob_match(pattern.as_bytes(), key.as_bytes())).collect();
+    Ok(bulk_array(&keys))
+}
+
+// SCAN command
+pub fn execute_scan_cmd(cursor: u64, options: ScanOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    let (next_cursor, page) = scan_page(cur_db.data.iter(), cursor, options.count);
+    let keys: Vec<&String> = page.into_iter()
+        .filter(|(key, value)| options.matches(key) && options.type_name.as_ref().is_none_or(|type_name| value.type_name().eq_ignore_ascii_case(type_name)))
+        .map(|(key, _)| key)
+        .collect();
+    Ok(scan_reply(next_cursor, bulk_array(&keys)))
+}
+
+// MOVE command. The key is only moved if the destination database does not hold it yet.
+pub fn execute_move_cmd(key: String, destination_db: u8, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    if destination_db == *selected_db {
+        return Err("source and destination objects are the same");
+    }
+    let exists_in_destination = db.get(&destination_db).is_some_and(|target| target.data.contains_key(&key));
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if exists_in_destination || !cur_db.data.contains_key(&key) {
+        return Ok(":0\r\n".to_string());
+    }
+
+    let value = cur_db.data.remove(&key).ok_or("no such key")?;
+    let meta = cur_db.meta.remove(&key);
+    let target = db.entry(destination_db).or_insert_with(Database::new);
+    if let Some(meta) = meta {
+        target.meta.insert(key.clone(), meta);
+    }
+    target.data.insert(key, value);
+    Ok(":1\r\n".to_string())
+}
+
+// The subcommands of OBJECT
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectSubcommand {
+    Encoding,
+    Idletime,
+    Freq,
+    Refcount,
+}
+
+impl ObjectSubcommand {
+    pub fn parse(subcommand: &str) -> Result<Self, &'static str> {
+        match subcommand.to_uppercase().as_str() {
+            "ENCODING" => Ok(ObjectSubcommand::Encoding),
+            "IDLETIME" => Ok(ObjectSubcommand::Idletime),
+            "FREQ" => Ok(ObjectSubcommand::Freq),
+            "REFCOUNT" => Ok(ObjectSubcommand::Refcount),
+            _ => Err("Unknown OBJECT subcommand"),
+        }
+    }
+}
+
+// OBJECT command. Inspecting a key does not count as an access.
+pub fn execute_object_cmd(subcommand: ObjectSubcommand, key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let value = match cur_db.data.get(&key) {
+        Some(value) => value,
+        None => return Ok("$-1\r\n".to_string()),
+    };
+
+    let meta = cur_db.key_meta(&key);
+    Ok(match subcommand {
+        ObjectSubcommand::Encoding => bulk_string(value.encoding()),
+        ObjectSubcommand::Idletime => format!(":{}\r\n", meta.idle_seconds()),
+        ObjectSubcommand::Freq => format!(":{}\r\n", meta.decayed_counter()),
+        // Values are never shared between keys
+        ObjectSubcommand::Refcount => ":1\r\n".to_string(),
+    })
+}
+
+// TOUCH command. The access itself is recorded once the command has run, like for any other command.
+pub fn execute_touch_cmd(keys: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    let count = keys.iter().filter(|key| cur_db.data.contains_key(*key)).count();
+    Ok(format!(":{}\r\n", count))
+}
+
+// RANDOMKEY command
+pub fn execute_randomkey_cmd(db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    Ok(optional_bulk_string(cur_db.data.keys().choose(&mut thread_rng()).map(String::as_str)))
+}
+
+// Record an access to the keys a command used
+pub(crate) fn touch_keys(keys: &[String], db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: u8) {
+    let mut db = db.lock().unwrap();
+    if let Some(cur_db) = db.get_mut(&selected_db) {
+        for key in keys {
+            cur_db.touch(key);
+        }
+    }
+}
+
+// The options of RESTORE
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestoreOptions {
+    pub replace: bool,
+    pub absttl: bool,
+    pub idletime: Option<u64>,
+    pub freq: Option<u8>,
+}
+
+// Parse "<ttl> [REPLACE] [ABSTTL] [IDLETIME <seconds>] [FREQ <frequency>]", the arguments of RESTORE around its payload
+pub fn parse_restore_args(ttl: &str, args: &[String]) -> Result<(u64, RestoreOptions), &'static str> {
+    let ttl = ttl.parse::<i64>().map_err(|_| "value is not an integer or out of range")?;
+    let ttl = u64::try_from(ttl).map_err(|_| "Invalid TTL value, must be >= 0")?;
+    let mut options = RestoreOptions::default();
+
+    let mut i = 0;
+    while i < args.len() {
+        let has_value = i + 1 < args.len();
+        match args[i].to_uppercase().as_str() {
+            "REPLACE" => options.replace = true,
+            "ABSTTL" => options.absttl = true,
+            "IDLETIME" if has_value && options.freq.is_none() => {
+                let idletime = args[i + 1].parse::<i64>().map_err(|_| "value is not an integer or out of range")?;
+                options.idletime = Some(u64::try_from(idletime).map_err(|_| "Invalid IDLETIME value, must be >= 0")?);
+                i += 1;
+            }
+            "FREQ" if has_value && options.idletime.is_none() => {
+                let freq = args[i + 1].parse::<i64>().map_err(|_| "value is not an integer or out of range")?;
+                options.freq = Some(u8::try_from(freq).map_err(|_| "Invalid FREQ value, must be >= 0 and <= 255")?);
+                i += 1;
+            }
+            _ => return Err("syntax error"),
+        }
+        i += 1;
+    }
+    Ok((ttl, options))
+}
+
+// DUMP command
+pub fn execute_dump_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<Vec<u8>, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    Ok(match cur_db.data.get(&key) {
+        Some(value) => bulk_bytes(&rdb::dump(value)?),
+        None => b"$-1\r\n".to_vec(),
+    })
+}
+
+// RESTORE command. Keys do not expire here, so any TTL other than 0 is rejected, relative
+// or absolute, rather than restoring a key that would outlive it.
+pub fn execute_restore_cmd(key: String, ttl: u64, payload: Vec<u8>, options: RestoreOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    if ttl != 0 {
+        return Err("keys with a TTL are not supported, the TTL must be 0");
+    }
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if !options.replace && cur_db.data.contains_key(&key) {
+        return Err("BUSYKEY Target key name already exists.");
+    }
+
+    let value = rdb::restore(&payload)?;
+    let now = now_ms();
+    let mut meta = KeyMeta { last_access_ms: now, lfu_counter: LFU_INIT_VAL, lfu_decay_minute: now / 60_000 };
+    if let Some(idletime) = options.idletime {
+        meta.last_access_ms = now.saturating_sub(idletime.saturating_mul(1000));
+    }
+    if let Some(freq) = options.freq {
+        meta.lfu_counter = freq;
+    }
+    cur_db.data.insert(key.clone(), value);
+    cur_db.meta.insert(key, meta);
+    Ok("+OK\r\n".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Data;
+    use crate::timeseries::{DuplicatePolicy, TimeSeries};
+
+    fn new_db() -> Arc<Mutex<HashMap<u8, Database>>> {
+        Arc::new(Mutex::new(HashMap::from([(0, Database::new())])))
+    }
+
+    #[test]
+    fn restore_rejects_any_ttl() {
+        let db = new_db();
+        let payload = rdb::dump(&Data::Scalar(b"v".to_vec())).unwrap();
+        let absttl = RestoreOptions { absttl: true, ..Default::default() };
+        assert!(execute_restore_cmd("k".to_string(), 1000, payload.clone(), RestoreOptions::default(), &db, &mut 0).is_err());
+        assert!(execute_restore_cmd("k".to_string(), 1, payload.clone(), absttl, &db, &mut 0).is_err());
+        assert_eq!(execute_restore_cmd("k".to_string(), 0, payload, RestoreOptions::default(), &db, &mut 0), Ok("+OK\r\n".to_string()));
+    }
+
+    #[test]
+    fn dump_refuses_time_series() {
+        let db = new_db();
+        let series = TimeSeries::new(0, DuplicatePolicy::Block, Vec::new());
+        db.lock().unwrap().get_mut(&0).unwrap().data.insert("ts".to_string(), Data::TimeSeries(series));
+        assert_eq!(execute_dump_cmd("ts".to_string(), &db, &mut 0), Err(rdb::TIMESERIES_DUMP));
+    }
+
+    #[test]
+    fn restore_rejects_non_utf8_elements() {
+        let db = new_db();
+        // A list holding the single byte 0xff, with a zero checksum, which is not checked
+        let mut payload = vec![1, 1, 1, 0xff];
+        payload.extend_from_slice(&rdb::RDB_VERSION.to_le_bytes());
+        payload.extend_from_slice(&[0; 8]);
+        assert_eq!(execute_restore_cmd("k".to_string(), 0, payload, RestoreOptions::default(), &db, &mut 0), Err(rdb::NOT_UTF8));
+        assert!(db.lock().unwrap()[&0].data.is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+// The server, shared by the mini1 server binary and the mini1-check tool.
+// Types are created with new() throughout, as they were when this was only a binary.
+#![allow(clippy::new_without_default)]
+
+pub mod command;
+pub mod database;
+pub mod connection;
+pub mod utils;
+pub mod execute_cmd;
+pub mod hash_cmd;
+pub mod set_cmd;
+pub mod sorted_set;
+pub mod zset_cmd;
+pub mod stream;
+pub mod stream_cmd;
+pub mod bit_cmd;
+pub mod hyperloglog;
+pub mod hll_cmd;
+pub mod geohash;
+pub mod geo_cmd;
+pub mod json_path;
+pub mod json_cmd;
+pub mod bloom;
+pub mod bloom_cmd;
+pub mod cms;
+pub mod cms_cmd;
+pub mod timeseries;
+pub mod timeseries_cmd;
+pub mod keyspace_cmd;
+pub mod db_cmd;
+pub mod rdb;
+pub mod config;
+pub mod migrate_cmd;
+pub mod sort_cmd;
+pub mod server;
+pub mod snapshot_cmd;
+pub mod aof;
+pub mod aof_cmd;
+pub mod replication;
+pub mod replication_cmd;
+pub mod cluster;
+pub mod cluster_cmd;
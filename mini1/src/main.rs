@@ -1,22 +1,38 @@
-mod command;
-mod database;
-mod connection;
-mod utils;
-mod execute_cmd;
-
 use tokio::net::TcpListener;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
-use crate::{database::Database, connection::handle_connection, utils::{parse_request, execute_command}};
+use mini1::{aof::{self, Aof, AppendFsync}, aof_cmd::run_aof_rewrite_rule, cluster::Cluster, cluster_cmd::{run_cluster_bus, run_cluster_cron}, config::Config, database::Database, connection::handle_connection, rdb, replication_cmd::{run_replication_cron, start_replication}, server::Server, snapshot_cmd::run_save_rules};
+
+// The address the server and the cluster bus listen on
+const BIND_ADDRESS: &str = "127.0.0.1";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:6379").await?;
+    let config = Config::from_args(std::env::args().skip(1))?;
+    let db = Arc::new(Mutex::new(HashMap::new()));
+    let aof = load_data(&config, &db).await?;
+    let cluster = load_cluster(&config)?;
+
+    let listener = TcpListener::bind((BIND_ADDRESS, config.port)).await?;
     println!("Listening on port {}", listener.local_addr()?.port());
 
-    let db = Arc::new(Mutex::new(HashMap::new()));
-    db.lock().unwrap().insert(0, Database { data: HashMap::new(), queue: VecDeque::new() }); // Default namespace
+    let server = Arc::new(Server::new(config, aof, cluster));
+    if server.cluster.is_some() {
+        let bus = TcpListener::bind((BIND_ADDRESS, server.config.cluster_bus_port())).await?;
+        println!("Cluster bus listening on port {}", server.config.cluster_bus_port());
+        tokio::spawn(run_cluster_bus(bus, Arc::clone(&server)));
+        tokio::spawn(run_cluster_cron(Arc::clone(&server)));
+    }
+    tokio::spawn(run_save_rules(Arc::clone(&db), Arc::clone(&server)));
+    if server.config.appendfsync == AppendFsync::Everysec {
+        tokio::spawn(aof::run_everysec_fsync(Arc::clone(&server)));
+    }
+    tokio::spawn(run_aof_rewrite_rule(Arc::clone(&db), Arc::clone(&server)));
+    tokio::spawn(run_replication_cron(Arc::clone(&server)));
+    if let Some((host, port)) = server.config.replicaof.clone() {
+        start_replication(&db, &server, host, port).await;
+    }
 
     // Create a channel for sending messages from the connection handlers to the main thread
     let (tx, mut rx) = mpsc::channel(32);
@@ -33,13 +49,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("Accepted connection from {}", addr);
 
         let db_clone = Arc::clone(&db);
+        let server_clone = Arc::clone(&server);
         let tx_clone = tx.clone(); // Clone the sender for use in this connection handler
         
         tokio::spawn(async move {
             println!("Spawning a new task for handling the connection");
-            handle_connection(&mut socket, &db_clone, tx_clone).await
+            handle_connection(&mut socket, &db_clone, &server_clone, tx_clone).await
                 .map_err(|e| { eprintln!("Error: {}", e); })
                 .ok();
         });
     }
-}
\ No newline at end of file
+}
+
+// Load the data before accepting connections: the AOF when it is enabled and exists, and
+// the last snapshot otherwise. A missing file means an empty dataset, while a file that
+// cannot be read stops the server. When the AOF is enabled it is opened, and created from
+// the loaded data if needed.
+async fn load_data(config: &Config, db: &Arc<Mutex<HashMap<u8, Database>>>) -> Result<Option<Aof>, Box<dyn std::error::Error>> {
+    db.lock().unwrap().insert(0, Database::new()); // Default namespace
+
+    let manifest = if config.appendonly { aof::read_manifest(config)? } else { None };
+    if let Some(manifest) = manifest {
+        // Replayed commands are not appended again
+        let replay_server = Arc::new(Server::new(config.clone(), None, None));
+        let count = aof::load(config, &manifest, db, &replay_server).await?;
+        println!("DB loaded from append only file: {} commands", count);
+        return Ok(Some(Aof::open(config, manifest)?));
+    }
+
+    match std::fs::read(config.rdb_path()) {
+        Ok(file) => {
+            let (databases, expiries) = rdb::load_with_expiries(&file).map_err(|e| format!("Error loading {}: {}", config.rdb_path().display(), e))?;
+            println!("DB loaded from disk: {} keys", databases.values().map(|database| database.data.len()).sum::<usize>());
+            if expiries.skipped + expiries.dropped > 0 {
+                println!("Keys do not expire: {} expired keys were skipped and {} keys were loaded without their expiry", expiries.skipped, expiries.dropped);
+            }
+            let mut db = db.lock().unwrap();
+            db.extend(databases);
+            db.entry(0).or_insert_with(Database::new);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    if !config.appendonly {
+        return Ok(None);
+    }
+    let manifest = aof::create(config, &db.lock().unwrap())?;
+    Ok(Some(Aof::open(config, manifest)?))
+}
+
+// Load the view of the cluster in cluster mode: the configuration file when it exists, and
+// otherwise a new cluster of this node alone
+fn load_cluster(config: &Config) -> Result<Option<Cluster>, Box<dyn std::error::Error>> {
+    if !config.cluster_enabled {
+        return Ok(None);
+    }
+    let cluster = match std::fs::read_to_string(config.cluster_config_path()) {
+        Ok(file) => Cluster::from_config(&file, BIND_ADDRESS, config.port, config.cluster_bus_port())?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Cluster::new(BIND_ADDRESS, config.port, config.cluster_bus_port()),
+        Err(e) => return Err(e.into()),
+    };
+    println!("Cluster node ID {}", cluster.myself);
+    Ok(Some(cluster))
+}
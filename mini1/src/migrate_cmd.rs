@@ -0,0 +1,250 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, time::timeout};
+
+use crate::database::Database;
+use crate::rdb;
+use crate::utils::encode_request;
+
+// The arguments of MIGRATE
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrateArgs {
+    pub host: String,
+    pub port: u16,
+    pub keys: Vec<String>,
+    pub destination_db: u8,
+    pub timeout_ms: u64,
+    pub copy: bool,
+    pub replace: bool,
+}
+
+// Parse "<host> <port> <key>|\"\" <destination-db> <timeout> [COPY] [REPLACE] [KEYS <key> ...]",
+// where the keys of KEYS take the rest of the arguments and require an empty key
+pub fn parse_migrate_args(args: &[String], destination_db: u8) -> Result<MigrateArgs, &'static str> {
+    let mut migrate = MigrateArgs {
+        host: args[0].clone(),
+        port: args[1].parse().map_err(|_| "Invalid port")?,
+        keys: vec![args[2].clone()],
+        destination_db,
+        // A timeout of 0 stands for the default of one second, like in Redis
+        timeout_ms: args[4].parse::<u64>().map_err(|_| "timeout is not an integer or out of range")?,
+        copy: false,
+        replace: false,
+    };
+    if migrate.timeout_ms == 0 {
+        migrate.timeout_ms = 1000;
+    }
+
+    let mut i = 5;
+    while i < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "COPY" => migrate.copy = true,
+            "REPLACE" => migrate.replace = true,
+            "KEYS" => {
+                if !args[2].is_empty() {
+                    return Err("When using MIGRATE KEYS option, the key argument must be set to the empty string");
+                }
+                if i + 1 == args.len() {
+                    return Err("syntax error");
+                }
+                migrate.keys = args[i + 1..].to_vec();
+                i = args.len();
+            }
+            _ => return Err("syntax error"),
+        }
+        i += 1;
+    }
+    Ok(migrate)
+}
+
+// Read one simple string or error reply per request, in order
+async fn read_replies(stream: &mut TcpStream, count: usize) -> std::io::Result<Vec<Result<(), String>>> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+    while buf.windows(2).filter(|window| window == b"\r\n").count() < count {
+        let bytes_read = stream.read(&mut chunk).await?;
+        if bytes_read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..bytes_read]);
+    }
+
+    Ok(buf.split(|byte| *byte == b'\n').take(count).map(|line| {
+        let line = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line)).into_owned();
+        match line.strip_prefix('-') {
+            Some(error) => Err(error.to_string()),
+            None => Ok(()),
+        }
+    }).collect())
+}
+
+// MIGRATE command. The keys are serialized like DUMP does and sent to the target
+// with RESTORE on a new connection, so nothing is sent if one of them is a time series. Unless COPY is given, the keys the target
+// accepted are then removed; the source database is not locked while waiting.
+// In cluster mode RESTORE-ASKING is sent instead, so the target accepts keys of a
+// slot it is still importing.
+pub async fn execute_migrate_cmd(args: MigrateArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8, cluster: bool) -> Result<String, &'static str> {
+    let payloads: Vec<(String, Vec<u8>)> = {
+        let db = db.lock().unwrap();
+        let cur_db = db.get(selected_db).ok_or("Database not found")?;
+        args.keys.iter()
+            .filter_map(|key| cur_db.data.get(key).map(|value| Ok((key.clone(), rdb::dump(value)?))))
+            .collect::<Result<_, &'static str>>()?
+    };
+    if payloads.is_empty() {
+        return Ok("+NOKEY\r\n".to_string());
+    }
+
+    let restore_cmd: &[u8] = if cluster { b"RESTORE-ASKING" } else { b"RESTORE" };
+    let mut request = encode_request(&[b"SELECT", args.destination_db.to_string().as_bytes()]);
+    for (key, payload) in &payloads {
+        let mut restore: Vec<&[u8]> = vec![restore_cmd, key.as_bytes(), b"0", payload];
+        if args.replace {
+            restore.push(b"REPLACE");
+        }
+        request.extend(encode_request(&restore));
+    }
+
+    let limit = Duration::from_millis(args.timeout_ms);
+    let mut stream = match timeout(limit, TcpStream::connect((args.host.as_str(), args.port))).await {
+        Ok(Ok(stream)) => stream,
+        _ => return Err("IOERR error or timeout connecting to the client"),
+    };
+    if !matches!(timeout(limit, stream.write_all(&request)).await, Ok(Ok(()))) {
+        return Err("IOERR error or timeout writing to target instance");
+    }
+    let replies = match timeout(limit, read_replies(&mut stream, payloads.len() + 1)).await {
+        Ok(Ok(replies)) => replies,
+        _ => return Err("IOERR error or timeout reading to target instance"),
+    };
+
+    // Keys restored into another database than the requested one are not removed
+    if let Err(error) = &replies[0] {
+        return Ok(format!("-Target instance replied with error: {}\r\n", error));
+    }
+
+    let mut first_error = None;
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    for ((key, _), reply) in payloads.iter().zip(&replies[1..]) {
+        match reply {
+            Ok(()) if !args.copy => {
+                cur_db.data.remove(key);
+            }
+            Ok(()) => {}
+            Err(error) => {
+                first_error.get_or_insert_with(|| error.clone());
+            }
+        }
+    }
+
+    Ok(match first_error {
+        Some(error) => format!("-Target instance replied with error: {}\r\n", error),
+        None => "+OK\r\n".to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{net::TcpListener, task::JoinHandle};
+    use crate::database::Data;
+    use crate::utils::{read_frame, Frame};
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn source_db() -> Arc<Mutex<HashMap<u8, Database>>> {
+        let mut database = Database::new();
+        database.data.insert("a".to_string(), Data::Scalar(b"1".to_vec()));
+        database.data.insert("b".to_string(), Data::Scalar(b"2".to_vec()));
+        Arc::new(Mutex::new(HashMap::from([(0, database)])))
+    }
+
+    // A target that reads the given number of requests, sends replies and returns the requests
+    async fn fake_target(requests: usize, replies: &'static [u8]) -> (u16, JoinHandle<Vec<Frame>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut pending = Vec::new();
+            let mut frames = Vec::new();
+            let mut chunk = [0; 1024];
+            while frames.len() < requests {
+                while let Some((frame, consumed)) = read_frame(&pending).unwrap() {
+                    frames.push(frame);
+                    pending.drain(..consumed);
+                }
+                if frames.len() < requests {
+                    let bytes_read = stream.read(&mut chunk).await.unwrap();
+                    pending.extend_from_slice(&chunk[..bytes_read]);
+                }
+            }
+            stream.write_all(replies).await.unwrap();
+            frames
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn migrate_parses_options() {
+        let migrate = parse_migrate_args(&args(&["127.0.0.1", "6380", "k", "3", "0", "copy", "REPLACE"]), 3).unwrap();
+        assert_eq!(migrate.keys, ["k"]);
+        assert_eq!(migrate.timeout_ms, 1000);
+        assert!(migrate.copy && migrate.replace);
+
+        let migrate = parse_migrate_args(&args(&["127.0.0.1", "6380", "", "0", "50", "KEYS", "a", "b"]), 0).unwrap();
+        assert_eq!(migrate.keys, ["a", "b"]);
+        assert_eq!(migrate.timeout_ms, 50);
+        assert!(!migrate.copy && !migrate.replace);
+    }
+
+    #[test]
+    fn migrate_rejects_bad_options() {
+        assert_eq!(parse_migrate_args(&args(&["h", "70000", "k", "0", "0"]), 0), Err("Invalid port"));
+        assert_eq!(parse_migrate_args(&args(&["h", "1", "k", "0", "-1"]), 0), Err("timeout is not an integer or out of range"));
+        assert_eq!(parse_migrate_args(&args(&["h", "1", "k", "0", "0", "KEYS", "a"]), 0), Err("When using MIGRATE KEYS option, the key argument must be set to the empty string"));
+        assert_eq!(parse_migrate_args(&args(&["h", "1", "", "0", "0", "KEYS"]), 0), Err("syntax error"));
+        assert_eq!(parse_migrate_args(&args(&["h", "1", "k", "0", "0", "MOVE"]), 0), Err("syntax error"));
+    }
+
+    #[tokio::test]
+    async fn migrate_without_keys_replies_nokey() {
+        let migrate = parse_migrate_args(&args(&["127.0.0.1", "1", "", "0", "0", "KEYS", "missing"]), 0).unwrap();
+        assert_eq!(execute_migrate_cmd(migrate, &source_db(), &mut 0, false).await, Ok("+NOKEY\r\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn migrate_removes_the_keys_the_target_accepted() {
+        let (port, target) = fake_target(3, b"+OK\r\n+OK\r\n-BUSYKEY Target key name already exists.\r\n").await;
+        let db = source_db();
+        let migrate = parse_migrate_args(&args(&["127.0.0.1", &port.to_string(), "", "5", "0", "KEYS", "a", "b"]), 5).unwrap();
+
+        let reply = execute_migrate_cmd(migrate, &db, &mut 0, false).await;
+        assert_eq!(reply, Ok("-Target instance replied with error: BUSYKEY Target key name already exists.\r\n".to_string()));
+        assert!(!db.lock().unwrap()[&0].data.contains_key("a"));
+        assert!(db.lock().unwrap()[&0].data.contains_key("b"));
+
+        let requests = target.await.unwrap();
+        assert_eq!(requests[0], [b"SELECT".to_vec(), b"5".to_vec()]);
+        assert_eq!(requests[1][..3], [b"RESTORE".to_vec(), b"a".to_vec(), b"0".to_vec()]);
+        assert_eq!(requests[2].len(), 4);
+    }
+
+    #[tokio::test]
+    async fn migrate_keeps_keys_when_copying_or_when_select_fails() {
+        let (port, target) = fake_target(2, b"+OK\r\n+OK\r\n").await;
+        let db = source_db();
+        let migrate = parse_migrate_args(&args(&["127.0.0.1", &port.to_string(), "a", "0", "0", "COPY", "REPLACE"]), 0).unwrap();
+        assert_eq!(execute_migrate_cmd(migrate, &db, &mut 0, true).await, Ok("+OK\r\n".to_string()));
+        assert!(db.lock().unwrap()[&0].data.contains_key("a"));
+        let requests = target.await.unwrap();
+        assert_eq!(requests[1][0], b"RESTORE-ASKING");
+        assert_eq!(requests[1][4], b"REPLACE");
+
+        let (port, _target) = fake_target(2, b"-ERR DB index is out of range\r\n+OK\r\n").await;
+        let migrate = parse_migrate_args(&args(&["127.0.0.1", &port.to_string(), "a", "99", "0"]), 99).unwrap();
+        assert_eq!(execute_migrate_cmd(migrate, &db, &mut 0, false).await, Ok("-Target instance replied with error: ERR DB index is out of range\r\n".to_string()));
+        assert!(db.lock().unwrap()[&0].data.contains_key("a"));
+    }
+}
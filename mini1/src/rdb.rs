@@ -0,0 +1,1419 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use crate::bloom::{bits_per_entry, BloomFilter, ScalableBloom, TIGHTENING_RATIO};
+use crate::cms::CountMinSketch;
+use crate::database::{Data, Database, KeyMeta, LFU_INIT_VAL};
+use crate::sorted_set::SortedSet;
+use crate::stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamId};
+use crate::stream::now_ms;
+use crate::timeseries::{DuplicatePolicy, TimeSeries};
+use crate::utils::MAX_DATABASES;
+
+// The RDB version written, and the newest one whose payloads are accepted
+pub const RDB_VERSION: u16 = 11;
+pub const RDB_MAX_LOAD_VERSION: u16 = 12;
+
+pub static BAD_DATA: &str = "Bad data format";
+// Time series have no Redis-compatible payload (see MODULE_TIMESERIES)
+pub static TIMESERIES_DUMP: &str = "DUMP payloads of time series are not supported";
+// Lists, hashes, sets, sorted sets and streams hold text, so their elements must be UTF-8
+pub static NOT_UTF8: &str = "Bad data format: element is not valid UTF-8";
+
+// Object types
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_MODULE_2: u8 = 7;
+const RDB_TYPE_HASH_ZIPMAP: u8 = 9;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+// Quicklist node containers
+const QUICKLIST_NODE_PLAIN: u64 = 1;
+const QUICKLIST_NODE_PACKED: u64 = 2;
+
+// The most elements written to one listpack of a list or stream
+const LISTPACK_NODE_ENTRIES: usize = 128;
+const STREAM_NODE_ENTRIES: usize = 100;
+
+// The most bytes one byte of LZF data expands to
+const LZF_MAX_EXPANSION: usize = 88;
+
+// Stream entry flags
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+// Module value opcodes
+const MODULE_OPCODE_EOF: u64 = 0;
+const MODULE_OPCODE_SINT: u64 = 1;
+const MODULE_OPCODE_UINT: u64 = 2;
+const MODULE_OPCODE_DOUBLE: u64 = 4;
+const MODULE_OPCODE_STRING: u64 = 5;
+
+// Module types: RedisJSON, RedisBloom's Bloom filter and Count-Min Sketch, and the
+// time series. The RedisTimeSeries layout (TSDB-TYPE) is not implemented, so time series
+// get a type of their own, which is only written to the RDB files of this server and is
+// never sent out as a DUMP payload.
+const MODULE_JSON: (&str, u64) = ("ReJSON-RL", 3);
+const MODULE_BLOOM: (&str, u64) = ("MBbloom--", 4);
+const MODULE_CMS: (&str, u64) = ("CMSk-TYPE", 0);
+const MODULE_TIMESERIES: (&str, u64) = ("mredis-TS", 0);
+const MODULE_NAME_CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// RedisBloom filter options
+const BLOOM_OPT_NOROUND: u64 = 1;
+const BLOOM_OPT_FORCE64: u64 = 4;
+const BLOOM_OPT_NO_SCALING: u64 = 8;
+
+const DUPLICATE_POLICIES: [DuplicatePolicy; 6] = [
+    DuplicatePolicy::Block, DuplicatePolicy::First, DuplicatePolicy::Last,
+    DuplicatePolicy::Min, DuplicatePolicy::Max, DuplicatePolicy::Sum,
+];
+
+// The CRC-64/Jones checksum used by Redis, continuing from crc
+pub fn crc64(mut crc: u64, bytes: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac_9329_ac4b_c9b5;
+    for byte in bytes {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+// Serialize a value the way DUMP does: the object, then the RDB version and a CRC64 of everything before it.
+// Time series are refused, since their payload would not be the one RedisTimeSeries reads.
+pub fn dump(data: &Data) -> Result<Vec<u8>, &'static str> {
+    if matches!(data, Data::TimeSeries(_)) {
+        return Err(TIMESERIES_DUMP);
+    }
+    let mut payload = vec![object_type(data)];
+    write_value(&mut payload, data);
+    payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let crc = crc64(0, &payload);
+    payload.extend_from_slice(&crc.to_le_bytes());
+    Ok(payload)
+}
+
+// Deserialize a DUMP payload, checking its version and checksum. A zero checksum is not checked, like in Redis.
+pub fn restore(payload: &[u8]) -> Result<Data, &'static str> {
+    let invalid = "DUMP payload version or checksum are wrong";
+    if payload.len() < 11 {
+        return Err(invalid);
+    }
+    let (body, crc) = payload.split_at(payload.len() - 8);
+    let version = u16::from_le_bytes([body[body.len() - 2], body[body.len() - 1]]);
+    let crc = u64::from_le_bytes(crc.try_into().unwrap());
+    if version > RDB_MAX_LOAD_VERSION || (crc != 0 && crc != crc64(0, body)) {
+        return Err(invalid);
+    }
+
+    let mut reader = RdbReader::new(&body[..body.len() - 2]);
+    let object_type = reader.read_u8()?;
+    let data = reader.read_value(object_type)?;
+    if !reader.is_empty() {
+        return Err(BAD_DATA);
+    }
+    if matches!(data, Data::TimeSeries(_)) {
+        return Err(TIMESERIES_DUMP);
+    }
+    Ok(data)
+}
+
+// The object type a value is written as
+pub fn object_type(data: &Data) -> u8 {
+    match data {
+        Data::Scalar(_) => RDB_TYPE_STRING,
+        Data::List(_) => RDB_TYPE_LIST_QUICKLIST_2,
+        Data::Hash(_) => RDB_TYPE_HASH,
+        Data::Set(_) => RDB_TYPE_SET,
+        Data::SortedSet(_) => RDB_TYPE_ZSET_2,
+        Data::Stream(_) => RDB_TYPE_STREAM_LISTPACKS_3,
+        Data::Json(_) | Data::Bloom(_) | Data::CountMinSketch(_) | Data::TimeSeries(_) => RDB_TYPE_MODULE_2,
+    }
+}
+
+// Write the length encoding of len
+pub fn write_length(out: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        out.push(len as u8);
+    } else if len < 1 << 14 {
+        out.extend_from_slice(&[(len >> 8) as u8 | 0x40, len as u8]);
+    } else if len <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+// Write a string, using the integer encodings for short canonical integers
+pub fn write_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    if let Some(value) = canonical_int(bytes).filter(|_| bytes.len() <= 11) {
+        if let Ok(value) = i8::try_from(value) {
+            out.push(0xc0);
+            out.extend_from_slice(&value.to_le_bytes());
+            return;
+        } else if let Ok(value) = i16::try_from(value) {
+            out.push(0xc1);
+            out.extend_from_slice(&value.to_le_bytes());
+            return;
+        } else if let Ok(value) = i32::try_from(value) {
+            out.push(0xc2);
+            out.extend_from_slice(&value.to_le_bytes());
+            return;
+        }
+    }
+    write_length(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+// The integer a string holds, if it is written exactly as Redis would print it
+fn canonical_int(bytes: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    text.parse::<i64>().ok().filter(|value| value.to_string() == text)
+}
+
+fn write_double(out: &mut Vec<u8>, value: f64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_ms_time(out: &mut Vec<u8>, ms: u64) {
+    out.extend_from_slice(&ms.to_le_bytes());
+}
+
+// A stream ID in the 128-bit big-endian form used as a key of the stream's radix tree
+fn raw_stream_id(id: &StreamId) -> [u8; 16] {
+    let mut raw = [0; 16];
+    raw[..8].copy_from_slice(&id.ms.to_be_bytes());
+    raw[8..].copy_from_slice(&id.seq.to_be_bytes());
+    raw
+}
+
+// Write the value of an object, without its type
+pub fn write_value(out: &mut Vec<u8>, data: &Data) {
+    match data {
+        Data::Scalar(value) => write_string(out, value),
+        Data::List(list) => {
+            let items: Vec<&[u8]> = list.iter().map(|item| item.as_bytes()).collect();
+            write_length(out, items.len().div_ceil(LISTPACK_NODE_ENTRIES) as u64);
+            for node in items.chunks(LISTPACK_NODE_ENTRIES) {
+                write_length(out, QUICKLIST_NODE_PACKED);
+                write_string(out, &listpack(node.iter().map(|item| ListpackEntry::from(*item))));
+            }
+        }
+        Data::Hash(hash) => {
+            write_length(out, hash.len() as u64);
+            for (field, value) in hash {
+                write_string(out, field.as_bytes());
+                write_string(out, value.as_bytes());
+            }
+        }
+        Data::Set(set) => {
+            write_length(out, set.len() as u64);
+            for member in set {
+                write_string(out, member.as_bytes());
+            }
+        }
+        Data::SortedSet(zset) => {
+            write_length(out, zset.len() as u64);
+            // Highest scores first, the order Redis writes them in
+            for (member, score) in zset.iter().rev() {
+                write_string(out, member.as_bytes());
+                write_double(out, score);
+            }
+        }
+        Data::Stream(stream) => write_stream(out, stream),
+        Data::Json(value) => {
+            let mut module = ModuleWriter::new(out, MODULE_JSON);
+            module.string(value.to_string().as_bytes());
+            module.finish();
+        }
+        Data::Bloom(bloom) => {
+            let mut options = BLOOM_OPT_NOROUND | BLOOM_OPT_FORCE64;
+            if bloom.expansion == 0 {
+                options |= BLOOM_OPT_NO_SCALING;
+            }
+            let mut module = ModuleWriter::new(out, MODULE_BLOOM);
+            module.unsigned(bloom.count());
+            module.unsigned(bloom.filters.len() as u64);
+            module.unsigned(options);
+            module.unsigned(bloom.expansion as u64);
+            for filter in &bloom.filters {
+                let bytes: Vec<u8> = filter.bits.iter().flat_map(|word| word.to_le_bytes()).take((filter.bit_count / 8) as usize).collect();
+                module.unsigned(filter.capacity);
+                module.double(filter.error_rate);
+                module.unsigned(filter.hashes as u64);
+                module.double(bits_per_entry(filter.error_rate));
+                module.unsigned(filter.bit_count);
+                module.unsigned(0);
+                module.string(&bytes);
+                module.unsigned(filter.count);
+            }
+            module.finish();
+        }
+        Data::CountMinSketch(cms) => {
+            let mut module = ModuleWriter::new(out, MODULE_CMS);
+            module.unsigned(cms.width as u64);
+            module.unsigned(cms.depth as u64);
+            module.unsigned(cms.count);
+            module.string(&cms.counters.iter().flat_map(|counter| counter.to_le_bytes()).collect::<Vec<u8>>());
+            module.finish();
+        }
+        Data::TimeSeries(series) => {
+            let mut module = ModuleWriter::new(out, MODULE_TIMESERIES);
+            module.unsigned(series.retention);
+            module.unsigned(DUPLICATE_POLICIES.iter().position(|policy| *policy == series.duplicate_policy).unwrap_or(0) as u64);
+            module.unsigned(series.labels.len() as u64);
+            for (label, value) in &series.labels {
+                module.string(label.as_bytes());
+                module.string(value.as_bytes());
+            }
+            module.unsigned(series.samples.len() as u64);
+            for (timestamp, value) in &series.samples {
+                module.unsigned(*timestamp);
+                module.double(*value);
+            }
+            module.finish();
+        }
+    }
+}
+
+// Write a stream as listpacks of up to STREAM_NODE_ENTRIES entries, followed by its metadata and consumer groups
+fn write_stream(out: &mut Vec<u8>, stream: &Stream) {
+    let entries: Vec<(&StreamId, &Vec<(String, String)>)> = stream.entries.iter().collect();
+    write_length(out, entries.len().div_ceil(STREAM_NODE_ENTRIES) as u64);
+    for node in entries.chunks(STREAM_NODE_ENTRIES) {
+        let (master_id, master_fields) = node[0];
+        let mut items = vec![
+            ListpackEntry::Int(node.len() as i64),
+            ListpackEntry::Int(0),
+            ListpackEntry::Int(master_fields.len() as i64),
+        ];
+        items.extend(master_fields.iter().map(|(field, _)| ListpackEntry::from(field.as_bytes())));
+        items.push(ListpackEntry::Int(0));
+
+        for (id, fields) in node {
+            let same_fields = fields.len() == master_fields.len()
+                && fields.iter().zip(master_fields.iter()).all(|((field, _), (master_field, _))| field == master_field);
+            items.push(ListpackEntry::Int(if same_fields { STREAM_ITEM_FLAG_SAMEFIELDS } else { 0 }));
+            items.push(ListpackEntry::Int(id.ms.wrapping_sub(master_id.ms) as i64));
+            items.push(ListpackEntry::Int((id.seq as i64).wrapping_sub(master_id.seq as i64)));
+            if same_fields {
+                items.extend(fields.iter().map(|(_, value)| ListpackEntry::from(value.as_bytes())));
+                items.push(ListpackEntry::Int(fields.len() as i64 + 3));
+            } else {
+                items.push(ListpackEntry::Int(fields.len() as i64));
+                for (field, value) in fields.iter() {
+                    items.push(ListpackEntry::from(field.as_bytes()));
+                    items.push(ListpackEntry::from(value.as_bytes()));
+                }
+                items.push(ListpackEntry::Int(fields.len() as i64 * 2 + 4));
+            }
+        }
+
+        write_string(out, &raw_stream_id(master_id));
+        write_string(out, &listpack(items.into_iter()));
+    }
+
+    let first_id = stream.entries.keys().next().copied().unwrap_or_default();
+    write_length(out, stream.entries.len() as u64);
+    for id in [stream.last_id, first_id, stream.max_deleted_id] {
+        write_length(out, id.ms);
+        write_length(out, id.seq);
+    }
+    write_length(out, stream.entries_added);
+
+    write_length(out, stream.groups.len() as u64);
+    for (name, group) in &stream.groups {
+        write_string(out, name.as_bytes());
+        write_length(out, group.last_delivered.ms);
+        write_length(out, group.last_delivered.seq);
+        // An unknown read count is written as -1
+        write_length(out, group.entries_read.unwrap_or(u64::MAX));
+
+        write_length(out, group.pending.len() as u64);
+        for (id, entry) in &group.pending {
+            out.extend_from_slice(&raw_stream_id(id));
+            write_ms_time(out, entry.delivery_time);
+            write_length(out, entry.delivery_count);
+        }
+
+        write_length(out, group.consumers.len() as u64);
+        for (consumer_name, consumer) in &group.consumers {
+            write_string(out, consumer_name.as_bytes());
+            write_ms_time(out, consumer.seen_time);
+            write_ms_time(out, consumer.active_time.unwrap_or(u64::MAX));
+            let owned: Vec<&StreamId> = group.pending.iter().filter(|(_, entry)| entry.consumer == *consumer_name).map(|(id, _)| id).collect();
+            write_length(out, owned.len() as u64);
+            for id in owned {
+                out.extend_from_slice(&raw_stream_id(id));
+            }
+        }
+    }
+}
+
+// The identifier of a module type: its nine character name in 6-bit symbols, then its encoding version
+fn module_type_id((name, encoding_version): (&str, u64)) -> u64 {
+    let name = name.bytes().fold(0, |id, c| (id << 6) | MODULE_NAME_CHARSET.iter().position(|symbol| *symbol == c).unwrap_or(0) as u64);
+    (name << 10) | encoding_version
+}
+
+// Writes the value of a module type as a sequence of typed fields
+struct ModuleWriter<'a> {
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> ModuleWriter<'a> {
+    fn new(out: &'a mut Vec<u8>, module: (&str, u64)) -> Self {
+        write_length(out, module_type_id(module));
+        Self { out }
+    }
+
+    fn unsigned(&mut self, value: u64) {
+        write_length(self.out, MODULE_OPCODE_UINT);
+        write_length(self.out, value);
+    }
+
+    fn double(&mut self, value: f64) {
+        write_length(self.out, MODULE_OPCODE_DOUBLE);
+        write_double(self.out, value);
+    }
+
+    fn string(&mut self, bytes: &[u8]) {
+        write_length(self.out, MODULE_OPCODE_STRING);
+        write_string(self.out, bytes);
+    }
+
+    fn finish(self) {
+        write_length(self.out, MODULE_OPCODE_EOF);
+    }
+}
+
+// An element of a listpack or ziplist
+#[derive(Debug, Clone, PartialEq)]
+enum ListpackEntry {
+    Int(i64),
+    Str(Vec<u8>),
+}
+
+impl From<&[u8]> for ListpackEntry {
+    // Canonical integers are stored as integers, like Redis does
+    fn from(bytes: &[u8]) -> Self {
+        match canonical_int(bytes) {
+            Some(value) => ListpackEntry::Int(value),
+            None => ListpackEntry::Str(bytes.to_vec()),
+        }
+    }
+}
+
+impl ListpackEntry {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            ListpackEntry::Int(value) => value.to_string().into_bytes(),
+            ListpackEntry::Str(bytes) => bytes,
+        }
+    }
+
+    fn into_string(self) -> Result<String, &'static str> {
+        utf8(self.into_bytes())
+    }
+
+    fn as_int(&self) -> Result<i64, &'static str> {
+        match self {
+            ListpackEntry::Int(value) => Ok(*value),
+            ListpackEntry::Str(bytes) => canonical_int(bytes).ok_or(BAD_DATA),
+        }
+    }
+
+    fn as_score(&self) -> Result<f64, &'static str> {
+        match self {
+            ListpackEntry::Int(value) => Ok(*value as f64),
+            ListpackEntry::Str(bytes) => std::str::from_utf8(bytes).ok().and_then(|text| text.parse().ok()).filter(|score: &f64| !score.is_nan()).ok_or(BAD_DATA),
+        }
+    }
+}
+
+// Encode elements as a listpack
+fn listpack(entries: impl Iterator<Item = ListpackEntry>) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut count = 0usize;
+    for entry in entries {
+        let start = body.len();
+        match entry {
+            ListpackEntry::Int(value) => match value {
+                0..=127 => body.push(value as u8),
+                -4096..=4095 => {
+                    let value = value as u16 & 0x1fff;
+                    body.extend_from_slice(&[0xc0 | (value >> 8) as u8, value as u8]);
+                }
+                _ if i16::try_from(value).is_ok() => {
+                    body.push(0xf1);
+                    body.extend_from_slice(&(value as i16).to_le_bytes());
+                }
+                -8_388_608..=8_388_607 => {
+                    body.push(0xf2);
+                    body.extend_from_slice(&(value as i32).to_le_bytes()[..3]);
+                }
+                _ if i32::try_from(value).is_ok() => {
+                    body.push(0xf3);
+                    body.extend_from_slice(&(value as i32).to_le_bytes());
+                }
+                _ => {
+                    body.push(0xf4);
+                    body.extend_from_slice(&value.to_le_bytes());
+                }
+            },
+            ListpackEntry::Str(bytes) => {
+                let len = bytes.len();
+                if len < 64 {
+                    body.push(0x80 | len as u8);
+                } else if len < 4096 {
+                    body.extend_from_slice(&[0xe0 | (len >> 8) as u8, len as u8]);
+                } else {
+                    body.push(0xf0);
+                    body.extend_from_slice(&(len as u32).to_le_bytes());
+                }
+                body.extend_from_slice(&bytes);
+            }
+        }
+
+        // The size of the entry, readable from its last byte to walk the listpack backwards
+        let size = body.len() - start;
+        let groups = backlen_size(size);
+        for group in (0..groups).rev() {
+            let bits = ((size >> (7 * group)) & 0x7f) as u8;
+            body.push(if group == groups - 1 { bits } else { bits | 0x80 });
+        }
+        count += 1;
+    }
+
+    let mut lp = Vec::with_capacity(body.len() + 7);
+    lp.extend_from_slice(&(body.len() as u32 + 7).to_le_bytes());
+    lp.extend_from_slice(&(count.min(u16::MAX as usize) as u16).to_le_bytes());
+    lp.extend_from_slice(&body);
+    lp.push(0xff);
+    lp
+}
+
+// The number of bytes used to store the size of a listpack entry
+fn backlen_size(size: usize) -> usize {
+    match size {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2_097_150 => 3,
+        2_097_151..=268_435_454 => 4,
+        _ => 5,
+    }
+}
+
+// A cursor over a byte buffer that fails with BAD_DATA when the buffer ends early
+struct Bytes<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.buf.len()).ok_or(BAD_DATA)?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> Result<u8, &'static str> {
+        self.buf.get(self.pos).copied().ok_or(BAD_DATA)
+    }
+
+    // A little-endian signed integer of n bytes
+    fn int_le(&mut self, n: usize) -> Result<i64, &'static str> {
+        let bytes = self.take(n)?;
+        let mut value = [if bytes[n - 1] & 0x80 != 0 { 0xff } else { 0 }; 8];
+        value[..n].copy_from_slice(bytes);
+        Ok(i64::from_le_bytes(value))
+    }
+
+    fn u32_le(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+fn next_item(items: &mut impl Iterator<Item = ListpackEntry>) -> Result<ListpackEntry, &'static str> {
+    items.next().ok_or(BAD_DATA)
+}
+
+fn next_int(items: &mut impl Iterator<Item = ListpackEntry>) -> Result<i64, &'static str> {
+    next_item(items)?.as_int()
+}
+
+// Decode the elements of a listpack
+fn parse_listpack(lp: &[u8]) -> Result<Vec<ListpackEntry>, &'static str> {
+    let mut bytes = Bytes { buf: lp, pos: 6 };
+    let mut entries = Vec::new();
+    loop {
+        let start = bytes.pos;
+        let encoding = bytes.u8()?;
+        let entry = match encoding {
+            0xff => return Ok(entries),
+            0x00..=0x7f => ListpackEntry::Int(encoding as i64),
+            0x80..=0xbf => ListpackEntry::Str(bytes.take((encoding & 0x3f) as usize)?.to_vec()),
+            0xc0..=0xdf => {
+                let value = (((encoding & 0x1f) as i64) << 8) | bytes.u8()? as i64;
+                ListpackEntry::Int(if value >= 1 << 12 { value - (1 << 13) } else { value })
+            }
+            0xe0..=0xef => {
+                let len = (((encoding & 0x0f) as usize) << 8) | bytes.u8()? as usize;
+                ListpackEntry::Str(bytes.take(len)?.to_vec())
+            }
+            0xf0 => {
+                let len = bytes.u32_le()? as usize;
+                ListpackEntry::Str(bytes.take(len)?.to_vec())
+            }
+            0xf1 => ListpackEntry::Int(bytes.int_le(2)?),
+            0xf2 => ListpackEntry::Int(bytes.int_le(3)?),
+            0xf3 => ListpackEntry::Int(bytes.int_le(4)?),
+            0xf4 => ListpackEntry::Int(bytes.int_le(8)?),
+            _ => return Err(BAD_DATA),
+        };
+        bytes.take(backlen_size(bytes.pos - start))?;
+        entries.push(entry);
+    }
+}
+
+// Decode the elements of a ziplist, the encoding listpacks replaced
+fn parse_ziplist(zl: &[u8]) -> Result<Vec<ListpackEntry>, &'static str> {
+    let mut bytes = Bytes { buf: zl, pos: 10 };
+    let mut entries = Vec::new();
+    loop {
+        match bytes.peek()? {
+            0xff => return Ok(entries),
+            0xfe => bytes.take(5)?,
+            _ => bytes.take(1)?,
+        };
+        let encoding = bytes.u8()?;
+        let entry = match encoding {
+            0x00..=0x3f => ListpackEntry::Str(bytes.take(encoding as usize)?.to_vec()),
+            0x40..=0x7f => {
+                let len = (((encoding & 0x3f) as usize) << 8) | bytes.u8()? as usize;
+                ListpackEntry::Str(bytes.take(len)?.to_vec())
+            }
+            0x80 => {
+                let len = u32::from_be_bytes(bytes.take(4)?.try_into().unwrap()) as usize;
+                ListpackEntry::Str(bytes.take(len)?.to_vec())
+            }
+            0xc0 => ListpackEntry::Int(bytes.int_le(2)?),
+            0xd0 => ListpackEntry::Int(bytes.int_le(4)?),
+            0xe0 => ListpackEntry::Int(bytes.int_le(8)?),
+            0xf0 => ListpackEntry::Int(bytes.int_le(3)?),
+            0xfe => ListpackEntry::Int(bytes.int_le(1)?),
+            0xf1..=0xfd => ListpackEntry::Int((encoding & 0x0f) as i64 - 1),
+            _ => return Err(BAD_DATA),
+        };
+        entries.push(entry);
+    }
+}
+
+// Decode the members of an intset
+fn parse_intset(intset: &[u8]) -> Result<Vec<i64>, &'static str> {
+    let mut bytes = Bytes { buf: intset, pos: 0 };
+    let width = bytes.u32_le()? as usize;
+    let len = bytes.u32_le()?;
+    if ![2, 4, 8].contains(&width) {
+        return Err(BAD_DATA);
+    }
+    (0..len).map(|_| bytes.int_le(width)).collect()
+}
+
+// Take bytes as text, rejecting them rather than replacing what is not UTF-8
+fn utf8(bytes: Vec<u8>) -> Result<String, &'static str> {
+    String::from_utf8(bytes).map_err(|_| NOT_UTF8)
+}
+
+// Decode the field-value pairs of a zipmap, the oldest compact hash encoding
+fn parse_zipmap(zipmap: &[u8]) -> Result<HashMap<String, String>, &'static str> {
+    let mut bytes = Bytes { buf: zipmap, pos: 1 };
+    let read_len = |bytes: &mut Bytes| -> Result<usize, &'static str> {
+        match bytes.u8()? {
+            254 => Ok(bytes.u32_le()? as usize),
+            255 => Err(BAD_DATA),
+            len => Ok(len as usize),
+        }
+    };
+    let mut hash = HashMap::new();
+    while bytes.peek()? != 0xff {
+        let field_len = read_len(&mut bytes)?;
+        let field = utf8(bytes.take(field_len)?.to_vec())?;
+        let value_len = read_len(&mut bytes)?;
+        let free = bytes.u8()? as usize;
+        let value = utf8(bytes.take(value_len)?.to_vec())?;
+        bytes.take(free)?;
+        hash.insert(field, value);
+    }
+    Ok(hash)
+}
+
+// Decompress LZF data into len bytes
+fn lzf_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, &'static str> {
+    // A back reference takes 3 bytes and copies at most 264, so a longer output is corrupt.
+    // Checking first keeps a forged length from reserving memory it can never fill.
+    if len > input.len().saturating_mul(LZF_MAX_EXPANSION) {
+        return Err(BAD_DATA);
+    }
+    let mut output = Vec::with_capacity(len);
+    let mut bytes = Bytes { buf: input, pos: 0 };
+    while bytes.pos < input.len() {
+        let ctrl = bytes.u8()? as usize;
+        if ctrl < 32 {
+            output.extend_from_slice(bytes.take(ctrl + 1)?);
+            continue;
+        }
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += bytes.u8()? as usize;
+        }
+        let back = ((ctrl & 0x1f) << 8) + bytes.u8()? as usize + 1;
+        let start = output.len().checked_sub(back).ok_or(BAD_DATA)?;
+        // The reference may overlap the bytes being written, so copy one byte at a time
+        for i in 0..run + 2 {
+            output.push(output[start + i]);
+        }
+    }
+    if output.len() != len {
+        return Err(BAD_DATA);
+    }
+    Ok(output)
+}
+
+// Reads RDB-encoded values
+pub struct RdbReader<'a> {
+    bytes: Bytes<'a>,
+}
+
+impl<'a> RdbReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { bytes: Bytes { buf, pos: 0 } }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.pos == self.bytes.buf.len()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, &'static str> {
+        self.bytes.u8()
+    }
+
+    // A length, or the kind of a specially encoded string when the second value is true
+    fn read_length_or_encoding(&mut self) -> Result<(u64, bool), &'static str> {
+        let first = self.bytes.u8()?;
+        Ok(match first >> 6 {
+            0 => ((first & 0x3f) as u64, false),
+            1 => ((((first & 0x3f) as u64) << 8) | self.bytes.u8()? as u64, false),
+            2 => match first {
+                0x80 => (u32::from_be_bytes(self.bytes.take(4)?.try_into().unwrap()) as u64, false),
+                0x81 => (u64::from_be_bytes(self.bytes.take(8)?.try_into().unwrap()), false),
+                _ => return Err(BAD_DATA),
+            },
+            _ => ((first & 0x3f) as u64, true),
+        })
+    }
+
+    pub fn read_length(&mut self) -> Result<u64, &'static str> {
+        match self.read_length_or_encoding()? {
+            (len, false) => Ok(len),
+            _ => Err(BAD_DATA),
+        }
+    }
+
+    // A length used to size an allocation, which cannot exceed the bytes left
+    fn read_count(&mut self) -> Result<usize, &'static str> {
+        let len = self.read_length()?;
+        usize::try_from(len).ok().filter(|len| *len <= self.bytes.buf.len() - self.bytes.pos).ok_or(BAD_DATA)
+    }
+
+    pub fn read_string(&mut self) -> Result<Vec<u8>, &'static str> {
+        match self.read_length_or_encoding()? {
+            (len, false) => Ok(self.bytes.take(usize::try_from(len).map_err(|_| BAD_DATA)?)?.to_vec()),
+            (0, true) => Ok(self.bytes.int_le(1)?.to_string().into_bytes()),
+            (1, true) => Ok(self.bytes.int_le(2)?.to_string().into_bytes()),
+            (2, true) => Ok(self.bytes.int_le(4)?.to_string().into_bytes()),
+            (3, true) => {
+                let compressed_len = self.read_count()?;
+                let len = self.read_length()?;
+                let compressed = self.bytes.take(compressed_len)?;
+                lzf_decompress(compressed, usize::try_from(len).map_err(|_| BAD_DATA)?)
+            }
+            _ => Err(BAD_DATA),
+        }
+    }
+
+    fn read_text(&mut self) -> Result<String, &'static str> {
+        utf8(self.read_string()?)
+    }
+
+    fn read_double(&mut self) -> Result<f64, &'static str> {
+        Ok(f64::from_le_bytes(self.bytes.take(8)?.try_into().unwrap()))
+    }
+
+    // A score of the original sorted set encoding, stored as text behind a one byte length
+    fn read_text_double(&mut self) -> Result<f64, &'static str> {
+        match self.bytes.u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => {
+                let text = self.bytes.take(len as usize)?;
+                std::str::from_utf8(text).ok().and_then(|text| text.parse().ok()).ok_or(BAD_DATA)
+            }
+        }
+    }
+
+    fn read_ms_time(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.bytes.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_raw_stream_id(&mut self) -> Result<StreamId, &'static str> {
+        let raw = self.bytes.take(16)?;
+        Ok(StreamId {
+            ms: u64::from_be_bytes(raw[..8].try_into().unwrap()),
+            seq: u64::from_be_bytes(raw[8..].try_into().unwrap()),
+        })
+    }
+
+    fn read_stream_id(&mut self) -> Result<StreamId, &'static str> {
+        Ok(StreamId { ms: self.read_length()?, seq: self.read_length()? })
+    }
+
+    // Read the value of an object of the given type, in any encoding Redis has written it in
+    pub fn read_value(&mut self, object_type: u8) -> Result<Data, &'static str> {
+        Ok(match object_type {
+            RDB_TYPE_STRING => Data::Scalar(self.read_string()?),
+            RDB_TYPE_LIST => {
+                let len = self.read_count()?;
+                Data::List((0..len).map(|_| self.read_text()).collect::<Result<_, _>>()?)
+            }
+            RDB_TYPE_LIST_ZIPLIST => Data::List(parse_ziplist(&self.read_string()?)?.into_iter().map(ListpackEntry::into_string).collect::<Result<_, _>>()?),
+            RDB_TYPE_LIST_QUICKLIST | RDB_TYPE_LIST_QUICKLIST_2 => {
+                let mut list = VecDeque::new();
+                for _ in 0..self.read_count()? {
+                    let container = if object_type == RDB_TYPE_LIST_QUICKLIST { QUICKLIST_NODE_PACKED } else { self.read_length()? };
+                    let node = self.read_string()?;
+                    match container {
+                        QUICKLIST_NODE_PLAIN => list.push_back(utf8(node)?),
+                        QUICKLIST_NODE_PACKED if object_type == RDB_TYPE_LIST_QUICKLIST => list.extend(parse_ziplist(&node)?.into_iter().map(ListpackEntry::into_string).collect::<Result<Vec<_>, _>>()?),
+                        QUICKLIST_NODE_PACKED => list.extend(parse_listpack(&node)?.into_iter().map(ListpackEntry::into_string).collect::<Result<Vec<_>, _>>()?),
+                        _ => return Err(BAD_DATA),
+                    }
+                }
+                Data::List(list)
+            }
+            RDB_TYPE_SET => {
+                let len = self.read_count()?;
+                Data::Set((0..len).map(|_| self.read_text()).collect::<Result<_, _>>()?)
+            }
+            RDB_TYPE_SET_INTSET => Data::Set(parse_intset(&self.read_string()?)?.into_iter().map(|member| member.to_string()).collect()),
+            RDB_TYPE_SET_LISTPACK => Data::Set(parse_listpack(&self.read_string()?)?.into_iter().map(ListpackEntry::into_string).collect::<Result<HashSet<_>, _>>()?),
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let mut zset = SortedSet::new();
+                for _ in 0..self.read_count()? {
+                    let member = self.read_text()?;
+                    let score = if object_type == RDB_TYPE_ZSET { self.read_text_double()? } else { self.read_double()? };
+                    if score.is_nan() {
+                        return Err(BAD_DATA);
+                    }
+                    zset.insert(member, score);
+                }
+                Data::SortedSet(zset)
+            }
+            RDB_TYPE_ZSET_ZIPLIST | RDB_TYPE_ZSET_LISTPACK => {
+                let node = self.read_string()?;
+                let entries = if object_type == RDB_TYPE_ZSET_ZIPLIST { parse_ziplist(&node)? } else { parse_listpack(&node)? };
+                if entries.len() % 2 != 0 {
+                    return Err(BAD_DATA);
+                }
+                let mut zset = SortedSet::new();
+                let mut entries = entries.into_iter();
+                while let (Some(member), Some(score)) = (entries.next(), entries.next()) {
+                    zset.insert(member.into_string()?, score.as_score()?);
+                }
+                Data::SortedSet(zset)
+            }
+            RDB_TYPE_HASH => {
+                let mut hash = HashMap::new();
+                for _ in 0..self.read_count()? {
+                    hash.insert(self.read_text()?, self.read_text()?);
+                }
+                Data::Hash(hash)
+            }
+            RDB_TYPE_HASH_ZIPMAP => Data::Hash(parse_zipmap(&self.read_string()?)?),
+            RDB_TYPE_HASH_ZIPLIST | RDB_TYPE_HASH_LISTPACK => {
+                let node = self.read_string()?;
+                let entries = if object_type == RDB_TYPE_HASH_ZIPLIST { parse_ziplist(&node)? } else { parse_listpack(&node)? };
+                if entries.len() % 2 != 0 {
+                    return Err(BAD_DATA);
+                }
+                let mut hash = HashMap::new();
+                let mut entries = entries.into_iter();
+                while let (Some(field), Some(value)) = (entries.next(), entries.next()) {
+                    hash.insert(field.into_string()?, value.into_string()?);
+                }
+                Data::Hash(hash)
+            }
+            RDB_TYPE_STREAM_LISTPACKS | RDB_TYPE_STREAM_LISTPACKS_2 | RDB_TYPE_STREAM_LISTPACKS_3 => Data::Stream(self.read_stream(object_type)?),
+            RDB_TYPE_MODULE_2 => self.read_module()?,
+            _ => return Err(BAD_DATA),
+        })
+    }
+
+    // Read a stream: its listpacks of entries, its metadata and its consumer groups
+    fn read_stream(&mut self, object_type: u8) -> Result<Stream, &'static str> {
+        let mut stream = Stream::new();
+        for _ in 0..self.read_count()? {
+            let master_key = self.read_string()?;
+            if master_key.len() != 16 {
+                return Err(BAD_DATA);
+            }
+            let master_id = RdbReader::new(&master_key).read_raw_stream_id()?;
+            let items = &mut parse_listpack(&self.read_string()?)?.into_iter();
+
+            let count = next_int(items)?;
+            let deleted = next_int(items)?;
+            let master_field_count = next_int(items)?;
+            let master_fields = (0..master_field_count)
+                .map(|_| next_item(items)?.into_string())
+                .collect::<Result<Vec<_>, &'static str>>()?;
+            next_item(items)?;
+
+            for _ in 0..count + deleted {
+                let flags = next_int(items)?;
+                let id = StreamId {
+                    ms: master_id.ms.wrapping_add(next_int(items)? as u64),
+                    seq: master_id.seq.wrapping_add(next_int(items)? as u64),
+                };
+                let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+                    master_fields.iter()
+                        .map(|field| Ok((field.clone(), next_item(items)?.into_string()?)))
+                        .collect::<Result<Vec<_>, &'static str>>()?
+                } else {
+                    (0..next_int(items)?)
+                        .map(|_| Ok((next_item(items)?.into_string()?, next_item(items)?.into_string()?)))
+                        .collect::<Result<Vec<_>, &'static str>>()?
+                };
+                // The element count that lets Redis walk the entries backwards
+                next_item(items)?;
+                if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+                    stream.entries.insert(id, fields);
+                }
+            }
+        }
+
+        let len = self.read_length()?;
+        stream.last_id = self.read_stream_id()?;
+        if object_type == RDB_TYPE_STREAM_LISTPACKS {
+            stream.entries_added = len;
+        } else {
+            self.read_stream_id()?;
+            stream.max_deleted_id = self.read_stream_id()?;
+            stream.entries_added = self.read_length()?;
+        }
+
+        for _ in 0..self.read_count()? {
+            let name = self.read_text()?;
+            let last_delivered = self.read_stream_id()?;
+            let entries_read = match object_type {
+                RDB_TYPE_STREAM_LISTPACKS => None,
+                _ => Some(self.read_length()?).filter(|read| *read != u64::MAX),
+            };
+            let mut group = ConsumerGroup::new(last_delivered, entries_read);
+
+            for _ in 0..self.read_count()? {
+                let id = self.read_raw_stream_id()?;
+                let delivery_time = self.read_ms_time()?;
+                let delivery_count = self.read_length()?;
+                group.pending.insert(id, PendingEntry { consumer: String::new(), delivery_time, delivery_count });
+            }
+
+            for _ in 0..self.read_count()? {
+                let consumer_name = self.read_text()?;
+                let seen_time = self.read_ms_time()?;
+                let active_time = match object_type {
+                    RDB_TYPE_STREAM_LISTPACKS_3 => Some(self.read_ms_time()?).filter(|time| *time != u64::MAX),
+                    _ => Some(seen_time),
+                };
+                for _ in 0..self.read_count()? {
+                    let id = self.read_raw_stream_id()?;
+                    group.pending.get_mut(&id).ok_or(BAD_DATA)?.consumer = consumer_name.clone();
+                }
+                group.consumers.insert(consumer_name, Consumer { seen_time, active_time });
+            }
+            if group.pending.values().any(|entry| entry.consumer.is_empty()) {
+                return Err(BAD_DATA);
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+
+    // Read the value of a module type, accepting the types with a known layout
+    fn read_module(&mut self) -> Result<Data, &'static str> {
+        let id = self.read_length()?;
+        let encoding_version = id & 0x3ff;
+        let is = |module: (&str, u64)| id >> 10 == module_type_id(module) >> 10;
+
+        let data = if is(MODULE_JSON) && encoding_version == MODULE_JSON.1 {
+            let text = self.module_string()?;
+            Data::Json(serde_json::from_slice(&text).map_err(|_| BAD_DATA)?)
+        } else if is(MODULE_BLOOM) && (2..=MODULE_BLOOM.1).contains(&encoding_version) {
+            Data::Bloom(self.read_bloom(encoding_version)?)
+        } else if is(MODULE_CMS) && encoding_version == MODULE_CMS.1 {
+            let width = self.module_unsigned()? as usize;
+            let depth = self.module_unsigned()? as usize;
+            let count = self.module_unsigned()?;
+            let counters = self.module_string()?;
+            if width == 0 || depth == 0 || Some(counters.len()) != width.checked_mul(depth).and_then(|len| len.checked_mul(4)) {
+                return Err(BAD_DATA);
+            }
+            let counters = counters.chunks_exact(4).map(|counter| u32::from_le_bytes(counter.try_into().unwrap())).collect();
+            Data::CountMinSketch(CountMinSketch { width, depth, counters, count })
+        } else if is(MODULE_TIMESERIES) && encoding_version == MODULE_TIMESERIES.1 {
+            let retention = self.module_unsigned()?;
+            let duplicate_policy = *DUPLICATE_POLICIES.get(self.module_unsigned()? as usize).ok_or(BAD_DATA)?;
+            let labels = (0..self.module_unsigned()?)
+                .map(|_| Ok((self.module_text()?, self.module_text()?)))
+                .collect::<Result<Vec<_>, &'static str>>()?;
+            let mut samples = BTreeMap::new();
+            for _ in 0..self.module_unsigned()? {
+                samples.insert(self.module_unsigned()?, self.module_double()?);
+            }
+            Data::TimeSeries(TimeSeries { samples, retention, duplicate_policy, labels })
+        } else {
+            return Err(BAD_DATA);
+        };
+
+        if self.read_length()? != MODULE_OPCODE_EOF {
+            return Err(BAD_DATA);
+        }
+        Ok(data)
+    }
+
+    // Read a RedisBloom scalable filter. Only 64-bit hashing is supported, which RedisBloom uses by default.
+    fn read_bloom(&mut self, encoding_version: u64) -> Result<ScalableBloom, &'static str> {
+        self.module_unsigned()?;
+        let filter_count = self.module_unsigned()?;
+        let options = self.module_unsigned()?;
+        let growth = if encoding_version >= 4 { self.module_unsigned()? } else { 2 };
+        if options & BLOOM_OPT_FORCE64 == 0 || filter_count == 0 {
+            return Err(BAD_DATA);
+        }
+
+        let mut filters = Vec::new();
+        for _ in 0..filter_count {
+            let capacity = self.module_unsigned()?;
+            let error_rate = self.module_double()?;
+            let hashes = u32::try_from(self.module_unsigned()?).map_err(|_| BAD_DATA)?;
+            self.module_double()?;
+            let bit_count = self.module_unsigned()?;
+            self.module_unsigned()?;
+            let bytes = self.module_string()?;
+            let count = self.module_unsigned()?;
+            if bit_count == 0 || bytes.len() as u64 != bit_count.div_ceil(8) {
+                return Err(BAD_DATA);
+            }
+            let bits = bytes.chunks(8).map(|word| {
+                let mut padded = [0; 8];
+                padded[..word.len()].copy_from_slice(word);
+                u64::from_le_bytes(padded)
+            }).collect();
+            filters.push(BloomFilter { bits, bit_count, hashes, capacity, count, error_rate });
+        }
+
+        let expansion = if options & BLOOM_OPT_NO_SCALING != 0 { 0 } else { u32::try_from(growth).map_err(|_| BAD_DATA)? };
+        let error_rate = filters[0].error_rate / TIGHTENING_RATIO;
+        Ok(ScalableBloom { filters, error_rate, expansion })
+    }
+
+    // Skip the auxiliary data of a module, which is kept by modules that are not loaded here
+    fn skip_module_aux(&mut self) -> Result<(), &'static str> {
+        self.read_length()?;
+        self.read_length()?;
+        loop {
+            match self.read_length()? {
+                MODULE_OPCODE_EOF => return Ok(()),
+                MODULE_OPCODE_SINT | MODULE_OPCODE_UINT => {
+                    self.read_length()?;
+                }
+                MODULE_OPCODE_FLOAT => {
+                    self.bytes.take(4)?;
+                }
+                MODULE_OPCODE_DOUBLE => {
+                    self.bytes.take(8)?;
+                }
+                MODULE_OPCODE_STRING => {
+                    self.read_string()?;
+                }
+                _ => return Err(BAD_DATA),
+            }
+        }
+    }
+
+    fn module_opcode(&mut self, expected: u64) -> Result<(), &'static str> {
+        if self.read_length()? != expected {
+            return Err(BAD_DATA);
+        }
+        Ok(())
+    }
+
+    fn module_unsigned(&mut self) -> Result<u64, &'static str> {
+        // Signed values are read back as their two's complement
+        match self.read_length()? {
+            MODULE_OPCODE_UINT | MODULE_OPCODE_SINT => self.read_length(),
+            _ => Err(BAD_DATA),
+        }
+    }
+
+    fn module_double(&mut self) -> Result<f64, &'static str> {
+        self.module_opcode(MODULE_OPCODE_DOUBLE)?;
+        self.read_double()
+    }
+
+    fn module_string(&mut self) -> Result<Vec<u8>, &'static str> {
+        self.module_opcode(MODULE_OPCODE_STRING)?;
+        self.read_string()
+    }
+
+    fn module_text(&mut self) -> Result<String, &'static str> {
+        utf8(self.module_string()?)
+    }
+}
+
+// Opcodes of an RDB file
+const RDB_OPCODE_SLOT_INFO: u8 = 0xf4;
+const RDB_OPCODE_FUNCTION2: u8 = 0xf5;
+const RDB_OPCODE_MODULE_AUX: u8 = 0xf7;
+const RDB_OPCODE_IDLE: u8 = 0xf8;
+const RDB_OPCODE_FREQ: u8 = 0xf9;
+const RDB_OPCODE_AUX: u8 = 0xfa;
+const RDB_OPCODE_RESIZEDB: u8 = 0xfb;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xfd;
+const RDB_OPCODE_SELECTDB: u8 = 0xfe;
+const RDB_OPCODE_EOF: u8 = 0xff;
+
+const MODULE_OPCODE_FLOAT: u64 = 3;
+
+// Serialize every database as an RDB file: a header, the keys of each database
+// with their access metadata, and a CRC64 of the whole file
+pub fn save(databases: &HashMap<u8, Database>) -> Vec<u8> {
+    let mut out = format!("REDIS{:04}", RDB_VERSION).into_bytes();
+    for (field, value) in [("redis-bits", "64".to_string()), ("ctime", (now_ms() / 1000).to_string()), ("aof-base", "0".to_string())] {
+        out.push(RDB_OPCODE_AUX);
+        write_string(&mut out, field.as_bytes());
+        write_string(&mut out, value.as_bytes());
+    }
+
+    let mut indexes: Vec<&u8> = databases.keys().collect();
+    indexes.sort();
+    for index in indexes {
+        let database = &databases[index];
+        if database.data.is_empty() {
+            continue;
+        }
+        out.push(RDB_OPCODE_SELECTDB);
+        write_length(&mut out, *index as u64);
+        out.push(RDB_OPCODE_RESIZEDB);
+        write_length(&mut out, database.data.len() as u64);
+        write_length(&mut out, 0);
+
+        for (key, value) in &database.data {
+            let meta = database.key_meta(key);
+            out.push(RDB_OPCODE_IDLE);
+            write_length(&mut out, meta.idle_seconds());
+            out.push(RDB_OPCODE_FREQ);
+            out.push(meta.decayed_counter());
+            out.push(object_type(value));
+            write_string(&mut out, key.as_bytes());
+            write_value(&mut out, value);
+        }
+    }
+
+    out.push(RDB_OPCODE_EOF);
+    let crc = crc64(0, &out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+// The keys of an RDB file that had an expiry. Keys cannot expire here, so those whose expiry
+// had passed are skipped and the others are loaded without it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Expiries {
+    pub skipped: usize,
+    pub dropped: usize,
+}
+
+// Load the databases of an RDB file
+pub fn load(file: &[u8]) -> Result<HashMap<u8, Database>, &'static str> {
+    load_with_expiries(file).map(|(databases, _)| databases)
+}
+
+// Load the databases of an RDB file, along with how many keys had an expiry
+pub fn load_with_expiries(file: &[u8]) -> Result<(HashMap<u8, Database>, Expiries), &'static str> {
+    if file.len() < 9 || &file[..5] != b"REDIS" {
+        return Err("Wrong signature trying to load DB from file");
+    }
+    let version: u16 = std::str::from_utf8(&file[5..9]).ok().and_then(|version| version.parse().ok()).ok_or("Wrong signature trying to load DB from file")?;
+    if !(1..=RDB_MAX_LOAD_VERSION).contains(&version) {
+        return Err("Can't handle RDB format version");
+    }
+
+    let mut databases = HashMap::new();
+    let mut expiries = Expiries::default();
+    let mut reader = RdbReader::new(file);
+    reader.bytes.pos = 9;
+    let mut index = 0;
+    let mut expire_ms = None;
+    let mut idle = None;
+    let mut freq = None;
+    let now = now_ms();
+    loop {
+        match reader.read_u8()? {
+            RDB_OPCODE_EOF => break,
+            RDB_OPCODE_AUX => {
+                reader.read_string()?;
+                reader.read_string()?;
+            }
+            RDB_OPCODE_RESIZEDB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            RDB_OPCODE_SELECTDB => {
+                index = u8::try_from(reader.read_length()?).ok().filter(|index| *index <= MAX_DATABASES).ok_or("FATAL: Data file was created with a Redis server configured to handle more databases")?;
+            }
+            RDB_OPCODE_EXPIRETIME => expire_ms = Some(reader.bytes.u32_le()? as u64 * 1000),
+            RDB_OPCODE_EXPIRETIME_MS => expire_ms = Some(reader.read_ms_time()?),
+            RDB_OPCODE_IDLE => idle = Some(reader.read_length()?),
+            RDB_OPCODE_FREQ => freq = Some(reader.read_u8()?),
+            RDB_OPCODE_MODULE_AUX => reader.skip_module_aux()?,
+            RDB_OPCODE_FUNCTION2 => {
+                reader.read_string()?;
+            }
+            RDB_OPCODE_SLOT_INFO => {
+                for _ in 0..3 {
+                    reader.read_length()?;
+                }
+            }
+            object_type => {
+                let key = reader.read_text()?;
+                let value = reader.read_value(object_type)?;
+                match expire_ms.take() {
+                    Some(expire_ms) if expire_ms <= now => {
+                        expiries.skipped += 1;
+                        idle = None;
+                        freq = None;
+                        continue;
+                    }
+                    Some(_) => expiries.dropped += 1,
+                    None => {}
+                }
+
+                let database = databases.entry(index).or_insert_with(Database::new);
+                let meta = KeyMeta {
+                    last_access_ms: now.saturating_sub(idle.take().unwrap_or(0).saturating_mul(1000)),
+                    lfu_counter: freq.take().unwrap_or(LFU_INIT_VAL),
+                    lfu_decay_minute: now / 60_000,
+                };
+                database.meta.insert(key.clone(), meta);
+                database.data.insert(key, value);
+            }
+        }
+    }
+
+    // Files from version 5 on end with a checksum, where 0 means it was not computed
+    if version >= 5 {
+        let end = reader.bytes.pos;
+        let crc = u64::from_le_bytes(reader.bytes.take(8)?.try_into().unwrap());
+        if crc != 0 && crc != crc64(0, &file[..end]) {
+            return Err("Wrong RDB checksum");
+        }
+    }
+    Ok((databases, expiries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &Data) -> Data {
+        restore(&dump(data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn crc64_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+        assert_eq!(crc64(crc64(0, b"1234"), b"56789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn restore_accepts_a_redis_payload() {
+        // DUMP of the string "10" by Redis 5 (RDB version 9), from the Redis documentation
+        let payload = b"\x00\xc0\n\t\x00\xbem\x06\x89Z(\x00\n";
+        assert!(matches!(restore(payload), Ok(Data::Scalar(value)) if value == b"10"));
+    }
+
+    #[test]
+    fn restore_checks_version_and_checksum() {
+        let mut payload = dump(&Data::Scalar(b"value".to_vec())).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        assert!(restore(&payload).is_err());
+
+        let mut payload = dump(&Data::Scalar(b"value".to_vec())).unwrap();
+        let version = payload.len() - 10;
+        payload[version] = (RDB_MAX_LOAD_VERSION + 1) as u8;
+        assert!(restore(&payload).is_err());
+        assert!(restore(b"\x00").is_err());
+    }
+
+    #[test]
+    fn restore_rejects_forged_lzf_lengths() {
+        // An LZF string claiming 1 << 40 bytes once decompressed, from 2 bytes of input
+        let mut payload = vec![RDB_TYPE_STRING, 0xc3, 2, 0x81];
+        payload.extend_from_slice(&(1u64 << 40).to_be_bytes());
+        payload.extend_from_slice(&[0, b'a']);
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        payload.extend_from_slice(&[0; 8]);
+        assert_eq!(restore(&payload).err(), Some(BAD_DATA));
+    }
+
+    #[test]
+    fn restore_decompresses_lzf_strings() {
+        // "aaaaaaaaaa": the literal "a", then a back reference one byte behind copying 9 more
+        let mut payload = vec![RDB_TYPE_STRING, 0xc3, 5, 10, 0, b'a', 0xe0, 0, 0];
+        payload.extend_from_slice(&RDB_VERSION.to_le_bytes());
+        payload.extend_from_slice(&[0; 8]);
+        assert!(matches!(restore(&payload), Ok(Data::Scalar(value)) if value == b"aaaaaaaaaa"));
+    }
+
+    #[test]
+    fn strings_use_integer_encodings() {
+        let encoded = |value: &[u8]| {
+            let mut out = Vec::new();
+            write_string(&mut out, value);
+            out
+        };
+        assert_eq!(encoded(b"10"), vec![0xc0, 10]);
+        assert_eq!(encoded(b"-300"), vec![0xc1, 0xd4, 0xfe]);
+        assert_eq!(encoded(b"100000"), vec![0xc2, 0xa0, 0x86, 0x01, 0x00]);
+        assert_eq!(encoded(b"010"), vec![3, b'0', b'1', b'0']);
+        assert!(matches!(round_trip(&Data::Scalar(b"-300".to_vec())), Data::Scalar(value) if value == b"-300"));
+    }
+
+    #[test]
+    fn lengths_use_every_width() {
+        let encoded = |len: u64| {
+            let mut out = Vec::new();
+            write_length(&mut out, len);
+            RdbReader::new(&out).read_length().unwrap()
+        };
+        for len in [0, 63, 64, 16383, 16384, u32::MAX as u64, u32::MAX as u64 + 1] {
+            assert_eq!(encoded(len), len);
+        }
+    }
+
+    #[test]
+    fn collections_round_trip() {
+        let list: VecDeque<String> = (0..300).map(|i| format!("item{}", i)).collect();
+        assert!(matches!(round_trip(&Data::List(list.clone())), Data::List(restored) if restored == list));
+
+        let hash: HashMap<String, String> = (0..10).map(|i| (format!("field{}", i), i.to_string())).collect();
+        assert!(matches!(round_trip(&Data::Hash(hash.clone())), Data::Hash(restored) if restored == hash));
+
+        let set: HashSet<String> = ["a", "b", "42"].iter().map(|m| m.to_string()).collect();
+        assert!(matches!(round_trip(&Data::Set(set.clone())), Data::Set(restored) if restored == set));
+
+        let mut zset = SortedSet::new();
+        zset.insert("one".to_string(), 1.0);
+        zset.insert("inf".to_string(), f64::INFINITY);
+        zset.insert("half".to_string(), -0.5);
+        match round_trip(&Data::SortedSet(zset)) {
+            Data::SortedSet(restored) => {
+                assert_eq!(restored.len(), 3);
+                assert_eq!(restored.score("inf"), Some(f64::INFINITY));
+                assert_eq!(restored.score("half"), Some(-0.5));
+            }
+            _ => panic!("expected a sorted set"),
+        }
+    }
+
+    #[test]
+    fn streams_round_trip() {
+        let mut stream = Stream::new();
+        for ms in 1..=150 {
+            let fields = if ms % 2 == 0 { vec![("a".to_string(), ms.to_string())] } else { vec![("b".to_string(), "x".to_string()), ("c".to_string(), "y".to_string())] };
+            stream.append(StreamId { ms, seq: 0 }, fields);
+        }
+        stream.delete(&StreamId { ms: 3, seq: 0 });
+        match round_trip(&Data::Stream(stream.clone())) {
+            Data::Stream(restored) => {
+                assert_eq!(restored.entries, stream.entries);
+                assert_eq!(restored.last_id, stream.last_id);
+                assert_eq!(restored.max_deleted_id, stream.max_deleted_id);
+                assert_eq!(restored.entries_added, stream.entries_added);
+            }
+            _ => panic!("expected a stream"),
+        }
+    }
+
+    #[test]
+    fn rdb_files_round_trip() {
+        let mut database = Database::new();
+        database.data.insert("s".to_string(), Data::Scalar(b"v".to_vec()));
+        database.data.insert("ts".to_string(), Data::TimeSeries(TimeSeries::new(0, DuplicatePolicy::Last, vec![("a".to_string(), "b".to_string())])));
+        let file = save(&HashMap::from([(3, database)]));
+
+        let loaded = load(&file).unwrap();
+        assert!(matches!(&loaded[&3].data["s"], Data::Scalar(value) if value == b"v"));
+        assert!(matches!(&loaded[&3].data["ts"], Data::TimeSeries(series) if series.duplicate_policy == DuplicatePolicy::Last));
+
+        let mut corrupted = file.clone();
+        corrupted[12] ^= 1;
+        assert!(load(&corrupted).is_err());
+    }
+
+    #[test]
+    fn load_counts_expiring_keys() {
+        let mut file = b"REDIS0011".to_vec();
+        for (expire_ms, key) in [(Some(1000), b'a'), (Some(u64::MAX), b'b'), (None, b'c')] {
+            if let Some(expire_ms) = expire_ms {
+                file.push(RDB_OPCODE_EXPIRETIME_MS);
+                file.extend_from_slice(&u64::to_le_bytes(expire_ms));
+            }
+            file.extend_from_slice(&[RDB_TYPE_STRING, 1, key, 1, b'v']);
+        }
+        file.push(RDB_OPCODE_EOF);
+        file.extend_from_slice(&[0; 8]);
+
+        let (databases, expiries) = load_with_expiries(&file).unwrap();
+        assert_eq!(expiries, Expiries { skipped: 1, dropped: 1 });
+        let mut keys: Vec<&String> = databases[&0].data.keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["b", "c"]);
+    }
+}
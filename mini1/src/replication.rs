@@ -0,0 +1,326 @@
+use std::collections::VecDeque;
+use rand::Rng;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::stream::now_ms;
+use crate::utils::{encode_request, Frame};
+
+// The replication ID that stands for no previous history
+const NO_REPLID: &str = "0000000000000000000000000000000000000000";
+
+// A new replication ID: 40 random hex characters, like Redis uses
+pub fn new_replid() -> String {
+    let mut rng = rand::thread_rng();
+    (0..40).map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap()).collect()
+}
+
+// Where a replica connected to this server is in its synchronization
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplicaState {
+    // Waiting for the snapshot of a full resynchronization
+    WaitBgsave,
+    // Receiving the snapshot
+    SendBulk,
+    // Receiving the stream of writes
+    Online,
+}
+
+impl ReplicaState {
+    // The name used by INFO replication
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReplicaState::WaitBgsave => "wait_bgsave",
+            ReplicaState::SendBulk => "send_bulk",
+            ReplicaState::Online => "online",
+        }
+    }
+}
+
+// A replica connected to this server
+#[derive(Debug)]
+pub struct ReplicaLink {
+    pub id: u64,
+    pub ip: String,
+    pub listening_port: u16,
+    pub state: ReplicaState,
+    // The offset the replica last acknowledged, and the Unix time in seconds it did
+    pub ack_offset: u64,
+    pub last_ack: u64,
+    // The offset up to which the replica reported its AOF fsynced, when its AOF is enabled
+    pub aof_ack_offset: Option<u64>,
+    // The stream of writes, which the connection of the replica sends on
+    sender: UnboundedSender<Vec<u8>>,
+}
+
+// Where the link of this server to its primary is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkState {
+    // Waiting to connect, after a failure or a REPLICAOF
+    Connect,
+    // Connecting and going through the handshake
+    Connecting,
+    // Receiving the snapshot of a full resynchronization
+    Sync,
+    // Receiving the stream of writes
+    Connected,
+}
+
+impl LinkState {
+    // The name used by ROLE
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        }
+    }
+}
+
+// The primary this server replicates from
+#[derive(Debug)]
+pub struct PrimaryLink {
+    pub host: String,
+    pub port: u16,
+    pub state: LinkState,
+    // Unix time in seconds of the last data from the primary
+    pub last_io: u64,
+    // Incremented by every REPLICAOF, so the task of a previous primary knows to stop
+    pub generation: u64,
+}
+
+// The replication state of the server. Every write is appended to the replication stream,
+// which is kept in a backlog of the last bytes and sent to the replicas. A replica runs the
+// stream of its primary and passes it on as it is, so offsets are the same on every server
+// of a replication ID.
+#[derive(Debug)]
+pub struct Replication {
+    pub replid: String,
+    // The ID of the previous primary, which is still accepted up to second_replid_offset
+    // after this replica was promoted
+    pub replid2: String,
+    pub second_replid_offset: i64,
+    // The number of bytes of the stream so far
+    pub offset: u64,
+    backlog: VecDeque<u8>,
+    backlog_size: usize,
+    // The database of the last write in the stream, so SELECT is only sent when it changes
+    selected_db: Option<u8>,
+    pub replicas: Vec<ReplicaLink>,
+    next_replica_id: u64,
+    pub primary: Option<PrimaryLink>,
+    next_generation: u64,
+}
+
+impl Replication {
+    pub fn new(backlog_size: u64) -> Self {
+        Self {
+            replid: new_replid(),
+            replid2: NO_REPLID.to_string(),
+            second_replid_offset: -1,
+            offset: 0,
+            backlog: VecDeque::new(),
+            backlog_size: backlog_size as usize,
+            selected_db: None,
+            replicas: Vec::new(),
+            next_replica_id: 0,
+            primary: None,
+            next_generation: 0,
+        }
+    }
+
+    // Append bytes to the stream: keep them in the backlog and send them to the replicas
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+        self.backlog.extend(bytes);
+        let excess = self.backlog.len().saturating_sub(self.backlog_size);
+        self.backlog.drain(..excess);
+        for replica in &self.replicas {
+            let _ = replica.sender.send(bytes.to_vec());
+        }
+    }
+
+    // Append the requests of a write that ran in selected_db to the stream
+    pub fn propagate(&mut self, selected_db: u8, requests: &[Frame]) {
+        let mut buf = Vec::new();
+        if self.selected_db != Some(selected_db) {
+            buf.extend(encode_request(&[b"SELECT", selected_db.to_string().as_bytes()]));
+            self.selected_db = Some(selected_db);
+        }
+        for request in requests {
+            let parts: Vec<&[u8]> = request.iter().map(Vec::as_slice).collect();
+            buf.extend(encode_request(&parts));
+        }
+        self.feed(&buf);
+    }
+
+    // The offset of the first byte in the backlog. Offsets of bytes start at 1.
+    pub fn backlog_first_byte(&self) -> u64 {
+        self.offset + 1 - self.backlog.len() as u64
+    }
+
+    pub fn backlog_len(&self) -> usize {
+        self.backlog.len()
+    }
+
+    pub fn backlog_size(&self) -> usize {
+        self.backlog_size
+    }
+
+    // The stream from psync_offset on, when a replica of replid can continue from there
+    pub fn backlog_from(&self, replid: &str, psync_offset: i64) -> Option<Vec<u8>> {
+        let known = replid == self.replid || (replid == self.replid2 && psync_offset <= self.second_replid_offset);
+        let first = self.backlog_first_byte() as i64;
+        if !known || psync_offset < first || psync_offset > self.offset as i64 + 1 {
+            return None;
+        }
+        Some(self.backlog.iter().skip((psync_offset - first) as usize).copied().collect())
+    }
+
+    // Start a new history at offset with the ID of a primary, after a full resynchronization
+    pub fn reset(&mut self, replid: String, offset: u64) {
+        self.replid = replid;
+        self.replid2 = NO_REPLID.to_string();
+        self.second_replid_offset = -1;
+        self.offset = offset;
+        self.backlog.clear();
+    }
+
+    // Take a new replication ID while keeping the current one as the previous ID, when a
+    // replica is promoted or its primary changed IDs, so its own replicas can continue
+    pub fn shift_replid(&mut self, replid: String) {
+        self.replid2 = std::mem::replace(&mut self.replid, replid);
+        self.second_replid_offset = self.offset as i64 + 1;
+        self.selected_db = None;
+    }
+
+    // Send the stream to a new replica from now on, and return its ID
+    pub fn add_replica(&mut self, ip: String, listening_port: u16, state: ReplicaState, sender: UnboundedSender<Vec<u8>>) -> u64 {
+        self.next_replica_id += 1;
+        // The next write is preceded by a SELECT, since the replica may start from any database
+        self.selected_db = None;
+        self.replicas.push(ReplicaLink { id: self.next_replica_id, ip, listening_port, state, ack_offset: 0, last_ack: now_ms() / 1000, aof_ack_offset: None, sender });
+        self.next_replica_id
+    }
+
+    // The number of replicas that acknowledged the stream up to offset
+    pub fn acked_replicas(&self, offset: u64) -> usize {
+        self.replicas.iter().filter(|replica| replica.state == ReplicaState::Online && replica.ack_offset >= offset).count()
+    }
+
+    // The number of replicas that fsynced the stream up to offset to their AOF
+    pub fn aof_acked_replicas(&self, offset: u64) -> usize {
+        self.replicas.iter()
+            .filter(|replica| replica.state == ReplicaState::Online && replica.aof_ack_offset.is_some_and(|aof_offset| aof_offset >= offset))
+            .count()
+    }
+
+    // Ask the replicas to acknowledge their offset now rather than at their next periodic acknowledgement
+    pub fn request_acks(&mut self) {
+        if !self.replicas.is_empty() {
+            self.feed(&encode_request(&[b"REPLCONF", b"GETACK", b"*"]));
+        }
+    }
+
+    pub fn replica_mut(&mut self, id: u64) -> Option<&mut ReplicaLink> {
+        self.replicas.iter_mut().find(|replica| replica.id == id)
+    }
+
+    // Forget a replica. Dropping its sender ends its connection.
+    pub fn remove_replica(&mut self, id: u64) {
+        self.replicas.retain(|replica| replica.id != id);
+    }
+
+    // Drop every replica, so they synchronize again and learn about a new history
+    pub fn disconnect_replicas(&mut self) {
+        self.replicas.clear();
+    }
+
+    // Replicate host:port from now on, and return the generation of the new link
+    pub fn set_primary(&mut self, host: String, port: u16) -> u64 {
+        self.next_generation += 1;
+        self.primary = Some(PrimaryLink { host, port, state: LinkState::Connect, last_io: 0, generation: self.next_generation });
+        self.next_generation
+    }
+
+    // The link to the primary, if it still belongs to generation
+    pub fn primary_mut(&mut self, generation: u64) -> Option<&mut PrimaryLink> {
+        self.primary.as_mut().filter(|primary| primary.generation == generation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn backlog_keeps_the_last_bytes_of_the_stream() {
+        let mut replication = Replication::new(4);
+        replication.feed(b"abc");
+        replication.feed(b"def");
+        assert_eq!(replication.offset, 6);
+        assert_eq!(replication.backlog_len(), 4);
+        assert_eq!(replication.backlog_first_byte(), 3);
+
+        let replid = replication.replid.clone();
+        assert_eq!(replication.backlog_from(&replid, 3), Some(b"cdef".to_vec()));
+        assert_eq!(replication.backlog_from(&replid, 7), Some(Vec::new()));
+        assert_eq!(replication.backlog_from(&replid, 2), None);
+        assert_eq!(replication.backlog_from(&replid, 8), None);
+        assert_eq!(replication.backlog_from(NO_REPLID, 5), None);
+    }
+
+    #[test]
+    fn previous_replid_is_accepted_up_to_the_shift() {
+        let mut replication = Replication::new(64);
+        replication.feed(b"abc");
+        let old = replication.replid.clone();
+        replication.shift_replid(new_replid());
+        replication.feed(b"de");
+        assert_eq!(replication.second_replid_offset, 4);
+        assert_eq!(replication.backlog_from(&old, 4), Some(b"de".to_vec()));
+        assert_eq!(replication.backlog_from(&old, 5), None);
+
+        replication.reset(new_replid(), 100);
+        assert_eq!(replication.backlog_from(&old, 4), None);
+        assert_eq!(replication.backlog_first_byte(), 101);
+    }
+
+    #[test]
+    fn propagate_selects_the_database_when_it_changes() {
+        let mut replication = Replication::new(1024);
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        replication.add_replica("127.0.0.1".to_string(), 6380, ReplicaState::Online, sender);
+        let set = vec![b"SET".to_vec(), b"k".to_vec(), b"v".to_vec()];
+
+        replication.propagate(1, std::slice::from_ref(&set));
+        replication.propagate(1, std::slice::from_ref(&set));
+        let select = encode_request(&[b"SELECT", b"1"]);
+        let write = encode_request(&[b"SET", b"k", b"v"]);
+        assert_eq!(receiver.try_recv().unwrap(), [select.as_slice(), write.as_slice()].concat());
+        assert_eq!(receiver.try_recv().unwrap(), write);
+        assert_eq!(replication.offset, (select.len() + 2 * write.len()) as u64);
+    }
+
+    #[test]
+    fn replicas_count_once_they_acknowledge() {
+        let mut replication = Replication::new(1024);
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let id = replication.add_replica("127.0.0.1".to_string(), 6380, ReplicaState::SendBulk, sender);
+        replication.feed(b"abc");
+
+        let replica = replication.replica_mut(id).unwrap();
+        replica.ack_offset = 3;
+        replica.aof_ack_offset = Some(2);
+        assert_eq!(replication.acked_replicas(3), 0);
+
+        replication.replica_mut(id).unwrap().state = ReplicaState::Online;
+        assert_eq!(replication.acked_replicas(3), 1);
+        assert_eq!(replication.aof_acked_replicas(3), 0);
+        assert_eq!(replication.aof_acked_replicas(2), 1);
+
+        replication.remove_replica(id);
+        assert_eq!(replication.acked_replicas(0), 0);
+    }
+}
@@ -0,0 +1,707 @@
+use std::{collections::HashMap, error::Error, future::Future, pin::Pin, sync::{Arc, Mutex}, time::Duration};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, sync::mpsc, time::timeout};
+
+use crate::aof::Aof;
+use crate::aof_cmd::start_rewrite;
+use crate::database::Database;
+use crate::execute_cmd::block_until;
+use crate::rdb;
+use crate::replication::{new_replid, LinkState, ReplicaState, Replication};
+use crate::server::Server;
+use crate::stream::now_ms;
+use crate::utils::{bulk_array, bulk_string, encode_request, execute_command, parse_request, read_frame, write_file_atomically, Frame};
+
+// Seconds between the PINGs a primary sends down the stream, so replicas can tell the link is alive
+const REPL_PING_PERIOD: u64 = 10;
+// Seconds to wait before connecting to the primary again after the link failed
+const REPL_RETRY_DELAY: u64 = 1;
+
+// REPLICAOF command. Replicating a new primary starts in the background; NO ONE promotes
+// the server, which keeps the history of its primary so other replicas can continue from it.
+pub async fn execute_replicaof_cmd(primary: Option<(String, u16)>, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<String, &'static str> {
+    if server.cluster.is_some() {
+        return Err("REPLICAOF not allowed in cluster mode.");
+    }
+    let (host, port) = match primary {
+        Some(primary) => primary,
+        None => {
+            let mut replication = server.replication.lock().await;
+            if replication.primary.take().is_some() {
+                replication.shift_replid(new_replid());
+                // The replicas of this server synchronize again to learn the new replication ID
+                replication.disconnect_replicas();
+                println!("MASTER MODE enabled");
+            }
+            return Ok("+OK\r\n".to_string());
+        }
+    };
+
+    if server.replication.lock().await.primary.as_ref().is_some_and(|primary| primary.host == host && primary.port == port) {
+        return Ok("+OK Already connected to specified master\r\n".to_string());
+    }
+    start_replication(db, server, host, port).await;
+    Ok("+OK\r\n".to_string())
+}
+
+// Replicate host:port from now on, on a task that stops when REPLICAOF changes the primary
+pub async fn start_replication(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, host: String, port: u16) {
+    let generation = {
+        let mut replication = server.replication.lock().await;
+        // The replicas of this server synchronize again, with the data of the new primary
+        replication.disconnect_replicas();
+        replication.set_primary(host.clone(), port)
+    };
+    println!("Connecting to MASTER {}:{}", host, port);
+    tokio::spawn(replica_task(Arc::clone(db), Arc::clone(server), generation));
+}
+
+// The task of run_replica, boxed since it runs commands and so may itself run REPLICAOF
+fn replica_task(db: Arc<Mutex<HashMap<u8, Database>>>, server: Arc<Server>, generation: u64) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    Box::pin(run_replica(db, server, generation))
+}
+
+// Whether generation is still the link to the primary
+async fn is_current(server: &Server, generation: u64) -> bool {
+    server.replication.lock().await.primary_mut(generation).is_some()
+}
+
+// Keep replicating the primary of generation, connecting again after the link fails
+async fn run_replica(db: Arc<Mutex<HashMap<u8, Database>>>, server: Arc<Server>, generation: u64) {
+    // The database the stream last selected, which a partial resynchronization continues with
+    let mut selected_db = 0;
+    loop {
+        let (host, port) = match server.replication.lock().await.primary_mut(generation) {
+            Some(primary) => {
+                primary.state = LinkState::Connecting;
+                (primary.host.clone(), primary.port)
+            }
+            None => return,
+        };
+        if let Err(e) = sync_with_primary(&host, port, &db, &server, generation, &mut selected_db).await {
+            eprintln!("Error replicating {}:{}: {}", host, port, e);
+        }
+        match server.replication.lock().await.primary_mut(generation) {
+            Some(primary) => primary.state = LinkState::Connect,
+            None => return,
+        }
+        tokio::time::sleep(Duration::from_secs(REPL_RETRY_DELAY)).await;
+    }
+}
+
+// Read more bytes from the primary into pending
+async fn read_more(stream: &mut TcpStream, pending: &mut Vec<u8>, limit: Duration) -> Result<(), String> {
+    let mut chunk = [0; 16 * 1024];
+    match timeout(limit, stream.read(&mut chunk)).await {
+        Ok(Ok(0)) => Err("connection closed by the primary".to_string()),
+        Ok(Ok(bytes_read)) => {
+            pending.extend_from_slice(&chunk[..bytes_read]);
+            Ok(())
+        }
+        Ok(Err(e)) => Err(format!("error reading from the primary: {}", e)),
+        Err(_) => Err("timeout reading from the primary".to_string()),
+    }
+}
+
+// Read a reply line of the handshake, skipping the empty lines a primary may send to keep the link alive
+async fn read_line(stream: &mut TcpStream, pending: &mut Vec<u8>, limit: Duration) -> Result<String, String> {
+    loop {
+        while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim_end().to_string();
+            if !line.is_empty() {
+                return Ok(line);
+            }
+        }
+        read_more(stream, pending, limit).await?;
+    }
+}
+
+// Send a request to the primary
+async fn send(stream: &mut TcpStream, request: &[&[u8]]) -> Result<(), String> {
+    stream.write_all(&encode_request(request)).await.map_err(|e| format!("error writing to the primary: {}", e))
+}
+
+// Connect to the primary, synchronize with it, and run the stream of writes it sends until
+// the link fails or REPLICAOF changes the primary. The replica asks to continue from its
+// offset, and gets a snapshot when the primary cannot.
+async fn sync_with_primary(host: &str, port: u16, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, generation: u64, selected_db: &mut u8) -> Result<(), String> {
+    let limit = Duration::from_secs(server.config.repl_timeout);
+    let mut stream = match timeout(limit, TcpStream::connect((host, port))).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return Err(format!("error connecting to the primary: {}", e)),
+        Err(_) => return Err("timeout connecting to the primary".to_string()),
+    };
+    let mut pending = Vec::new();
+
+    let listening_port = server.config.port.to_string();
+    let handshake: [&[&[u8]]; 3] = [&[b"PING"], &[b"REPLCONF", b"listening-port", listening_port.as_bytes()], &[b"REPLCONF", b"capa", b"psync2"]];
+    for request in handshake {
+        send(&mut stream, request).await?;
+        let reply = read_line(&mut stream, &mut pending, limit).await?;
+        if reply.starts_with('-') {
+            return Err(format!("the primary replied to the handshake with {}", reply));
+        }
+    }
+
+    let (replid, offset) = {
+        let replication = server.replication.lock().await;
+        (replication.replid.clone(), replication.offset)
+    };
+    send(&mut stream, &[b"PSYNC", replid.as_bytes(), (offset + 1).to_string().as_bytes()]).await?;
+    let reply = read_line(&mut stream, &mut pending, limit).await?;
+    if let Some(full) = reply.strip_prefix("+FULLRESYNC ") {
+        let (new_replid, new_offset) = full.split_once(' ')
+            .and_then(|(replid, offset)| Some((replid.to_string(), offset.parse::<u64>().ok()?)))
+            .ok_or_else(|| format!("unexpected reply to PSYNC: {}", reply))?;
+        match server.replication.lock().await.primary_mut(generation) {
+            Some(primary) => primary.state = LinkState::Sync,
+            None => return Ok(()),
+        }
+        println!("Full resync from master: {}:{}", new_replid, new_offset);
+
+        // The snapshot is sent as "$<length>\r\n" and the RDB file, without a CRLF after it
+        let header = read_line(&mut stream, &mut pending, limit).await?;
+        let length = header.strip_prefix('$').and_then(|length| length.parse::<usize>().ok())
+            .ok_or_else(|| format!("unexpected snapshot header from the primary: {}", header))?;
+        while pending.len() < length {
+            read_more(&mut stream, &mut pending, limit).await?;
+        }
+        let snapshot: Vec<u8> = pending.drain(..length).collect();
+        if !is_current(server, generation).await {
+            return Ok(());
+        }
+        load_snapshot(snapshot, db, server).await?;
+
+        let mut replication = server.replication.lock().await;
+        replication.reset(new_replid, new_offset);
+        // The replicas of this server hold the data from before, so they synchronize again
+        replication.disconnect_replicas();
+        *selected_db = 0;
+        println!("MASTER <-> REPLICA sync: Finished with success");
+    } else if let Some(continued) = reply.strip_prefix("+CONTINUE") {
+        let new_replid = continued.trim();
+        let mut replication = server.replication.lock().await;
+        if !new_replid.is_empty() && new_replid != replication.replid {
+            replication.shift_replid(new_replid.to_string());
+            replication.disconnect_replicas();
+        }
+        println!("MASTER <-> REPLICA sync: Master accepted a Partial Resynchronization.");
+    } else {
+        return Err(format!("unexpected reply to PSYNC: {}", reply));
+    }
+
+    match server.replication.lock().await.primary_mut(generation) {
+        Some(primary) => {
+            primary.state = LinkState::Connected;
+            primary.last_io = now_ms() / 1000;
+        }
+        None => return Ok(()),
+    }
+    run_stream(stream, pending, db, server, generation, selected_db).await
+}
+
+// Replace the data with the snapshot of the primary, keep it as the RDB file, and rewrite
+// the AOF from it when enabled. Every database is kept with its command queue, since
+// clients may have it selected.
+async fn load_snapshot(snapshot: Vec<u8>, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<(), String> {
+    let path = server.config.rdb_path();
+    let databases = tokio::task::spawn_blocking(move || {
+        let databases = rdb::load(&snapshot);
+        if databases.is_ok() {
+            if let Err(e) = write_file_atomically(&path, &snapshot) {
+                eprintln!("Error saving the snapshot of the primary: {}", e);
+            }
+        }
+        databases
+    }).await.map_err(|e| e.to_string())?.map_err(|e| format!("error loading the snapshot of the primary: {}", e))?;
+
+    {
+        let mut db = db.lock().unwrap();
+        for database in db.values_mut() {
+            database.data.clear();
+            database.meta.clear();
+        }
+        for (index, loaded) in databases {
+            let database = db.entry(index).or_insert_with(Database::new);
+            database.data = loaded.data;
+            database.meta = loaded.meta;
+        }
+    }
+    if server.aof.is_some() {
+        if let Err(e) = start_rewrite(db, server).await {
+            eprintln!("Error rewriting the append only file after the sync: {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Whether a request of the stream is REPLCONF GETACK, which asks for the offset of the replica
+fn is_getack(frame: &Frame) -> bool {
+    frame.len() >= 2 && frame[0].eq_ignore_ascii_case(b"REPLCONF") && frame[1].eq_ignore_ascii_case(b"GETACK")
+}
+
+// The acknowledgement of the offset of a replica: "REPLCONF ACK <offset>", followed by
+// "FACK <offset>" with the offset its AOF fsynced when the AOF is enabled
+async fn ack_request(server: &Server) -> Vec<u8> {
+    let aof = match &server.aof {
+        Some(aof) => Some(aof.lock().await),
+        None => None,
+    };
+    let offset = server.replication.lock().await.offset;
+    let offset_arg = offset.to_string();
+    match aof {
+        Some(aof) => {
+            let aof_offset = aof.fsynced_offset(offset).to_string();
+            encode_request(&[b"REPLCONF", b"ACK", offset_arg.as_bytes(), b"FACK", aof_offset.as_bytes()])
+        }
+        None => encode_request(&[b"REPLCONF", b"ACK", offset_arg.as_bytes()]),
+    }
+}
+
+// Run the stream of writes from the primary and pass it on to the replicas of this server.
+// The offset is acknowledged once a second and when the primary asks for it.
+async fn run_stream(mut stream: TcpStream, mut pending: Vec<u8>, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, generation: u64, selected_db: &mut u8) -> Result<(), String> {
+    let (mut reader, mut writer) = stream.split();
+    let mut chunk = [0; 16 * 1024];
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        // Run the complete requests received so far
+        loop {
+            let (frame, consumed) = match read_frame(&pending) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                Err(e) => return Err(format!("protocol error from the primary: {}", e)),
+            };
+            let bytes: Vec<u8> = pending.drain(..consumed).collect();
+            if !is_current(server, generation).await {
+                return Ok(());
+            }
+
+            if is_getack(&frame) {
+                writer.write_all(&ack_request(server).await).await.map_err(|e| format!("error writing to the primary: {}", e))?;
+            } else {
+                match parse_request(frame.clone()) {
+                    Ok((cmd, _)) => {
+                        // Every command may switch databases, since SELECT entries can appear anywhere
+                        let mut cmd_executed = false;
+                        execute_command(cmd, db, server, selected_db, &mut cmd_executed, frame).await;
+                    }
+                    Err(e) => eprintln!("Error running a command from the primary: {}", e),
+                }
+            }
+            server.replication.lock().await.feed(&bytes);
+        }
+
+        tokio::select! {
+            read = reader.read(&mut chunk) => {
+                let bytes_read = read.map_err(|e| format!("error reading from the primary: {}", e))?;
+                if bytes_read == 0 {
+                    return Err("connection closed by the primary".to_string());
+                }
+                pending.extend_from_slice(&chunk[..bytes_read]);
+                match server.replication.lock().await.primary_mut(generation) {
+                    Some(primary) => primary.last_io = now_ms() / 1000,
+                    None => return Ok(()),
+                }
+            }
+            _ = interval.tick() => {
+                match server.replication.lock().await.primary_mut(generation) {
+                    Some(primary) if now_ms() / 1000 - primary.last_io > server.config.repl_timeout => {
+                        return Err("timeout, no data from the primary".to_string());
+                    }
+                    Some(_) => {}
+                    None => return Ok(()),
+                }
+                writer.write_all(&ack_request(server).await).await.map_err(|e| format!("error writing to the primary: {}", e))?;
+            }
+        }
+    }
+}
+
+// Serve a replica on its connection after PSYNC, or SYNC when psync is None. The replica
+// continues from the backlog when it holds its offset, and gets a snapshot otherwise; then
+// the stream of writes follows. The replica acknowledges its offset with REPLCONF ACK, and
+// the offset its AOF fsynced with FACK.
+pub async fn serve_replica(socket: &mut TcpStream, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, listening_port: Option<u16>, psync: Option<(String, i64)>) -> Result<(), Box<dyn Error>> {
+    let peer = socket.peer_addr()?;
+    let (ip, listening_port) = (peer.ip().to_string(), listening_port.unwrap_or(peer.port()));
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let (id, full_sync) = {
+        let mut replication = server.replication.lock().await;
+        if replication.primary.as_ref().is_some_and(|primary| primary.state != LinkState::Connected) {
+            socket.write_all(b"-NOMASTERLINK Can't SYNC while not connected with my master\r\n").await?;
+            return Ok(());
+        }
+
+        match psync.as_ref().and_then(|(replid, offset)| replication.backlog_from(replid, *offset)) {
+            Some(backlog) => {
+                let mut reply = format!("+CONTINUE {}\r\n", replication.replid).into_bytes();
+                reply.extend(backlog);
+                let _ = sender.send(reply);
+                println!("Partial resynchronization request from {}:{} accepted", ip, listening_port);
+                (replication.add_replica(ip.clone(), listening_port, ReplicaState::Online, sender), None)
+            }
+            None => {
+                // The databases are copied under the lock of the stream, so the snapshot holds
+                // exactly the writes before its offset
+                let databases = db.lock().unwrap().clone();
+                let header = match psync {
+                    Some(_) => format!("+FULLRESYNC {} {}\r\n", replication.replid, replication.offset),
+                    None => String::new(),
+                };
+                println!("Starting full resynchronization with replica {}:{}", ip, listening_port);
+                (replication.add_replica(ip.clone(), listening_port, ReplicaState::WaitBgsave, sender), Some((databases, header)))
+            }
+        }
+    };
+
+    let result = run_replica_link(socket, server, id, receiver, full_sync).await;
+    server.replication.lock().await.remove_replica(id);
+    println!("Connection with replica {}:{} lost", ip, listening_port);
+    result.map_err(|e| e as Box<dyn Error>)
+}
+
+// Send the snapshot of a full resynchronization if there is one, and then the stream of
+// writes, while reading the acknowledgements of the replica
+// The offsets of a "REPLCONF ACK <offset> [FACK <aof-offset>]" from a replica: the stream it
+// ran, and the stream its AOF fsynced when its AOF is enabled
+fn parse_ack(frame: &Frame) -> Option<(u64, Option<u64>)> {
+    let parse = |offset: &[u8]| String::from_utf8_lossy(offset).parse::<u64>().ok();
+    match frame.as_slice() {
+        [cmd, option, offset, rest @ ..] if cmd.eq_ignore_ascii_case(b"REPLCONF") && option.eq_ignore_ascii_case(b"ACK") => {
+            let aof_offset = match rest {
+                [fack, aof_offset] if fack.eq_ignore_ascii_case(b"FACK") => parse(aof_offset),
+                _ => None,
+            };
+            parse(offset).map(|offset| (offset, aof_offset))
+        }
+        _ => None,
+    }
+}
+
+async fn run_replica_link(socket: &mut TcpStream, server: &Arc<Server>, id: u64, mut receiver: mpsc::UnboundedReceiver<Vec<u8>>, full_sync: Option<(HashMap<u8, Database>, String)>) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if let Some((databases, header)) = full_sync {
+        let snapshot = tokio::task::spawn_blocking(move || rdb::save(&databases)).await?;
+        if let Some(replica) = server.replication.lock().await.replica_mut(id) {
+            replica.state = ReplicaState::SendBulk;
+        }
+        let mut reply = header.into_bytes();
+        reply.extend(format!("${}\r\n", snapshot.len()).into_bytes());
+        reply.extend(snapshot);
+        socket.write_all(&reply).await?;
+        if let Some(replica) = server.replication.lock().await.replica_mut(id) {
+            replica.state = ReplicaState::Online;
+            replica.last_ack = now_ms() / 1000;
+        }
+    }
+
+    let (mut reader, mut writer) = socket.split();
+    let mut buf = [0; 1024];
+    let mut pending = Vec::new();
+    loop {
+        tokio::select! {
+            bytes = receiver.recv() => match bytes {
+                Some(bytes) => writer.write_all(&bytes).await?,
+                // The replica was dropped
+                None => return Ok(()),
+            },
+            read = reader.read(&mut buf) => {
+                let bytes_read = read?;
+                if bytes_read == 0 {
+                    return Ok(());
+                }
+                pending.extend_from_slice(&buf[..bytes_read]);
+                while let Some((frame, consumed)) = read_frame(&pending)? {
+                    pending.drain(..consumed);
+                    if let Some((offset, aof_offset)) = parse_ack(&frame) {
+                        if let Some(replica) = server.replication.lock().await.replica_mut(id) {
+                            replica.ack_offset = offset;
+                            replica.aof_ack_offset = aof_offset;
+                            replica.last_ack = now_ms() / 1000;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// WAIT command. Blocks until numreplicas replicas acknowledged the stream up to the offset
+// at the time of the call, so every write made before it, or until the timeout in
+// milliseconds expires, and replies with the number of replicas that did.
+pub async fn execute_wait_cmd(numreplicas: usize, timeout_ms: i64, server: &Arc<Server>) -> Result<String, &'static str> {
+    let offset = {
+        let mut replication = server.replication.lock().await;
+        if replication.primary.is_some() {
+            return Err("WAIT cannot be used with replica instances. Please also note that since Redis 4.0 if a replica is configured to be writable (which is not the default) writes to replicas are just local and are not propagated.");
+        }
+        let acked = replication.acked_replicas(replication.offset);
+        if acked >= numreplicas {
+            return Ok(format!(":{}\r\n", acked));
+        }
+        let offset = replication.offset;
+        replication.request_acks();
+        offset
+    };
+
+    // The stream is busy while a write runs, in which case the next poll checks again
+    let response = block_until(timeout_ms as f64 / 1000.0, || {
+        Ok(server.replication.try_lock().ok()
+            .map(|replication| replication.acked_replicas(offset))
+            .filter(|acked| *acked >= numreplicas)
+            .map(|acked| format!(":{}\r\n", acked)))
+    }).await?;
+    match response {
+        Some(response) => Ok(response),
+        None => Ok(format!(":{}\r\n", server.replication.lock().await.acked_replicas(offset))),
+    }
+}
+
+// Whether the local AOF, and how many replicas, fsynced the stream up to offset
+fn count_fsynced(aof: Option<&Aof>, replication: &Replication, offset: u64) -> (usize, usize) {
+    let local = aof.is_some_and(|aof| aof.fsynced_offset(replication.offset) >= offset);
+    (usize::from(local), replication.aof_acked_replicas(offset))
+}
+
+// WAITAOF command. Blocks until the local AOF when numlocal is 1, and the AOFs of numreplicas
+// replicas, fsynced the stream up to the offset at the time of the call, or until the timeout
+// in milliseconds expires, and replies with how many of each did.
+pub async fn execute_waitaof_cmd(numlocal: usize, numreplicas: usize, timeout_ms: i64, server: &Arc<Server>) -> Result<String, &'static str> {
+    let reply = |(local, replicas): (usize, usize)| format!("*2\r\n:{}\r\n:{}\r\n", local, replicas);
+    let offset = {
+        let aof = match &server.aof {
+            Some(aof) => Some(aof.lock().await),
+            None => None,
+        };
+        let mut replication = server.replication.lock().await;
+        if replication.primary.is_some() {
+            return Err("WAITAOF cannot be used with replica instances. Please also note that writes to replicas are just local and are not propagated.");
+        }
+        if numlocal > 0 && aof.is_none() {
+            return Err("WAITAOF cannot be used when numlocal is set but appendonly is disabled.");
+        }
+        let offset = replication.offset;
+        let counts = count_fsynced(aof.as_deref(), &replication, offset);
+        if counts.0 >= numlocal && counts.1 >= numreplicas {
+            return Ok(reply(counts));
+        }
+        replication.request_acks();
+        offset
+    };
+
+    let counts = || -> Option<(usize, usize)> {
+        let aof = match &server.aof {
+            Some(aof) => Some(aof.try_lock().ok()?),
+            None => None,
+        };
+        let replication = server.replication.try_lock().ok()?;
+        Some(count_fsynced(aof.as_deref(), &replication, offset))
+    };
+    let response = block_until(timeout_ms as f64 / 1000.0, || {
+        Ok(counts().filter(|(local, replicas)| *local >= numlocal && *replicas >= numreplicas).map(reply))
+    }).await?;
+    match response {
+        Some(response) => Ok(response),
+        None => {
+            let aof = match &server.aof {
+                Some(aof) => Some(aof.lock().await),
+                None => None,
+            };
+            let replication = server.replication.lock().await;
+            Ok(reply(count_fsynced(aof.as_deref(), &replication, offset)))
+        }
+    }
+}
+
+// Whether writes from clients are refused, as on a read-only replica
+pub(crate) async fn refuses_writes(server: &Server) -> bool {
+    server.config.replica_read_only && server.replication.lock().await.primary.is_some()
+}
+
+// REPLCONF command, which replicas send during the handshake. The listening port is kept
+// by the connection, and acknowledgements are read on the link to the replica.
+pub fn execute_replconf_cmd(options: Vec<(String, String)>) -> Result<String, &'static str> {
+    for (option, value) in &options {
+        match option.as_str() {
+            "listening-port" => {
+                value.parse::<u16>().map_err(|_| "value is not an integer or out of range")?;
+            }
+            "capa" | "ip-address" => {}
+            // Acknowledgements only mean something on the link between a primary and a replica
+            "ack" | "getack" => return Ok(String::new()),
+            _ => return Err("Unrecognized REPLCONF option"),
+        }
+    }
+    Ok("+OK\r\n".to_string())
+}
+
+// ROLE command
+pub async fn execute_role_cmd(server: &Arc<Server>) -> Result<String, &'static str> {
+    let replication = server.replication.lock().await;
+    Ok(match &replication.primary {
+        None => {
+            let mut response = format!("*3\r\n{}:{}\r\n*{}\r\n", bulk_string("master"), replication.offset, replication.replicas.len());
+            for replica in &replication.replicas {
+                response += &bulk_array(&[replica.ip.clone(), replica.listening_port.to_string(), replica.ack_offset.to_string()]);
+            }
+            response
+        }
+        Some(primary) => {
+            let offset = if primary.state == LinkState::Connected { replication.offset as i64 } else { -1 };
+            format!("*5\r\n{}{}:{}\r\n{}:{}\r\n", bulk_string("slave"), bulk_string(&primary.host), primary.port, bulk_string(primary.state.name()), offset)
+        }
+    })
+}
+
+// INFO command. Only the replication and cluster sections are kept so far; other sections are empty.
+pub async fn execute_info_cmd(section: Option<String>, server: &Arc<Server>) -> Result<String, &'static str> {
+    let all = matches!(section.as_deref(), None | Some("default" | "all" | "everything"));
+    let mut info = String::new();
+    if all || section.as_deref() == Some("replication") {
+        info += &replication_info(server).await;
+    }
+    if all || section.as_deref() == Some("cluster") {
+        if !info.is_empty() {
+            info += "\r\n";
+        }
+        info += &format!("# Cluster\r\ncluster_enabled:{}\r\n", u8::from(server.cluster.is_some()));
+    }
+    Ok(bulk_string(&info))
+}
+
+// The replication section of INFO
+async fn replication_info(server: &Server) -> String {
+    let replication = server.replication.lock().await;
+    let now = now_ms() / 1000;
+    let mut info = String::from("# Replication\r\n");
+    match &replication.primary {
+        None => info += "role:master\r\n",
+        Some(primary) => {
+            let connected = primary.state == LinkState::Connected;
+            info += "role:slave\r\n";
+            info += &format!("master_host:{}\r\nmaster_port:{}\r\n", primary.host, primary.port);
+            info += &format!("master_link_status:{}\r\n", if connected { "up" } else { "down" });
+            info += &format!("master_last_io_seconds_ago:{}\r\n", if connected { now.saturating_sub(primary.last_io) as i64 } else { -1 });
+            info += &format!("master_sync_in_progress:{}\r\n", u8::from(primary.state == LinkState::Sync));
+            info += &format!("slave_read_repl_offset:{}\r\nslave_repl_offset:{}\r\n", replication.offset, replication.offset);
+            info += &format!("slave_read_only:{}\r\n", u8::from(server.config.replica_read_only));
+        }
+    }
+    info += &format!("connected_slaves:{}\r\n", replication.replicas.len());
+    for (i, replica) in replication.replicas.iter().enumerate() {
+        info += &format!("slave{}:ip={},port={},state={},offset={},lag={}\r\n",
+            i, replica.ip, replica.listening_port, replica.state.name(), replica.ack_offset, now.saturating_sub(replica.last_ack));
+    }
+    info += &format!("master_replid:{}\r\nmaster_replid2:{}\r\n", replication.replid, replication.replid2);
+    info += &format!("master_repl_offset:{}\r\nsecond_repl_offset:{}\r\n", replication.offset, replication.second_replid_offset);
+    info += &format!("repl_backlog_active:1\r\nrepl_backlog_size:{}\r\n", replication.backlog_size());
+    info += &format!("repl_backlog_first_byte_offset:{}\r\nrepl_backlog_histlen:{}\r\n", replication.backlog_first_byte(), replication.backlog_len());
+    info
+}
+
+// Once a second, drop the replicas that stopped acknowledging, and every REPL_PING_PERIOD
+// seconds send a PING down the stream when replicas are connected. A replica passes on the
+// PINGs of its primary instead.
+pub async fn run_replication_cron(server: Arc<Server>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut ticks: u64 = 0;
+    loop {
+        interval.tick().await;
+        ticks += 1;
+        let now = now_ms() / 1000;
+        let mut replication = server.replication.lock().await;
+        replication.replicas.retain(|replica| {
+            let alive = replica.state != ReplicaState::Online || now.saturating_sub(replica.last_ack) <= server.config.repl_timeout;
+            if !alive {
+                println!("Disconnecting timedout replica {}:{}", replica.ip, replica.listening_port);
+            }
+            alive
+        });
+        if replication.primary.is_none() && !replication.replicas.is_empty() && ticks.is_multiple_of(REPL_PING_PERIOD) {
+            replication.feed(&encode_request(&[b"PING"]));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aof::{self, AppendFsync};
+    use crate::config::Config;
+
+    fn frame(parts: &[&str]) -> Frame {
+        parts.iter().map(|p| p.as_bytes().to_vec()).collect()
+    }
+
+    // A server with one online replica, and the stream the replica is sent
+    async fn server_with_replica(server: Server) -> (Arc<Server>, u64, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let id = {
+            let mut replication = server.replication.lock().await;
+            let id = replication.add_replica("127.0.0.1".to_string(), 6380, ReplicaState::Online, sender);
+            replication.feed(b"abc");
+            id
+        };
+        (Arc::new(server), id, receiver)
+    }
+
+    #[test]
+    fn acks_are_parsed_with_their_aof_offset() {
+        assert_eq!(parse_ack(&frame(&["REPLCONF", "ACK", "42"])), Some((42, None)));
+        assert_eq!(parse_ack(&frame(&["replconf", "ack", "42", "fack", "40"])), Some((42, Some(40))));
+        assert_eq!(parse_ack(&frame(&["REPLCONF", "ACK", "x"])), None);
+        assert_eq!(parse_ack(&frame(&["REPLCONF", "GETACK", "*"])), None);
+    }
+
+    #[tokio::test]
+    async fn wait_counts_the_replicas_that_acknowledged() {
+        let (server, id, mut receiver) = server_with_replica(Server::new(Config::default(), None, None)).await;
+        assert_eq!(execute_wait_cmd(0, 10, &server).await, Ok(":0\r\n".to_string()));
+
+        // Replicas are asked to acknowledge, and the reply is what was acknowledged at the timeout
+        assert_eq!(execute_wait_cmd(1, 10, &server).await, Ok(":0\r\n".to_string()));
+        receiver.try_recv().unwrap();
+        assert_eq!(receiver.try_recv().unwrap(), encode_request(&[b"REPLCONF", b"GETACK", b"*"]));
+
+        let offset = server.replication.lock().await.offset;
+        server.replication.lock().await.replica_mut(id).unwrap().ack_offset = offset;
+        assert_eq!(execute_wait_cmd(1, 10, &server).await, Ok(":1\r\n".to_string()));
+
+        server.replication.lock().await.set_primary("127.0.0.1".to_string(), 6380);
+        assert!(execute_wait_cmd(1, 10, &server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn waitaof_counts_the_replicas_that_fsynced() {
+        let (server, id, _receiver) = server_with_replica(Server::new(Config::default(), None, None)).await;
+        assert!(execute_waitaof_cmd(1, 0, 10, &server).await.is_err());
+        assert_eq!(execute_waitaof_cmd(0, 1, 10, &server).await, Ok("*2\r\n:0\r\n:0\r\n".to_string()));
+
+        let offset = server.replication.lock().await.offset;
+        let mut replication = server.replication.lock().await;
+        let replica = replication.replica_mut(id).unwrap();
+        replica.ack_offset = offset;
+        replica.aof_ack_offset = Some(offset);
+        drop(replication);
+        assert_eq!(execute_waitaof_cmd(0, 1, 10, &server).await, Ok("*2\r\n:0\r\n:1\r\n".to_string()));
+    }
+
+    #[tokio::test]
+    async fn waitaof_waits_for_the_local_fsync() {
+        let dir = std::env::temp_dir().join(format!("mini1-waitaof-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config { dir: dir.clone(), appendonly: true, appendfsync: AppendFsync::Everysec, ..Config::default() };
+        let manifest = aof::create(&config, &HashMap::new()).unwrap();
+        let aof = Aof::open(&config, manifest).unwrap();
+        let (server, _, _receiver) = server_with_replica(Server::new(config, Some(aof), None)).await;
+
+        // Nothing waits for an fsync yet, so the whole stream is covered
+        assert_eq!(execute_waitaof_cmd(1, 0, 10, &server).await, Ok("*2\r\n:1\r\n:0\r\n".to_string()));
+
+        server.aof.as_ref().unwrap().lock().await.append(0, &[frame(&["SET", "k", "v"])]);
+        server.replication.lock().await.propagate(0, &[frame(&["SET", "k", "v"])]);
+        assert_eq!(execute_waitaof_cmd(1, 0, 10, &server).await, Ok("*2\r\n:0\r\n:0\r\n".to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
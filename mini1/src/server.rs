@@ -0,0 +1,54 @@
+use std::sync::Mutex;
+
+use crate::aof::Aof;
+use crate::cluster::Cluster;
+use crate::config::Config;
+use crate::replication::Replication;
+use crate::stream::now_ms;
+
+// State shared by every connection that is not part of the databases
+#[derive(Debug)]
+pub struct Server {
+    pub config: Config,
+    pub snapshot: Mutex<SnapshotState>,
+    // The append only file, when enabled. Writes hold it while they run, so they are
+    // appended in the order they changed the data.
+    pub aof: Option<tokio::sync::Mutex<Aof>>,
+    // The replication stream and the links to the primary and the replicas. Writes hold
+    // it while they run too, so replicas get them in the order they changed the data.
+    pub replication: tokio::sync::Mutex<Replication>,
+    // The view of the cluster, in cluster mode
+    pub cluster: Option<Mutex<Cluster>>,
+}
+
+// Bookkeeping for RDB snapshots
+#[derive(Debug)]
+pub struct SnapshotState {
+    // Writes since the last successful snapshot
+    pub dirty: u64,
+    // Unix time in seconds of the last successful snapshot, or of the start of the server
+    pub last_save: u64,
+    // Unix time in seconds of the last attempt, successful or not
+    pub last_attempt: u64,
+    pub last_ok: bool,
+    pub in_progress: bool,
+}
+
+impl Server {
+    pub fn new(config: Config, aof: Option<Aof>, cluster: Option<Cluster>) -> Self {
+        let now = now_ms() / 1000;
+        let replication = Replication::new(config.repl_backlog_size);
+        Self {
+            config,
+            snapshot: Mutex::new(SnapshotState { dirty: 0, last_save: now, last_attempt: now, last_ok: true, in_progress: false }),
+            aof: aof.map(tokio::sync::Mutex::new),
+            replication: tokio::sync::Mutex::new(replication),
+            cluster: cluster.map(Mutex::new),
+        }
+    }
+
+    // Count a command that changed the data
+    pub fn record_write(&self) {
+        self.snapshot.lock().unwrap().dirty += 1;
+    }
+}
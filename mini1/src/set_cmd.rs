@@ -0,0 +1,293 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
+use rand::{seq::IteratorRandom, seq::SliceRandom, thread_rng};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::{MAX_RANDOM_PICKS, WRONG_TYPE};
+use crate::keyspace_cmd::{scan_page, scan_reply, ScanOptions};
+use crate::utils::{bulk_array, optional_bulk_string};
+
+// The algebra applied by SINTER, SUNION, SDIFF and their STORE variants
+#[derive(Debug, Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+// Look up the set stored at key, failing if the key holds another type
+fn get_set<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a HashSet<String>>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Set(set)) => Ok(Some(set)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the set stored at key, creating an empty one if the key does not exist
+fn get_or_create_set<'a>(cur_db: &'a mut Database, key: &str) -> Result<&'a mut HashSet<String>, &'static str> {
+    match cur_db.data.entry(key.to_string()).or_insert_with(|| Data::Set(HashSet::new())) {
+        Data::Set(set) => Ok(set),
+        _ => Err(WRONG_TYPE),
+    }
+}
+
+// Remove the key if it holds an empty set, like Redis does
+fn remove_if_empty(cur_db: &mut Database, key: &str) {
+    if matches!(cur_db.data.get(key), Some(Data::Set(set)) if set.is_empty()) {
+        cur_db.data.remove(key);
+    }
+}
+
+// Compute the intersection, union or difference of the sets stored at keys.
+// Missing keys behave like empty sets.
+fn compute_set_operation(cur_db: &Database, operation: SetOperation, keys: &[String]) -> Result<HashSet<String>, &'static str> {
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+        sets.push(get_set(cur_db, key)?);
+    }
+
+    let empty = HashSet::new();
+    let first = sets[0].unwrap_or(&empty);
+    let result = match operation {
+        SetOperation::Inter => first.iter()
+            .filter(|member| sets[1..].iter().all(|set| set.is_some_and(|set| set.contains(*member))))
+            .cloned()
+            .collect(),
+        SetOperation::Union => sets.iter().flatten().flat_map(|set| set.iter()).cloned().collect(),
+        SetOperation::Diff => first.iter()
+            .filter(|member| !sets[1..].iter().any(|set| set.is_some_and(|set| set.contains(*member))))
+            .cloned()
+            .collect(),
+    };
+
+    Ok(result)
+}
+
+// SADD command
+pub fn execute_sadd_cmd(key: String, members: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let set = get_or_create_set(cur_db, &key)?;
+
+    let added = members.into_iter().filter(|member| set.insert(member.clone())).count();
+
+    Ok(format!(":{}\r\n", added))
+}
+
+// SREM command
+pub fn execute_srem_cmd(key: String, members: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let removed = match cur_db.data.get_mut(&key) {
+        Some(Data::Set(set)) => members.iter().filter(|member| set.remove(*member)).count(),
+        Some(_) => return Err(WRONG_TYPE),
+        None => 0,
+    };
+    remove_if_empty(cur_db, &key);
+
+    Ok(format!(":{}\r\n", removed))
+}
+
+// SISMEMBER command
+pub fn execute_sismember_cmd(key: String, member: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let is_member = get_set(cur_db, &key)?.is_some_and(|set| set.contains(&member));
+
+    Ok(format!(":{}\r\n", is_member as u8))
+}
+
+// SMISMEMBER command
+pub fn execute_smismember_cmd(key: String, members: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let set = get_set(cur_db, &key)?;
+
+    let mut response = format!("*{}\r\n", members.len());
+    for member in &members {
+        let is_member = set.is_some_and(|set| set.contains(member));
+        response += &format!(":{}\r\n", is_member as u8);
+    }
+
+    Ok(response)
+}
+
+// SMEMBERS command
+pub fn execute_smembers_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let members: Vec<&String> = get_set(cur_db, &key)?.map_or(Vec::new(), |set| set.iter().collect());
+
+    Ok(bulk_array(&members))
+}
+
+// SCARD command
+pub fn execute_scard_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let len = get_set(cur_db, &key)?.map_or(0, |set| set.len());
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// SPOP command
+pub fn execute_spop_cmd(key: String, count: Option<usize>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let mut rng = thread_rng();
+
+    let popped: Vec<String> = match cur_db.data.get_mut(&key) {
+        Some(Data::Set(set)) => {
+            // choose_multiple reserves room for the whole count up front
+            let count = count.unwrap_or(1).min(set.len());
+            let picked = set.iter().cloned().choose_multiple(&mut rng, count);
+            for member in &picked {
+                set.remove(member);
+            }
+            picked
+        }
+        Some(_) => return Err(WRONG_TYPE),
+        None => Vec::new(),
+    };
+    remove_if_empty(cur_db, &key);
+
+    match count {
+        Some(_) => Ok(bulk_array(&popped)),
+        None => Ok(optional_bulk_string(popped.first().map(|m| m.as_str()))),
+    }
+}
+
+// SRANDMEMBER command
+pub fn execute_srandmember_cmd(key: String, count: Option<i64>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let set = get_set(cur_db, &key)?;
+    let mut rng = thread_rng();
+
+    // Without a count, reply with a single member (or nil for a missing key)
+    let count = match count {
+        Some(count) => count,
+        None => {
+            let member = set.and_then(|set| set.iter().choose(&mut rng));
+            return Ok(optional_bulk_string(member.map(|m| m.as_str())));
+        }
+    };
+    if count < 0 && count.unsigned_abs() > MAX_RANDOM_PICKS {
+        return Err("value is out of range");
+    }
+
+    let members: Vec<&String> = set.map_or(Vec::new(), |set| set.iter().collect());
+    let picked: Vec<&String> = if members.is_empty() {
+        Vec::new()
+    } else if count >= 0 {
+        // A positive count returns distinct members
+        members.choose_multiple(&mut rng, count as usize).copied().collect()
+    } else {
+        // A negative count may return the same member several times
+        (0..count.unsigned_abs()).map(|_| *members.choose(&mut rng).unwrap()).collect()
+    };
+
+    Ok(bulk_array(&picked))
+}
+
+// SMOVE command
+pub fn execute_smove_cmd(source: String, destination: String, member: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    // Both keys are type checked before anything is modified
+    get_set(cur_db, &destination)?;
+    let moved = match cur_db.data.get_mut(&source) {
+        Some(Data::Set(set)) => set.remove(&member),
+        Some(_) => return Err(WRONG_TYPE),
+        None => false,
+    };
+    if !moved {
+        return Ok(":0\r\n".to_string());
+    }
+
+    remove_if_empty(cur_db, &source);
+    get_or_create_set(cur_db, &destination)?.insert(member);
+
+    Ok(":1\r\n".to_string())
+}
+
+// SINTER, SUNION and SDIFF commands
+pub fn execute_set_operation_cmd(operation: SetOperation, keys: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let result: Vec<String> = compute_set_operation(cur_db, operation, &keys)?.into_iter().collect();
+
+    Ok(bulk_array(&result))
+}
+
+// SINTERSTORE, SUNIONSTORE and SDIFFSTORE commands
+pub fn execute_set_operation_store_cmd(operation: SetOperation, destination: String, keys: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let result = compute_set_operation(cur_db, operation, &keys)?;
+    let len = result.len();
+
+    // The destination is overwritten whatever it held, and removed when the result is empty
+    if result.is_empty() {
+        cur_db.data.remove(&destination);
+    } else {
+        cur_db.data.insert(destination, Data::Set(result));
+    }
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// SINTERCARD command
+pub fn execute_sintercard_cmd(keys: Vec<String>, limit: usize, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let len = compute_set_operation(cur_db, SetOperation::Inter, &keys)?.len();
+
+    // A limit of 0 means no limit
+    let len = if limit > 0 { len.min(limit) } else { len };
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// SSCAN command
+pub fn execute_sscan_cmd(key: String, cursor: u64, options: ScanOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let set = match get_set(cur_db, &key)? {
+        Some(set) => set,
+        None => return Ok(scan_reply(0, bulk_array::<&str>(&[]))),
+    };
+
+    let (next_cursor, page) = scan_page(set.iter().map(|member| (member, ())), cursor, options.count);
+    let members: Vec<&String> = page.into_iter()
+        .map(|(member, _)| member)
+        .filter(|member| options.matches(member))
+        .collect();
+    Ok(scan_reply(next_cursor, bulk_array(&members)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srandmember_bounds_negative_count() {
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        db.lock().unwrap().get_mut(&0).unwrap().data.insert("s".to_string(), Data::Set(HashSet::from(["m".to_string()])));
+
+        assert_eq!(execute_srandmember_cmd("s".to_string(), Some(-2), &db, &mut 0), Ok(bulk_array(&["m", "m"])));
+        assert_eq!(execute_srandmember_cmd("s".to_string(), Some(i64::MIN), &db, &mut 0), Err("value is out of range"));
+        assert_eq!(execute_srandmember_cmd("s".to_string(), Some(i64::MAX), &db, &mut 0), Ok(bulk_array(&["m"])));
+    }
+
+    #[test]
+    fn spop_clamps_huge_count() {
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        db.lock().unwrap().get_mut(&0).unwrap().data.insert("s".to_string(), Data::Set(HashSet::from(["m".to_string()])));
+
+        assert_eq!(execute_spop_cmd("s".to_string(), Some(100_000_000_000), &db, &mut 0), Ok(bulk_array(&["m"])));
+        assert!(!db.lock().unwrap()[&0].data.contains_key("s"));
+    }
+}
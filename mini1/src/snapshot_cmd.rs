@@ -0,0 +1,97 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use crate::database::Database;
+use crate::rdb;
+use crate::server::Server;
+use crate::stream::now_ms;
+use crate::utils::write_file_atomically;
+
+// Seconds to wait after a failed snapshot before the save rules try again
+const SAVE_RETRY_DELAY: u64 = 5;
+
+// Record the outcome of a snapshot taken when dirty_at_start writes were pending
+fn finish_save(server: &Server, dirty_at_start: u64, result: std::io::Result<()>) -> Result<(), &'static str> {
+    let mut state = server.snapshot.lock().unwrap();
+    let now = now_ms() / 1000;
+    state.last_attempt = now;
+    state.last_ok = result.is_ok();
+    match result {
+        Ok(()) => {
+            state.dirty = state.dirty.saturating_sub(dirty_at_start);
+            state.last_save = now;
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Error saving the snapshot: {}", e);
+            Err("Error saving the snapshot, check the server logs for details")
+        }
+    }
+}
+
+// SAVE command. Clients wait while the databases are serialized, as with Redis.
+pub fn execute_save_cmd(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<String, &'static str> {
+    if server.snapshot.lock().unwrap().in_progress {
+        return Err("Background save already in progress");
+    }
+
+    let (contents, dirty_at_start) = {
+        let db = db.lock().unwrap();
+        (rdb::save(&db), server.snapshot.lock().unwrap().dirty)
+    };
+    finish_save(server, dirty_at_start, write_file_atomically(&server.config.rdb_path(), &contents))?;
+    Ok("+OK\r\n".to_string())
+}
+
+// Take a snapshot in the background. The databases are copied under the lock, and the
+// copy is serialized and written on a blocking task, so clients are not held up.
+pub(crate) fn start_background_save(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<(), &'static str> {
+    let (databases, dirty_at_start) = {
+        let db = db.lock().unwrap();
+        let mut state = server.snapshot.lock().unwrap();
+        if state.in_progress {
+            return Err("Background save already in progress");
+        }
+        state.in_progress = true;
+        (db.clone(), state.dirty)
+    };
+
+    let server = Arc::clone(server);
+    tokio::task::spawn_blocking(move || {
+        let result = write_file_atomically(&server.config.rdb_path(), &rdb::save(&databases));
+        let _ = finish_save(&server, dirty_at_start, result);
+        server.snapshot.lock().unwrap().in_progress = false;
+    });
+    Ok(())
+}
+
+// BGSAVE command
+pub fn execute_bgsave_cmd(db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>) -> Result<String, &'static str> {
+    start_background_save(db, server)?;
+    Ok("+Background saving started\r\n".to_string())
+}
+
+// LASTSAVE command
+pub fn execute_lastsave_cmd(server: &Arc<Server>) -> Result<String, &'static str> {
+    Ok(format!(":{}\r\n", server.snapshot.lock().unwrap().last_save))
+}
+
+// Check the save rules once a second and start a background save when one of them
+// is met. After a failed snapshot the rules wait a few seconds before trying again.
+pub async fn run_save_rules(db: Arc<Mutex<HashMap<u8, Database>>>, server: Arc<Server>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let due = {
+            let state = server.snapshot.lock().unwrap();
+            let now = now_ms() / 1000;
+            !state.in_progress
+                && (state.last_ok || now.saturating_sub(state.last_attempt) >= SAVE_RETRY_DELAY)
+                && server.config.save.iter().any(|(seconds, changes)| {
+                    state.dirty >= *changes && now.saturating_sub(state.last_save) >= *seconds
+                })
+        };
+        if due {
+            let _ = start_background_save(&db, &server);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::{cmp::Ordering, collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+use crate::utils::optional_bulk_string;
+
+// The arguments of SORT and SORT_RO
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortArgs {
+    pub key: String,
+    pub by: Option<String>,
+    pub limit: Option<(usize, Option<usize>)>,
+    pub gets: Vec<String>,
+    pub desc: bool,
+    pub alpha: bool,
+    pub store: Option<String>,
+}
+
+// Parse "<key> [BY <pattern>] [LIMIT <offset> <count>] [GET <pattern> ...] [ASC|DESC] [ALPHA] [STORE <destination>]".
+// STORE is not accepted by SORT_RO.
+pub fn parse_sort_args(args: &[String], read_only: bool) -> Result<SortArgs, &'static str> {
+    let mut sort = SortArgs { key: args[0].clone(), by: None, limit: None, gets: Vec::new(), desc: false, alpha: false, store: None };
+
+    let mut i = 1;
+    while i < args.len() {
+        let values = args.len() - i - 1;
+        match args[i].to_uppercase().as_str() {
+            "ASC" => sort.desc = false,
+            "DESC" => sort.desc = true,
+            "ALPHA" => sort.alpha = true,
+            "BY" if values >= 1 => {
+                sort.by = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "GET" if values >= 1 => {
+                sort.gets.push(args[i + 1].clone());
+                i += 1;
+            }
+            "STORE" if values >= 1 && !read_only => {
+                sort.store = Some(args[i + 1].clone());
+                i += 1;
+            }
+            "LIMIT" if values >= 2 => {
+                let offset = args[i + 1].parse::<i64>().map_err(|_| "value is not an integer or out of range")?;
+                let count = args[i + 2].parse::<i64>().map_err(|_| "value is not an integer or out of range")?;
+                // A negative count means every element after the offset
+                sort.limit = Some((offset.max(0) as usize, usize::try_from(count).ok()));
+                i += 2;
+            }
+            _ => return Err("syntax error"),
+        }
+        i += 1;
+    }
+    Ok(sort)
+}
+
+// Look up the value a BY or GET pattern names for an element. The first "*" is replaced by
+// the element, and "key->field" reads a hash field. "#" stands for the element itself.
+fn lookup_pattern(cur_db: &Database, pattern: &str, element: &str) -> Option<String> {
+    if pattern == "#" {
+        return Some(element.to_string());
+    }
+    let star = pattern.find('*')?;
+    let (key_pattern, field) = match pattern[star + 1..].find("->") {
+        Some(arrow) if star + 3 + arrow < pattern.len() => (&pattern[..star + 1 + arrow], Some(&pattern[star + 3 + arrow..])),
+        _ => (pattern, None),
+    };
+    let key = format!("{}{}{}", &key_pattern[..star], element, &key_pattern[star + 1..]);
+
+    match (cur_db.data.get(&key)?, field) {
+        (Data::Scalar(value), None) => Some(String::from_utf8_lossy(value).into_owned()),
+        (Data::Hash(hash), Some(field)) => hash.get(field).cloned(),
+        _ => None,
+    }
+}
+
+// SORT and SORT_RO commands. Elements are ordered by their own value or by the value BY
+// names, as numbers or with ALPHA as strings, and ties are ordered by the elements
+// themselves. A BY pattern without "*" skips sorting.
+pub fn execute_sort_cmd(args: SortArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let elements: Vec<String> = match cur_db.data.get(&args.key) {
+        Some(Data::List(list)) => list.iter().cloned().collect(),
+        Some(Data::Set(set)) => set.iter().cloned().collect(),
+        Some(Data::SortedSet(zset)) => zset.iter().map(|(member, _)| member.clone()).collect(),
+        Some(_) => return Err(WRONG_TYPE),
+        None => Vec::new(),
+    };
+
+    let mut sorted = elements;
+    if args.by.as_ref().is_none_or(|by| by.contains('*')) {
+        let mut weighted = Vec::with_capacity(sorted.len());
+        for element in sorted {
+            let weight = match &args.by {
+                Some(by) => lookup_pattern(cur_db, by, &element),
+                None => Some(element.clone()),
+            };
+            // Numeric weights are parsed up front, with a missing weight counting as 0
+            let score = match &weight {
+                Some(weight) if !args.alpha => weight.trim().parse::<f64>().ok().filter(|score| !score.is_nan())
+                    .ok_or("One or more scores can't be converted into double")?,
+                _ => 0.0,
+            };
+            weighted.push((element, weight, score));
+        }
+
+        weighted.sort_by(|(a, a_weight, a_score), (b, b_weight, b_score)| {
+            let ordering = if args.alpha { a_weight.cmp(b_weight) } else { a_score.partial_cmp(b_score).unwrap_or(Ordering::Equal) };
+            let ordering = ordering.then_with(|| a.cmp(b));
+            if args.desc { ordering.reverse() } else { ordering }
+        });
+        sorted = weighted.into_iter().map(|(element, ..)| element).collect();
+    } else if args.desc {
+        sorted.reverse();
+    }
+
+    if let Some((offset, count)) = args.limit {
+        let start = offset.min(sorted.len());
+        let end = count.map_or(sorted.len(), |count| start.saturating_add(count).min(sorted.len()));
+        sorted = sorted.drain(start..end).collect();
+    }
+
+    let results: Vec<Option<String>> = if args.gets.is_empty() {
+        sorted.into_iter().map(Some).collect()
+    } else {
+        sorted.iter()
+            .flat_map(|element| args.gets.iter().map(|get| lookup_pattern(cur_db, get, element)).collect::<Vec<_>>())
+            .collect()
+    };
+
+    match args.store {
+        // Missing values are stored as empty strings, and an empty result removes the destination
+        Some(destination) => {
+            let len = results.len();
+            if len == 0 {
+                cur_db.data.remove(&destination);
+            } else {
+                let list: VecDeque<String> = results.into_iter().map(Option::unwrap_or_default).collect();
+                cur_db.data.insert(destination, Data::List(list));
+            }
+            Ok(format!(":{}\r\n", len))
+        }
+        None => {
+            let mut response = format!("*{}\r\n", results.len());
+            for result in &results {
+                response += &optional_bulk_string(result.as_deref());
+            }
+            Ok(response)
+        }
+    }
+}
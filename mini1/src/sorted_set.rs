@@ -0,0 +1,192 @@
+use std::{cmp::Ordering, collections::{BTreeSet, HashMap}};
+
+// A score that can be ordered. NaN is never stored in a sorted set, so the
+// partial order of f64 is total for every value we keep.
+#[derive(Debug, Clone, Copy)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+// Members ordered by score, then lexicographically, plus a hash index from
+// member to score for O(1) lookups.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+#[allow(dead_code)]
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    // Insert or update a member, returning true if it was newly added
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.ordered.remove(&(Score(old), member.clone()));
+                self.ordered.insert((Score(score), member));
+                false
+            }
+            None => {
+                self.ordered.insert((Score(score), member));
+                true
+            }
+        }
+    }
+
+    // Remove a member, returning true if it was present
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered.remove(&(Score(score), member.to_string()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The 0-based position of member in ascending (or descending) order
+    pub fn rank(&self, member: &str, rev: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self.ordered.range(..(Score(score), member.to_string())).count();
+        Some(if rev { self.len() - 1 - rank } else { rank })
+    }
+
+    // Iterate over (member, score) pairs in ascending order
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&String, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+
+    // Members whose score lies within the given bounds, in ascending order
+    pub fn range_by_score(&self, min: &ScoreBound, max: &ScoreBound) -> Vec<(&String, f64)> {
+        self.ordered.range((Score(min.value), String::new())..)
+            .skip_while(|(score, _)| !min.admits_from_below(score.0))
+            .take_while(|(score, _)| max.admits_from_above(score.0))
+            .map(|(score, member)| (member, score.0))
+            .collect()
+    }
+
+    // Members within the given lexicographical bounds, in ascending order.
+    // Only meaningful when every member has the same score.
+    pub fn range_by_lex(&self, min: &LexBound, max: &LexBound) -> Vec<(&String, f64)> {
+        self.iter()
+            .filter(|(member, _)| min.admits_from_below(member) && max.admits_from_above(member))
+            .collect()
+    }
+}
+
+// One end of a score interval, as written in ZCOUNT or ZRANGE BYSCORE
+#[derive(Debug, Clone, Copy)]
+pub struct ScoreBound {
+    pub value: f64,
+    pub exclusive: bool,
+}
+
+impl ScoreBound {
+    // Parse "1.5", "(1.5", "-inf" or "+inf"
+    pub fn parse(bound: &str) -> Result<Self, &'static str> {
+        let (exclusive, value) = match bound.strip_prefix('(') {
+            Some(rest) => (true, rest),
+            None => (false, bound),
+        };
+        let value: f64 = parse_score(value).map_err(|_| "min or max is not a float")?;
+        Ok(Self { value, exclusive })
+    }
+
+    pub fn admits_from_below(&self, score: f64) -> bool {
+        if self.exclusive { score > self.value } else { score >= self.value }
+    }
+
+    pub fn admits_from_above(&self, score: f64) -> bool {
+        if self.exclusive { score < self.value } else { score <= self.value }
+    }
+}
+
+// One end of a lexicographical interval, as written in ZRANGE BYLEX
+#[derive(Debug, Clone)]
+pub enum LexBound {
+    NegativeInfinity,
+    PositiveInfinity,
+    Inclusive(String),
+    Exclusive(String),
+}
+
+impl LexBound {
+    // Parse "-", "+", "[member" or "(member"
+    pub fn parse(bound: &str) -> Result<Self, &'static str> {
+        if bound == "-" {
+            Ok(LexBound::NegativeInfinity)
+        } else if bound == "+" {
+            Ok(LexBound::PositiveInfinity)
+        } else if let Some(member) = bound.strip_prefix('[') {
+            Ok(LexBound::Inclusive(member.to_string()))
+        } else if let Some(member) = bound.strip_prefix('(') {
+            Ok(LexBound::Exclusive(member.to_string()))
+        } else {
+            Err("min or max not valid string range item")
+        }
+    }
+
+    pub fn admits_from_below(&self, member: &str) -> bool {
+        match self {
+            LexBound::NegativeInfinity => true,
+            LexBound::PositiveInfinity => false,
+            LexBound::Inclusive(bound) => member >= bound.as_str(),
+            LexBound::Exclusive(bound) => member > bound.as_str(),
+        }
+    }
+
+    pub fn admits_from_above(&self, member: &str) -> bool {
+        match self {
+            LexBound::NegativeInfinity => false,
+            LexBound::PositiveInfinity => true,
+            LexBound::Inclusive(bound) => member <= bound.as_str(),
+            LexBound::Exclusive(bound) => member < bound.as_str(),
+        }
+    }
+}
+
+// Parse a score, accepting the "inf"/"+inf"/"-inf" spellings Redis uses
+pub fn parse_score(value: &str) -> Result<f64, &'static str> {
+    let score: f64 = match value.to_lowercase().as_str() {
+        "inf" | "+inf" => f64::INFINITY,
+        "-inf" => f64::NEG_INFINITY,
+        _ => value.parse().map_err(|_| "value is not a valid float")?,
+    };
+    if score.is_nan() {
+        return Err("value is not a valid float");
+    }
+    Ok(score)
+}
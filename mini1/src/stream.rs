@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, fmt, time::{SystemTime, UNIX_EPOCH}};
+
+// The ID of a stream entry: a millisecond timestamp and a sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    // Parse "ms-seq", or "ms" with the given default sequence number
+    pub fn parse_with_default_seq(id: &str, default_seq: u64) -> Result<Self, &'static str> {
+        let invalid = "Invalid stream ID specified as stream command argument";
+        match id.split_once('-') {
+            Some((ms, seq)) => Ok(StreamId {
+                ms: ms.parse().map_err(|_| invalid)?,
+                seq: seq.parse().map_err(|_| invalid)?,
+            }),
+            None => Ok(StreamId { ms: id.parse().map_err(|_| invalid)?, seq: default_seq }),
+        }
+    }
+
+    // Parse a complete ID, where a missing sequence number means 0
+    pub fn parse(id: &str) -> Result<Self, &'static str> {
+        Self::parse_with_default_seq(id, 0)
+    }
+
+    // Parse the start of an XRANGE interval: "-", "ms", "ms-seq" or "(ms-seq"
+    pub fn parse_range_start(id: &str) -> Result<Self, &'static str> {
+        if id == "-" {
+            return Ok(StreamId::MIN);
+        }
+        match id.strip_prefix('(') {
+            Some(id) => Self::parse_with_default_seq(id, 0)?.next().ok_or("invalid start ID for the interval"),
+            None => Self::parse_with_default_seq(id, 0),
+        }
+    }
+
+    // Parse the end of an XRANGE interval: "+", "ms", "ms-seq" or "(ms-seq"
+    pub fn parse_range_end(id: &str) -> Result<Self, &'static str> {
+        if id == "+" {
+            return Ok(StreamId::MAX);
+        }
+        match id.strip_prefix('(') {
+            Some(id) => Self::parse_with_default_seq(id, u64::MAX)?.prev().ok_or("invalid end ID for the interval"),
+            None => Self::parse_with_default_seq(id, u64::MAX),
+        }
+    }
+
+    // The smallest ID greater than this one
+    pub fn next(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => self.ms.checked_add(1).map(|ms| StreamId { ms, seq: 0 }),
+        }
+    }
+
+    // The greatest ID smaller than this one
+    pub fn prev(&self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => self.ms.checked_sub(1).map(|ms| StreamId { ms, seq: u64::MAX }),
+        }
+    }
+}
+
+// Milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+// A delivered but not yet acknowledged entry of a consumer group
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+// A consumer of a consumer group
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    pub seen_time: u64,
+    pub active_time: Option<u64>,
+}
+
+impl Consumer {
+    pub fn new() -> Self {
+        Self { seen_time: now_ms(), active_time: None }
+    }
+}
+
+// A consumer group with its pending entries list (PEL)
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    pub entries_read: Option<u64>,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered: StreamId, entries_read: Option<u64>) -> Self {
+        Self { last_delivered, entries_read, pending: BTreeMap::new(), consumers: BTreeMap::new() }
+    }
+
+    // Look up a consumer, creating it on first use like XREADGROUP does
+    pub fn consumer(&mut self, name: &str) -> &mut Consumer {
+        self.consumers.entry(name.to_string()).or_insert_with(Consumer::new)
+    }
+}
+
+// The entries of a stream, keyed by ID
+pub type StreamEntries = BTreeMap<StreamId, Vec<(String, String)>>;
+
+// An append-only log of field-value entries ordered by ID
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    pub entries: StreamEntries,
+    pub last_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Resolve the ID requested by XADD ("*", "ms-*" or "ms-seq") against the last ID
+    pub fn next_id(&self, requested: &str) -> Result<StreamId, &'static str> {
+        let too_small = "The ID specified in XADD is equal or smaller than the target stream top item";
+        let id = if requested == "*" {
+            let ms = now_ms();
+            if ms > self.last_id.ms {
+                StreamId { ms, seq: 0 }
+            } else {
+                self.last_id.next().ok_or(too_small)?
+            }
+        } else if let Some(ms) = requested.strip_suffix("-*") {
+            let ms: u64 = ms.parse().map_err(|_| "Invalid stream ID specified as stream command argument")?;
+            if ms == self.last_id.ms {
+                self.last_id.next().ok_or(too_small)?
+            } else {
+                StreamId { ms, seq: if ms == 0 { 1 } else { 0 } }
+            }
+        } else {
+            StreamId::parse(requested)?
+        };
+
+        if id == StreamId::MIN {
+            return Err("The ID specified in XADD must be greater than 0-0");
+        }
+        if id <= self.last_id {
+            return Err(too_small);
+        }
+        Ok(id)
+    }
+
+    // Append an entry whose ID was validated by next_id
+    pub fn append(&mut self, id: StreamId, fields: Vec<(String, String)>) {
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+    }
+
+    // Delete an entry, returning true if it existed
+    pub fn delete(&mut self, id: &StreamId) -> bool {
+        if self.entries.remove(id).is_none() {
+            return false;
+        }
+        self.max_deleted_id = self.max_deleted_id.max(*id);
+        true
+    }
+
+    // Entries with IDs between start and end (both inclusive), in ascending order
+    pub fn range(&self, start: StreamId, end: StreamId) -> impl DoubleEndedIterator<Item = (&StreamId, &Vec<(String, String)>)> {
+        (start <= end).then(|| self.entries.range(start..=end)).into_iter().flatten()
+    }
+
+    // Remove the oldest entries according to a MAXLEN or MINID strategy, returning how many were removed
+    pub fn trim(&mut self, strategy: &TrimStrategy, limit: Option<usize>) -> usize {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut removed = 0;
+        while removed < limit {
+            let oldest = match self.entries.keys().next() {
+                Some(id) => *id,
+                None => break,
+            };
+            let remove = match strategy {
+                TrimStrategy::MaxLen(max_len) => self.entries.len() as u64 > *max_len,
+                TrimStrategy::MinId(min_id) => oldest < *min_id,
+            };
+            if !remove {
+                break;
+            }
+            self.delete(&oldest);
+            removed += 1;
+        }
+        removed
+    }
+}
+
+// How XADD and XTRIM decide which entries to evict
+#[derive(Debug, Clone)]
+pub enum TrimStrategy {
+    MaxLen(u64),
+    MinId(StreamId),
+}
@@ -0,0 +1,837 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::{block_until, WRONG_TYPE};
+use crate::stream::{now_ms, ConsumerGroup, PendingEntry, Stream, StreamEntries, StreamId, TrimStrategy};
+use crate::utils::{bulk_array, bulk_string};
+
+static NO_GROUP: &str = "NOGROUP No such key or consumer group";
+
+// The MAXLEN/MINID clause of XADD and XTRIM
+#[derive(Debug, Clone)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    pub limit: Option<usize>,
+}
+
+// The arguments of XADD after the key
+#[derive(Debug, Clone)]
+pub struct XaddArgs {
+    pub nomkstream: bool,
+    pub trim: Option<StreamTrim>,
+    pub id: String,
+    pub fields: Vec<(String, String)>,
+}
+
+// The arguments of XREAD and XREADGROUP. IDs are resolved when the command runs,
+// because "$" refers to the last ID at that moment.
+#[derive(Debug, Clone)]
+pub struct XreadArgs {
+    pub group: Option<(String, String)>,
+    pub count: Option<usize>,
+    pub block: Option<u64>,
+    pub noack: bool,
+    pub keys: Vec<String>,
+    pub ids: Vec<String>,
+}
+
+// The subcommands of XGROUP
+#[derive(Debug, Clone)]
+pub enum XgroupArgs {
+    Create { key: String, group: String, id: String, mkstream: bool, entries_read: Option<u64> },
+    SetId { key: String, group: String, id: String, entries_read: Option<u64> },
+    Destroy { key: String, group: String },
+    CreateConsumer { key: String, group: String, consumer: String },
+    DelConsumer { key: String, group: String, consumer: String },
+}
+
+// The optional range of the extended XPENDING form
+#[derive(Debug, Clone)]
+pub struct XpendingRange {
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<String>,
+}
+
+// The options of XCLAIM after the IDs
+#[derive(Debug, Clone, Default)]
+pub struct XclaimOptions {
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub just_id: bool,
+}
+
+// The subcommands of XINFO
+#[derive(Debug, Clone)]
+pub enum XinfoArgs {
+    Stream(String),
+    Groups(String),
+    Consumers(String, String),
+}
+
+fn parse_integer<T: std::str::FromStr>(value: Option<&String>) -> Result<T, &'static str> {
+    value.and_then(|v| v.parse().ok()).ok_or("value is not an integer or out of range")
+}
+
+// Parse a MAXLEN|MINID [=|~] threshold [LIMIT count] clause starting at args[*index]
+fn parse_trim_args(args: &[String], index: &mut usize) -> Result<Option<StreamTrim>, &'static str> {
+    let kind = match args.get(*index).map(|a| a.to_uppercase()) {
+        Some(kind) if kind == "MAXLEN" || kind == "MINID" => kind,
+        _ => return Ok(None),
+    };
+    *index += 1;
+
+    let mut approximate = false;
+    match args.get(*index).map(|a| a.as_str()) {
+        Some("~") => { approximate = true; *index += 1; }
+        Some("=") => *index += 1,
+        _ => {}
+    }
+
+    let threshold = args.get(*index).ok_or("syntax error")?;
+    let strategy = if kind == "MAXLEN" {
+        TrimStrategy::MaxLen(threshold.parse().map_err(|_| "The MAXLEN argument must be >= 0.")?)
+    } else {
+        TrimStrategy::MinId(StreamId::parse(threshold)?)
+    };
+    *index += 1;
+
+    let mut limit = None;
+    if args.get(*index).is_some_and(|a| a.eq_ignore_ascii_case("LIMIT")) {
+        if !approximate {
+            return Err("syntax error, LIMIT cannot be used without the special ~ option");
+        }
+        limit = Some(parse_integer(args.get(*index + 1))?);
+        *index += 2;
+    }
+
+    Ok(Some(StreamTrim { strategy, limit }))
+}
+
+// Parse XADD arguments after the key: [NOMKSTREAM] [MAXLEN|MINID [=|~] threshold [LIMIT count]] id field value [field value ...]
+pub fn parse_xadd_args(args: &[String]) -> Result<XaddArgs, &'static str> {
+    let mut index = 0;
+    let mut nomkstream = false;
+    let mut trim = None;
+    loop {
+        if args.get(index).is_some_and(|a| a.eq_ignore_ascii_case("NOMKSTREAM")) {
+            nomkstream = true;
+            index += 1;
+        } else if let Some(parsed) = parse_trim_args(args, &mut index)? {
+            trim = Some(parsed);
+        } else {
+            break;
+        }
+    }
+
+    let rest = &args[index.min(args.len())..];
+    if rest.len() < 3 || rest.len().is_multiple_of(2) {
+        return Err("wrong number of arguments for 'xadd' command");
+    }
+    let fields = rest[1..].chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+
+    Ok(XaddArgs { nomkstream, trim, id: rest[0].clone(), fields })
+}
+
+// Parse XTRIM arguments after the key: MAXLEN|MINID [=|~] threshold [LIMIT count]
+pub fn parse_xtrim_args(args: &[String]) -> Result<StreamTrim, &'static str> {
+    let mut index = 0;
+    let trim = parse_trim_args(args, &mut index)?.ok_or("syntax error")?;
+    if index != args.len() {
+        return Err("syntax error");
+    }
+    Ok(trim)
+}
+
+// Parse XREAD or XREADGROUP arguments:
+// [GROUP group consumer] [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key [key ...] id [id ...]
+pub fn parse_xread_args(args: &[String], with_group: bool) -> Result<XreadArgs, &'static str> {
+    let mut parsed = XreadArgs { group: None, count: None, block: None, noack: false, keys: Vec::new(), ids: Vec::new() };
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        match arg.to_uppercase().as_str() {
+            "GROUP" if with_group => {
+                let group = args.get(index + 1).ok_or("syntax error")?;
+                let consumer = args.get(index + 2).ok_or("syntax error")?;
+                parsed.group = Some((group.clone(), consumer.clone()));
+                index += 2;
+            }
+            "COUNT" => {
+                parsed.count = Some(parse_integer(args.get(index + 1))?);
+                index += 1;
+            }
+            "BLOCK" => {
+                parsed.block = Some(parse_integer(args.get(index + 1)).map_err(|_| "timeout is not an integer or out of range")?);
+                index += 1;
+            }
+            "NOACK" if with_group => parsed.noack = true,
+            "STREAMS" => {
+                let streams = &args[index + 1..];
+                if streams.is_empty() || !streams.len().is_multiple_of(2) {
+                    return Err("Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.");
+                }
+                let (keys, ids) = streams.split_at(streams.len() / 2);
+                parsed.keys = keys.to_vec();
+                parsed.ids = ids.to_vec();
+                break;
+            }
+            _ => return Err("syntax error"),
+        }
+        index += 1;
+    }
+
+    if parsed.keys.is_empty() {
+        return Err("syntax error");
+    }
+    if with_group && parsed.group.is_none() {
+        return Err("Missing GROUP option for XREADGROUP");
+    }
+    Ok(parsed)
+}
+
+// Parse XGROUP arguments, starting with the subcommand
+pub fn parse_xgroup_args(args: &[String]) -> Result<XgroupArgs, &'static str> {
+    let subcommand = args.first().ok_or("syntax error")?.to_uppercase();
+    let key = args.get(1).cloned().ok_or("syntax error")?;
+    let group = args.get(2).cloned().ok_or("syntax error")?;
+
+    // CREATE and SETID accept [MKSTREAM] [ENTRIESREAD entries-read] after the ID
+    let mut mkstream = false;
+    let mut entries_read = None;
+    if subcommand == "CREATE" || subcommand == "SETID" {
+        let mut index = 4;
+        while let Some(arg) = args.get(index) {
+            match arg.to_uppercase().as_str() {
+                "MKSTREAM" if subcommand == "CREATE" => mkstream = true,
+                "ENTRIESREAD" => {
+                    entries_read = Some(parse_integer(args.get(index + 1))?);
+                    index += 1;
+                }
+                _ => return Err("syntax error"),
+            }
+            index += 1;
+        }
+    } else if args.len() > 4 {
+        return Err("syntax error");
+    }
+
+    let third = args.get(3).cloned();
+    match subcommand.as_str() {
+        "CREATE" => Ok(XgroupArgs::Create { key, group, id: third.ok_or("syntax error")?, mkstream, entries_read }),
+        "SETID" => Ok(XgroupArgs::SetId { key, group, id: third.ok_or("syntax error")?, entries_read }),
+        "DESTROY" if third.is_none() => Ok(XgroupArgs::Destroy { key, group }),
+        "CREATECONSUMER" => Ok(XgroupArgs::CreateConsumer { key, group, consumer: third.ok_or("syntax error")? }),
+        "DELCONSUMER" => Ok(XgroupArgs::DelConsumer { key, group, consumer: third.ok_or("syntax error")? }),
+        _ => Err("Unknown XGROUP subcommand. Try CREATE, SETID, DESTROY, CREATECONSUMER or DELCONSUMER."),
+    }
+}
+
+// Parse the extended XPENDING form after the group: [IDLE min-idle-time] start end count [consumer]
+pub fn parse_xpending_args(args: &[String]) -> Result<Option<XpendingRange>, &'static str> {
+    if args.is_empty() {
+        return Ok(None);
+    }
+
+    let mut index = 0;
+    let mut min_idle = 0;
+    if args[0].eq_ignore_ascii_case("IDLE") {
+        min_idle = parse_integer(args.get(1))?;
+        index = 2;
+    }
+    let rest = &args[index.min(args.len())..];
+    if rest.len() < 3 || rest.len() > 4 {
+        return Err("syntax error");
+    }
+
+    Ok(Some(XpendingRange {
+        min_idle,
+        start: StreamId::parse_range_start(&rest[0])?,
+        end: StreamId::parse_range_end(&rest[1])?,
+        count: parse_integer(rest.get(2))?,
+        consumer: rest.get(3).cloned(),
+    }))
+}
+
+// Parse XCLAIM arguments after the min-idle-time: id [id ...] [IDLE ms] [TIME unix-time-ms] [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID id]
+pub fn parse_xclaim_args(args: &[String]) -> Result<(Vec<StreamId>, XclaimOptions), &'static str> {
+    let mut ids = Vec::new();
+    let mut options = XclaimOptions::default();
+    let mut index = 0;
+    while let Some(Ok(id)) = args.get(index).map(|id| StreamId::parse(id)) {
+        ids.push(id);
+        index += 1;
+    }
+    if ids.is_empty() {
+        return Err("Invalid stream ID specified as stream command argument");
+    }
+
+    while let Some(arg) = args.get(index) {
+        match arg.to_uppercase().as_str() {
+            "IDLE" => { options.idle = Some(parse_integer(args.get(index + 1))?); index += 1; }
+            "TIME" => { options.time = Some(parse_integer(args.get(index + 1))?); index += 1; }
+            "RETRYCOUNT" => { options.retry_count = Some(parse_integer(args.get(index + 1))?); index += 1; }
+            "FORCE" => options.force = true,
+            "JUSTID" => options.just_id = true,
+            "LASTID" => { StreamId::parse(args.get(index + 1).ok_or("syntax error")?)?; index += 1; }
+            _ => return Err("syntax error"),
+        }
+        index += 1;
+    }
+
+    Ok((ids, options))
+}
+
+// Parse XINFO arguments, starting with the subcommand
+pub fn parse_xinfo_args(args: &[String]) -> Result<XinfoArgs, &'static str> {
+    let subcommand = args.first().map(|a| a.to_uppercase());
+    match (subcommand.as_deref(), args.len()) {
+        (Some("STREAM"), 2) => Ok(XinfoArgs::Stream(args[1].clone())),
+        (Some("GROUPS"), 2) => Ok(XinfoArgs::Groups(args[1].clone())),
+        (Some("CONSUMERS"), 3) => Ok(XinfoArgs::Consumers(args[1].clone(), args[2].clone())),
+        _ => Err("Unknown XINFO subcommand or wrong number of arguments. Try STREAM, GROUPS or CONSUMERS."),
+    }
+}
+
+// Look up the stream stored at key, failing if the key holds another type
+fn get_stream<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a Stream>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the stream stored at key mutably, failing if the key holds another type
+fn get_stream_mut<'a>(cur_db: &'a mut Database, key: &str) -> Result<Option<&'a mut Stream>, &'static str> {
+    match cur_db.data.get_mut(key) {
+        Some(Data::Stream(stream)) => Ok(Some(stream)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up a consumer group of the stream stored at key, along with the stream entries
+fn get_group_mut<'a>(cur_db: &'a mut Database, key: &str, group: &str) -> Result<(&'a mut ConsumerGroup, &'a StreamEntries), &'static str> {
+    let Stream { entries, groups, .. } = get_stream_mut(cur_db, key)?.ok_or(NO_GROUP)?;
+    let group = groups.get_mut(group).ok_or(NO_GROUP)?;
+    Ok((group, entries))
+}
+
+// Encode a stream entry as [id, [field, value, ...]]
+fn entry_reply(id: &StreamId, fields: &[(String, String)]) -> String {
+    let flat: Vec<&String> = fields.iter().flat_map(|(field, value)| [field, value]).collect();
+    format!("*2\r\n{}{}", bulk_string(&id.to_string()), bulk_array(&flat))
+}
+
+// Encode a list of stream entries
+fn entries_reply<'a>(entries: impl Iterator<Item = (&'a StreamId, &'a Vec<(String, String)>)>) -> String {
+    let mut count = 0;
+    let mut body = String::new();
+    for (id, fields) in entries {
+        body += &entry_reply(id, fields);
+        count += 1;
+    }
+    format!("*{}\r\n{}", count, body)
+}
+
+// Apply a MAXLEN/MINID clause to a stream
+fn apply_trim(stream: &mut Stream, trim: &StreamTrim) -> usize {
+    stream.trim(&trim.strategy, trim.limit)
+}
+
+// XADD command
+pub fn execute_xadd_cmd(key: String, args: XaddArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    if get_stream(cur_db, &key)?.is_none() {
+        if args.nomkstream {
+            return Ok("$-1\r\n".to_string());
+        }
+        cur_db.data.insert(key.clone(), Data::Stream(Stream::new()));
+    }
+    let stream = get_stream_mut(cur_db, &key)?.ok_or("Database not found")?;
+
+    let id = stream.next_id(&args.id)?;
+    stream.append(id, args.fields);
+    if let Some(trim) = &args.trim {
+        apply_trim(stream, trim);
+    }
+
+    Ok(bulk_string(&id.to_string()))
+}
+
+// XLEN command
+pub fn execute_xlen_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let len = get_stream(cur_db, &key)?.map_or(0, |stream| stream.entries.len());
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// XRANGE and XREVRANGE commands
+pub fn execute_xrange_cmd(key: String, start: StreamId, end: StreamId, count: Option<usize>, rev: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let count = count.unwrap_or(usize::MAX);
+
+    let stream = match get_stream(cur_db, &key)? {
+        Some(stream) => stream,
+        None => return Ok("*0\r\n".to_string()),
+    };
+    if rev {
+        Ok(entries_reply(stream.range(start, end).rev().take(count)))
+    } else {
+        Ok(entries_reply(stream.range(start, end).take(count)))
+    }
+}
+
+// XDEL command
+pub fn execute_xdel_cmd(key: String, ids: Vec<StreamId>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let deleted = match get_stream_mut(cur_db, &key)? {
+        Some(stream) => ids.iter().filter(|id| stream.delete(id)).count(),
+        None => 0,
+    };
+
+    Ok(format!(":{}\r\n", deleted))
+}
+
+// XTRIM command
+pub fn execute_xtrim_cmd(key: String, trim: StreamTrim, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let removed = get_stream_mut(cur_db, &key)?.map_or(0, |stream| apply_trim(stream, &trim));
+
+    Ok(format!(":{}\r\n", removed))
+}
+
+// Read new entries of every stream in args, returning None when there is nothing to reply with
+fn read_streams(args: &XreadArgs, start_ids: &[StreamId], db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<Option<String>, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let count = args.count.filter(|count| *count > 0).unwrap_or(usize::MAX);
+
+    let mut streams = Vec::new();
+    for (key, start) in args.keys.iter().zip(start_ids) {
+        let Some(stream) = get_stream(cur_db, key)? else { continue };
+        let Some(from) = start.next() else { continue };
+        let mut entries = stream.range(from, StreamId::MAX).take(count).peekable();
+        if entries.peek().is_some() {
+            streams.push(format!("*2\r\n{}{}", bulk_string(key), entries_reply(entries)));
+        }
+    }
+
+    if streams.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!("*{}\r\n{}", streams.len(), streams.concat())))
+}
+
+// Serve XREADGROUP for every stream in args, returning None when there is nothing to reply with
+fn read_streams_for_group(args: &XreadArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<Option<String>, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let (group_name, consumer_name) = args.group.as_ref().ok_or("Missing GROUP option for XREADGROUP")?;
+    let count = args.count.filter(|count| *count > 0).unwrap_or(usize::MAX);
+    let now = now_ms();
+
+    // Every key and group is checked before anything is delivered
+    for key in &args.keys {
+        get_group_mut(cur_db, key, group_name)?;
+    }
+
+    let mut streams = Vec::new();
+    let mut has_history = false;
+    for (key, id) in args.keys.iter().zip(&args.ids) {
+        let (group, entries) = get_group_mut(cur_db, key, group_name)?;
+        let consumer = group.consumer(consumer_name);
+        consumer.seen_time = now;
+
+        let mut reply = String::new();
+        let mut delivered = 0;
+        if id == ">" {
+            // Deliver entries never delivered to any consumer of the group
+            let from = group.last_delivered.next().unwrap_or(StreamId::MAX);
+            let new_entries: Vec<StreamId> = entries.range(from..).take(count).map(|(id, _)| *id).collect();
+            if !new_entries.is_empty() {
+                group.consumer(consumer_name).active_time = Some(now);
+            }
+            for entry_id in new_entries {
+                group.last_delivered = entry_id;
+                group.entries_read = group.entries_read.map(|read| read + 1);
+                if !args.noack {
+                    let pending = PendingEntry { consumer: consumer_name.clone(), delivery_time: now, delivery_count: 1 };
+                    group.pending.insert(entry_id, pending);
+                }
+                reply += &entry_reply(&entry_id, &entries[&entry_id]);
+                delivered += 1;
+            }
+        } else {
+            // Replay the history of entries pending for this consumer
+            has_history = true;
+            let from = StreamId::parse(id)?.next().unwrap_or(StreamId::MAX);
+            let history = group.pending.range(from..)
+                .filter(|(_, pending)| pending.consumer == *consumer_name)
+                .take(count);
+            for (entry_id, _) in history {
+                reply += &match entries.get(entry_id) {
+                    Some(fields) => entry_reply(entry_id, fields),
+                    None => format!("*2\r\n{}*-1\r\n", bulk_string(&entry_id.to_string())),
+                };
+                delivered += 1;
+            }
+        }
+
+        if delivered > 0 || has_history {
+            streams.push(format!("*2\r\n{}*{}\r\n{}", bulk_string(key), delivered, reply));
+        }
+    }
+
+    if streams.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(format!("*{}\r\n{}", streams.len(), streams.concat())))
+}
+
+// XREAD and XREADGROUP commands
+pub async fn execute_xread_cmd(args: XreadArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let response = if args.group.is_some() {
+        // Only reads of new entries (">") block; history is replied right away
+        let blocking = args.ids.iter().all(|id| id == ">");
+        match args.block {
+            Some(block) if blocking => block_until(block as f64 / 1000.0, || read_streams_for_group(&args, db, selected_db)).await?,
+            _ => read_streams_for_group(&args, db, selected_db)?,
+        }
+    } else {
+        // "$" means the last ID of the stream when the command starts
+        let mut start_ids = Vec::with_capacity(args.ids.len());
+        {
+            let db = db.lock().unwrap();
+            let cur_db = db.get(selected_db).ok_or("Database not found")?;
+            for (key, id) in args.keys.iter().zip(&args.ids) {
+                start_ids.push(match id.as_str() {
+                    "$" => get_stream(cur_db, key)?.map_or(StreamId::MIN, |stream| stream.last_id),
+                    _ => StreamId::parse(id)?,
+                });
+            }
+        }
+        match args.block {
+            Some(block) => block_until(block as f64 / 1000.0, || read_streams(&args, &start_ids, db, selected_db)).await?,
+            None => read_streams(&args, &start_ids, db, selected_db)?,
+        }
+    };
+
+    // A timed out BLOCK replies with a null array, like a read that found nothing
+    Ok(response.unwrap_or_else(|| "*-1\r\n".to_string()))
+}
+
+// XGROUP command
+pub fn execute_xgroup_cmd(args: XgroupArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let missing_key = "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.";
+
+    match args {
+        XgroupArgs::Create { key, group, id, mkstream, entries_read } => {
+            if get_stream(cur_db, &key)?.is_none() {
+                if !mkstream {
+                    return Err(missing_key);
+                }
+                cur_db.data.insert(key.clone(), Data::Stream(Stream::new()));
+            }
+            let stream = get_stream_mut(cur_db, &key)?.ok_or(missing_key)?;
+            if stream.groups.contains_key(&group) {
+                return Err("BUSYGROUP Consumer Group name already exists");
+            }
+            let (last_delivered, known_read) = resolve_group_id(stream, &id)?;
+            stream.groups.insert(group, ConsumerGroup::new(last_delivered, entries_read.or(known_read)));
+            Ok("+OK\r\n".to_string())
+        }
+        XgroupArgs::SetId { key, group, id, entries_read } => {
+            let stream = get_stream_mut(cur_db, &key)?.ok_or(missing_key)?;
+            let (last_delivered, known_read) = resolve_group_id(stream, &id)?;
+            let group = stream.groups.get_mut(&group).ok_or(NO_GROUP)?;
+            group.last_delivered = last_delivered;
+            group.entries_read = entries_read.or(known_read);
+            Ok("+OK\r\n".to_string())
+        }
+        XgroupArgs::Destroy { key, group } => {
+            let stream = get_stream_mut(cur_db, &key)?.ok_or(missing_key)?;
+            Ok(format!(":{}\r\n", stream.groups.remove(&group).is_some() as u8))
+        }
+        XgroupArgs::CreateConsumer { key, group, consumer } => {
+            let (group, _) = get_group_mut(cur_db, &key, &group)?;
+            let created = !group.consumers.contains_key(&consumer);
+            group.consumer(&consumer);
+            Ok(format!(":{}\r\n", created as u8))
+        }
+        XgroupArgs::DelConsumer { key, group, consumer } => {
+            let (group, _) = get_group_mut(cur_db, &key, &group)?;
+            let before = group.pending.len();
+            group.pending.retain(|_, pending| pending.consumer != consumer);
+            group.consumers.remove(&consumer);
+            Ok(format!(":{}\r\n", before - group.pending.len()))
+        }
+    }
+}
+
+// Resolve the ID given to XGROUP CREATE/SETID, and how many entries it implies were read
+fn resolve_group_id(stream: &Stream, id: &str) -> Result<(StreamId, Option<u64>), &'static str> {
+    if id == "$" {
+        return Ok((stream.last_id, Some(stream.entries_added)));
+    }
+    let id = StreamId::parse(id)?;
+    // Starting from the beginning of a stream nothing was ever deleted from, nothing was read yet
+    let known_read = (id == StreamId::MIN && stream.max_deleted_id == StreamId::MIN).then_some(0);
+    Ok((id, known_read))
+}
+
+// XACK command
+pub fn execute_xack_cmd(key: String, group: String, ids: Vec<StreamId>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let acknowledged = match get_stream_mut(cur_db, &key)?.and_then(|stream| stream.groups.get_mut(&group)) {
+        Some(group) => ids.iter().filter(|id| group.pending.remove(id).is_some()).count(),
+        None => 0,
+    };
+
+    Ok(format!(":{}\r\n", acknowledged))
+}
+
+// XPENDING command
+pub fn execute_xpending_cmd(key: String, group: String, range: Option<XpendingRange>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let (group, _) = get_group_mut(cur_db, &key, &group)?;
+    let now = now_ms();
+
+    let range = match range {
+        Some(range) => range,
+        None => {
+            // Summary form: count, smallest and greatest pending IDs, and pending count per consumer
+            if group.pending.is_empty() {
+                return Ok("*4\r\n:0\r\n$-1\r\n$-1\r\n*-1\r\n".to_string());
+            }
+            let mut per_consumer: Vec<(&String, usize)> = Vec::new();
+            for pending in group.pending.values() {
+                match per_consumer.iter_mut().find(|(name, _)| **name == pending.consumer) {
+                    Some((_, count)) => *count += 1,
+                    None => per_consumer.push((&pending.consumer, 1)),
+                }
+            }
+            per_consumer.sort();
+            let first = group.pending.keys().next().unwrap();
+            let last = group.pending.keys().next_back().unwrap();
+            let mut response = format!("*4\r\n:{}\r\n{}{}*{}\r\n", group.pending.len(), bulk_string(&first.to_string()), bulk_string(&last.to_string()), per_consumer.len());
+            for (name, count) in per_consumer {
+                response += &bulk_array(&[name.clone(), count.to_string()]);
+            }
+            return Ok(response);
+        }
+    };
+
+    let mut entries = Vec::new();
+    if range.start <= range.end {
+        for (id, pending) in group.pending.range(range.start..=range.end) {
+            if entries.len() >= range.count {
+                break;
+            }
+            let idle = now.saturating_sub(pending.delivery_time);
+            if idle < range.min_idle || range.consumer.as_ref().is_some_and(|consumer| *consumer != pending.consumer) {
+                continue;
+            }
+            entries.push(format!("*4\r\n{}{}:{}\r\n:{}\r\n", bulk_string(&id.to_string()), bulk_string(&pending.consumer), idle, pending.delivery_count));
+        }
+    }
+
+    Ok(format!("*{}\r\n{}", entries.len(), entries.concat()))
+}
+
+// Transfer ownership of a pending entry to consumer, returning its reply if it still exists
+fn claim_entry(group: &mut ConsumerGroup, entries: &StreamEntries, id: &StreamId, consumer: &str, options: &XclaimOptions, now: u64) -> Option<String> {
+    if !entries.contains_key(id) {
+        // The entry was deleted from the stream, so it can no longer be claimed
+        group.pending.remove(id);
+        return None;
+    }
+
+    let delivery_time = match (options.idle, options.time) {
+        (Some(idle), _) => now.saturating_sub(idle),
+        (None, Some(time)) => time,
+        (None, None) => now,
+    };
+    let pending = group.pending.entry(*id).or_insert(PendingEntry { consumer: consumer.to_string(), delivery_time, delivery_count: 0 });
+    pending.consumer = consumer.to_string();
+    pending.delivery_time = delivery_time;
+    if let Some(retry_count) = options.retry_count {
+        pending.delivery_count = retry_count;
+    } else if !options.just_id {
+        pending.delivery_count += 1;
+    }
+
+    Some(if options.just_id { bulk_string(&id.to_string()) } else { entry_reply(id, &entries[id]) })
+}
+
+// XCLAIM command
+#[allow(clippy::too_many_arguments)]
+pub fn execute_xclaim_cmd(key: String, group: String, consumer: String, min_idle: u64, ids: Vec<StreamId>, options: XclaimOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let (group, entries) = get_group_mut(cur_db, &key, &group)?;
+    let now = now_ms();
+
+    let mut claimed = Vec::new();
+    for id in &ids {
+        let eligible = match group.pending.get(id) {
+            Some(pending) => now.saturating_sub(pending.delivery_time) >= min_idle,
+            None => options.force && entries.contains_key(id),
+        };
+        if eligible {
+            claimed.extend(claim_entry(group, entries, id, &consumer, &options, now));
+        }
+    }
+    if !claimed.is_empty() {
+        group.consumer(&consumer).active_time = Some(now);
+    }
+    group.consumer(&consumer).seen_time = now;
+
+    Ok(format!("*{}\r\n{}", claimed.len(), claimed.concat()))
+}
+
+// XAUTOCLAIM command
+#[allow(clippy::too_many_arguments)]
+pub fn execute_xautoclaim_cmd(key: String, group: String, consumer: String, min_idle: u64, start: StreamId, count: usize, just_id: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let (group, entries) = get_group_mut(cur_db, &key, &group)?;
+    let now = now_ms();
+    let options = XclaimOptions { just_id, ..XclaimOptions::default() };
+
+    // Scan at most count pending entries starting from the cursor
+    let scanned: Vec<(StreamId, u64)> = group.pending.range(start..)
+        .take(count + 1)
+        .map(|(id, pending)| (*id, pending.delivery_time))
+        .collect();
+    let next_cursor = if scanned.len() > count { scanned[count].0 } else { StreamId::MIN };
+
+    let (mut claimed, mut deleted) = (Vec::new(), Vec::new());
+    for (id, delivery_time) in scanned.into_iter().take(count) {
+        if now.saturating_sub(delivery_time) < min_idle {
+            continue;
+        }
+        match claim_entry(group, entries, &id, &consumer, &options, now) {
+            Some(reply) => claimed.push(reply),
+            None => deleted.push(id.to_string()),
+        }
+    }
+    if !claimed.is_empty() {
+        group.consumer(&consumer).active_time = Some(now);
+    }
+    group.consumer(&consumer).seen_time = now;
+
+    Ok(format!("*3\r\n{}*{}\r\n{}{}", bulk_string(&next_cursor.to_string()), claimed.len(), claimed.concat(), bulk_array(&deleted)))
+}
+
+// XINFO command
+pub fn execute_xinfo_cmd(args: XinfoArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let now = now_ms();
+
+    match args {
+        XinfoArgs::Stream(key) => {
+            let stream = get_stream(cur_db, &key)?.ok_or("no such key")?;
+            let edge_entry = |entry: Option<(&StreamId, &Vec<(String, String)>)>| match entry {
+                Some((id, fields)) => entry_reply(id, fields),
+                None => "$-1\r\n".to_string(),
+            };
+            let fields = [
+                ("length", format!(":{}\r\n", stream.entries.len())),
+                ("last-generated-id", bulk_string(&stream.last_id.to_string())),
+                ("max-deleted-entry-id", bulk_string(&stream.max_deleted_id.to_string())),
+                ("entries-added", format!(":{}\r\n", stream.entries_added)),
+                ("recorded-first-entry-id", bulk_string(&stream.entries.keys().next().copied().unwrap_or_default().to_string())),
+                ("groups", format!(":{}\r\n", stream.groups.len())),
+                ("first-entry", edge_entry(stream.entries.iter().next())),
+                ("last-entry", edge_entry(stream.entries.iter().next_back())),
+            ];
+            // The header counts names and values, so it always matches the fields written
+            let mut response = format!("*{}\r\n", fields.len() * 2);
+            for (name, value) in fields {
+                response += &bulk_string(name);
+                response += &value;
+            }
+            Ok(response)
+        }
+        XinfoArgs::Groups(key) => {
+            let stream = get_stream(cur_db, &key)?.ok_or("no such key")?;
+            let mut response = format!("*{}\r\n", stream.groups.len());
+            for (name, group) in &stream.groups {
+                let entries_read = group.entries_read.map_or("$-1\r\n".to_string(), |read| format!(":{}\r\n", read));
+                let lag = group.entries_read.map_or("$-1\r\n".to_string(), |read| format!(":{}\r\n", stream.entries_added.saturating_sub(read)));
+                response += &format!(
+                    "*12\r\n{}{}{}:{}\r\n{}:{}\r\n{}{}{}{}{}{}",
+                    bulk_string("name"), bulk_string(name),
+                    bulk_string("consumers"), group.consumers.len(),
+                    bulk_string("pending"), group.pending.len(),
+                    bulk_string("last-delivered-id"), bulk_string(&group.last_delivered.to_string()),
+                    bulk_string("entries-read"), entries_read,
+                    bulk_string("lag"), lag,
+                );
+            }
+            Ok(response)
+        }
+        XinfoArgs::Consumers(key, group_name) => {
+            let stream = get_stream(cur_db, &key)?.ok_or("no such key")?;
+            let group = stream.groups.get(&group_name).ok_or(NO_GROUP)?;
+            let mut response = format!("*{}\r\n", group.consumers.len());
+            for (name, consumer) in &group.consumers {
+                let pending = group.pending.values().filter(|pending| pending.consumer == *name).count();
+                let inactive = consumer.active_time.map_or(-1, |active| now.saturating_sub(active) as i64);
+                response += &format!(
+                    "*8\r\n{}{}{}:{}\r\n{}:{}\r\n{}:{}\r\n",
+                    bulk_string("name"), bulk_string(name),
+                    bulk_string("pending"), pending,
+                    bulk_string("idle"), now.saturating_sub(consumer.seen_time),
+                    bulk_string("inactive"), inactive,
+                );
+            }
+            Ok(response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xinfo_stream_header_matches_fields() {
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        let args = XaddArgs { nomkstream: false, trim: None, id: "1-1".to_string(), fields: vec![("f".to_string(), "v".to_string())] };
+        execute_xadd_cmd("s".to_string(), args, &db, &mut 0).unwrap();
+
+        let reply = execute_xinfo_cmd(XinfoArgs::Stream("s".to_string()), &db, &mut 0).unwrap();
+        assert!(reply.starts_with("*16\r\n"));
+        assert!(reply.ends_with(&format!("{}{}", bulk_string("last-entry"), entry_reply(&StreamId { ms: 1, seq: 1 }, &[("f".to_string(), "v".to_string())]))));
+    }
+
+    #[tokio::test]
+    async fn xread_block_timeout_replies_null() {
+        let db = Arc::new(Mutex::new(HashMap::from([(0, Database::new())])));
+        let args = parse_xread_args(&["BLOCK", "10", "STREAMS", "s", "$"].map(String::from), false).unwrap();
+        assert_eq!(execute_xread_cmd(args, &db, &mut 0).await, Ok("*-1\r\n".to_string()));
+
+        execute_xgroup_cmd(parse_xgroup_args(&["CREATE", "s", "g", "$", "MKSTREAM"].map(String::from)).unwrap(), &db, &mut 0).unwrap();
+        let args = parse_xread_args(&["GROUP", "g", "c", "BLOCK", "10", "STREAMS", "s", ">"].map(String::from), true).unwrap();
+        assert_eq!(execute_xread_cmd(args, &db, &mut 0).await, Ok("*-1\r\n".to_string()));
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+
+// What to do when a sample is added at a timestamp that already has one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    Block,
+    First,
+    Last,
+    Min,
+    Max,
+    Sum,
+}
+
+impl DuplicatePolicy {
+    pub fn parse(policy: &str) -> Result<Self, &'static str> {
+        match policy.to_uppercase().as_str() {
+            "BLOCK" => Ok(DuplicatePolicy::Block),
+            "FIRST" => Ok(DuplicatePolicy::First),
+            "LAST" => Ok(DuplicatePolicy::Last),
+            "MIN" => Ok(DuplicatePolicy::Min),
+            "MAX" => Ok(DuplicatePolicy::Max),
+            "SUM" => Ok(DuplicatePolicy::Sum),
+            _ => Err("TSDB: Unknown DUPLICATE_POLICY"),
+        }
+    }
+}
+
+// How the samples of a bucket are combined
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Avg,
+    Min,
+    Max,
+    Sum,
+    Count,
+}
+
+impl Aggregation {
+    pub fn parse(aggregation: &str) -> Result<Self, &'static str> {
+        match aggregation.to_uppercase().as_str() {
+            "AVG" => Ok(Aggregation::Avg),
+            "MIN" => Ok(Aggregation::Min),
+            "MAX" => Ok(Aggregation::Max),
+            "SUM" => Ok(Aggregation::Sum),
+            "COUNT" => Ok(Aggregation::Count),
+            _ => Err("TSDB: Unknown aggregation type"),
+        }
+    }
+
+    fn apply(self, values: &[f64]) -> f64 {
+        match self {
+            Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+            Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Count => values.len() as f64,
+        }
+    }
+}
+
+// A time series: samples keyed by millisecond timestamp, and labels used to find series with TS.MRANGE.
+// A retention of 0 keeps every sample.
+#[derive(Debug, Clone)]
+pub struct TimeSeries {
+    pub samples: BTreeMap<u64, f64>,
+    pub retention: u64,
+    pub duplicate_policy: DuplicatePolicy,
+    pub labels: Vec<(String, String)>,
+}
+
+impl TimeSeries {
+    pub fn new(retention: u64, duplicate_policy: DuplicatePolicy, labels: Vec<(String, String)>) -> Self {
+        Self { samples: BTreeMap::new(), retention, duplicate_policy, labels }
+    }
+
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels.iter().find(|(label, _)| label == name).map(|(_, value)| value.as_str())
+    }
+
+    fn last_timestamp(&self) -> Option<u64> {
+        self.samples.keys().next_back().copied()
+    }
+
+    // Add a sample, resolving an existing sample at the same timestamp with the policy
+    pub fn add(&mut self, timestamp: u64, value: f64, policy: Option<DuplicatePolicy>) -> Result<u64, &'static str> {
+        if let Some(last) = self.last_timestamp() {
+            if self.retention > 0 && timestamp < last.saturating_sub(self.retention) {
+                return Err("TSDB: Timestamp is older than retention");
+            }
+        }
+
+        let value = match self.samples.get(&timestamp) {
+            None => value,
+            Some(&old) => match policy.unwrap_or(self.duplicate_policy) {
+                DuplicatePolicy::Block => return Err("TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode"),
+                DuplicatePolicy::First => old,
+                DuplicatePolicy::Last => value,
+                DuplicatePolicy::Min => old.min(value),
+                DuplicatePolicy::Max => old.max(value),
+                DuplicatePolicy::Sum => old + value,
+            },
+        };
+        self.samples.insert(timestamp, value);
+        self.trim();
+        Ok(timestamp)
+    }
+
+    // Drop samples that fell out of the retention period
+    fn trim(&mut self) {
+        if let Some(last) = self.last_timestamp().filter(|_| self.retention > 0) {
+            self.samples = self.samples.split_off(&last.saturating_sub(self.retention));
+        }
+    }
+
+    // The samples between from and to (inclusive), optionally aggregated into buckets aligned to timestamp 0
+    pub fn range(&self, from: u64, to: u64, aggregation: Option<(Aggregation, u64)>) -> Vec<(u64, f64)> {
+        if from > to {
+            return Vec::new();
+        }
+        let samples = self.samples.range(from..=to).map(|(timestamp, value)| (*timestamp, *value));
+        let (aggregation, bucket) = match aggregation {
+            Some(aggregation) => aggregation,
+            None => return samples.collect(),
+        };
+
+        let mut buckets: Vec<(u64, Vec<f64>)> = Vec::new();
+        for (timestamp, value) in samples {
+            let start = timestamp - timestamp % bucket;
+            match buckets.last_mut() {
+                Some((last_start, values)) if *last_start == start => values.push(value),
+                _ => buckets.push((start, vec![value])),
+            }
+        }
+        buckets.into_iter().map(|(start, values)| (start, aggregation.apply(&values))).collect()
+    }
+}
@@ -0,0 +1,289 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::WRONG_TYPE;
+use crate::stream::now_ms;
+use crate::timeseries::{Aggregation, DuplicatePolicy, TimeSeries};
+use crate::utils::{bulk_string, format_float};
+
+static KEY_DOES_NOT_EXIST: &str = "TSDB: the key does not exist";
+
+// The options of TS.CREATE, which TS.ADD also accepts for the series it creates
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TsCreateOptions {
+    pub retention: Option<u64>,
+    pub duplicate_policy: Option<DuplicatePolicy>,
+    pub labels: Option<Vec<(String, String)>>,
+    // TS.ADD only: the policy for this sample, overriding the series policy
+    pub on_duplicate: Option<DuplicatePolicy>,
+}
+
+// A TS.MRANGE filter: "label=value", "label!=value", "label=(a,b)" or "label!=(a,b)".
+// An empty value stands for a missing label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelFilter {
+    pub label: String,
+    pub values: Vec<String>,
+    pub negate: bool,
+}
+
+impl LabelFilter {
+    fn parse(filter: &str) -> Result<Self, &'static str> {
+        let (label, negate, value) = match filter.split_once("!=") {
+            Some((label, value)) => (label, true, value),
+            None => match filter.split_once('=') {
+                Some((label, value)) => (label, false, value),
+                None => return Err("TSDB: failed parsing labels"),
+            },
+        };
+        if label.is_empty() {
+            return Err("TSDB: failed parsing labels");
+        }
+        let values = match value.strip_prefix('(').and_then(|value| value.strip_suffix(')')) {
+            Some(list) => list.split(',').map(|value| value.trim().to_string()).collect(),
+            None => vec![value.to_string()],
+        };
+        Ok(Self { label: label.to_string(), values, negate })
+    }
+
+    fn matches(&self, series: &TimeSeries) -> bool {
+        let value = series.label(&self.label).unwrap_or("");
+        self.values.iter().any(|candidate| candidate == value) != self.negate
+    }
+
+    // Whether the filter requires a label to have a value, which TS.MRANGE needs at least one of
+    fn is_positive(&self) -> bool {
+        !self.negate && self.values.iter().any(|value| !value.is_empty())
+    }
+}
+
+// The arguments of TS.RANGE, TS.REVRANGE and TS.MRANGE. The labels and filters are only used by TS.MRANGE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TsRangeArgs {
+    pub from: u64,
+    pub to: u64,
+    pub count: Option<usize>,
+    pub aggregation: Option<(Aggregation, u64)>,
+    pub with_labels: bool,
+    pub filters: Vec<LabelFilter>,
+}
+
+// Parse a sample timestamp, where "*" means now
+pub fn parse_timestamp(timestamp: &str) -> Result<u64, &'static str> {
+    if timestamp == "*" {
+        return Ok(now_ms());
+    }
+    timestamp.parse().map_err(|_| "TSDB: invalid timestamp")
+}
+
+// Parse a sample value
+pub fn parse_sample_value(value: &str) -> Result<f64, &'static str> {
+    value.parse().ok().filter(|value: &f64| !value.is_nan()).ok_or("TSDB: invalid value")
+}
+
+// Parse "[RETENTION <ms>] [DUPLICATE_POLICY <policy>] [ON_DUPLICATE <policy>] [LABELS <label> <value> ...]".
+// ON_DUPLICATE is only accepted by TS.ADD.
+pub fn parse_ts_create_args(args: &[String], allow_on_duplicate: bool) -> Result<TsCreateOptions, &'static str> {
+    let mut options = TsCreateOptions::default();
+    let mut i = 0;
+    while i < args.len() {
+        let has_value = i + 1 < args.len();
+        match args[i].to_uppercase().as_str() {
+            "RETENTION" if has_value => {
+                options.retention = Some(args[i + 1].parse().map_err(|_| "TSDB: invalid RETENTION")?);
+                i += 2;
+            }
+            "DUPLICATE_POLICY" if has_value => {
+                options.duplicate_policy = Some(DuplicatePolicy::parse(&args[i + 1])?);
+                i += 2;
+            }
+            "ON_DUPLICATE" if has_value && allow_on_duplicate => {
+                options.on_duplicate = Some(DuplicatePolicy::parse(&args[i + 1])?);
+                i += 2;
+            }
+            // Labels take the rest of the arguments
+            "LABELS" => {
+                let labels = &args[i + 1..];
+                if !labels.len().is_multiple_of(2) {
+                    return Err("TSDB: wrong number of arguments for LABELS");
+                }
+                options.labels = Some(labels.chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect());
+                i = args.len();
+            }
+            _ => return Err("TSDB: invalid arguments"),
+        }
+    }
+    Ok(options)
+}
+
+// Parse "<from> <to> [COUNT <count>] [AGGREGATION <aggregator> <bucket>]", and for TS.MRANGE
+// "[WITHLABELS] FILTER <filter> ..." where the filters take the rest of the arguments
+pub fn parse_ts_range_args(args: &[String], multiple: bool) -> Result<TsRangeArgs, &'static str> {
+    let parse_bound = |bound: &str, open: u64| match bound {
+        "-" | "+" => Ok(open),
+        _ => bound.parse().map_err(|_| "TSDB: invalid timestamp"),
+    };
+    let mut range = TsRangeArgs {
+        from: parse_bound(&args[0], 0)?,
+        to: parse_bound(&args[1], u64::MAX)?,
+        count: None,
+        aggregation: None,
+        with_labels: false,
+        filters: Vec::new(),
+    };
+
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].to_uppercase().as_str() {
+            "COUNT" if i + 1 < args.len() => {
+                range.count = Some(args[i + 1].parse().map_err(|_| "TSDB: invalid COUNT")?);
+                i += 2;
+            }
+            "AGGREGATION" if i + 2 < args.len() => {
+                let bucket = args[i + 2].parse().ok().filter(|bucket| *bucket > 0).ok_or("TSDB: bucketDuration must be greater than zero")?;
+                range.aggregation = Some((Aggregation::parse(&args[i + 1])?, bucket));
+                i += 3;
+            }
+            "WITHLABELS" if multiple => {
+                range.with_labels = true;
+                i += 1;
+            }
+            "FILTER" if multiple => {
+                range.filters = args[i + 1..].iter().map(|filter| LabelFilter::parse(filter)).collect::<Result<_, _>>()?;
+                i = args.len();
+            }
+            _ => return Err("TSDB: invalid arguments"),
+        }
+    }
+
+    if multiple && !range.filters.iter().any(LabelFilter::is_positive) {
+        return Err("TSDB: please provide at least one matcher");
+    }
+    Ok(range)
+}
+
+// Look up the time series stored at key, failing if the key holds another type or does not exist
+fn get_series<'a>(cur_db: &'a Database, key: &str) -> Result<&'a TimeSeries, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::TimeSeries(series)) => Ok(series),
+        Some(_) => Err(WRONG_TYPE),
+        None => Err(KEY_DOES_NOT_EXIST),
+    }
+}
+
+// Look up the time series stored at key mutably, failing if the key holds another type or does not exist
+fn get_series_mut<'a>(cur_db: &'a mut Database, key: &str) -> Result<&'a mut TimeSeries, &'static str> {
+    match cur_db.data.get_mut(key) {
+        Some(Data::TimeSeries(series)) => Ok(series),
+        Some(_) => Err(WRONG_TYPE),
+        None => Err(KEY_DOES_NOT_EXIST),
+    }
+}
+
+fn new_series(options: &TsCreateOptions) -> TimeSeries {
+    TimeSeries::new(
+        options.retention.unwrap_or(0),
+        options.duplicate_policy.unwrap_or(DuplicatePolicy::Block),
+        options.labels.clone().unwrap_or_default(),
+    )
+}
+
+// The samples of a series within a range, newest first if reversed
+fn select_samples(series: &TimeSeries, range: &TsRangeArgs, reverse: bool) -> Vec<(u64, f64)> {
+    let mut samples = series.range(range.from, range.to, range.aggregation);
+    if reverse {
+        samples.reverse();
+    }
+    if let Some(count) = range.count {
+        samples.truncate(count);
+    }
+    samples
+}
+
+// Serialize samples as an array of [timestamp, value] pairs
+fn samples_array(samples: &[(u64, f64)]) -> String {
+    let mut response = format!("*{}\r\n", samples.len());
+    for (timestamp, value) in samples {
+        response += &format!("*2\r\n:{}\r\n", timestamp);
+        response += &bulk_string(&format_float(*value));
+    }
+    response
+}
+
+// TS.CREATE command
+pub fn execute_ts_create_cmd(key: String, options: TsCreateOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if cur_db.data.contains_key(&key) {
+        return Err("TSDB: key already exists");
+    }
+
+    cur_db.data.insert(key, Data::TimeSeries(new_series(&options)));
+    Ok("+OK\r\n".to_string())
+}
+
+// TS.ADD command. A missing series is created with the given options.
+pub fn execute_ts_add_cmd(key: String, timestamp: u64, value: f64, options: TsCreateOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    if !cur_db.data.contains_key(&key) {
+        cur_db.data.insert(key.clone(), Data::TimeSeries(new_series(&options)));
+    }
+
+    let series = get_series_mut(cur_db, &key)?;
+    Ok(format!(":{}\r\n", series.add(timestamp, value, options.on_duplicate)?))
+}
+
+// TS.MADD command. Each sample is answered with its timestamp or its own error.
+pub fn execute_ts_madd_cmd(samples: Vec<(String, u64, f64)>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let mut response = format!("*{}\r\n", samples.len());
+    for (key, timestamp, value) in &samples {
+        response += &match get_series_mut(cur_db, key).and_then(|series| series.add(*timestamp, *value, None)) {
+            Ok(timestamp) => format!(":{}\r\n", timestamp),
+            Err(e) => format!("-{}\r\n", e),
+        };
+    }
+    Ok(response)
+}
+
+// TS.RANGE and TS.REVRANGE commands
+pub fn execute_ts_range_cmd(key: String, range: TsRangeArgs, reverse: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let series = get_series(cur_db, &key)?;
+
+    Ok(samples_array(&select_samples(series, &range, reverse)))
+}
+
+// TS.MRANGE command. Every series whose labels match all filters is answered with
+// its key, its labels (with WITHLABELS) and its samples, ordered by key.
+pub fn execute_ts_mrange_cmd(range: TsRangeArgs, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+
+    let mut matches: Vec<(&String, &TimeSeries)> = cur_db.data.iter()
+        .filter_map(|(key, data)| match data {
+            Data::TimeSeries(series) if range.filters.iter().all(|filter| filter.matches(series)) => Some((key, series)),
+            _ => None,
+        })
+        .collect();
+    matches.sort_by_key(|(key, _)| *key);
+
+    let mut response = format!("*{}\r\n", matches.len());
+    for (key, series) in matches {
+        response += "*3\r\n";
+        response += &bulk_string(key);
+        let labels: &[(String, String)] = if range.with_labels { &series.labels } else { &[] };
+        response += &format!("*{}\r\n", labels.len());
+        for (label, value) in labels {
+            response += "*2\r\n";
+            response += &bulk_string(label);
+            response += &bulk_string(value);
+        }
+        response += &samples_array(&select_samples(series, &range, false));
+    }
+    Ok(response)
+}
@@ -1,42 +1,78 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
+use std::{collections::HashMap, fs, io::Write, path::Path, sync::{Arc, Mutex}};
 
-use crate::{command::Command, database::Database, execute_cmd::{*}};
+use crate::{command::Command, database::Database, execute_cmd::{*}, hash_cmd::{*}, set_cmd::{*}, zset_cmd::{*}, sorted_set::{parse_score, ScoreBound}, stream_cmd::{*}, stream::StreamId, bit_cmd::{*}, hll_cmd::{*}, geo_cmd::{*}, json_cmd::{*}, json_path::JsonPath, bloom_cmd::{execute_bf_add_cmd, execute_bf_exists_cmd, execute_bf_info_cmd, execute_bf_reserve_cmd, parse_bf_reserve_args}, cms_cmd::{execute_cms_incrby_cmd, execute_cms_info_cmd, execute_cms_init_cmd, execute_cms_query_cmd, parse_cms_dimensions, parse_cms_probabilities}, timeseries_cmd::{execute_ts_add_cmd, execute_ts_create_cmd, execute_ts_madd_cmd, execute_ts_mrange_cmd, execute_ts_range_cmd, parse_sample_value, parse_timestamp, parse_ts_create_args, parse_ts_range_args}, keyspace_cmd::{execute_copy_cmd, execute_del_cmd, execute_dump_cmd, execute_keys_cmd, execute_move_cmd, execute_object_cmd, execute_randomkey_cmd, execute_rename_cmd, execute_restore_cmd, execute_scan_cmd, execute_touch_cmd, execute_type_cmd, parse_restore_args, parse_scan_args, touch_keys, ObjectSubcommand}, db_cmd::{execute_dbsize_cmd, execute_flush_cmd, execute_swapdb_cmd}, migrate_cmd::{execute_migrate_cmd, parse_migrate_args}, sort_cmd::{execute_sort_cmd, parse_sort_args}, aof::log_entries, aof_cmd::execute_bgrewriteaof_cmd, cluster_cmd::{execute_asking_cmd, execute_cluster_cmd, ClusterSubcommand}, replication_cmd::{execute_info_cmd, execute_replconf_cmd, execute_replicaof_cmd, execute_role_cmd, execute_wait_cmd, execute_waitaof_cmd}, server::Server, snapshot_cmd::{execute_bgsave_cmd, execute_lastsave_cmd, execute_save_cmd}};
 
-static MAX_DATABASES: u8 = 15;
+pub(crate) static MAX_DATABASES: u8 = 15;
 static MAX_KEYS: usize = 5;
 
-// Parse the incoming request
-pub(crate) fn parse_request(request: &str) -> Result<(Command, Vec<String>), &'static str> {
-    println!("----------------- Parse Request -----------------");
+// Parse a database index given as a command argument
+fn parse_db_index(index: &str) -> Result<u8, &'static str> {
+    index.parse().ok().filter(|index| *index <= MAX_DATABASES).ok_or("Invalid database index")
+}
 
-    let mut iter = request.split("\r\n");
-    // println!("Iter: {:?}", iter);
-    let header = iter.next().ok_or("Empty request")?;
-    // println!("Header: {:?}", header);
+// The arguments of one request, as raw bytes
+pub type Frame = Vec<Vec<u8>>;
 
-    if !header.starts_with("*") {
-        return Err("Invalid request format");
+// Read one RESP request (an array of bulk strings) from the front of buf.
+// Returns the arguments and the number of bytes consumed, or None if the request is incomplete.
+pub fn read_frame(buf: &[u8]) -> Result<Option<(Frame, usize)>, &'static str> {
+    // Read a "<prefix><number>\r\n" header starting at pos
+    fn read_header(buf: &[u8], pos: usize, prefix: u8) -> Result<Option<(usize, usize)>, &'static str> {
+        let line_end = match buf[pos..].windows(2).position(|w| w == b"\r\n") {
+            Some(offset) => pos + offset,
+            None => return Ok(None),
+        };
+        if buf[pos] != prefix {
+            return Err("Invalid request format");
+        }
+        let number = std::str::from_utf8(&buf[pos + 1..line_end]).ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or("Invalid request format")?;
+        Ok(Some((number, line_end + 2)))
     }
 
-    let count: usize = header[1..].parse().map_err(|_| "Invalid request format")?;
-    let mut parts = Vec::with_capacity(count);
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    let (count, mut pos) = match read_header(buf, 0, b'*')? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
 
+    // Every part takes at least a byte of input, so a forged count can't reserve more than that
+    let mut parts = Vec::with_capacity(count.min(buf.len() - pos));
     for _ in 0..count {
-        let length_header = iter.next().ok_or("Invalid request format")?;
-
-        if !length_header.starts_with("$") {
-            return Err("Invalid request format");
+        if pos >= buf.len() {
+            return Ok(None);
         }
-
-        let length: usize = length_header[1..].parse().map_err(|_| "Invalid request format")?;
-        let arg = iter.next().ok_or("Invalid request format")?;
-
-        if arg.len() != length {
+        let (length, start) = match read_header(buf, pos, b'$')? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        if buf.len() - start < length.saturating_add(2) {
+            return Ok(None);
+        }
+        if &buf[start + length..start + length + 2] != b"\r\n" {
             return Err("Invalid request format");
         }
+        parts.push(buf[start..start + length].to_vec());
+        pos = start + length + 2;
+    }
 
-        parts.push(arg.to_string());
+    if parts.is_empty() {
+        return Err("Empty request");
     }
+    Ok(Some((parts, pos)))
+}
+
+// Parse the incoming request
+pub(crate) fn parse_request(frame: Frame) -> Result<(Command, Vec<String>), &'static str> {
+    println!("----------------- Parse Request -----------------");
+
+    // Most arguments are handled as text; raw keeps the exact bytes for binary-safe values
+    let mut raw = frame;
+    let mut parts: Vec<String> = raw.iter().map(|part| String::from_utf8_lossy(part).into_owned()).collect();
+    raw.remove(0);
 
     let cmd = parts.remove(0);
     println!("Command: {:?}", cmd);
@@ -45,9 +81,7 @@ pub(crate) fn parse_request(request: &str) -> Result<(Command, Vec<String>), &'s
     let command = match cmd.to_uppercase().as_str() {
         "SELECT" => {
             if parts.len() != 1 { return Err("Syntax error. Usage: SELECT <index>"); }
-            let index: u8 = parts[0].parse().map_err(|_| "Invalid database index")?;
-            if index > MAX_DATABASES { return Err("Invalid database index"); }
-            Command::Select(index)
+            Command::Select(parse_db_index(&parts[0])?)
         },
         "GET" => {
             if parts.len() != 1 { return Err("Syntax error. Usage: GET <key>"); }
@@ -55,10 +89,10 @@ pub(crate) fn parse_request(request: &str) -> Result<(Command, Vec<String>), &'s
         },
         "SET" => {
             if parts.len() != 2 { return Err("Syntax error. Usage: SET <key> <value>"); }
-            Command::Set(parts[0].clone(), parts[1].clone())
+            Command::Set(parts[0].clone(), raw[1].clone())
         },
         "PING" => {
-            Command::Ping(parts.first().map(|s| s.clone()))
+            Command::Ping(parts.first().cloned())
         },
         "EXISTS" => {
             if parts.is_empty() { return Err("Syntax error. Usage: EXISTS <key> [<key> ...]"); }
@@ -82,6 +116,634 @@ pub(crate) fn parse_request(request: &str) -> Result<(Command, Vec<String>), &'s
             if parts.len() > MAX_KEYS { return Err("Exceeded maximum number of keys (5)"); }
             Command::BRPOP(parts[..parts.len() - 1].to_vec(), parts[parts.len() - 1].parse().map_err(|_| "Invalid timeout")?)
         },
+        "HSET" => {
+            if parts.len() < 3 || parts.len().is_multiple_of(2) { return Err("Syntax error. Usage: HSET <key> <field> <value> [<field> <value> ...]"); }
+            let pairs = parts[1..].chunks(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect();
+            Command::Hset(parts[0].clone(), pairs)
+        },
+        "HSETNX" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: HSETNX <key> <field> <value>"); }
+            Command::Hsetnx(parts[0].clone(), parts[1].clone(), parts[2].clone())
+        },
+        "HGET" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: HGET <key> <field>"); }
+            Command::Hget(parts[0].clone(), parts[1].clone())
+        },
+        "HMGET" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: HMGET <key> <field> [<field> ...]"); }
+            Command::Hmget(parts[0].clone(), parts[1..].to_vec())
+        },
+        "HGETALL" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: HGETALL <key>"); }
+            Command::Hgetall(parts[0].clone())
+        },
+        "HDEL" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: HDEL <key> <field> [<field> ...]"); }
+            Command::Hdel(parts[0].clone(), parts[1..].to_vec())
+        },
+        "HEXISTS" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: HEXISTS <key> <field>"); }
+            Command::Hexists(parts[0].clone(), parts[1].clone())
+        },
+        "HLEN" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: HLEN <key>"); }
+            Command::Hlen(parts[0].clone())
+        },
+        "HKEYS" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: HKEYS <key>"); }
+            Command::Hkeys(parts[0].clone())
+        },
+        "HVALS" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: HVALS <key>"); }
+            Command::Hvals(parts[0].clone())
+        },
+        "HSTRLEN" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: HSTRLEN <key> <field>"); }
+            Command::Hstrlen(parts[0].clone(), parts[1].clone())
+        },
+        "HINCRBY" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: HINCRBY <key> <field> <increment>"); }
+            let increment = parts[2].parse().map_err(|_| "value is not an integer or out of range")?;
+            Command::Hincrby(parts[0].clone(), parts[1].clone(), increment)
+        },
+        "HINCRBYFLOAT" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: HINCRBYFLOAT <key> <field> <increment>"); }
+            let increment: f64 = parts[2].parse().map_err(|_| "value is not a valid float")?;
+            if !increment.is_finite() { return Err("value is not a valid float"); }
+            Command::Hincrbyfloat(parts[0].clone(), parts[1].clone(), increment)
+        },
+        "HRANDFIELD" => {
+            if parts.is_empty() || parts.len() > 3 { return Err("Syntax error. Usage: HRANDFIELD <key> [<count> [WITHVALUES]]"); }
+            let count = match parts.get(1) {
+                Some(count) => Some(count.parse().map_err(|_| "value is not an integer or out of range")?),
+                None => None,
+            };
+            let with_values = match parts.get(2) {
+                Some(option) if option.eq_ignore_ascii_case("WITHVALUES") => true,
+                Some(_) => return Err("Syntax error. Usage: HRANDFIELD <key> [<count> [WITHVALUES]]"),
+                None => false,
+            };
+            Command::Hrandfield(parts[0].clone(), count, with_values)
+        },
+        "SADD" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: SADD <key> <member> [<member> ...]"); }
+            Command::Sadd(parts[0].clone(), parts[1..].to_vec())
+        },
+        "SREM" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: SREM <key> <member> [<member> ...]"); }
+            Command::Srem(parts[0].clone(), parts[1..].to_vec())
+        },
+        "SISMEMBER" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: SISMEMBER <key> <member>"); }
+            Command::Sismember(parts[0].clone(), parts[1].clone())
+        },
+        "SMISMEMBER" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: SMISMEMBER <key> <member> [<member> ...]"); }
+            Command::Smismember(parts[0].clone(), parts[1..].to_vec())
+        },
+        "SMEMBERS" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: SMEMBERS <key>"); }
+            Command::Smembers(parts[0].clone())
+        },
+        "SCARD" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: SCARD <key>"); }
+            Command::Scard(parts[0].clone())
+        },
+        "SPOP" => {
+            if parts.is_empty() || parts.len() > 2 { return Err("Syntax error. Usage: SPOP <key> [<count>]"); }
+            let count = match parts.get(1) {
+                Some(count) => Some(count.parse().map_err(|_| "value is out of range, must be positive")?),
+                None => None,
+            };
+            Command::Spop(parts[0].clone(), count)
+        },
+        "SRANDMEMBER" => {
+            if parts.is_empty() || parts.len() > 2 { return Err("Syntax error. Usage: SRANDMEMBER <key> [<count>]"); }
+            let count = match parts.get(1) {
+                Some(count) => Some(count.parse().map_err(|_| "value is not an integer or out of range")?),
+                None => None,
+            };
+            Command::Srandmember(parts[0].clone(), count)
+        },
+        "SMOVE" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: SMOVE <source> <destination> <member>"); }
+            Command::Smove(parts[0].clone(), parts[1].clone(), parts[2].clone())
+        },
+        "SINTER" | "SUNION" | "SDIFF" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: SINTER|SUNION|SDIFF <key> [<key> ...]"); }
+            let operation = match cmd.to_uppercase().as_str() {
+                "SINTER" => SetOperation::Inter,
+                "SUNION" => SetOperation::Union,
+                _ => SetOperation::Diff,
+            };
+            Command::SetOperation(operation, parts.clone())
+        },
+        "SINTERSTORE" | "SUNIONSTORE" | "SDIFFSTORE" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: SINTERSTORE|SUNIONSTORE|SDIFFSTORE <destination> <key> [<key> ...]"); }
+            let operation = match cmd.to_uppercase().as_str() {
+                "SINTERSTORE" => SetOperation::Inter,
+                "SUNIONSTORE" => SetOperation::Union,
+                _ => SetOperation::Diff,
+            };
+            Command::SetOperationStore(operation, parts[0].clone(), parts[1..].to_vec())
+        },
+        "SINTERCARD" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: SINTERCARD <numkeys> <key> [<key> ...] [LIMIT <limit>]"); }
+            let numkeys: usize = parts[0].parse().map_err(|_| "numkeys should be greater than 0")?;
+            if numkeys == 0 { return Err("numkeys should be greater than 0"); }
+            if numkeys >= parts.len() { return Err("Number of keys can't be greater than number of args"); }
+            let limit = match &parts[numkeys + 1..] {
+                [] => 0,
+                [option, limit] if option.eq_ignore_ascii_case("LIMIT") => limit.parse().map_err(|_| "LIMIT can't be negative")?,
+                _ => return Err("Syntax error. Usage: SINTERCARD <numkeys> <key> [<key> ...] [LIMIT <limit>]"),
+            };
+            Command::Sintercard(parts[1..numkeys + 1].to_vec(), limit)
+        },
+        "ZADD" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: ZADD <key> [NX|XX] [GT|LT] [CH] [INCR] <score> <member> [<score> <member> ...]"); }
+            let (options, pairs) = parse_zadd_args(&parts[1..])?;
+            Command::Zadd(parts[0].clone(), options, pairs)
+        },
+        "ZINCRBY" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: ZINCRBY <key> <increment> <member>"); }
+            let increment = parse_score(&parts[1])?;
+            Command::Zincrby(parts[0].clone(), increment, parts[2].clone())
+        },
+        "ZREM" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: ZREM <key> <member> [<member> ...]"); }
+            Command::Zrem(parts[0].clone(), parts[1..].to_vec())
+        },
+        "ZSCORE" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: ZSCORE <key> <member>"); }
+            Command::Zscore(parts[0].clone(), parts[1].clone())
+        },
+        "ZRANK" => {
+            let with_score = match parts.get(2) {
+                Some(option) if option.eq_ignore_ascii_case("WITHSCORE") => true,
+                Some(_) => return Err("Syntax error. Usage: ZRANK <key> <member> [WITHSCORE]"),
+                None => false,
+            };
+            if parts.len() < 2 || parts.len() > 3 { return Err("Syntax error. Usage: ZRANK <key> <member> [WITHSCORE]"); }
+            Command::Zrank(parts[0].clone(), parts[1].clone(), with_score)
+        },
+        "ZCARD" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: ZCARD <key>"); }
+            Command::Zcard(parts[0].clone())
+        },
+        "ZCOUNT" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: ZCOUNT <key> <min> <max>"); }
+            Command::Zcount(parts[0].clone(), ScoreBound::parse(&parts[1])?, ScoreBound::parse(&parts[2])?)
+        },
+        "ZRANGE" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: ZRANGE <key> <start> <stop> [BYSCORE|BYLEX] [REV] [LIMIT <offset> <count>] [WITHSCORES]"); }
+            Command::Zrange(parts[0].clone(), parse_zrange_args(&parts[1..], true)?)
+        },
+        "ZRANGESTORE" => {
+            if parts.len() < 4 { return Err("Syntax error. Usage: ZRANGESTORE <dst> <src> <min> <max> [BYSCORE|BYLEX] [REV] [LIMIT <offset> <count>]"); }
+            Command::Zrangestore(parts[0].clone(), parts[1].clone(), parse_zrange_args(&parts[2..], false)?)
+        },
+        "ZPOPMIN" | "ZPOPMAX" => {
+            if parts.is_empty() || parts.len() > 2 { return Err("Syntax error. Usage: ZPOPMIN|ZPOPMAX <key> [<count>]"); }
+            let count = match parts.get(1) {
+                Some(count) => Some(count.parse().map_err(|_| "value is out of range, must be positive")?),
+                None => None,
+            };
+            Command::Zpop(parts[0].clone(), count, cmd.eq_ignore_ascii_case("ZPOPMAX"))
+        },
+        "BZPOPMIN" | "BZPOPMAX" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: BZPOPMIN|BZPOPMAX <key> [<key> ...] <timeout>"); }
+            if parts.len() > MAX_KEYS { return Err("Exceeded maximum number of keys (5)"); }
+            let timeout = parts[parts.len() - 1].parse().map_err(|_| "Invalid timeout")?;
+            Command::Bzpop(parts[..parts.len() - 1].to_vec(), timeout, cmd.eq_ignore_ascii_case("BZPOPMAX"))
+        },
+        "ZUNION" | "ZINTER" | "ZDIFF" => {
+            let operation = match cmd.to_uppercase().as_str() {
+                "ZUNION" => SetOperation::Union,
+                "ZINTER" => SetOperation::Inter,
+                _ => SetOperation::Diff,
+            };
+            let (keys, options) = parse_zcombine_args(&parts, operation, true)?;
+            Command::ZsetOperation(operation, keys, options)
+        },
+        "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: ZUNIONSTORE|ZINTERSTORE|ZDIFFSTORE <destination> <numkeys> <key> [<key> ...]"); }
+            let operation = match cmd.to_uppercase().as_str() {
+                "ZUNIONSTORE" => SetOperation::Union,
+                "ZINTERSTORE" => SetOperation::Inter,
+                _ => SetOperation::Diff,
+            };
+            let (keys, options) = parse_zcombine_args(&parts[1..], operation, false)?;
+            Command::ZsetOperationStore(operation, parts[0].clone(), keys, options)
+        },
+        "XADD" => {
+            if parts.len() < 4 { return Err("Syntax error. Usage: XADD <key> [NOMKSTREAM] [MAXLEN|MINID [=|~] <threshold> [LIMIT <count>]] <*|id> <field> <value> [<field> <value> ...]"); }
+            Command::Xadd(parts[0].clone(), parse_xadd_args(&parts[1..])?)
+        },
+        "XLEN" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: XLEN <key>"); }
+            Command::Xlen(parts[0].clone())
+        },
+        "XRANGE" | "XREVRANGE" => {
+            let rev = cmd.eq_ignore_ascii_case("XREVRANGE");
+            let count = match &parts[parts.len().min(3)..] {
+                [] => None,
+                [option, count] if option.eq_ignore_ascii_case("COUNT") => Some(count.parse().map_err(|_| "value is not an integer or out of range")?),
+                _ => return Err("Syntax error. Usage: XRANGE <key> <start> <end> [COUNT <count>]"),
+            };
+            if parts.len() < 3 { return Err("Syntax error. Usage: XRANGE <key> <start> <end> [COUNT <count>]"); }
+            // XREVRANGE takes the end of the interval first
+            let (start, end) = if rev { (&parts[2], &parts[1]) } else { (&parts[1], &parts[2]) };
+            Command::Xrange(parts[0].clone(), StreamId::parse_range_start(start)?, StreamId::parse_range_end(end)?, count, rev)
+        },
+        "XDEL" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: XDEL <key> <id> [<id> ...]"); }
+            let ids = parts[1..].iter().map(|id| StreamId::parse(id)).collect::<Result<_, _>>()?;
+            Command::Xdel(parts[0].clone(), ids)
+        },
+        "XTRIM" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: XTRIM <key> MAXLEN|MINID [=|~] <threshold> [LIMIT <count>]"); }
+            Command::Xtrim(parts[0].clone(), parse_xtrim_args(&parts[1..])?)
+        },
+        "XREAD" | "XREADGROUP" => {
+            Command::Xread(parse_xread_args(&parts, cmd.eq_ignore_ascii_case("XREADGROUP"))?)
+        },
+        "XGROUP" => {
+            Command::Xgroup(parse_xgroup_args(&parts)?)
+        },
+        "XACK" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: XACK <key> <group> <id> [<id> ...]"); }
+            let ids = parts[2..].iter().map(|id| StreamId::parse(id)).collect::<Result<_, _>>()?;
+            Command::Xack(parts[0].clone(), parts[1].clone(), ids)
+        },
+        "XPENDING" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: XPENDING <key> <group> [[IDLE <min-idle-time>] <start> <end> <count> [<consumer>]]"); }
+            Command::Xpending(parts[0].clone(), parts[1].clone(), parse_xpending_args(&parts[2..])?)
+        },
+        "XCLAIM" => {
+            if parts.len() < 5 { return Err("Syntax error. Usage: XCLAIM <key> <group> <consumer> <min-idle-time> <id> [<id> ...] [IDLE <ms>] [TIME <unix-time-ms>] [RETRYCOUNT <count>] [FORCE] [JUSTID]"); }
+            let min_idle = parts[3].parse().map_err(|_| "Invalid min-idle-time argument for XCLAIM")?;
+            let (ids, options) = parse_xclaim_args(&parts[4..])?;
+            Command::Xclaim(parts[0].clone(), parts[1].clone(), parts[2].clone(), min_idle, ids, options)
+        },
+        "XAUTOCLAIM" => {
+            if parts.len() < 5 { return Err("Syntax error. Usage: XAUTOCLAIM <key> <group> <consumer> <min-idle-time> <start> [COUNT <count>] [JUSTID]"); }
+            let min_idle = parts[3].parse().map_err(|_| "Invalid min-idle-time argument for XAUTOCLAIM")?;
+            let start = StreamId::parse_range_start(&parts[4])?;
+            let (mut count, mut just_id) = (100, false);
+            let mut index = 5;
+            while let Some(option) = parts.get(index) {
+                match option.to_uppercase().as_str() {
+                    "COUNT" => {
+                        count = parts.get(index + 1).and_then(|c| c.parse().ok()).filter(|c| *c > 0).ok_or("COUNT must be > 0")?;
+                        index += 1;
+                    }
+                    "JUSTID" => just_id = true,
+                    _ => return Err("syntax error"),
+                }
+                index += 1;
+            }
+            Command::Xautoclaim(parts[0].clone(), parts[1].clone(), parts[2].clone(), min_idle, start, count, just_id)
+        },
+        "XINFO" => {
+            Command::Xinfo(parse_xinfo_args(&parts)?)
+        },
+        "SETBIT" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: SETBIT <key> <offset> <0|1>"); }
+            Command::Setbit(parts[0].clone(), parse_bit_offset(&parts[1])?, parse_bit(&parts[2])?)
+        },
+        "GETBIT" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: GETBIT <key> <offset>"); }
+            Command::Getbit(parts[0].clone(), parse_bit_offset(&parts[1])?)
+        },
+        "BITCOUNT" => {
+            if parts.is_empty() || parts.len() > 4 { return Err("Syntax error. Usage: BITCOUNT <key> [<start> <end> [BYTE|BIT]]"); }
+            Command::Bitcount(parts[0].clone(), parse_bit_range(&parts[1..], true)?)
+        },
+        "BITPOS" => {
+            if parts.len() < 2 || parts.len() > 5 { return Err("Syntax error. Usage: BITPOS <key> <0|1> [<start> [<end> [BYTE|BIT]]]"); }
+            Command::Bitpos(parts[0].clone(), parse_bit(&parts[1])?, parse_bit_range(&parts[2..], false)?)
+        },
+        "BITOP" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: BITOP <AND|OR|XOR|NOT> <destkey> <key> [<key> ...]"); }
+            let operation = match parts[0].to_uppercase().as_str() {
+                "AND" => BitOperation::And,
+                "OR" => BitOperation::Or,
+                "XOR" => BitOperation::Xor,
+                "NOT" => BitOperation::Not,
+                _ => return Err("syntax error"),
+            };
+            if matches!(operation, BitOperation::Not) && parts.len() != 3 { return Err("BITOP NOT must be called with a single source key."); }
+            Command::Bitop(operation, parts[1].clone(), parts[2..].to_vec())
+        },
+        "BITFIELD" | "BITFIELD_RO" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: BITFIELD <key> [GET <type> <offset>] [SET <type> <offset> <value>] [INCRBY <type> <offset> <increment>] [OVERFLOW <WRAP|SAT|FAIL>] ..."); }
+            let read_only = cmd.eq_ignore_ascii_case("BITFIELD_RO");
+            Command::Bitfield(parts[0].clone(), parse_bitfield_args(&parts[1..], read_only)?)
+        },
+        "PFADD" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: PFADD <key> [<element> ...]"); }
+            Command::Pfadd(parts[0].clone(), raw[1..].to_vec())
+        },
+        "PFCOUNT" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: PFCOUNT <key> [<key> ...]"); }
+            Command::Pfcount(parts.clone())
+        },
+        "PFMERGE" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: PFMERGE <destkey> [<sourcekey> ...]"); }
+            Command::Pfmerge(parts[0].clone(), parts[1..].to_vec())
+        },
+        "GEOADD" => {
+            if parts.len() < 4 { return Err("Syntax error. Usage: GEOADD <key> [NX|XX] [CH] <longitude> <latitude> <member> [<longitude> <latitude> <member> ...]"); }
+            let (options, positions) = parse_geoadd_args(&parts[1..])?;
+            Command::Geoadd(parts[0].clone(), options, positions)
+        },
+        "GEOPOS" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: GEOPOS <key> [<member> ...]"); }
+            Command::Geopos(parts[0].clone(), parts[1..].to_vec())
+        },
+        "GEODIST" => {
+            if parts.len() != 3 && parts.len() != 4 { return Err("Syntax error. Usage: GEODIST <key> <member1> <member2> [M|KM|FT|MI]"); }
+            let unit = match parts.get(3) {
+                Some(unit) => parse_unit(unit)?,
+                None => 1.0,
+            };
+            Command::Geodist(parts[0].clone(), parts[1].clone(), parts[2].clone(), unit)
+        },
+        "GEOHASH" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: GEOHASH <key> [<member> ...]"); }
+            Command::Geohash(parts[0].clone(), parts[1..].to_vec())
+        },
+        "GEOSEARCH" => {
+            if parts.len() < 5 { return Err("Syntax error. Usage: GEOSEARCH <key> <FROMMEMBER <member>|FROMLONLAT <longitude> <latitude>> <BYRADIUS <radius> <unit>|BYBOX <width> <height> <unit>> [ASC|DESC] [COUNT <count> [ANY]] [WITHCOORD] [WITHDIST] [WITHHASH]"); }
+            Command::Geosearch(parts[0].clone(), parse_geosearch_args(&parts[1..], false)?)
+        },
+        "GEOSEARCHSTORE" => {
+            if parts.len() < 6 { return Err("Syntax error. Usage: GEOSEARCHSTORE <destination> <source> <FROMMEMBER <member>|FROMLONLAT <longitude> <latitude>> <BYRADIUS <radius> <unit>|BYBOX <width> <height> <unit>> [ASC|DESC] [COUNT <count> [ANY]] [STOREDIST]"); }
+            Command::Geosearchstore(parts[0].clone(), parts[1].clone(), parse_geosearch_args(&parts[2..], true)?)
+        },
+        "JSON.SET" => {
+            if parts.len() != 3 && parts.len() != 4 { return Err("Syntax error. Usage: JSON.SET <key> <path> <value> [NX|XX]"); }
+            let condition = match parts.get(3).map(|arg| arg.to_uppercase()).as_deref() {
+                None => JsonSetCondition::Always,
+                Some("NX") => JsonSetCondition::Nx,
+                Some("XX") => JsonSetCondition::Xx,
+                Some(_) => return Err("Syntax error. Usage: JSON.SET <key> <path> <value> [NX|XX]"),
+            };
+            Command::JsonSet(parts[0].clone(), JsonPath::parse(&parts[1])?, parse_json(&parts[2])?, condition)
+        },
+        "JSON.GET" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: JSON.GET <key> [<path> ...]"); }
+            let paths = if parts.len() == 1 { vec!["."] } else { parts[1..].iter().map(|path| path.as_str()).collect() };
+            Command::JsonGet(parts[0].clone(), paths.into_iter().map(JsonPath::parse).collect::<Result<_, _>>()?)
+        },
+        "JSON.DEL" | "JSON.FORGET" => {
+            if parts.is_empty() || parts.len() > 2 { return Err("Syntax error. Usage: JSON.DEL <key> [<path>]"); }
+            Command::JsonDel(parts[0].clone(), JsonPath::parse(parts.get(1).map_or("$", |path| path.as_str()))?)
+        },
+        "JSON.NUMINCRBY" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: JSON.NUMINCRBY <key> <path> <value>"); }
+            Command::JsonNumincrby(parts[0].clone(), JsonPath::parse(&parts[1])?, parse_json_number(&parts[2])?)
+        },
+        "JSON.ARRAPPEND" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: JSON.ARRAPPEND <key> <path> <value> [<value> ...]"); }
+            let values = parts[2..].iter().map(|value| parse_json(value)).collect::<Result<_, _>>()?;
+            Command::JsonArrappend(parts[0].clone(), JsonPath::parse(&parts[1])?, values)
+        },
+        "JSON.TYPE" => {
+            if parts.is_empty() || parts.len() > 2 { return Err("Syntax error. Usage: JSON.TYPE <key> [<path>]"); }
+            Command::JsonType(parts[0].clone(), JsonPath::parse(parts.get(1).map_or(".", |path| path.as_str()))?)
+        },
+        "BF.RESERVE" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: BF.RESERVE <key> <error_rate> <capacity> [EXPANSION <expansion>] [NONSCALING]"); }
+            Command::BfReserve(parts[0].clone(), parse_bf_reserve_args(&parts[1..])?)
+        },
+        "BF.ADD" | "BF.EXISTS" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: BF.ADD|BF.EXISTS <key> <item>"); }
+            if cmd.eq_ignore_ascii_case("BF.ADD") {
+                Command::BfAdd(parts[0].clone(), raw[1..].to_vec(), false)
+            } else {
+                Command::BfExists(parts[0].clone(), raw[1..].to_vec(), false)
+            }
+        },
+        "BF.MADD" | "BF.MEXISTS" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: BF.MADD|BF.MEXISTS <key> <item> [<item> ...]"); }
+            if cmd.eq_ignore_ascii_case("BF.MADD") {
+                Command::BfAdd(parts[0].clone(), raw[1..].to_vec(), true)
+            } else {
+                Command::BfExists(parts[0].clone(), raw[1..].to_vec(), true)
+            }
+        },
+        "BF.INFO" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: BF.INFO <key>"); }
+            Command::BfInfo(parts[0].clone())
+        },
+        "CMS.INITBYDIM" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: CMS.INITBYDIM <key> <width> <depth>"); }
+            let (width, depth) = parse_cms_dimensions(&parts[1..])?;
+            Command::CmsInit(parts[0].clone(), width, depth)
+        },
+        "CMS.INITBYPROB" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: CMS.INITBYPROB <key> <error> <probability>"); }
+            let (width, depth) = parse_cms_probabilities(&parts[1..])?;
+            Command::CmsInit(parts[0].clone(), width, depth)
+        },
+        "CMS.INCRBY" => {
+            if parts.len() < 3 || parts.len().is_multiple_of(2) { return Err("Syntax error. Usage: CMS.INCRBY <key> <item> <increment> [<item> <increment> ...]"); }
+            let increments = raw[1..].chunks(2)
+                .zip(parts[1..].chunks(2))
+                .map(|(item, args)| Ok((item[0].clone(), args[1].parse().map_err(|_| "CMS: Cannot parse number")?)))
+                .collect::<Result<_, &'static str>>()?;
+            Command::CmsIncrby(parts[0].clone(), increments)
+        },
+        "CMS.QUERY" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: CMS.QUERY <key> <item> [<item> ...]"); }
+            Command::CmsQuery(parts[0].clone(), raw[1..].to_vec())
+        },
+        "CMS.INFO" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: CMS.INFO <key>"); }
+            Command::CmsInfo(parts[0].clone())
+        },
+        "TS.CREATE" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: TS.CREATE <key> [RETENTION <ms>] [DUPLICATE_POLICY <policy>] [LABELS <label> <value> ...]"); }
+            Command::TsCreate(parts[0].clone(), parse_ts_create_args(&parts[1..], false)?)
+        },
+        "TS.ADD" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: TS.ADD <key> <timestamp>|* <value> [RETENTION <ms>] [DUPLICATE_POLICY <policy>] [ON_DUPLICATE <policy>] [LABELS <label> <value> ...]"); }
+            Command::TsAdd(parts[0].clone(), parse_timestamp(&parts[1])?, parse_sample_value(&parts[2])?, parse_ts_create_args(&parts[3..], true)?)
+        },
+        "TS.MADD" => {
+            if parts.is_empty() || !parts.len().is_multiple_of(3) { return Err("Syntax error. Usage: TS.MADD <key> <timestamp>|* <value> [<key> <timestamp>|* <value> ...]"); }
+            let samples = parts.chunks(3)
+                .map(|sample| Ok((sample[0].clone(), parse_timestamp(&sample[1])?, parse_sample_value(&sample[2])?)))
+                .collect::<Result<_, &'static str>>()?;
+            Command::TsMadd(samples)
+        },
+        "TS.RANGE" | "TS.REVRANGE" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: TS.RANGE|TS.REVRANGE <key> <from> <to> [COUNT <count>] [AGGREGATION <aggregator> <bucket>]"); }
+            Command::TsRange(parts[0].clone(), parse_ts_range_args(&parts[1..], false)?, cmd.eq_ignore_ascii_case("TS.REVRANGE"))
+        },
+        "TS.MRANGE" => {
+            if parts.len() < 4 { return Err("Syntax error. Usage: TS.MRANGE <from> <to> [COUNT <count>] [AGGREGATION <aggregator> <bucket>] [WITHLABELS] FILTER <filter> ..."); }
+            Command::TsMrange(parse_ts_range_args(&parts, true)?)
+        },
+        "DEL" | "UNLINK" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: DEL|UNLINK <key> [<key> ...]"); }
+            Command::Del(parts.clone())
+        },
+        "TYPE" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: TYPE <key>"); }
+            Command::Type(parts[0].clone())
+        },
+        "RENAME" | "RENAMENX" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: RENAME|RENAMENX <key> <newkey>"); }
+            Command::Rename(parts[0].clone(), parts[1].clone(), cmd.eq_ignore_ascii_case("RENAMENX"))
+        },
+        "COPY" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: COPY <source> <destination> [DB <destination-db>] [REPLACE]"); }
+            let (mut destination_db, mut replace) = (None, false);
+            let mut i = 2;
+            while i < parts.len() {
+                match parts[i].to_uppercase().as_str() {
+                    "DB" if i + 1 < parts.len() => {
+                        destination_db = Some(parse_db_index(&parts[i + 1])?);
+                        i += 2;
+                    }
+                    "REPLACE" => {
+                        replace = true;
+                        i += 1;
+                    }
+                    _ => return Err("Syntax error. Usage: COPY <source> <destination> [DB <destination-db>] [REPLACE]"),
+                }
+            }
+            Command::Copy(parts[0].clone(), parts[1].clone(), destination_db, replace)
+        },
+        "KEYS" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: KEYS <pattern>"); }
+            Command::Keys(parts[0].clone())
+        },
+        "SCAN" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: SCAN <cursor> [MATCH <pattern>] [COUNT <count>] [TYPE <type>]"); }
+            let (cursor, options) = parse_scan_args(&parts, true)?;
+            Command::Scan(cursor, options)
+        },
+        "HSCAN" | "SSCAN" | "ZSCAN" => {
+            if parts.len() < 2 { return Err("Syntax error. Usage: HSCAN|SSCAN|ZSCAN <key> <cursor> [MATCH <pattern>] [COUNT <count>]"); }
+            let (cursor, options) = parse_scan_args(&parts[1..], false)?;
+            match cmd.to_uppercase().as_str() {
+                "HSCAN" => Command::Hscan(parts[0].clone(), cursor, options),
+                "SSCAN" => Command::Sscan(parts[0].clone(), cursor, options),
+                _ => Command::Zscan(parts[0].clone(), cursor, options),
+            }
+        },
+        "DBSIZE" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: DBSIZE"); }
+            Command::Dbsize
+        },
+        "FLUSHDB" | "FLUSHALL" => {
+            let asynchronous = match parts.first().map(|mode| mode.to_uppercase()).as_deref() {
+                None | Some("SYNC") if parts.len() <= 1 => false,
+                Some("ASYNC") if parts.len() == 1 => true,
+                _ => return Err("Syntax error. Usage: FLUSHDB|FLUSHALL [ASYNC|SYNC]"),
+            };
+            Command::Flush(cmd.eq_ignore_ascii_case("FLUSHALL"), asynchronous)
+        },
+        "SWAPDB" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: SWAPDB <index1> <index2>"); }
+            Command::Swapdb(parse_db_index(&parts[0])?, parse_db_index(&parts[1])?)
+        },
+        "MOVE" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: MOVE <key> <db>"); }
+            Command::Move(parts[0].clone(), parse_db_index(&parts[1])?)
+        },
+        "OBJECT" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: OBJECT ENCODING|IDLETIME|FREQ|REFCOUNT <key>"); }
+            Command::Object(ObjectSubcommand::parse(&parts[0])?, parts[1].clone())
+        },
+        "TOUCH" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: TOUCH <key> [<key> ...]"); }
+            Command::Touch(parts.clone())
+        },
+        "RANDOMKEY" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: RANDOMKEY"); }
+            Command::Randomkey
+        },
+        "DUMP" => {
+            if parts.len() != 1 { return Err("Syntax error. Usage: DUMP <key>"); }
+            Command::Dump(parts[0].clone())
+        },
+        "RESTORE" | "RESTORE-ASKING" => {
+            if parts.len() < 3 { return Err("Syntax error. Usage: RESTORE <key> <ttl> <serialized-value> [REPLACE] [ABSTTL] [IDLETIME <seconds>] [FREQ <frequency>]"); }
+            let (ttl, options) = parse_restore_args(&parts[1], &parts[3..])?;
+            Command::Restore(parts[0].clone(), ttl, raw[2].clone(), options)
+        },
+        "MIGRATE" => {
+            if parts.len() < 5 { return Err("Syntax error. Usage: MIGRATE <host> <port> <key>|\"\" <destination-db> <timeout> [COPY] [REPLACE] [KEYS <key> [<key> ...]]"); }
+            Command::Migrate(parse_migrate_args(&parts, parse_db_index(&parts[3])?)?)
+        },
+        "SORT" | "SORT_RO" => {
+            if parts.is_empty() { return Err("Syntax error. Usage: SORT <key> [BY <pattern>] [LIMIT <offset> <count>] [GET <pattern> ...] [ASC|DESC] [ALPHA] [STORE <destination>]"); }
+            Command::Sort(parse_sort_args(&parts, cmd.eq_ignore_ascii_case("SORT_RO"))?)
+        },
+        "SAVE" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: SAVE"); }
+            Command::Save
+        },
+        "BGSAVE" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: BGSAVE"); }
+            Command::Bgsave
+        },
+        "LASTSAVE" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: LASTSAVE"); }
+            Command::Lastsave
+        },
+        "BGREWRITEAOF" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: BGREWRITEAOF"); }
+            Command::Bgrewriteaof
+        },
+        "REPLICAOF" | "SLAVEOF" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: REPLICAOF <host> <port>|NO ONE"); }
+            if parts[0].eq_ignore_ascii_case("NO") && parts[1].eq_ignore_ascii_case("ONE") {
+                Command::Replicaof(None)
+            } else {
+                Command::Replicaof(Some((parts[0].clone(), parts[1].parse().map_err(|_| "Invalid master port")?)))
+            }
+        },
+        "REPLCONF" => {
+            if parts.is_empty() || !parts.len().is_multiple_of(2) { return Err("Syntax error. Usage: REPLCONF <option> <value> [<option> <value> ...]"); }
+            Command::Replconf(parts.chunks(2).map(|pair| (pair[0].to_lowercase(), pair[1].clone())).collect())
+        },
+        "PSYNC" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: PSYNC <replicationid> <offset>"); }
+            Command::Psync(parts[0].clone(), parts[1].parse().map_err(|_| "value is not an integer or out of range")?)
+        },
+        "SYNC" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: SYNC"); }
+            Command::Sync
+        },
+        "ROLE" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: ROLE"); }
+            Command::Role
+        },
+        "INFO" => {
+            if parts.len() > 1 { return Err("Syntax error. Usage: INFO [<section>]"); }
+            Command::Info(parts.first().map(|section| section.to_lowercase()))
+        },
+        "WAIT" => {
+            if parts.len() != 2 { return Err("Syntax error. Usage: WAIT <numreplicas> <timeout>"); }
+            let numreplicas = parts[0].parse().map_err(|_| "value is not an integer or out of range")?;
+            Command::Wait(numreplicas, parts[1].parse().map_err(|_| "timeout is not an integer or out of range")?)
+        },
+        "WAITAOF" => {
+            if parts.len() != 3 { return Err("Syntax error. Usage: WAITAOF <numlocal> <numreplicas> <timeout>"); }
+            let numlocal = parts[0].parse().ok().filter(|numlocal| *numlocal <= 1).ok_or("value is out of range, must be 0 or 1")?;
+            let numreplicas = parts[1].parse().map_err(|_| "value is not an integer or out of range")?;
+            Command::Waitaof(numlocal, numreplicas, parts[2].parse().map_err(|_| "timeout is not an integer or out of range")?)
+        },
+        "CLUSTER" => Command::Cluster(ClusterSubcommand::parse(&parts)?),
+        "ASKING" => {
+            if !parts.is_empty() { return Err("Syntax error. Usage: ASKING"); }
+            Command::Asking
+        },
         _ => return Err("Unsupported command"),
     };
 
@@ -91,79 +753,783 @@ pub(crate) fn parse_request(request: &str) -> Result<(Command, Vec<String>), &'s
 // Execute the parsed command
 #[allow(unreachable_patterns)]
 #[allow(unused_variables)]
-pub async fn execute_command(cmd: Command, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8, cmd_executed: &mut bool, args: Vec<String>) -> String {
-    match cmd {
+pub async fn execute_command(cmd: Command, db: &Arc<Mutex<HashMap<u8, Database>>>, server: &Arc<Server>, selected_db: &mut u8, cmd_executed: &mut bool, request: Frame) -> Vec<u8> {
+    // OBJECT, TYPE and EXISTS only inspect keys, so they do not count as an access,
+    // and RESTORE sets the access metadata of the key itself
+    let accessed_keys: Vec<String> = match &cmd {
+        Command::Object(..) | Command::Type(_) | Command::Exists(_) | Command::Restore(..) => Vec::new(),
+        _ => cmd.keys().into_iter().map(String::from).collect(),
+    };
+    let is_write = cmd.is_write();
+
+    // Writes hold the AOF and the replication stream while they run, so they are appended in
+    // the order they ran. Commands that may wait on other clients or the network take their
+    // turn afterwards.
+    let logged_cmd = is_write.then(|| cmd.clone());
+    let mut aof_guard = match &server.aof {
+        Some(aof) if is_write && !cmd.may_wait() => Some(aof.lock().await),
+        _ => None,
+    };
+    let mut replication_guard = if is_write && !cmd.may_wait() { Some(server.replication.lock().await) } else { None };
+
+    let response = match cmd {
         Command::Select(index) => {
             if *cmd_executed {
-                "-SELECT can only be called at the start of the session\r\n".to_string()
+                b"-SELECT can only be called at the start of the session\r\n".to_vec()
             } else {
                 *cmd_executed = true;
                 *selected_db = index;
-                match execute_select_cmd(index, db, selected_db).await {
-                    Ok(response) => response,
-                    Err(e) => format!("-{}\r\n", e),
-                }
+                to_response(execute_select_cmd(index, db, selected_db).await)
             }
         }
         Command::Get(key) => {
             *cmd_executed = true;
-            match execute_get_cmd(key, db, selected_db) {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e),
-            }
+            to_response(execute_get_cmd(key, db, selected_db))
         }
         Command::Set(key, value) => {
             *cmd_executed = true;
-            match execute_set_cmd(key, value, db, selected_db) {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e),
-            }
+            to_response(execute_set_cmd(key, value, db, selected_db))
         }
         Command::Ping(message) => {
             *cmd_executed = true;
-            match execute_ping_cmd(message) {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e)
-            }
+            to_response(execute_ping_cmd(message))
         }
         Command::Exists(key) => {
             *cmd_executed = true;
-            match execute_exists_cmd(key, db, selected_db) {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e),
-            }
+            to_response(execute_exists_cmd(key, db, selected_db))
         },
         Command::Rpush(key, values) => {
             *cmd_executed = true;
-            match execute_rpush_cmd(key, values, db, selected_db) {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e),
-            }
+            to_response(execute_rpush_cmd(key, values, db, selected_db))
         }
         Command::Lpush(key, values) => {
             *cmd_executed = true;
-            match execute_lpush_cmd(key, values, db, selected_db) {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e),
-            }
+            to_response(execute_lpush_cmd(key, values, db, selected_db))
         }
         Command::BLPOP(keys, timeout) => {
             *cmd_executed = true;
-            let result = execute_blpop_cmd(keys, timeout, db, selected_db).await;
-                match result {
-                    Ok(response) => response,
-                    Err(e) => format!("-{}\r\n", e),
-                }
+            to_response(execute_blpop_cmd(keys, timeout, db, selected_db).await)
         }
         Command::BRPOP(keys, timeout) => {
             *cmd_executed = true;
-            let result = execute_brpop_cmd(keys, timeout, db, selected_db).await;
-            match result {
-                Ok(response) => response,
-                Err(e) => format!("-{}\r\n", e),
+            to_response(execute_brpop_cmd(keys, timeout, db, selected_db).await)
+        }
+        Command::Hset(key, pairs) => {
+            *cmd_executed = true;
+            to_response(execute_hset_cmd(key, pairs, db, selected_db))
+        }
+        Command::Hsetnx(key, field, value) => {
+            *cmd_executed = true;
+            to_response(execute_hsetnx_cmd(key, field, value, db, selected_db))
+        }
+        Command::Hget(key, field) => {
+            *cmd_executed = true;
+            to_response(execute_hget_cmd(key, field, db, selected_db))
+        }
+        Command::Hmget(key, fields) => {
+            *cmd_executed = true;
+            to_response(execute_hmget_cmd(key, fields, db, selected_db))
+        }
+        Command::Hgetall(key) => {
+            *cmd_executed = true;
+            to_response(execute_hgetall_cmd(key, db, selected_db))
+        }
+        Command::Hdel(key, fields) => {
+            *cmd_executed = true;
+            to_response(execute_hdel_cmd(key, fields, db, selected_db))
+        }
+        Command::Hexists(key, field) => {
+            *cmd_executed = true;
+            to_response(execute_hexists_cmd(key, field, db, selected_db))
+        }
+        Command::Hlen(key) => {
+            *cmd_executed = true;
+            to_response(execute_hlen_cmd(key, db, selected_db))
+        }
+        Command::Hkeys(key) => {
+            *cmd_executed = true;
+            to_response(execute_hkeys_cmd(key, db, selected_db))
+        }
+        Command::Hvals(key) => {
+            *cmd_executed = true;
+            to_response(execute_hvals_cmd(key, db, selected_db))
+        }
+        Command::Hstrlen(key, field) => {
+            *cmd_executed = true;
+            to_response(execute_hstrlen_cmd(key, field, db, selected_db))
+        }
+        Command::Hincrby(key, field, increment) => {
+            *cmd_executed = true;
+            to_response(execute_hincrby_cmd(key, field, increment, db, selected_db))
+        }
+        Command::Hincrbyfloat(key, field, increment) => {
+            *cmd_executed = true;
+            to_response(execute_hincrbyfloat_cmd(key, field, increment, db, selected_db))
+        }
+        Command::Hrandfield(key, count, with_values) => {
+            *cmd_executed = true;
+            to_response(execute_hrandfield_cmd(key, count, with_values, db, selected_db))
+        }
+        Command::Sadd(key, members) => {
+            *cmd_executed = true;
+            to_response(execute_sadd_cmd(key, members, db, selected_db))
+        }
+        Command::Srem(key, members) => {
+            *cmd_executed = true;
+            to_response(execute_srem_cmd(key, members, db, selected_db))
+        }
+        Command::Sismember(key, member) => {
+            *cmd_executed = true;
+            to_response(execute_sismember_cmd(key, member, db, selected_db))
+        }
+        Command::Smismember(key, members) => {
+            *cmd_executed = true;
+            to_response(execute_smismember_cmd(key, members, db, selected_db))
+        }
+        Command::Smembers(key) => {
+            *cmd_executed = true;
+            to_response(execute_smembers_cmd(key, db, selected_db))
+        }
+        Command::Scard(key) => {
+            *cmd_executed = true;
+            to_response(execute_scard_cmd(key, db, selected_db))
+        }
+        Command::Spop(key, count) => {
+            *cmd_executed = true;
+            to_response(execute_spop_cmd(key, count, db, selected_db))
+        }
+        Command::Srandmember(key, count) => {
+            *cmd_executed = true;
+            to_response(execute_srandmember_cmd(key, count, db, selected_db))
+        }
+        Command::Smove(source, destination, member) => {
+            *cmd_executed = true;
+            to_response(execute_smove_cmd(source, destination, member, db, selected_db))
+        }
+        Command::SetOperation(operation, keys) => {
+            *cmd_executed = true;
+            to_response(execute_set_operation_cmd(operation, keys, db, selected_db))
+        }
+        Command::SetOperationStore(operation, destination, keys) => {
+            *cmd_executed = true;
+            to_response(execute_set_operation_store_cmd(operation, destination, keys, db, selected_db))
+        }
+        Command::Sintercard(keys, limit) => {
+            *cmd_executed = true;
+            to_response(execute_sintercard_cmd(keys, limit, db, selected_db))
+        }
+        Command::Zadd(key, options, pairs) => {
+            *cmd_executed = true;
+            to_response(execute_zadd_cmd(key, options, pairs, db, selected_db))
+        }
+        Command::Zincrby(key, increment, member) => {
+            *cmd_executed = true;
+            to_response(execute_zincrby_cmd(key, increment, member, db, selected_db))
+        }
+        Command::Zrem(key, members) => {
+            *cmd_executed = true;
+            to_response(execute_zrem_cmd(key, members, db, selected_db))
+        }
+        Command::Zscore(key, member) => {
+            *cmd_executed = true;
+            to_response(execute_zscore_cmd(key, member, db, selected_db))
+        }
+        Command::Zrank(key, member, with_score) => {
+            *cmd_executed = true;
+            to_response(execute_zrank_cmd(key, member, with_score, db, selected_db))
+        }
+        Command::Zcard(key) => {
+            *cmd_executed = true;
+            to_response(execute_zcard_cmd(key, db, selected_db))
+        }
+        Command::Zcount(key, min, max) => {
+            *cmd_executed = true;
+            to_response(execute_zcount_cmd(key, min, max, db, selected_db))
+        }
+        Command::Zrange(key, options) => {
+            *cmd_executed = true;
+            to_response(execute_zrange_cmd(key, options, db, selected_db))
+        }
+        Command::Zrangestore(destination, source, options) => {
+            *cmd_executed = true;
+            to_response(execute_zrangestore_cmd(destination, source, options, db, selected_db))
+        }
+        Command::Zpop(key, count, max) => {
+            *cmd_executed = true;
+            to_response(execute_zpop_cmd(key, count, max, db, selected_db))
+        }
+        Command::Bzpop(keys, timeout, max) => {
+            *cmd_executed = true;
+            to_response(execute_bzpop_cmd(keys, timeout, max, db, selected_db).await)
+        }
+        Command::ZsetOperation(operation, keys, options) => {
+            *cmd_executed = true;
+            to_response(execute_zset_operation_cmd(operation, keys, options, db, selected_db))
+        }
+        Command::ZsetOperationStore(operation, destination, keys, options) => {
+            *cmd_executed = true;
+            to_response(execute_zset_operation_store_cmd(operation, destination, keys, options, db, selected_db))
+        }
+        Command::Xadd(key, args) => {
+            *cmd_executed = true;
+            to_response(execute_xadd_cmd(key, args, db, selected_db))
+        }
+        Command::Xlen(key) => {
+            *cmd_executed = true;
+            to_response(execute_xlen_cmd(key, db, selected_db))
+        }
+        Command::Xrange(key, start, end, count, rev) => {
+            *cmd_executed = true;
+            to_response(execute_xrange_cmd(key, start, end, count, rev, db, selected_db))
+        }
+        Command::Xdel(key, ids) => {
+            *cmd_executed = true;
+            to_response(execute_xdel_cmd(key, ids, db, selected_db))
+        }
+        Command::Xtrim(key, trim) => {
+            *cmd_executed = true;
+            to_response(execute_xtrim_cmd(key, trim, db, selected_db))
+        }
+        Command::Xread(args) => {
+            *cmd_executed = true;
+            to_response(execute_xread_cmd(args, db, selected_db).await)
+        }
+        Command::Xgroup(args) => {
+            *cmd_executed = true;
+            to_response(execute_xgroup_cmd(args, db, selected_db))
+        }
+        Command::Xack(key, group, ids) => {
+            *cmd_executed = true;
+            to_response(execute_xack_cmd(key, group, ids, db, selected_db))
+        }
+        Command::Xpending(key, group, range) => {
+            *cmd_executed = true;
+            to_response(execute_xpending_cmd(key, group, range, db, selected_db))
+        }
+        Command::Xclaim(key, group, consumer, min_idle, ids, options) => {
+            *cmd_executed = true;
+            to_response(execute_xclaim_cmd(key, group, consumer, min_idle, ids, options, db, selected_db))
+        }
+        Command::Xautoclaim(key, group, consumer, min_idle, start, count, just_id) => {
+            *cmd_executed = true;
+            to_response(execute_xautoclaim_cmd(key, group, consumer, min_idle, start, count, just_id, db, selected_db))
+        }
+        Command::Xinfo(args) => {
+            *cmd_executed = true;
+            to_response(execute_xinfo_cmd(args, db, selected_db))
+        }
+        Command::Setbit(key, offset, value) => {
+            *cmd_executed = true;
+            to_response(execute_setbit_cmd(key, offset, value, db, selected_db))
+        }
+        Command::Getbit(key, offset) => {
+            *cmd_executed = true;
+            to_response(execute_getbit_cmd(key, offset, db, selected_db))
+        }
+        Command::Bitcount(key, range) => {
+            *cmd_executed = true;
+            to_response(execute_bitcount_cmd(key, range, db, selected_db))
+        }
+        Command::Bitpos(key, bit, range) => {
+            *cmd_executed = true;
+            to_response(execute_bitpos_cmd(key, bit, range, db, selected_db))
+        }
+        Command::Bitop(operation, destination, keys) => {
+            *cmd_executed = true;
+            to_response(execute_bitop_cmd(operation, destination, keys, db, selected_db))
+        }
+        Command::Bitfield(key, ops) => {
+            *cmd_executed = true;
+            to_response(execute_bitfield_cmd(key, ops, db, selected_db))
+        }
+        Command::Pfadd(key, elements) => {
+            *cmd_executed = true;
+            to_response(execute_pfadd_cmd(key, elements, db, selected_db))
+        }
+        Command::Pfcount(keys) => {
+            *cmd_executed = true;
+            to_response(execute_pfcount_cmd(keys, db, selected_db))
+        }
+        Command::Pfmerge(destination, sources) => {
+            *cmd_executed = true;
+            to_response(execute_pfmerge_cmd(destination, sources, db, selected_db))
+        }
+        Command::Geoadd(key, options, positions) => {
+            *cmd_executed = true;
+            to_response(execute_geoadd_cmd(key, options, positions, db, selected_db))
+        }
+        Command::Geopos(key, members) => {
+            *cmd_executed = true;
+            to_response(execute_geopos_cmd(key, members, db, selected_db))
+        }
+        Command::Geodist(key, member1, member2, unit) => {
+            *cmd_executed = true;
+            to_response(execute_geodist_cmd(key, member1, member2, unit, db, selected_db))
+        }
+        Command::Geohash(key, members) => {
+            *cmd_executed = true;
+            to_response(execute_geohash_cmd(key, members, db, selected_db))
+        }
+        Command::Geosearch(key, options) => {
+            *cmd_executed = true;
+            to_response(execute_geosearch_cmd(key, options, db, selected_db))
+        }
+        Command::Geosearchstore(destination, source, options) => {
+            *cmd_executed = true;
+            to_response(execute_geosearchstore_cmd(destination, source, options, db, selected_db))
+        }
+        Command::JsonSet(key, path, value, condition) => {
+            *cmd_executed = true;
+            to_response(execute_json_set_cmd(key, path, value, condition, db, selected_db))
+        }
+        Command::JsonGet(key, paths) => {
+            *cmd_executed = true;
+            to_response(execute_json_get_cmd(key, paths, db, selected_db))
+        }
+        Command::JsonDel(key, path) => {
+            *cmd_executed = true;
+            to_response(execute_json_del_cmd(key, path, db, selected_db))
+        }
+        Command::JsonNumincrby(key, path, increment) => {
+            *cmd_executed = true;
+            to_response(execute_json_numincrby_cmd(key, path, increment, db, selected_db))
+        }
+        Command::JsonArrappend(key, path, values) => {
+            *cmd_executed = true;
+            to_response(execute_json_arrappend_cmd(key, path, values, db, selected_db))
+        }
+        Command::JsonType(key, path) => {
+            *cmd_executed = true;
+            to_response(execute_json_type_cmd(key, path, db, selected_db))
+        }
+        Command::BfReserve(key, options) => {
+            *cmd_executed = true;
+            to_response(execute_bf_reserve_cmd(key, options, db, selected_db))
+        }
+        Command::BfAdd(key, items, multiple) => {
+            *cmd_executed = true;
+            to_response(execute_bf_add_cmd(key, items, multiple, db, selected_db))
+        }
+        Command::BfExists(key, items, multiple) => {
+            *cmd_executed = true;
+            to_response(execute_bf_exists_cmd(key, items, multiple, db, selected_db))
+        }
+        Command::BfInfo(key) => {
+            *cmd_executed = true;
+            to_response(execute_bf_info_cmd(key, db, selected_db))
+        }
+        Command::CmsInit(key, width, depth) => {
+            *cmd_executed = true;
+            to_response(execute_cms_init_cmd(key, width, depth, db, selected_db))
+        }
+        Command::CmsIncrby(key, increments) => {
+            *cmd_executed = true;
+            to_response(execute_cms_incrby_cmd(key, increments, db, selected_db))
+        }
+        Command::CmsQuery(key, items) => {
+            *cmd_executed = true;
+            to_response(execute_cms_query_cmd(key, items, db, selected_db))
+        }
+        Command::CmsInfo(key) => {
+            *cmd_executed = true;
+            to_response(execute_cms_info_cmd(key, db, selected_db))
+        }
+        Command::TsCreate(key, options) => {
+            *cmd_executed = true;
+            to_response(execute_ts_create_cmd(key, options, db, selected_db))
+        }
+        Command::TsAdd(key, timestamp, value, options) => {
+            *cmd_executed = true;
+            to_response(execute_ts_add_cmd(key, timestamp, value, options, db, selected_db))
+        }
+        Command::TsMadd(samples) => {
+            *cmd_executed = true;
+            to_response(execute_ts_madd_cmd(samples, db, selected_db))
+        }
+        Command::TsRange(key, range, reverse) => {
+            *cmd_executed = true;
+            to_response(execute_ts_range_cmd(key, range, reverse, db, selected_db))
+        }
+        Command::TsMrange(range) => {
+            *cmd_executed = true;
+            to_response(execute_ts_mrange_cmd(range, db, selected_db))
+        }
+        Command::Del(keys) => {
+            *cmd_executed = true;
+            to_response(execute_del_cmd(keys, db, selected_db))
+        }
+        Command::Type(key) => {
+            *cmd_executed = true;
+            to_response(execute_type_cmd(key, db, selected_db))
+        }
+        Command::Rename(key, new_key, nx) => {
+            *cmd_executed = true;
+            to_response(execute_rename_cmd(key, new_key, nx, db, selected_db))
+        }
+        Command::Copy(source, destination, destination_db, replace) => {
+            *cmd_executed = true;
+            to_response(execute_copy_cmd(source, destination, destination_db, replace, db, selected_db))
+        }
+        Command::Keys(pattern) => {
+            *cmd_executed = true;
+            to_response(execute_keys_cmd(pattern, db, selected_db))
+        }
+        Command::Scan(cursor, options) => {
+            *cmd_executed = true;
+            to_response(execute_scan_cmd(cursor, options, db, selected_db))
+        }
+        Command::Hscan(key, cursor, options) => {
+            *cmd_executed = true;
+            to_response(execute_hscan_cmd(key, cursor, options, db, selected_db))
+        }
+        Command::Sscan(key, cursor, options) => {
+            *cmd_executed = true;
+            to_response(execute_sscan_cmd(key, cursor, options, db, selected_db))
+        }
+        Command::Zscan(key, cursor, options) => {
+            *cmd_executed = true;
+            to_response(execute_zscan_cmd(key, cursor, options, db, selected_db))
+        }
+        Command::Dbsize => {
+            *cmd_executed = true;
+            to_response(execute_dbsize_cmd(db, selected_db))
+        }
+        Command::Flush(all, asynchronous) => {
+            *cmd_executed = true;
+            to_response(execute_flush_cmd(all, asynchronous, db, selected_db))
+        }
+        Command::Swapdb(first, second) => {
+            *cmd_executed = true;
+            to_response(execute_swapdb_cmd(first, second, db))
+        }
+        Command::Move(key, destination_db) => {
+            *cmd_executed = true;
+            to_response(execute_move_cmd(key, destination_db, db, selected_db))
+        }
+        Command::Object(subcommand, key) => {
+            *cmd_executed = true;
+            to_response(execute_object_cmd(subcommand, key, db, selected_db))
+        }
+        Command::Touch(keys) => {
+            *cmd_executed = true;
+            to_response(execute_touch_cmd(keys, db, selected_db))
+        }
+        Command::Randomkey => {
+            *cmd_executed = true;
+            to_response(execute_randomkey_cmd(db, selected_db))
+        }
+        Command::Dump(key) => {
+            *cmd_executed = true;
+            to_response(execute_dump_cmd(key, db, selected_db))
+        }
+        Command::Restore(key, ttl, payload, options) => {
+            *cmd_executed = true;
+            to_response(execute_restore_cmd(key, ttl, payload, options, db, selected_db))
+        }
+        Command::Migrate(args) => {
+            *cmd_executed = true;
+            to_response(execute_migrate_cmd(args, db, selected_db, server.cluster.is_some()).await)
+        }
+        Command::Sort(args) => {
+            *cmd_executed = true;
+            to_response(execute_sort_cmd(args, db, selected_db))
+        }
+        Command::Save => {
+            *cmd_executed = true;
+            to_response(execute_save_cmd(db, server))
+        }
+        Command::Bgsave => {
+            *cmd_executed = true;
+            to_response(execute_bgsave_cmd(db, server))
+        }
+        Command::Lastsave => {
+            *cmd_executed = true;
+            to_response(execute_lastsave_cmd(server))
+        }
+        Command::Bgrewriteaof => {
+            *cmd_executed = true;
+            to_response(execute_bgrewriteaof_cmd(db, server).await)
+        }
+        Command::Replicaof(primary) => {
+            *cmd_executed = true;
+            to_response(execute_replicaof_cmd(primary, db, server).await)
+        }
+        Command::Replconf(options) => {
+            *cmd_executed = true;
+            to_response(execute_replconf_cmd(options))
+        }
+        // PSYNC and SYNC turn the connection into the link to a replica before they get here
+        Command::Psync(..) | Command::Sync => {
+            *cmd_executed = true;
+            b"-Can't SYNC from this connection\r\n".to_vec()
+        }
+        Command::Role => {
+            *cmd_executed = true;
+            to_response(execute_role_cmd(server).await)
+        }
+        Command::Info(section) => {
+            *cmd_executed = true;
+            to_response(execute_info_cmd(section, server).await)
+        }
+        Command::Wait(numreplicas, timeout) => {
+            *cmd_executed = true;
+            to_response(execute_wait_cmd(numreplicas, timeout, server).await)
+        }
+        Command::Waitaof(numlocal, numreplicas, timeout) => {
+            *cmd_executed = true;
+            to_response(execute_waitaof_cmd(numlocal, numreplicas, timeout, server).await)
+        }
+        Command::Cluster(subcommand) => {
+            *cmd_executed = true;
+            to_response(execute_cluster_cmd(subcommand, db, server).await)
+        }
+        Command::Asking => {
+            *cmd_executed = true;
+            to_response(execute_asking_cmd(server))
+        }
+        _ => b"Unsupported command".to_vec(),
+    };
+
+    touch_keys(&accessed_keys, db, *selected_db);
+    // Writes that did not fail count towards the save rules
+    if is_write && !response.starts_with(b"-") {
+        server.record_write();
+    }
+    if let Some(cmd) = logged_cmd {
+        let entries = log_entries(&cmd, request, &response, db, *selected_db);
+        if !entries.is_empty() {
+            if let Some(aof) = &server.aof {
+                let mut aof_guard = match aof_guard.take() {
+                    Some(guard) => guard,
+                    None => aof.lock().await,
+                };
+                aof_guard.append(*selected_db, &entries);
             }
+            let mut replication = match replication_guard.take() {
+                Some(guard) => guard,
+                None => server.replication.lock().await,
+            };
+            // A replica passes on the stream of its primary instead of its own writes
+            if replication.primary.is_none() {
+                replication.propagate(*selected_db, &entries);
+            }
+        }
+    }
+    response
+}
+
+// Turn the result of a command into the RESP reply sent to the client
+fn to_response<R: Into<Vec<u8>>>(result: Result<R, &'static str>) -> Vec<u8> {
+    match result {
+        Ok(response) => response.into(),
+        Err(e) => format!("-{}\r\n", e).into_bytes(),
+    }
+}
+
+
+// Encode a RESP2 bulk string
+pub(crate) fn bulk_string(value: &str) -> String {
+    format!("${}\r\n{}\r\n", value.len(), value)
+}
+
+// Encode a request as a RESP2 array of bulk strings, the way clients send commands
+pub fn encode_request(args: &[&[u8]]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend(bulk_bytes(arg));
+    }
+    request
+}
+
+// Encode a RESP2 bulk string holding arbitrary bytes
+pub(crate) fn bulk_bytes(value: &[u8]) -> Vec<u8> {
+    let mut response = format!("${}\r\n", value.len()).into_bytes();
+    response.extend_from_slice(value);
+    response.extend_from_slice(b"\r\n");
+    response
+}
+
+// Encode a RESP2 bulk string, or a null bulk string when there is no value
+pub(crate) fn optional_bulk_string(value: Option<&str>) -> String {
+    match value {
+        Some(value) => bulk_string(value),
+        None => "$-1\r\n".to_string(),
+    }
+}
+
+// Encode a RESP2 array of bulk strings
+pub(crate) fn bulk_array<S: AsRef<str>>(values: &[S]) -> String {
+    let mut response = format!("*{}\r\n", values.len());
+    for value in values {
+        response += &bulk_string(value.as_ref());
+    }
+    response
+}
+
+// Format a float the way Redis replies to INCRBYFLOAT-style commands
+pub(crate) fn format_float(value: f64) -> String {
+    if value.is_infinite() {
+        if value > 0.0 { "inf".to_string() } else { "-inf".to_string() }
+    } else {
+        format!("{}", value)
+    }
+}
+
+// Match a string against a Redis glob pattern: "*", "?", "[abc]", "[^abc]", "[a-z]" and "\" escapes.
+// Like stringmatchlen in Redis, a mismatch only goes back to the last star, which then takes
+// one more byte, so matching takes at most pattern length times string length steps.
+pub(crate) fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // The pattern position after the last star and the string position it resumes from
+    let mut star: Option<(usize, usize)> = None;
+    loop {
+        if p == pattern.len() && s == string.len() {
+            return true;
+        }
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            star = Some((p, s));
+            continue;
+        }
+        match glob_match_one(pattern, p, string.get(s)) {
+            Some(next) => {
+                p = next;
+                s += 1;
+            }
+            None => match star {
+                Some((star_p, star_s)) if star_s < string.len() => {
+                    star = Some((star_p, star_s + 1));
+                    p = star_p;
+                    s = star_s + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+}
+
+// Match the single byte c against the pattern element at p, other than a star, and return
+// the position of the next element if it matches
+fn glob_match_one(pattern: &[u8], mut p: usize, c: Option<&u8>) -> Option<usize> {
+    let c = *c?;
+    match *pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'[' => {
+            p += 1;
+            let negate = pattern.get(p) == Some(&b'^');
+            if negate {
+                p += 1;
+            }
+            let mut matched = false;
+            loop {
+                match pattern.get(p) {
+                    // An unterminated class ends with the pattern
+                    None => break,
+                    Some(b']') => {
+                        p += 1;
+                        break;
+                    }
+                    Some(b'\\') if p + 1 < pattern.len() => {
+                        p += 1;
+                        matched |= pattern[p] == c;
+                    }
+                    Some(&start) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                        let end = pattern[p + 2];
+                        matched |= (start.min(end)..=start.max(end)).contains(&c);
+                        p += 2;
+                    }
+                    Some(&other) => matched |= other == c,
+                }
+                p += 1;
+            }
+            (matched != negate).then_some(p)
         }
-        _ => "Unsupported command".to_string(),
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        other => (other == c).then_some(p + 1),
     }
 }
 
+// Write a file through a temporary file in the same directory that is renamed over path,
+// so a crash while writing never leaves a truncated file behind
+pub(crate) fn write_file_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp = path.with_file_name(format!("temp-{}-{}", std::process::id(), name));
+    let result = fs::File::create(&temp).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    }).and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(parts: &[&str]) -> Frame {
+        parts.iter().map(|p| p.as_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn read_frame_handles_forged_headers() {
+        assert_eq!(read_frame(b"*99999999999999\r\n$4\r\nPING\r\n"), Ok(None));
+        assert_eq!(read_frame(b"*1\r\n$99999999999999999999\r\nPING\r\n"), Err("Invalid request format"));
+        assert_eq!(read_frame(b"*1\r\n$18446744073709551615\r\nPING\r\n"), Ok(None));
+        assert_eq!(read_frame(b"*1\r\n$4\r\nPING\r\n"), Ok(Some((frame(&["PING"]), 14))));
+    }
+
+    #[test]
+    fn glob_match_patterns() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*", "", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h*llo", "heeeello", true),
+            ("h[ae]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-b]llo", "hbllo", true),
+            ("h[b-a]llo", "hallo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("*.txt", "notes.txt.bak", false),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYbZ", false),
+            ("[abc", "b", true),
+            ("user:*", "user:", true),
+        ];
+        for (pattern, string, expected) in cases {
+            assert_eq!(glob_match(pattern.as_bytes(), string.as_bytes()), *expected, "{} against {}", pattern, string);
+        }
+    }
+
+    #[test]
+    fn glob_match_many_stars_is_fast() {
+        // Recursing on every star takes exponential time on this pattern
+        let pattern = "a*".repeat(30) + "b";
+        let string = "a".repeat(60);
+        assert!(!glob_match(pattern.as_bytes(), string.as_bytes()));
+    }
+
+    #[test]
+    fn sintercard_parses_keys_and_limit() {
+        match parse_request(frame(&["SINTERCARD", "2", "a", "b", "LIMIT", "5"])) {
+            Ok((Command::Sintercard(keys, limit), _)) => {
+                assert_eq!(keys, vec!["a".to_string(), "b".to_string()]);
+                assert_eq!(limit, 5);
+            }
+            _ => panic!("SINTERCARD should parse"),
+        }
+    }
+
+    #[test]
+    fn sintercard_rejects_bad_numkeys() {
+        assert!(parse_request(frame(&["SINTERCARD", "0", "a"])).is_err());
+        assert!(parse_request(frame(&["SINTERCARD", "2", "a"])).is_err());
+        let max = usize::MAX.to_string();
+        assert!(parse_request(frame(&["SINTERCARD", &max, "a"])).is_err());
+    }
+}
@@ -0,0 +1,591 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use crate::database::{Database, Data};
+use crate::execute_cmd::{block_until_ready, WRONG_TYPE};
+use crate::keyspace_cmd::{scan_page, scan_reply, ScanOptions};
+use crate::set_cmd::SetOperation;
+use crate::sorted_set::{parse_score, LexBound, ScoreBound, SortedSet};
+use crate::utils::{bulk_array, bulk_string, format_float, optional_bulk_string};
+
+// The flags accepted by ZADD
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZaddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+    pub incr: bool,
+}
+
+// Which members ZRANGE selects. Score and lex bounds are always stored as (min, max),
+// whatever order REV made the client write them in.
+#[derive(Debug, Clone)]
+pub enum RangeSpec {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+// The arguments of ZRANGE and ZRANGESTORE after the key
+#[derive(Debug, Clone)]
+pub struct ZrangeOptions {
+    pub spec: RangeSpec,
+    pub rev: bool,
+    pub limit: Option<(usize, i64)>,
+    pub with_scores: bool,
+}
+
+// The parsed flags and (score, member) pairs of a ZADD command
+type ZaddArgs = (ZaddOptions, Vec<(f64, String)>);
+
+// Parse ZADD arguments after the key: [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]
+pub fn parse_zadd_args(args: &[String]) -> Result<ZaddArgs, &'static str> {
+    let mut options = ZaddOptions::default();
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        match arg.to_uppercase().as_str() {
+            "NX" => options.nx = true,
+            "XX" => options.xx = true,
+            "GT" => options.gt = true,
+            "LT" => options.lt = true,
+            "CH" => options.ch = true,
+            "INCR" => options.incr = true,
+            _ => break,
+        }
+        index += 1;
+    }
+
+    if options.nx && options.xx {
+        return Err("XX and NX options at the same time are not compatible");
+    }
+    if (options.gt && options.lt) || (options.nx && (options.gt || options.lt)) {
+        return Err("GT, LT, and/or NX options at the same time are not compatible");
+    }
+
+    let rest = &args[index..];
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err("Syntax error. Usage: ZADD <key> [NX|XX] [GT|LT] [CH] [INCR] <score> <member> [<score> <member> ...]");
+    }
+    if options.incr && rest.len() != 2 {
+        return Err("INCR option supports a single increment-element pair");
+    }
+
+    let mut pairs = Vec::with_capacity(rest.len() / 2);
+    for pair in rest.chunks(2) {
+        pairs.push((parse_score(&pair[0])?, pair[1].clone()));
+    }
+
+    Ok((options, pairs))
+}
+
+// Parse ZRANGE arguments after the key: start stop [BYSCORE|BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+pub fn parse_zrange_args(args: &[String], allow_with_scores: bool) -> Result<ZrangeOptions, &'static str> {
+    if args.len() < 2 {
+        return Err("Syntax error. Usage: ZRANGE <key> <start> <stop> [BYSCORE|BYLEX] [REV] [LIMIT <offset> <count>] [WITHSCORES]");
+    }
+
+    let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+    let mut limit = None;
+    let mut index = 2;
+    while let Some(arg) = args.get(index) {
+        match arg.to_uppercase().as_str() {
+            "BYSCORE" => by_score = true,
+            "BYLEX" => by_lex = true,
+            "REV" => rev = true,
+            "WITHSCORES" if allow_with_scores => with_scores = true,
+            "LIMIT" => {
+                let offset = args.get(index + 1).and_then(|o| o.parse::<i64>().ok());
+                let count = args.get(index + 2).and_then(|c| c.parse::<i64>().ok());
+                let (offset, count) = offset.zip(count).ok_or("value is not an integer or out of range")?;
+                // A negative offset selects nothing, like in Redis
+                limit = Some((usize::try_from(offset).unwrap_or(usize::MAX), count));
+                index += 2;
+            }
+            _ => return Err("syntax error"),
+        }
+        index += 1;
+    }
+
+    if by_score && by_lex {
+        return Err("syntax error");
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err("syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX");
+    }
+    if with_scores && by_lex {
+        return Err("syntax error, WITHSCORES not supported in combination with BYLEX");
+    }
+
+    // With REV, score and lex ranges are written from max to min
+    let (low, high) = if rev && (by_score || by_lex) { (&args[1], &args[0]) } else { (&args[0], &args[1]) };
+    let spec = if by_score {
+        RangeSpec::Score(ScoreBound::parse(low)?, ScoreBound::parse(high)?)
+    } else if by_lex {
+        RangeSpec::Lex(LexBound::parse(low)?, LexBound::parse(high)?)
+    } else {
+        let start = low.parse().map_err(|_| "value is not an integer or out of range")?;
+        let stop = high.parse().map_err(|_| "value is not an integer or out of range")?;
+        RangeSpec::Rank(start, stop)
+    };
+
+    Ok(ZrangeOptions { spec, rev, limit, with_scores })
+}
+
+// Look up the sorted set stored at key, failing if the key holds another type
+pub(crate) fn get_zset<'a>(cur_db: &'a Database, key: &str) -> Result<Option<&'a SortedSet>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::SortedSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Look up the sorted set stored at key mutably, failing if the key holds another type
+pub(crate) fn get_zset_mut<'a>(cur_db: &'a mut Database, key: &str) -> Result<Option<&'a mut SortedSet>, &'static str> {
+    match cur_db.data.get_mut(key) {
+        Some(Data::SortedSet(zset)) => Ok(Some(zset)),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(None),
+    }
+}
+
+// Store a sorted set at key, or remove the key when the set is empty
+pub(crate) fn store_zset(cur_db: &mut Database, key: String, zset: SortedSet) {
+    if zset.is_empty() {
+        cur_db.data.remove(&key);
+    } else {
+        cur_db.data.insert(key, Data::SortedSet(zset));
+    }
+}
+
+// Encode (member, score) pairs as a RESP array, optionally interleaving the scores
+pub(crate) fn zset_array(entries: &[(&String, f64)], with_scores: bool) -> String {
+    let per_entry = if with_scores { 2 } else { 1 };
+    let mut response = format!("*{}\r\n", entries.len() * per_entry);
+    for (member, score) in entries {
+        response += &bulk_string(member);
+        if with_scores {
+            response += &bulk_string(&format_float(*score));
+        }
+    }
+    response
+}
+
+// Select the members described by the ZRANGE options, in reply order
+fn select_range<'a>(zset: &'a SortedSet, options: &ZrangeOptions) -> Vec<(&'a String, f64)> {
+    let mut entries = match &options.spec {
+        RangeSpec::Rank(start, stop) => {
+            let len = zset.len() as i64;
+            let start = if *start < 0 { (len + start).max(0) } else { *start };
+            let stop = if *stop < 0 { len + stop } else { (*stop).min(len - 1) };
+            if start > stop || start >= len {
+                return Vec::new();
+            }
+            let (skip, take) = (start as usize, (stop - start + 1) as usize);
+            return if options.rev {
+                zset.iter().rev().skip(skip).take(take).collect()
+            } else {
+                zset.iter().skip(skip).take(take).collect()
+            };
+        }
+        RangeSpec::Score(min, max) => zset.range_by_score(min, max),
+        RangeSpec::Lex(min, max) => zset.range_by_lex(min, max),
+    };
+
+    if options.rev {
+        entries.reverse();
+    }
+    if let Some((offset, count)) = options.limit {
+        // A negative count returns every member from the offset on
+        let count = usize::try_from(count).unwrap_or(usize::MAX);
+        entries = entries.into_iter().skip(offset).take(count).collect();
+    }
+    entries
+}
+
+// ZADD command
+pub fn execute_zadd_cmd(key: String, options: ZaddOptions, pairs: Vec<(f64, String)>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let mut zset = match get_zset_mut(cur_db, &key)? {
+        Some(zset) => std::mem::take(zset),
+        None => SortedSet::new(),
+    };
+
+    let (mut added, mut changed) = (0, 0);
+    let mut incr_result = None;
+    let mut result = Ok(());
+    for (score, member) in pairs {
+        let current = zset.score(&member);
+        if (options.nx && current.is_some()) || (options.xx && current.is_none()) {
+            continue;
+        }
+
+        let score = if options.incr { current.unwrap_or(0.0) + score } else { score };
+        if score.is_nan() {
+            result = Err("resulting score is not a number (NaN)");
+            break;
+        }
+
+        match current {
+            Some(current) => {
+                if (options.gt && score <= current) || (options.lt && score >= current) {
+                    continue;
+                }
+                if score != current {
+                    zset.insert(member, score);
+                    changed += 1;
+                }
+            }
+            None => {
+                zset.insert(member, score);
+                added += 1;
+            }
+        }
+        incr_result = Some(score);
+    }
+
+    store_zset(cur_db, key, zset);
+    result?;
+
+    if options.incr {
+        return Ok(optional_bulk_string(incr_result.map(format_float).as_deref()));
+    }
+    Ok(format!(":{}\r\n", if options.ch { added + changed } else { added }))
+}
+
+// ZINCRBY command
+pub fn execute_zincrby_cmd(key: String, increment: f64, member: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let options = ZaddOptions { incr: true, ..ZaddOptions::default() };
+    execute_zadd_cmd(key, options, vec![(increment, member)], db, selected_db)
+}
+
+// ZREM command
+pub fn execute_zrem_cmd(key: String, members: Vec<String>, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let (removed, now_empty) = match get_zset_mut(cur_db, &key)? {
+        Some(zset) => (members.iter().filter(|member| zset.remove(member)).count(), zset.is_empty()),
+        None => (0, false),
+    };
+    if now_empty {
+        cur_db.data.remove(&key);
+    }
+
+    Ok(format!(":{}\r\n", removed))
+}
+
+// ZSCORE command
+pub fn execute_zscore_cmd(key: String, member: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let score = get_zset(cur_db, &key)?.and_then(|zset| zset.score(&member));
+
+    Ok(optional_bulk_string(score.map(format_float).as_deref()))
+}
+
+// ZRANK command
+pub fn execute_zrank_cmd(key: String, member: String, with_score: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let zset = get_zset(cur_db, &key)?;
+
+    let rank = zset.and_then(|zset| zset.rank(&member, false).zip(zset.score(&member)));
+    match rank {
+        Some((rank, score)) if with_score => Ok(format!("*2\r\n:{}\r\n{}", rank, bulk_string(&format_float(score)))),
+        Some((rank, _)) => Ok(format!(":{}\r\n", rank)),
+        None => Ok("$-1\r\n".to_string()),
+    }
+}
+
+// ZCARD command
+pub fn execute_zcard_cmd(key: String, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let len = get_zset(cur_db, &key)?.map_or(0, |zset| zset.len());
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// ZCOUNT command
+pub fn execute_zcount_cmd(key: String, min: ScoreBound, max: ScoreBound, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let count = get_zset(cur_db, &key)?.map_or(0, |zset| zset.range_by_score(&min, &max).len());
+
+    Ok(format!(":{}\r\n", count))
+}
+
+// ZRANGE command
+pub fn execute_zrange_cmd(key: String, options: ZrangeOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let entries = get_zset(cur_db, &key)?.map_or(Vec::new(), |zset| select_range(zset, &options));
+
+    Ok(zset_array(&entries, options.with_scores))
+}
+
+// ZRANGESTORE command
+pub fn execute_zrangestore_cmd(destination: String, source: String, options: ZrangeOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    let mut result = SortedSet::new();
+    if let Some(zset) = get_zset(cur_db, &source)? {
+        for (member, score) in select_range(zset, &options) {
+            result.insert(member.clone(), score);
+        }
+    }
+    let len = result.len();
+    store_zset(cur_db, destination, result);
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// How ZUNION and ZINTER combine the scores of a member found in several inputs
+#[derive(Debug, Clone, Copy)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+// The arguments of ZUNION, ZINTER and ZDIFF after the input keys
+#[derive(Debug, Clone)]
+pub struct ZcombineOptions {
+    pub weights: Vec<f64>,
+    pub aggregate: Aggregate,
+    pub with_scores: bool,
+}
+
+// Parse the arguments of ZUNION/ZINTER/ZDIFF starting at numkeys:
+// numkeys key [key ...] [WEIGHTS weight [weight ...]] [AGGREGATE SUM|MIN|MAX] [WITHSCORES]
+pub fn parse_zcombine_args(args: &[String], operation: SetOperation, allow_with_scores: bool) -> Result<(Vec<String>, ZcombineOptions), &'static str> {
+    let numkeys: usize = args.first().and_then(|n| n.parse().ok()).ok_or("value is not an integer or out of range")?;
+    if numkeys == 0 {
+        return Err("at least 1 input key is needed for this command");
+    }
+    // Compared without adding to numkeys, which may be as large as usize::MAX
+    if numkeys >= args.len() {
+        return Err("syntax error");
+    }
+    let keys = args[1..numkeys + 1].to_vec();
+
+    let mut options = ZcombineOptions { weights: vec![1.0; numkeys], aggregate: Aggregate::Sum, with_scores: false };
+    let mut index = numkeys + 1;
+    let weighted = !matches!(operation, SetOperation::Diff);
+    while let Some(arg) = args.get(index) {
+        match arg.to_uppercase().as_str() {
+            "WEIGHTS" if weighted => {
+                if args.len() - index - 1 < numkeys {
+                    return Err("syntax error");
+                }
+                for (weight, arg) in options.weights.iter_mut().zip(&args[index + 1..index + 1 + numkeys]) {
+                    *weight = parse_score(arg).map_err(|_| "weight value is not a float")?;
+                }
+                index += numkeys;
+            }
+            "AGGREGATE" if weighted => {
+                options.aggregate = match args.get(index + 1).map(|a| a.to_uppercase()).as_deref() {
+                    Some("SUM") => Aggregate::Sum,
+                    Some("MIN") => Aggregate::Min,
+                    Some("MAX") => Aggregate::Max,
+                    _ => return Err("syntax error"),
+                };
+                index += 1;
+            }
+            "WITHSCORES" if allow_with_scores => options.with_scores = true,
+            _ => return Err("syntax error"),
+        }
+        index += 1;
+    }
+
+    Ok((keys, options))
+}
+
+// Read the (member, score) pairs of a sorted set or a plain set, whose members all score 1
+fn zcombine_input(cur_db: &Database, key: &str) -> Result<Vec<(String, f64)>, &'static str> {
+    match cur_db.data.get(key) {
+        Some(Data::SortedSet(zset)) => Ok(zset.iter().map(|(member, score)| (member.clone(), score)).collect()),
+        Some(Data::Set(set)) => Ok(set.iter().map(|member| (member.clone(), 1.0)).collect()),
+        Some(_) => Err(WRONG_TYPE),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Combine two scores; inf - inf is defined as 0 like in Redis
+fn aggregate_scores(aggregate: Aggregate, a: f64, b: f64) -> f64 {
+    let score = match aggregate {
+        Aggregate::Sum => a + b,
+        Aggregate::Min => a.min(b),
+        Aggregate::Max => a.max(b),
+    };
+    if score.is_nan() { 0.0 } else { score }
+}
+
+// Compute the union, intersection or difference of the sorted sets stored at keys
+fn compute_zset_operation(cur_db: &Database, operation: SetOperation, keys: &[String], options: &ZcombineOptions) -> Result<SortedSet, &'static str> {
+    let mut inputs = Vec::with_capacity(keys.len());
+    for (key, weight) in keys.iter().zip(&options.weights) {
+        let weighted = zcombine_input(cur_db, key)?.into_iter().map(|(member, score)| {
+            let score = score * weight;
+            (member, if score.is_nan() { 0.0 } else { score })
+        });
+        inputs.push(weighted.collect::<HashMap<String, f64>>());
+    }
+
+    let mut inputs = inputs.into_iter();
+    let mut combined = inputs.next().unwrap_or_default();
+    for input in inputs {
+        match operation {
+            SetOperation::Union => {
+                for (member, score) in input {
+                    combined.entry(member)
+                        .and_modify(|current| *current = aggregate_scores(options.aggregate, *current, score))
+                        .or_insert(score);
+                }
+            }
+            SetOperation::Inter => {
+                combined.retain(|member, _| input.contains_key(member));
+                for (member, current) in combined.iter_mut() {
+                    *current = aggregate_scores(options.aggregate, *current, input[member]);
+                }
+            }
+            SetOperation::Diff => combined.retain(|member, _| !input.contains_key(member)),
+        }
+    }
+
+    let mut result = SortedSet::new();
+    for (member, score) in combined {
+        result.insert(member, score);
+    }
+    Ok(result)
+}
+
+// ZUNION, ZINTER and ZDIFF commands
+pub fn execute_zset_operation_cmd(operation: SetOperation, keys: Vec<String>, options: ZcombineOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let result = compute_zset_operation(cur_db, operation, &keys, &options)?;
+    let entries: Vec<(&String, f64)> = result.iter().collect();
+
+    Ok(zset_array(&entries, options.with_scores))
+}
+
+// ZUNIONSTORE, ZINTERSTORE and ZDIFFSTORE commands
+pub fn execute_zset_operation_store_cmd(operation: SetOperation, destination: String, keys: Vec<String>, options: ZcombineOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let result = compute_zset_operation(cur_db, operation, &keys, &options)?;
+    let len = result.len();
+    store_zset(cur_db, destination, result);
+
+    Ok(format!(":{}\r\n", len))
+}
+
+// Pop up to count members with the lowest (or highest) scores from the sorted set at key
+fn pop_from_zset(cur_db: &mut Database, key: &str, count: usize, max: bool) -> Result<Vec<(String, f64)>, &'static str> {
+    let (popped, now_empty) = match get_zset_mut(cur_db, key)? {
+        Some(zset) => {
+            let picked: Vec<(String, f64)> = if max {
+                zset.iter().rev().take(count).map(|(member, score)| (member.clone(), score)).collect()
+            } else {
+                zset.iter().take(count).map(|(member, score)| (member.clone(), score)).collect()
+            };
+            for (member, _) in &picked {
+                zset.remove(member);
+            }
+            (picked, zset.is_empty())
+        }
+        None => (Vec::new(), false),
+    };
+    if now_empty {
+        cur_db.data.remove(key);
+    }
+
+    Ok(popped)
+}
+
+// ZPOPMIN and ZPOPMAX commands
+pub fn execute_zpop_cmd(key: String, count: Option<usize>, max: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+    let popped = pop_from_zset(cur_db, &key, count.unwrap_or(1), max)?;
+    let entries: Vec<(&String, f64)> = popped.iter().map(|(member, score)| (member, *score)).collect();
+
+    Ok(zset_array(&entries, true))
+}
+
+// Pop one member from the first non-empty sorted set among keys, replying with [key, member, score]
+fn pop_from_zsets(keys: &[String], max: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<Option<String>, &'static str> {
+    let mut db = db.lock().unwrap();
+    let cur_db = db.get_mut(selected_db).ok_or("Database not found")?;
+
+    for key in keys {
+        if let Some((member, score)) = pop_from_zset(cur_db, key, 1, max)?.pop() {
+            return Ok(Some(bulk_array(&[key.clone(), member, format_float(score)])));
+        }
+    }
+
+    Ok(None)
+}
+
+// BZPOPMIN and BZPOPMAX commands
+pub async fn execute_bzpop_cmd(keys: Vec<String>, timeout_duration: f64, max: bool, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    block_until_ready(timeout_duration, || pop_from_zsets(&keys, max, db, selected_db)).await
+}
+
+// ZSCAN command
+pub fn execute_zscan_cmd(key: String, cursor: u64, options: ScanOptions, db: &Arc<Mutex<HashMap<u8, Database>>>, selected_db: &mut u8) -> Result<String, &'static str> {
+    let db = db.lock().unwrap();
+    let cur_db = db.get(selected_db).ok_or("Database not found")?;
+    let zset = match get_zset(cur_db, &key)? {
+        Some(zset) => zset,
+        None => return Ok(scan_reply(0, zset_array(&[], true))),
+    };
+
+    let (next_cursor, page) = scan_page(zset.iter(), cursor, options.count);
+    let entries: Vec<(&String, f64)> = page.into_iter().filter(|(member, _)| options.matches(member)).collect();
+    Ok(scan_reply(next_cursor, zset_array(&entries, true)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn zcombine_parses_keys_and_options() {
+        let (keys, options) = parse_zcombine_args(&args(&["2", "a", "b", "WEIGHTS", "2", "3", "AGGREGATE", "MAX", "WITHSCORES"]), SetOperation::Union, true).unwrap();
+        assert_eq!(keys, args(&["a", "b"]));
+        assert_eq!(options.weights, vec![2.0, 3.0]);
+        assert!(matches!(options.aggregate, Aggregate::Max));
+        assert!(options.with_scores);
+    }
+
+    #[test]
+    fn zcombine_rejects_bad_numkeys() {
+        assert!(parse_zcombine_args(&args(&["0", "a"]), SetOperation::Union, false).is_err());
+        assert!(parse_zcombine_args(&args(&["3", "a", "b"]), SetOperation::Inter, false).is_err());
+        let max = usize::MAX.to_string();
+        for operation in [SetOperation::Union, SetOperation::Inter, SetOperation::Diff] {
+            // ZUNION, ZINTER and ZDIFF allow WITHSCORES, their STORE forms don't
+            for allow_with_scores in [true, false] {
+                assert!(parse_zcombine_args(&args(&[&max, "a"]), operation, allow_with_scores).is_err());
+                assert!(parse_zcombine_args(&args(&["2", "a", "b", "WEIGHTS", &max]), operation, allow_with_scores).is_err());
+                assert!(parse_zcombine_args(&args(&["1", "a"]), operation, allow_with_scores).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn zcombine_rejects_short_weights() {
+        assert!(parse_zcombine_args(&args(&["2", "a", "b", "WEIGHTS", "1"]), SetOperation::Union, false).is_err());
+        assert!(parse_zcombine_args(&args(&["1", "a", "WEIGHTS", "1"]), SetOperation::Diff, false).is_err());
+    }
+}